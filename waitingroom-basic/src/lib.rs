@@ -1,16 +1,30 @@
+use std::{path::Path, sync::Arc};
+
 use waitingroom_core::{
+    admission::AdmissionPolicy,
+    challenge::{Challenge, ChallengeKey, ChallengeSolution},
+    journal::{Journal, JournalEntry},
+    observer::{NoopObserver, WaitingRoomEvent, WaitingRoomEventKind, WaitingRoomObserver},
     pass::Pass,
     random::RandomProvider,
-    settings,
-    ticket::{Ticket, TicketIdentifier, TicketType},
-    time::TimeProvider,
-    NodeId, WaitingRoomError, WaitingRoomMessageTriggered, WaitingRoomTimerTriggered,
-    WaitingRoomUserTriggered,
+    settings::{self, RoomPhase},
+    snapshot::RoomSnapshot,
+    status::RoomStatus,
+    throughput::ThroughputEstimator,
+    ticket::{PriorityClass, Ticket, TicketIdentifier, TicketType},
+    time::{Time, TimeProvider},
+    token::TokenCodec,
+    NodeId, RoomId, WaitingRoomError, WaitingRoomMessageTriggered, WaitingRoomTimerTriggered,
+    WaitingRoomTokenTriggered, WaitingRoomUserTriggered,
 };
-use waitingroom_local_queue::LocalQueue;
+use waitingroom_local_queue::{ChallengeList, LocalQueue, PreQueue, RevocationList};
+use waitingroom_metrics::{Counter, Gauge, Histogram, Metrics, MetricsSink};
 
 pub use settings::GeneralWaitingRoomSettings;
 
+#[cfg(test)]
+mod test;
+
 /// Since we always only have a single node in the basic waiting rooms,
 /// we just hardcode the node id as 0.
 const SELF_NODE_ID: NodeId = 0;
@@ -23,10 +37,34 @@ where
     R: RandomProvider,
 {
     local_queue: LocalQueue,
+    /// Users who joined before the room opened. See [`settings::ActivationWindow`].
+    pre_queue: PreQueue,
     queue_leaving_list: Vec<Ticket>,
     on_site_list: Vec<Pass>,
+    /// Tickets and passes that were revoked with [`BasicWaitingRoom::kick_ticket`] and [`BasicWaitingRoom::revoke_pass`].
+    revoked_tickets: RevocationList,
+    revoked_passes: RevocationList,
+    /// Signs the challenges given out, and remembers the ones that were solved. See [`WaitingRoomUserTriggered::challenge`].
+    challenges: ChallengeList,
 
     settings: GeneralWaitingRoomSettings,
+    /// Decides how many users are let out of the queue at every eviction.
+    admission_policy: Box<dyn AdmissionPolicy>,
+    /// Measures how many users are let out of the queue, to estimate when the users in the queue are let out.
+    throughput: ThroughputEstimator,
+    /// The last time [`WaitingRoomTimerTriggered::eviction`] was called. See [`BasicWaitingRoom::status`].
+    last_eviction_time: Option<Time>,
+    /// Gets told about everything that happens in the room, and can veto joins and leaves.
+    observer: Box<dyn WaitingRoomObserver>,
+    /// Every change to the queue and the on site list is written here, so it can be replayed after a crash.
+    journal: Option<Journal>,
+    /// Tickets and passes from other rooms are rejected. See [`WaitingRoomUserTriggered::room_id`].
+    room_id: RoomId,
+
+    /// Where the metrics of the room are recorded. See [`waitingroom_metrics`].
+    metrics: Metrics,
+    /// Used to encode and decode tokens given to clients. See [`WaitingRoomTokenTriggered`].
+    token_codec: Option<TokenCodec>,
 
     time_provider: T,
     random_provider: R,
@@ -37,15 +75,43 @@ where
     T: TimeProvider,
     R: RandomProvider,
 {
-    fn join(&mut self) -> Result<waitingroom_core::ticket::Ticket, WaitingRoomError> {
-        let ticket = waitingroom_core::ticket::Ticket::new(
-            SELF_NODE_ID,
-            self.settings.ticket_refresh_time,
-            self.settings.ticket_expiry_time,
-            &self.time_provider,
-            &self.random_provider,
-        );
-        self.enqueue(ticket);
+    fn room_id(&self) -> RoomId {
+        self.room_id
+    }
+
+    fn join_with_priority(
+        &mut self,
+        priority_class: PriorityClass,
+    ) -> Result<waitingroom_core::ticket::Ticket, WaitingRoomError> {
+        if self.settings.challenge.is_some() {
+            return Err(WaitingRoomError::ChallengeRequired);
+        }
+        self.issue_ticket(priority_class)
+    }
+
+    fn challenge(&mut self) -> Option<Challenge> {
+        let settings = self.settings.challenge?;
+        Some(self.challenges.issue(
+            self.random_provider.random_u64(),
+            self.time_provider.get_now_time(),
+            settings.expiry_time,
+            settings.difficulty,
+        ))
+    }
+
+    fn join_with_priority_and_solution(
+        &mut self,
+        priority_class: PriorityClass,
+        solution: ChallengeSolution,
+    ) -> Result<Ticket, WaitingRoomError> {
+        if self.settings.challenge.is_none() {
+            return self.issue_ticket(priority_class);
+        }
+        // The solution is only used up once the user has a ticket, so it can be tried again if the queue is full.
+        let now_time = self.time_provider.get_now_time();
+        self.challenges.check(solution, now_time)?;
+        let ticket = self.issue_ticket(priority_class)?;
+        self.challenges.redeem(solution, now_time)?;
         Ok(ticket)
     }
 
@@ -53,7 +119,19 @@ where
         &mut self,
         ticket: waitingroom_core::ticket::Ticket,
     ) -> Result<waitingroom_core::CheckInResponse, WaitingRoomError> {
-        if ticket.is_expired(&self.time_provider) {
+        if ticket.room_id != self.room_id {
+            return Err(WaitingRoomError::TicketAtWrongRoom);
+        }
+
+        if self.revoked_tickets.contains(ticket.identifier) {
+            return Err(WaitingRoomError::TicketRevoked);
+        }
+
+        if self
+            .stored_ticket(ticket.identifier)
+            .unwrap_or(&ticket)
+            .is_expired(&self.time_provider)
+        {
             // This happens when a user has not refreshed their ticket in time.
             return Err(WaitingRoomError::TicketExpired);
         }
@@ -64,10 +142,15 @@ where
             self.enqueue(ticket);
         }
 
+        self.open_if_due();
+
         let position_estimate = match self.local_queue.get_position(ticket.identifier) {
             Some(position) => position + 1, // 0 is reserved for users who are allowed to leave the queue.
             None => {
-                if self.queue_leaving_list.contains(&ticket) {
+                if self.pre_queue.contains(ticket.identifier) {
+                    // The lottery has not been drawn yet, so everyone in the pre-queue could end up last.
+                    self.pre_queue.len()
+                } else if self.queue_leaving_list.contains(&ticket) {
                     // The ticket is in the queue leaving list.
                     // This means that the user can now leave the queue.
                     // When this happens, we send the user's position estimate as 0.
@@ -94,6 +177,7 @@ where
         let ticket = self
             .local_queue
             .entry(ticket.identifier)
+            .or_else(|| self.pre_queue.entry(ticket.identifier))
             .or_else(|| {
                 // If it's not in the local queue but we did get here, it's in the queue leaving list.
                 // So, we need to update the ticket in the queue leaving list.
@@ -112,13 +196,21 @@ where
                     &self.time_provider,
                     SELF_NODE_ID,
                 );
-                ticket
+                *ticket
             })
             .unwrap();
+        self.journal(JournalEntry::TicketRefresh(ticket));
 
+        self.notify(WaitingRoomEventKind::CheckIn {
+            ticket: ticket.identifier,
+            position_estimate,
+        });
         Ok(waitingroom_core::CheckInResponse {
-            new_ticket: *ticket,
+            new_ticket: ticket,
             position_estimate,
+            admission_estimate: self
+                .throughput
+                .estimate(position_estimate, self.time_provider.get_now_time()),
         })
     }
 
@@ -126,7 +218,19 @@ where
         &mut self,
         ticket: waitingroom_core::ticket::Ticket,
     ) -> Result<waitingroom_core::pass::Pass, WaitingRoomError> {
-        if ticket.is_expired(&self.time_provider) {
+        if ticket.room_id != self.room_id {
+            return Err(WaitingRoomError::TicketAtWrongRoom);
+        }
+
+        if self.revoked_tickets.contains(ticket.identifier) {
+            return Err(WaitingRoomError::TicketRevoked);
+        }
+
+        if self
+            .stored_ticket(ticket.identifier)
+            .unwrap_or(&ticket)
+            .is_expired(&self.time_provider)
+        {
             // This happens when a user has not refreshed their ticket in time.
             return Err(WaitingRoomError::TicketExpired);
         }
@@ -137,31 +241,37 @@ where
             return Err(WaitingRoomError::TicketAtWrongNode);
         }
 
-        if !self.queue_leaving_list.contains(&ticket) {
+        // We need the ticket from the queue leaving list, instead of the one passed in.
+        // This is because this one might have more updated information. (eg. eviction time)
+        let ticket = match self.queue_leaving_list.iter().find(|t| **t == ticket) {
+            Some(ticket) => *ticket,
             // The user is not allowed to leave the queue yet.
-            return Err(WaitingRoomError::TicketCannotLeaveYet);
-        }
+            None => return Err(WaitingRoomError::TicketCannotLeaveYet),
+        };
+        let event = WaitingRoomEventKind::Leave {
+            ticket: ticket.identifier,
+        };
+        self.allow(event)?;
 
         // The user is allowed to leave the queue.
         // We remove the ticket from the queue leaving list.
         self.queue_leaving_list.retain(|t| t != &ticket);
         // We know the number of items removed here is always 1.
-        metrics::gauge!(
-            "waitingroom.to_let_in_count",
-            &[("node", SELF_NODE_ID.to_string())]
-        )
-        .decrement(1);
+        self.metrics.decrement_gauge(Gauge::ToLetIn, 1);
 
         // Generate a pass for the user.
         let pass = Pass::from_ticket(ticket, self.settings.pass_expiry_time, &self.time_provider);
 
         // And add the pass to the users on site list.
         self.on_site_list.push(pass);
-        metrics::gauge!(
-            "waitingroom.on_site_count",
-            &[("node", SELF_NODE_ID.to_string())]
-        )
-        .increment(1);
+        self.journal(JournalEntry::Leave(pass));
+        self.metrics.increment_gauge(Gauge::OnSite, 1);
+        self.metrics.increment_counter(Counter::Leaves, 1);
+        self.metrics.record_duration(
+            Histogram::WaitTime,
+            self.time_provider.get_now_time() - ticket.join_time,
+        );
+        self.notify(event);
 
         Ok(pass)
     }
@@ -170,19 +280,28 @@ where
         &mut self,
         pass: waitingroom_core::pass::Pass,
     ) -> Result<waitingroom_core::pass::Pass, WaitingRoomError> {
+        if pass.room_id != self.room_id {
+            return Err(WaitingRoomError::PassAtWrongRoom);
+        }
+
+        if self.revoked_passes.contains(pass.identifier) {
+            return Err(WaitingRoomError::PassRevoked);
+        }
+
         let now_time = self.time_provider.get_now_time();
 
-        if pass.expiry_time < now_time {
+        let expiry_time = self
+            .on_site_list
+            .iter()
+            .find(|p| p.identifier == pass.identifier)
+            .map_or(pass.expiry_time, |p| p.expiry_time);
+        if expiry_time < now_time {
             return Err(WaitingRoomError::PassExpired);
         }
 
         if pass.node_id != SELF_NODE_ID {
             self.on_site_list.push(pass);
-            metrics::gauge!(
-                "waitingroom.on_site_count",
-                &[("node", SELF_NODE_ID.to_string())]
-            )
-            .increment(1);
+            self.metrics.increment_gauge(Gauge::OnSite, 1);
         }
 
         let pass = self
@@ -197,11 +316,55 @@ where
                 );
                 pass
             });
-        match pass {
-            Some(pass) => Ok(*pass),
+        match pass.copied() {
+            Some(pass) => {
+                self.journal(JournalEntry::PassRefresh(pass));
+                Ok(pass)
+            }
             None => Err(WaitingRoomError::PassNotInList),
         }
     }
+
+    fn abandon(
+        &mut self,
+        ticket: waitingroom_core::ticket::Ticket,
+    ) -> Result<(), WaitingRoomError> {
+        if ticket.room_id != self.room_id {
+            return Err(WaitingRoomError::TicketAtWrongRoom);
+        }
+
+        if !self.remove_ticket(ticket.identifier) {
+            return Err(WaitingRoomError::TicketNotInQueue);
+        }
+        self.notify(WaitingRoomEventKind::Abandon {
+            ticket: ticket.identifier,
+        });
+        Ok(())
+    }
+
+    fn release(&mut self, pass: waitingroom_core::pass::Pass) -> Result<(), WaitingRoomError> {
+        if pass.room_id != self.room_id {
+            return Err(WaitingRoomError::PassAtWrongRoom);
+        }
+
+        if !self.remove_pass(pass.identifier) {
+            return Err(WaitingRoomError::PassNotInList);
+        }
+        self.notify(WaitingRoomEventKind::Release {
+            pass: pass.identifier,
+        });
+        Ok(())
+    }
+}
+
+impl<T, R> WaitingRoomTokenTriggered for BasicWaitingRoom<T, R>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+{
+    fn token_codec(&self) -> Option<&TokenCodec> {
+        self.token_codec.as_ref()
+    }
 }
 
 impl<T, R> WaitingRoomTimerTriggered for BasicWaitingRoom<T, R>
@@ -214,42 +377,75 @@ where
 
         // Remove expired tickets from the local queue.
         let removed_count = self.local_queue.remove_expired(now_time);
-        metrics::gauge!(
-            "waitingroom.in_queue_count",
-            &[("node", SELF_NODE_ID.to_string())]
-        )
-        .decrement(removed_count as f64);
-
-        self.on_site_list.retain(|pass| pass.expiry_time > now_time);
-        metrics::gauge!(
-            "waitingroom.on_site_count",
-            &[("node", SELF_NODE_ID.to_string())]
-        )
-        .set(self.on_site_list.len() as f64);
+        let mut expired_ticket_count = removed_count + self.pre_queue.remove_expired(now_time);
+        self.revoked_tickets.remove_expired(now_time);
+        self.revoked_passes.remove_expired(now_time);
+        self.challenges.remove_expired(now_time);
+        self.metrics
+            .decrement_gauge(Gauge::InQueue, removed_count as usize);
+
+        let (on_site, expired): (Vec<Pass>, Vec<Pass>) = self
+            .on_site_list
+            .drain(..)
+            .partition(|pass| pass.expiry_time > now_time);
+        self.on_site_list = on_site;
+        self.metrics
+            .increment_counter(Counter::PassExpiries, expired.len() as u64);
+        for pass in expired {
+            self.notify(WaitingRoomEventKind::PassExpired {
+                pass: pass.identifier,
+            });
+        }
+        self.metrics
+            .set_gauge(Gauge::OnSite, self.on_site_list.len());
 
         // TODO: Replace this with something in an operation queue.
         // This method should not be called inside another method.
         self.let_users_out_of_queue(removed_count as usize)?;
 
+        let leaving_count = self.queue_leaving_list.len();
         self.queue_leaving_list
             .retain(|ticket| ticket.expiry_time > now_time);
-        metrics::gauge!(
-            "waitingroom.to_let_in_count",
-            &[("node", SELF_NODE_ID.to_string())]
-        )
-        .set(self.queue_leaving_list.len() as f64);
+        expired_ticket_count += (leaving_count - self.queue_leaving_list.len()) as u64;
+        self.metrics
+            .set_gauge(Gauge::ToLetIn, self.queue_leaving_list.len());
+        self.metrics
+            .increment_counter(Counter::TicketExpiries, expired_ticket_count);
+
+        self.journal(JournalEntry::Expire(now_time));
+        if self
+            .journal
+            .as_ref()
+            .is_some_and(|journal| journal.needs_compaction())
+        {
+            self.compact_journal()?;
+        }
 
         Ok(())
     }
 
     fn eviction(&mut self) -> Result<(), WaitingRoomError> {
+        self.last_eviction_time = Some(self.time_provider.get_now_time());
+        self.open_if_due();
+        self.throughput.record(self.time_provider.get_now_time(), 0);
+
+        if self.phase() == RoomPhase::Inactive {
+            // The activation window has ended, so everyone who is still in the queue is let through.
+            return self.let_users_out_of_queue(self.local_queue.len());
+        }
+
         // We use this user count, because people that are about to leave the queue
         // should be counted as users on site.
         let user_count = self.on_site_list.len() + self.queue_leaving_list.len();
 
-        // If there are too few users on site, let users out of the queue.
-        if user_count < self.settings.target_user_count {
-            self.let_users_out_of_queue(self.settings.target_user_count - self.on_site_list.len())?;
+        let now_time = self.time_provider.get_now_time();
+        self.admission_policy
+            .set_target_user_count(self.settings.target_user_count_at(now_time));
+        let to_let_out =
+            self.admission_policy
+                .users_to_let_in(self.local_queue.len(), user_count, now_time);
+        if to_let_out > 0 {
+            self.let_users_out_of_queue(to_let_out)?;
         }
 
 
@@ -277,12 +473,192 @@ where
     pub fn new(settings: GeneralWaitingRoomSettings, time_provider: T, random_provider: R) -> Self {
         Self {
             local_queue: LocalQueue::new(),
+            pre_queue: PreQueue::new(),
             queue_leaving_list: Vec::new(),
             on_site_list: Vec::new(),
+            revoked_tickets: RevocationList::new(),
+            revoked_passes: RevocationList::new(),
+            challenges: ChallengeList::new(ChallengeKey::random()),
             time_provider,
             random_provider,
+            admission_policy: settings.admission_policy(),
+            throughput: ThroughputEstimator::default(),
+            last_eviction_time: None,
+            observer: Box::new(NoopObserver),
+            journal: None,
             settings,
+            room_id: waitingroom_core::DEFAULT_ROOM_ID,
+            metrics: Metrics::for_room(SELF_NODE_ID, waitingroom_core::DEFAULT_ROOM_ID),
+            token_codec: None,
+        }
+    }
+
+    /// Sets the ID of this room. This should be done before any tickets are given out,
+    /// since tickets and passes from a different room are rejected.
+    pub fn set_room_id(&mut self, room_id: RoomId) {
+        self.room_id = room_id;
+        self.metrics.set_room_id(room_id);
+    }
+
+    /// Sets the codec used for the token based operations in [`WaitingRoomTokenTriggered`].
+    pub fn set_token_codec(&mut self, token_codec: TokenCodec) {
+        self.token_codec = Some(token_codec);
+    }
+
+    /// Sets the key challenges are signed with. By default, a random key is used. Challenges that were given out
+    /// before are not accepted anymore.
+    pub fn set_challenge_key(&mut self, key: ChallengeKey) {
+        self.challenges.set_key(key);
+    }
+
+    /// Replaces the settings of a running waiting room. The new settings are validated first.
+    /// A new `target_user_count` is used from the next eviction, and new ticket and pass times
+    /// are used from the next time a ticket or pass is created or refreshed.
+    pub fn update_settings(
+        &mut self,
+        settings: GeneralWaitingRoomSettings,
+    ) -> Result<(), WaitingRoomError> {
+        settings.validate()?;
+        if self.settings.admission_changed(&settings) {
+            self.admission_policy = settings.admission_policy();
         }
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Replaces the admission policy created from the settings with a custom one.
+    /// It is replaced again if the admission settings are updated.
+    pub fn set_admission_policy(&mut self, admission_policy: Box<dyn AdmissionPolicy>) {
+        self.admission_policy = admission_policy;
+    }
+
+    /// Replaces the observer, which gets told about everything that happens in the room.
+    pub fn set_observer(&mut self, observer: Box<dyn WaitingRoomObserver>) {
+        self.observer = observer;
+    }
+
+    /// Replaces the sink the metrics of the room are recorded to, which is a [`waitingroom_metrics::RecorderSink`] by default.
+    pub fn set_metrics_sink(&mut self, metrics_sink: Arc<dyn MetricsSink>) {
+        self.metrics.set_sink(metrics_sink);
+    }
+
+    /// Takes a snapshot of the state of the room, which can be restored with [`BasicWaitingRoom::restore`]
+    /// after the process restarts.
+    pub fn snapshot(&self) -> RoomSnapshot {
+        RoomSnapshot {
+            room_id: self.room_id,
+            node_id: SELF_NODE_ID,
+            taken_at: self.time_provider.get_now_time(),
+            queue: self.local_queue.iter().copied().collect(),
+            pre_queue: self.pre_queue.iter().copied().collect(),
+            queue_leaving_list: self.queue_leaving_list.clone(),
+            on_site_list: self.on_site_list.clone(),
+            revoked_tickets: self.revoked_tickets.iter().collect(),
+            revoked_passes: self.revoked_passes.iter().collect(),
+        }
+    }
+
+    /// Returns the current state of the queue and the site, without changing the room.
+    pub fn status(&self) -> RoomStatus {
+        RoomStatus {
+            room_id: self.room_id,
+            queue_length: self.local_queue.len(),
+            leaving_list_length: self.queue_leaving_list.len(),
+            on_site_count: self.on_site_list.len(),
+            target_user_count: self
+                .settings
+                .target_user_count_at(self.time_provider.get_now_time()),
+            last_eviction_time: self.last_eviction_time,
+        }
+    }
+
+    /// Adds the users in a snapshot back to the room. This should be called on a new room, before anyone joins.
+    /// The deadlines in the snapshot are moved by the time the room was down. See [`RoomSnapshot::adjust_times`].
+    pub fn restore(&mut self, mut snapshot: RoomSnapshot) -> Result<(), WaitingRoomError> {
+        if snapshot.room_id != self.room_id {
+            return Err(WaitingRoomError::SnapshotAtWrongRoom);
+        }
+        snapshot.adjust_times(self.time_provider.get_now_time());
+
+        for ticket in snapshot.queue {
+            self.enqueue(ticket);
+        }
+        for ticket in snapshot.pre_queue {
+            self.pre_queue.push(ticket);
+        }
+        self.metrics
+            .increment_gauge(Gauge::ToLetIn, snapshot.queue_leaving_list.len());
+        self.queue_leaving_list.extend(snapshot.queue_leaving_list);
+        self.metrics
+            .increment_gauge(Gauge::OnSite, snapshot.on_site_list.len());
+        self.on_site_list.extend(snapshot.on_site_list);
+        for (identifier, until) in snapshot.revoked_tickets {
+            self.revoked_tickets.revoke(identifier, until);
+        }
+        for (identifier, until) in snapshot.revoked_passes {
+            self.revoked_passes.revoke(identifier, until);
+        }
+        Ok(())
+    }
+
+    /// Starts writing every change to the room to the journal at `path`. If the journal already has changes from
+    /// before the process restarted, they are restored first, like a snapshot. See [`BasicWaitingRoom::restore`].
+    /// The journal is then compacted, so it only has the current state.
+    pub fn open_journal(&mut self, path: impl AsRef<Path>) -> Result<(), WaitingRoomError> {
+        let (journal, replayed) = Journal::open(path)?;
+        if let Some(snapshot) = replayed {
+            self.restore(snapshot)?;
+        }
+        self.journal = Some(journal);
+        self.compact_journal()
+    }
+
+    /// Replaces everything in the journal with a snapshot of the current state.
+    /// This also happens at a cleanup once the journal has grown large enough.
+    pub fn compact_journal(&mut self) -> Result<(), WaitingRoomError> {
+        let snapshot = self.snapshot();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.compact(&snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a change to the journal, if there is one. A failed write is logged, but the change is still made,
+    /// since the room works fine without its journal until it restarts.
+    fn journal(&mut self, entry: JournalEntry) {
+        let now_time = self.time_provider.get_now_time();
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(err) = journal.append(now_time, entry) {
+                log::error!("Failed to write to the journal: {}", err);
+            }
+        }
+    }
+
+    /// Asks the observer whether an event may happen, before it does.
+    fn allow(&mut self, kind: WaitingRoomEventKind) -> Result<(), WaitingRoomError> {
+        let event = self.event(kind);
+        self.observer
+            .allow(&event)
+            .map_err(WaitingRoomError::Vetoed)
+    }
+
+    /// Tells the observer about an event that happened.
+    fn notify(&mut self, kind: WaitingRoomEventKind) {
+        let event = self.event(kind);
+        self.observer.notify(&event);
+    }
+
+    fn event(&self, kind: WaitingRoomEventKind) -> WaitingRoomEvent {
+        WaitingRoomEvent {
+            kind,
+            room_id: self.room_id,
+            node_id: SELF_NODE_ID,
+            time: self.time_provider.get_now_time(),
+        }
+    }
+
+    pub fn get_settings(&self) -> &GeneralWaitingRoomSettings {
+        &self.settings
     }
 
     pub fn let_users_out_of_queue(&mut self, count: usize) -> Result<(), WaitingRoomError> {
@@ -291,17 +667,20 @@ where
             .filter_map(|_| self.dequeue())
             .collect::<Vec<_>>();
 
+        let now_time = self.time_provider.get_now_time();
         let mut idx = 0;
         while idx < tickets.len() {
-            let ticket = tickets[idx];
+            let mut ticket = tickets[idx];
             match ticket.ticket_type {
                 TicketType::Normal => {
+                    ticket.set_eviction_time(now_time);
                     self.queue_leaving_list.push(ticket);
-                    metrics::gauge!(
-                        "waitingroom.to_let_in_count",
-                        &[("node", SELF_NODE_ID.to_string())]
-                    )
-                    .increment(1);
+                    self.journal(JournalEntry::LetOut(ticket));
+                    self.throughput.record(now_time, 1);
+                    self.notify(WaitingRoomEventKind::LetOut {
+                        ticket: ticket.identifier,
+                    });
+                    self.metrics.increment_gauge(Gauge::ToLetIn, 1);
                 }
                 TicketType::Drain => {
                     // This ticket is a dummy ticket. We shouldn't do anything with it.
@@ -320,6 +699,174 @@ where
         Ok(())
     }
 
+    /// Gives the user a ticket, after any challenge has been checked.
+    fn issue_ticket(&mut self, priority_class: PriorityClass) -> Result<Ticket, WaitingRoomError> {
+        let ticket = waitingroom_core::ticket::Ticket::new(
+            SELF_NODE_ID,
+            self.room_id,
+            self.settings.ticket_refresh_time,
+            self.settings.ticket_expiry_time,
+            priority_class,
+            &self.time_provider,
+            &self.random_provider,
+        );
+        let event = WaitingRoomEventKind::Join {
+            ticket: ticket.identifier,
+            priority_class,
+        };
+        if self.phase() != RoomPhase::Inactive {
+            self.ensure_queue_not_full()?;
+        }
+        self.allow(event)?;
+        self.open_if_due();
+        let ticket = match self.phase() {
+            RoomPhase::Inactive => self.let_through(ticket),
+            RoomPhase::PreQueue => {
+                self.pre_queue.push(ticket);
+                self.journal(JournalEntry::PreQueue(ticket));
+                ticket
+            }
+            RoomPhase::Open => {
+                self.enqueue(ticket);
+                ticket
+            }
+        };
+        self.notify(event);
+        self.metrics.increment_counter(Counter::Joins, 1);
+        Ok(ticket)
+    }
+
+    /// Returns [`WaitingRoomError::QueueFull`] if the queue, including the pre-queue, has reached its maximum length.
+    fn ensure_queue_not_full(&self) -> Result<(), WaitingRoomError> {
+        let Some(max_queue_length) = self.settings.max_queue_length else {
+            return Ok(());
+        };
+        let queue_length = self.local_queue.len() + self.pre_queue.len();
+        if queue_length < max_queue_length {
+            return Ok(());
+        }
+        Err(WaitingRoomError::QueueFull {
+            retry_after: self.throughput.retry_after(
+                queue_length + 1 - max_queue_length,
+                self.time_provider.get_now_time(),
+                self.settings.eviction_interval,
+            ),
+        })
+    }
+
+    /// Returns the phase of the activation window the room is in. Rooms without a window are always open.
+    fn phase(&self) -> RoomPhase {
+        match self.settings.activation_window {
+            Some(window) => window.phase(self.time_provider.get_now_time()),
+            None => RoomPhase::Open,
+        }
+    }
+
+    /// Draws the lottery for the users in the pre-queue once the room has opened, and puts them in the queue.
+    fn open_if_due(&mut self) {
+        if self.pre_queue.is_empty() || self.phase() == RoomPhase::PreQueue {
+            return;
+        }
+        let tickets = match self.settings.activation_window {
+            Some(window) => self.pre_queue.draw(&window, &self.random_provider),
+            // The window was removed from the settings before the room opened, so there is no lottery.
+            None => self.pre_queue.take_all(),
+        };
+        for ticket in tickets {
+            self.enqueue(ticket);
+        }
+    }
+
+    /// Lets a user through without queueing, for when the room is outside of its activation window.
+    fn let_through(&mut self, mut ticket: Ticket) -> Ticket {
+        ticket.set_eviction_time(self.time_provider.get_now_time());
+        self.queue_leaving_list.push(ticket);
+        self.journal(JournalEntry::LetOut(ticket));
+        self.notify(WaitingRoomEventKind::LetOut {
+            ticket: ticket.identifier,
+        });
+        self.metrics.increment_gauge(Gauge::ToLetIn, 1);
+        ticket
+    }
+
+    /// Removes a user from the queue, and makes sure their ticket can't be used again.
+    /// Returns [`WaitingRoomError::TicketNotInQueue`] if the ticket is not in the queue,
+    /// but it is revoked anyway, in case it is used later.
+    pub fn kick_ticket(
+        &mut self,
+        ticket_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        // A ticket can't be used after it expired, and the expiry time is at most this far away.
+        let until = self.time_provider.get_now_time() + self.settings.ticket_expiry_time;
+        self.revoked_tickets.revoke(ticket_identifier, until);
+        self.journal(JournalEntry::TicketRevoked {
+            identifier: ticket_identifier,
+            until,
+        });
+        if !self.remove_ticket(ticket_identifier) {
+            return Err(WaitingRoomError::TicketNotInQueue);
+        }
+        Ok(())
+    }
+
+    /// Removes a user from the site, and makes sure their pass can't be used again.
+    /// Returns [`WaitingRoomError::PassNotInList`] if the pass is not on the site,
+    /// but it is revoked anyway, in case it is used later.
+    pub fn revoke_pass(
+        &mut self,
+        pass_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        // A pass can't be used after it expired, and the expiry time is at most this far away.
+        let until = self.time_provider.get_now_time() + self.settings.pass_expiry_time;
+        self.revoked_passes.revoke(pass_identifier, until);
+        self.journal(JournalEntry::PassRevoked {
+            identifier: pass_identifier,
+            until,
+        });
+        if !self.remove_pass(pass_identifier) {
+            return Err(WaitingRoomError::PassNotInList);
+        }
+        Ok(())
+    }
+
+    /// Removes a ticket from wherever it is in the room. Returns false if the ticket was not found.
+    /// In the basic waiting room, the ticket at the front of the queue can simply be removed, since
+    /// nothing else depends on it.
+    fn remove_ticket(&mut self, ticket_identifier: TicketIdentifier) -> bool {
+        if self.local_queue.contains(ticket_identifier) {
+            self.remove_from_queue(ticket_identifier);
+        } else if self.pre_queue.remove(ticket_identifier).is_some() {
+            // The lottery has not been drawn yet, so there is nothing else to do.
+        } else if let Some(index) = self
+            .queue_leaving_list
+            .iter()
+            .position(|t| t.identifier == ticket_identifier)
+        {
+            // The user was let out of the queue, but never left it. Their place is taken over at the next eviction.
+            self.queue_leaving_list.remove(index);
+            self.metrics.decrement_gauge(Gauge::ToLetIn, 1);
+        } else {
+            return false;
+        }
+        self.journal(JournalEntry::TicketRemoved(ticket_identifier));
+        true
+    }
+
+    /// Removes a pass from the on site list. Returns false if the pass was not found.
+    fn remove_pass(&mut self, pass_identifier: TicketIdentifier) -> bool {
+        let Some(index) = self
+            .on_site_list
+            .iter()
+            .position(|p| p.identifier == pass_identifier)
+        else {
+            return false;
+        };
+        self.on_site_list.remove(index);
+        self.metrics.decrement_gauge(Gauge::OnSite, 1);
+        self.journal(JournalEntry::PassRemoved(pass_identifier));
+        true
+    }
+
     pub fn get_user_count(&self) -> usize {
         self.local_queue.len()
     }
@@ -328,11 +875,8 @@ where
     pub fn enqueue(&mut self, ticket: Ticket) {
         self.local_queue.enqueue(ticket);
         if ticket.ticket_type == TicketType::Normal {
-            metrics::gauge!(
-                "waitingroom.in_queue_count",
-                &[("node", SELF_NODE_ID.to_string())]
-            )
-            .increment(1);
+            self.journal(JournalEntry::Enqueue(ticket));
+            self.metrics.increment_gauge(Gauge::InQueue, 1);
         }
     }
 
@@ -340,24 +884,26 @@ where
     pub fn dequeue(&mut self) -> Option<Ticket> {
         let element = self.local_queue.dequeue();
         if element.is_some() && element.as_ref().unwrap().ticket_type == TicketType::Normal {
-            metrics::gauge!(
-                "waitingroom.in_queue_count",
-                &[("node", SELF_NODE_ID.to_string())]
-            )
-            .decrement(1);
+            self.metrics.decrement_gauge(Gauge::InQueue, 1);
         }
         element
     }
 
+    /// Returns the copy of a ticket kept at this node, if there is one. Its deadlines are used instead of the ones
+    /// on the ticket of the user, since they are moved when the node is restored from a snapshot.
+    fn stored_ticket(&self, ticket_identifier: TicketIdentifier) -> Option<&Ticket> {
+        self.local_queue
+            .iter()
+            .chain(self.pre_queue.iter())
+            .chain(self.queue_leaving_list.iter())
+            .find(|ticket| ticket.identifier == ticket_identifier)
+    }
+
     // / Remove a specific element from the local queue by identifier, decrementing the metric if the ticket type is normal.
     pub fn remove_from_queue(&mut self, ticket_identifier: TicketIdentifier) {
         if let Some(ticket) = self.local_queue.remove(ticket_identifier) {
             if ticket.ticket_type == TicketType::Normal {
-                metrics::gauge!(
-                    "waitingroom.in_queue_count",
-                    &[("node", SELF_NODE_ID.to_string())]
-                )
-                .decrement(1);
+                self.metrics.decrement_gauge(Gauge::InQueue, 1);
             }
         }
     }
@@ -0,0 +1,514 @@
+use std::sync::{Arc, Mutex};
+
+use waitingroom_core::{
+    random::DeterministicRandomProvider,
+    settings::{
+        ActivationWindow, AdmissionMode, CapacityRange, ChallengeSettings,
+        GeneralWaitingRoomSettings,
+    },
+    ticket::Ticket,
+    time::{DummyTimeProvider, TimeProvider},
+    WaitingRoomError, WaitingRoomTimerTriggered, WaitingRoomUserTriggered,
+};
+use waitingroom_metrics::{Counter, Gauge, GaugeUpdate, Histogram, Labels, MetricsSink};
+
+use crate::BasicWaitingRoom;
+
+type Room = BasicWaitingRoom<DummyTimeProvider, DeterministicRandomProvider>;
+
+fn new_room(settings: &GeneralWaitingRoomSettings, time_provider: &DummyTimeProvider) -> Room {
+    BasicWaitingRoom::new(
+        settings.clone(),
+        time_provider.clone(),
+        DeterministicRandomProvider::new(1),
+    )
+}
+
+/// Moves the time past the eviction interval, and runs a cleanup and an eviction.
+fn evict(time_provider: &DummyTimeProvider, room: &mut Room) {
+    time_provider.increase_by(6001);
+    room.cleanup().unwrap();
+    room.eviction().unwrap();
+}
+
+/// Runs an eviction, and lets every user who was let out of the queue leave. Returns the number of users who left.
+fn evict_and_leave(
+    time_provider: &DummyTimeProvider,
+    room: &mut Room,
+    tickets: &mut Vec<Ticket>,
+) -> usize {
+    evict(time_provider, room);
+    let mut left = 0;
+    for ticket in std::mem::take(tickets) {
+        let response = room.check_in(ticket).unwrap();
+        if response.position_estimate == 0 {
+            room.leave(response.new_ticket).unwrap();
+            left += 1;
+        } else {
+            tickets.push(response.new_ticket);
+        }
+    }
+    left
+}
+
+/// Keeps the gauges recorded by a room, so the tests can check them.
+#[derive(Debug, Default)]
+struct GaugeSink {
+    updates: Mutex<Vec<(Gauge, GaugeUpdate)>>,
+}
+
+impl GaugeSink {
+    fn value(&self, gauge: Gauge) -> f64 {
+        self.updates
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(g, _)| *g == gauge)
+            .fold(0.0, |value, (_, update)| match update {
+                GaugeUpdate::Set(new_value) => *new_value,
+                GaugeUpdate::Increment(increment) => value + increment,
+                GaugeUpdate::Decrement(decrement) => value - decrement,
+            })
+    }
+}
+
+impl MetricsSink for GaugeSink {
+    fn gauge(&self, gauge: Gauge, _labels: &Labels, update: GaugeUpdate) {
+        self.updates.lock().unwrap().push((gauge, update));
+    }
+
+    fn counter(&self, _counter: Counter, _labels: &Labels, _increment: u64) {}
+
+    fn histogram(&self, _histogram: Histogram, _labels: &Labels, _value: f64) {}
+}
+
+#[test]
+fn journal_replay() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 2,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let path = std::env::temp_dir().join(format!(
+        "waitingroom-basic-journal-replay-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let mut room = new_room(&settings, &dummy_time_provider);
+    room.open_journal(&path).unwrap();
+    let mut tickets = vec![];
+    for _ in 0..5 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(room.join().unwrap());
+    }
+
+    // The first two users are let out, and only the first one leaves. The fourth user is kicked.
+    dummy_time_provider.increase_by(6001);
+    room.eviction().unwrap();
+    let response = room.check_in(tickets[0]).unwrap();
+    let pass = room.leave(response.new_ticket).unwrap();
+    room.kick_ticket(tickets[3].identifier).unwrap();
+
+    // The room crashes without taking a snapshot, and is down for longer than tickets last without being refreshed.
+    drop(room);
+    dummy_time_provider.increase_by(20000);
+    let sink = Arc::new(GaugeSink::default());
+    let mut room = new_room(&settings, &dummy_time_provider);
+    room.set_metrics_sink(sink.clone());
+    room.open_journal(&path).unwrap();
+
+    let status = room.status();
+    assert_eq!(status.queue_length, 2);
+    assert_eq!(status.leaving_list_length, 1);
+    assert_eq!(status.on_site_count, 1);
+    assert_eq!(sink.value(Gauge::InQueue), 2.0);
+    assert_eq!(sink.value(Gauge::ToLetIn), 1.0);
+    assert_eq!(sink.value(Gauge::OnSite), 1.0);
+
+    // The queue is in the same order, and the users who were let out can still leave.
+    assert_eq!(room.check_in(tickets[2]).unwrap().position_estimate, 1);
+    assert_eq!(room.check_in(tickets[4]).unwrap().position_estimate, 2);
+    let response = room.check_in(tickets[1]).unwrap();
+    assert_eq!(response.position_estimate, 0);
+    room.leave(response.new_ticket).unwrap();
+    room.validate_and_refresh_pass(pass).unwrap();
+    assert!(matches!(
+        room.check_in(tickets[3]),
+        Err(WaitingRoomError::TicketRevoked)
+    ));
+
+    // Changes made after the journal was compacted at opening are replayed as well, and so is a compacted journal.
+    let reopen = |room: Room| {
+        drop(room);
+        let sink = Arc::new(GaugeSink::default());
+        let mut room = new_room(&settings, &dummy_time_provider);
+        room.set_metrics_sink(sink.clone());
+        room.open_journal(&path).unwrap();
+        (room, sink)
+    };
+    let (mut room, _) = reopen(room);
+    room.compact_journal().unwrap();
+    let (mut room, sink) = reopen(room);
+    let status = room.status();
+    assert_eq!(status.queue_length, 2);
+    assert_eq!(status.leaving_list_length, 0);
+    assert_eq!(status.on_site_count, 2);
+    assert_eq!(sink.value(Gauge::ToLetIn), 0.0);
+    assert_eq!(sink.value(Gauge::OnSite), 2.0);
+    assert_eq!(room.check_in(tickets[2]).unwrap().position_estimate, 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn queue_full() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 2,
+        max_queue_length: Some(3),
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let mut room = new_room(&settings, &dummy_time_provider);
+    // The throughput is measured from the first eviction.
+    room.eviction().unwrap();
+
+    let mut tickets = vec![];
+    for _ in 0..3 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(room.join().unwrap());
+    }
+    match room.join() {
+        Err(WaitingRoomError::QueueFull { retry_after }) => {
+            // Nobody was let out yet, so there is no better estimate than the next eviction.
+            assert_eq!(retry_after, settings.eviction_interval)
+        }
+        other => panic!("Expected the queue to be full, got {:?}", other),
+    }
+
+    // Two users were let out in the first 6 seconds, so the next place in the queue frees up in 3 seconds.
+    dummy_time_provider.increase_by(6000 - dummy_time_provider.get_now_time());
+    room.eviction().unwrap();
+    for _ in 0..2 {
+        room.join().unwrap();
+    }
+    match room.join() {
+        Err(WaitingRoomError::QueueFull { retry_after }) => assert_eq!(retry_after, 3000),
+        other => panic!("Expected the queue to be full, got {:?}", other),
+    }
+
+    // A user who abandons the queue makes space right away.
+    room.abandon(tickets[2]).unwrap();
+    room.join().unwrap();
+    assert!(matches!(
+        room.join(),
+        Err(WaitingRoomError::QueueFull { .. })
+    ));
+}
+
+#[test]
+fn challenge_is_kept_when_joining_fails() {
+    let settings = GeneralWaitingRoomSettings {
+        challenge: Some(ChallengeSettings {
+            difficulty: 8,
+            expiry_time: 1000,
+        }),
+        target_user_count: 0,
+        max_queue_length: Some(1),
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let mut room = new_room(&settings, &dummy_time_provider);
+    let solution = room.challenge().unwrap().solve();
+    let ticket = room.join_with_solution(solution).unwrap();
+
+    // A user who is turned away because the queue is full can use the same solution once there is space.
+    let solution = room.challenge().unwrap().solve();
+    assert!(matches!(
+        room.join_with_solution(solution),
+        Err(WaitingRoomError::QueueFull { .. })
+    ));
+    room.abandon(ticket).unwrap();
+    room.join_with_solution(solution).unwrap();
+    assert!(matches!(
+        room.join_with_solution(solution),
+        Err(WaitingRoomError::InvalidChallengeSolution)
+    ));
+}
+
+#[test]
+fn activation_window_lottery() {
+    let window = ActivationWindow {
+        starts_at: 1000,
+        open_at: 10000,
+        ends_at: Some(60000),
+    };
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        // The pre-queue counts towards the maximum length.
+        max_queue_length: Some(6),
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 6000,
+        activation_window: Some(window),
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let mut room = new_room(&settings, &dummy_time_provider);
+
+    // Before the window starts, users are let through without queueing.
+    let early = room.join().unwrap();
+    assert_eq!(room.check_in(early).unwrap().position_estimate, 0);
+    room.leave(early).unwrap();
+
+    dummy_time_provider.increase_by(window.starts_at - dummy_time_provider.get_now_time());
+    let mut tickets: Vec<Ticket> = (0..6)
+        .map(|_| {
+            dummy_time_provider.increase_by(10);
+            room.join().unwrap()
+        })
+        .collect();
+    let join_order: Vec<_> = tickets.iter().map(|ticket| ticket.identifier).collect();
+    assert!(matches!(
+        room.join(),
+        Err(WaitingRoomError::QueueFull { .. })
+    ));
+
+    // Nobody is let out of the pre-queue before the room opens, and everyone in it could end up last.
+    evict(&dummy_time_provider, &mut room);
+    for ticket in tickets.iter_mut() {
+        let response = room.check_in(*ticket).unwrap();
+        assert_eq!(response.position_estimate, 6);
+        *ticket = response.new_ticket;
+    }
+    assert_eq!(room.status().queue_length, 0);
+
+    dummy_time_provider.increase_by(window.open_at - dummy_time_provider.get_now_time());
+    room.abandon(tickets.pop().unwrap()).unwrap();
+    let late = room.join().unwrap();
+    tickets.push(late);
+
+    let mut order = vec![];
+    while !tickets.is_empty() {
+        let before = tickets.clone();
+        assert_eq!(
+            evict_and_leave(&dummy_time_provider, &mut room, &mut tickets),
+            1
+        );
+        let left = before
+            .iter()
+            .find(|ticket| !tickets.contains(ticket))
+            .unwrap();
+        order.push(left.identifier);
+    }
+    // Everyone from the pre-queue is let out before the user who arrived after the opening,
+    // but not in the order they joined.
+    assert_eq!(order[5], late.identifier);
+    assert_ne!(order[..5], join_order[..5]);
+
+    // After the window ends, users are let through again.
+    dummy_time_provider.increase_by(window.ends_at.unwrap() - dummy_time_provider.get_now_time());
+    let after = room.join().unwrap();
+    assert_eq!(room.check_in(after).unwrap().position_estimate, 0);
+}
+
+#[test]
+fn rate_admission() {
+    let settings = GeneralWaitingRoomSettings {
+        // This is ignored in rate mode, so users are let in even though it is reached.
+        target_user_count: 1,
+        admission_mode: AdmissionMode::Rate {
+            users_per_minute: 10,
+            burst: 2,
+        },
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let mut room = new_room(&settings, &dummy_time_provider);
+    let mut tickets: Vec<Ticket> = (0..6).map(|_| room.join().unwrap()).collect();
+
+    // The bucket starts with the burst, and then refills at one user every 6 seconds.
+    let left: Vec<usize> = (0..3)
+        .map(|_| evict_and_leave(&dummy_time_provider, &mut room, &mut tickets))
+        .collect();
+    assert_eq!(left, [2, 1, 1]);
+}
+
+#[test]
+fn abandon_and_release() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let mut room = new_room(&settings, &dummy_time_provider);
+    let mut tickets = vec![];
+    for _ in 0..4 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(room.join().unwrap());
+    }
+
+    // The first user is at the front of the queue, and the third user is behind the second one.
+    room.abandon(tickets[0]).unwrap();
+    room.abandon(tickets[2]).unwrap();
+    assert!(matches!(
+        room.abandon(tickets[0]),
+        Err(WaitingRoomError::TicketNotInQueue)
+    ));
+    for ticket in [tickets[0], tickets[2]] {
+        assert!(matches!(
+            room.check_in(ticket),
+            Err(WaitingRoomError::TicketNotInQueue)
+        ));
+    }
+
+    // The second user is let in in place of the first one.
+    evict(&dummy_time_provider, &mut room);
+    let response = room.check_in(tickets[1]).unwrap();
+    assert_eq!(response.position_estimate, 0);
+    let pass = room.leave(response.new_ticket).unwrap();
+
+    // The pass doesn't expire for a while, so nobody else is let in until it is released.
+    evict(&dummy_time_provider, &mut room);
+    let response = room.check_in(tickets[3]).unwrap();
+    assert_ne!(response.position_estimate, 0);
+    room.release(pass).unwrap();
+    assert!(matches!(
+        room.release(pass),
+        Err(WaitingRoomError::PassNotInList)
+    ));
+    assert!(matches!(
+        room.validate_and_refresh_pass(pass),
+        Err(WaitingRoomError::PassNotInList)
+    ));
+    evict(&dummy_time_provider, &mut room);
+    assert_eq!(
+        room.check_in(response.new_ticket)
+            .unwrap()
+            .position_estimate,
+        0
+    );
+}
+
+#[test]
+fn kick_and_revoke() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let mut room = new_room(&settings, &dummy_time_provider);
+    let mut tickets = vec![];
+    for _ in 0..3 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(room.join().unwrap());
+    }
+
+    // The first user is at the front of the queue. Their ticket can't be used again, even though it is not expired.
+    room.kick_ticket(tickets[0].identifier).unwrap();
+    assert!(matches!(
+        room.kick_ticket(tickets[0].identifier),
+        Err(WaitingRoomError::TicketNotInQueue)
+    ));
+    assert!(matches!(
+        room.check_in(tickets[0]),
+        Err(WaitingRoomError::TicketRevoked)
+    ));
+    assert!(matches!(
+        room.leave(tickets[0]),
+        Err(WaitingRoomError::TicketRevoked)
+    ));
+
+    // The second user is let in in place of the first one.
+    evict(&dummy_time_provider, &mut room);
+    let response = room.check_in(tickets[1]).unwrap();
+    assert_eq!(response.position_estimate, 0);
+    let pass = room.leave(response.new_ticket).unwrap();
+
+    // The revoked pass no longer takes up a place on the site, so the third user is let in.
+    room.revoke_pass(pass.identifier).unwrap();
+    assert!(matches!(
+        room.validate_and_refresh_pass(pass),
+        Err(WaitingRoomError::PassRevoked)
+    ));
+    let response = room.check_in(tickets[2]).unwrap();
+    evict(&dummy_time_provider, &mut room);
+    assert_eq!(
+        room.check_in(response.new_ticket)
+            .unwrap()
+            .position_estimate,
+        0
+    );
+}
+
+#[test]
+fn status() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        capacity_schedule: vec![CapacityRange {
+            from: 0,
+            until: 60_000,
+            target_user_count: 2,
+        }],
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let mut room = new_room(&settings, &dummy_time_provider);
+    room.set_room_id(7);
+
+    let status = room.status();
+    assert_eq!(status.room_id, 7);
+    assert_eq!(status.last_eviction_time, None);
+    assert_eq!(status.target_user_count, 2);
+
+    let mut tickets = vec![];
+    for _ in 0..3 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(room.join().unwrap());
+    }
+    assert_eq!(room.status().queue_length, 3);
+
+    dummy_time_provider.increase_by(6001);
+    let eviction_time = dummy_time_provider.get_now_time();
+    room.eviction().unwrap();
+    let response = room.check_in(tickets[0]).unwrap();
+    room.leave(response.new_ticket).unwrap();
+
+    let status = room.status();
+    assert_eq!(status.queue_length, 1);
+    assert_eq!(status.leaving_list_length, 1);
+    assert_eq!(status.on_site_count, 1);
+    assert_eq!(status.last_eviction_time, Some(eviction_time));
+
+    // Outside of the capacity schedule, the target user count from the settings is used.
+    dummy_time_provider.increase_by(60_000);
+    assert_eq!(room.status().target_user_count, 1);
+}
@@ -0,0 +1,155 @@
+//! Admission policies decide how many users are let out of the queue at every eviction.
+
+use std::fmt::Debug;
+
+use crate::time::Time;
+
+/// Decides how many users are let out of the queue at an eviction. The rooms build the policy from
+/// [`crate::settings::AdmissionMode`], but any policy can be set on a room directly.
+pub trait AdmissionPolicy: Debug + Send {
+    /// Returns the number of users to let out of the queue right now. `queue_count` is the number of users
+    /// in the queue, and `on_site_count` includes the users who are let out, but haven't left the queue yet.
+    /// The result must not be more than `queue_count`, since the policy may count the users as let in.
+    fn users_to_let_in(&mut self, queue_count: usize, on_site_count: usize, now: Time) -> usize;
+
+    /// Changes the number of users the policy aims to have on the site. The rooms call this at every eviction,
+    /// with the target from [`crate::settings::GeneralWaitingRoomSettings::capacity_schedule`].
+    /// Policies that don't use a target can ignore it.
+    fn set_target_user_count(&mut self, _target_user_count: usize) {}
+
+    /// Returns the state the policy keeps between evictions, if it has any. In the distributed room, the node
+    /// that decides how many users to let in changes over time, so it sends this to all other nodes.
+    fn state(&self) -> Option<AdmissionState> {
+        None
+    }
+
+    /// Continues from a state returned by [`AdmissionPolicy::state`] on another node.
+    fn set_state(&mut self, _state: AdmissionState) {}
+}
+
+/// The state of an [`AdmissionPolicy`], as it is shared between nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionState {
+    /// The tokens left in a [`TokenBucket`], in 1/60000th of a user.
+    pub tokens: u128,
+    /// The time the state was last updated. Newer states replace older ones.
+    pub updated_at: Time,
+}
+
+/// Lets users in until there are `target_user_count` users on the site at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConcurrentTarget {
+    pub target_user_count: usize,
+}
+
+impl ConcurrentTarget {
+    pub fn new(target_user_count: usize) -> Self {
+        Self { target_user_count }
+    }
+}
+
+impl AdmissionPolicy for ConcurrentTarget {
+    fn users_to_let_in(&mut self, queue_count: usize, on_site_count: usize, _now: Time) -> usize {
+        queue_count.min(self.target_user_count.saturating_sub(on_site_count))
+    }
+
+    fn set_target_user_count(&mut self, target_user_count: usize) {
+        self.target_user_count = target_user_count;
+    }
+}
+
+/// Lets users in at a fixed rate, no matter how many users are on the site. This is for origins that are
+/// limited by the number of new sessions, like a payment provider that allows a number of checkouts per minute.
+///
+/// The bucket holds up to `burst` users, and refills at `users_per_minute`. It starts full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBucket {
+    users_per_minute: u64,
+    burst: u64,
+    /// The tokens are counted in 1/60000th of a user, so refilling every millisecond doesn't lose any precision.
+    tokens: u128,
+    last_refill: Option<Time>,
+}
+
+const MILLIS_PER_MINUTE: u128 = 60 * 1000;
+
+impl TokenBucket {
+    pub fn new(users_per_minute: u64, burst: u64) -> Self {
+        Self {
+            users_per_minute,
+            burst,
+            tokens: burst as u128 * MILLIS_PER_MINUTE,
+            last_refill: None,
+        }
+    }
+
+    /// Returns the number of whole users that can be let in right now.
+    pub fn available(&self) -> u64 {
+        (self.tokens / MILLIS_PER_MINUTE) as u64
+    }
+
+    fn refill(&mut self, now: Time) {
+        if let Some(last_refill) = self.last_refill {
+            let elapsed = now.saturating_sub(last_refill);
+            self.tokens = self
+                .tokens
+                .saturating_add(elapsed.saturating_mul(self.users_per_minute as u128))
+                .min(self.burst as u128 * MILLIS_PER_MINUTE);
+        }
+        self.last_refill = Some(now);
+    }
+}
+
+impl AdmissionPolicy for TokenBucket {
+    fn users_to_let_in(&mut self, queue_count: usize, _on_site_count: usize, now: Time) -> usize {
+        self.refill(now);
+        let count = queue_count.min(self.available() as usize);
+        self.tokens -= count as u128 * MILLIS_PER_MINUTE;
+        count
+    }
+
+    fn state(&self) -> Option<AdmissionState> {
+        self.last_refill.map(|updated_at| AdmissionState {
+            tokens: self.tokens,
+            updated_at,
+        })
+    }
+
+    fn set_state(&mut self, state: AdmissionState) {
+        self.tokens = state.tokens.min(self.burst as u128 * MILLIS_PER_MINUTE);
+        self.last_refill = Some(state.updated_at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_target() {
+        let mut policy = ConcurrentTarget::new(10);
+        assert_eq!(policy.users_to_let_in(100, 4, 0), 6);
+        assert_eq!(policy.users_to_let_in(3, 4, 0), 3);
+        assert_eq!(policy.users_to_let_in(100, 12, 0), 0);
+    }
+
+    #[test]
+    fn token_bucket() {
+        let mut policy = TokenBucket::new(30, 5);
+        // The bucket starts full, and the users on site don't matter.
+        assert_eq!(policy.users_to_let_in(100, 1000, 0), 5);
+        assert_eq!(policy.users_to_let_in(100, 0, 1000), 0);
+        // 30 users per minute is one user every 2 seconds, and partial users are kept.
+        assert_eq!(policy.users_to_let_in(100, 0, 3000), 1);
+        assert_eq!(policy.users_to_let_in(100, 0, 4000), 1);
+        // Unused tokens are kept, but never more than the burst.
+        assert_eq!(policy.users_to_let_in(2, 0, 60000), 2);
+        assert_eq!(policy.available(), 3);
+        assert_eq!(policy.users_to_let_in(100, 0, 60000), 3);
+
+        // Another bucket continues where this one stopped.
+        let mut other = TokenBucket::new(30, 5);
+        other.set_state(policy.state().unwrap());
+        assert_eq!(other.users_to_let_in(100, 0, 62000), 1);
+    }
+}
@@ -0,0 +1,273 @@
+//! Proof-of-work challenges, which make joining the queue cost some work, so bots can't take the front of the
+//! queue by joining as fast as the server responds.
+//!
+//! A challenge is solved by finding a number that, appended to the [`Challenge::prefix`] as eight big-endian
+//! bytes, gives a SHA-256 hash that starts with at least [`Challenge::difficulty`] zero bits. Finding one takes
+//! `2^difficulty` hashes on average, while checking one only takes a single hash.
+//!
+//! Rooms don't keep the challenges they give out. Instead, every challenge is signed with a [`ChallengeKey`],
+//! and the user sends the challenge back together with the solution. This way, every node with the same key
+//! can check the solution, and giving out challenges doesn't take any memory.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{time::Time, WaitingRoomError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The highest difficulty a room can be configured with. Every extra bit doubles the work, and a browser would
+/// take hours to solve a challenge above this.
+pub const MAX_CHALLENGE_DIFFICULTY: u8 = 32;
+
+/// The length of a challenge token, in bytes, before it is base64 encoded: the nonce, the two times,
+/// the difficulty and the signature.
+const TOKEN_LENGTH: usize = 8 + 16 + 16 + 1 + 32;
+
+/// A challenge given out by a room. The signature covers all other fields, so the user can't change them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Challenge {
+    /// A random number, which identifies the challenge, and makes sure solutions can't be computed in advance.
+    pub nonce: u64,
+    pub issued_at: Time,
+    /// After this time, solutions to the challenge are not accepted anymore.
+    pub expires_at: Time,
+    /// The number of leading zero bits the hash of a solution needs to have.
+    pub difficulty: u8,
+    /// The HMAC-SHA256 of the other fields. See [`ChallengeKey`].
+    pub signature: [u8; 32],
+}
+
+/// A solution to a challenge. It contains the challenge itself, so any node can check it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChallengeSolution {
+    pub challenge: Challenge,
+    pub solution: u64,
+}
+
+impl Challenge {
+    /// Creates a challenge without a signature, which no room accepts. Use [`ChallengeKey::issue`] to give
+    /// out challenges.
+    pub fn new(nonce: u64, issued_at: Time, expiry_time: Time, difficulty: u8) -> Self {
+        Self {
+            nonce,
+            issued_at,
+            expires_at: issued_at + expiry_time,
+            difficulty,
+            signature: [0; 32],
+        }
+    }
+
+    /// Returns the bytes that a solution is appended to before hashing: the nonce and the time the challenge
+    /// was issued, both big-endian.
+    pub fn prefix(&self) -> [u8; 24] {
+        let mut prefix = [0; 24];
+        prefix[..8].copy_from_slice(&self.nonce.to_be_bytes());
+        prefix[8..].copy_from_slice(&self.issued_at.to_be_bytes());
+        prefix
+    }
+
+    /// Returns true if the hash of the prefix followed by the solution has enough leading zero bits.
+    pub fn is_solved_by(&self, solution: u64) -> bool {
+        let hash = Sha256::new()
+            .chain_update(self.prefix())
+            .chain_update(solution.to_be_bytes())
+            .finalize();
+        leading_zero_bits(&hash) >= self.difficulty as u32
+    }
+
+    /// Finds the lowest solution to the challenge. This is meant for tests and tools, since clients normally
+    /// solve challenges themselves, for example with the solver served by the HTTP server.
+    pub fn solve(&self) -> ChallengeSolution {
+        let solution = (0..).find(|solution| self.is_solved_by(*solution)).unwrap();
+        ChallengeSolution {
+            challenge: *self,
+            solution,
+        }
+    }
+
+    /// Returns true if the challenge can't be solved anymore.
+    pub fn is_expired(&self, now: Time) -> bool {
+        self.expires_at < now
+    }
+
+    /// Returns the fields the signature covers, big-endian: the prefix, the expiry time and the difficulty.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.prefix().to_vec();
+        bytes.extend_from_slice(&self.expires_at.to_be_bytes());
+        bytes.push(self.difficulty);
+        bytes
+    }
+
+    /// Encodes the challenge as a URL-safe base64 string, which can be handed to the user.
+    pub fn to_token(&self) -> String {
+        let mut bytes = self.signed_bytes();
+        bytes.extend_from_slice(&self.signature);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Decodes a challenge from [`Challenge::to_token`]. The signature is not checked here, see
+    /// [`ChallengeKey::verify`].
+    pub fn from_token(token: &str) -> Result<Self, WaitingRoomError> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| WaitingRoomError::MalformedToken)?;
+        if bytes.len() != TOKEN_LENGTH {
+            return Err(WaitingRoomError::MalformedToken);
+        }
+        Ok(Self {
+            nonce: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            issued_at: Time::from_be_bytes(bytes[8..24].try_into().unwrap()),
+            expires_at: Time::from_be_bytes(bytes[24..40].try_into().unwrap()),
+            difficulty: bytes[40],
+            signature: bytes[41..].try_into().unwrap(),
+        })
+    }
+}
+
+/// The secret challenges are signed with. All nodes of a distributed room need the same key, so a challenge
+/// given out by one node can be solved at any other node.
+#[derive(Clone)]
+pub struct ChallengeKey {
+    secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for ChallengeKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The secret is left out, so it doesn't end up in the logs.
+        f.debug_struct("ChallengeKey").finish_non_exhaustive()
+    }
+}
+
+impl ChallengeKey {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+
+    /// Creates a key with a random secret. Challenges signed with it can only be solved at the room that
+    /// created the key. The secret doesn't come from the [`crate::random::RandomProvider`] of the room, so it
+    /// can't be predicted, and doesn't change the random numbers a simulation gets.
+    pub fn random() -> Self {
+        Self::new(&rand::random::<[u8; 32]>())
+    }
+
+    /// Creates a new signed challenge.
+    pub fn issue(
+        &self,
+        nonce: u64,
+        issued_at: Time,
+        expiry_time: Time,
+        difficulty: u8,
+    ) -> Challenge {
+        let mut challenge = Challenge::new(nonce, issued_at, expiry_time, difficulty);
+        challenge.signature = self.mac(&challenge).finalize().into_bytes().into();
+        challenge
+    }
+
+    /// Returns true if the challenge was signed with this key, so none of its fields were changed.
+    pub fn verify(&self, challenge: &Challenge) -> bool {
+        self.mac(challenge)
+            .verify_slice(&challenge.signature)
+            .is_ok()
+    }
+
+    fn mac(&self, challenge: &Challenge) -> HmacSha256 {
+        // HMAC accepts keys of any length, so this can never fail.
+        let mut mac = HmacSha256::new_from_slice(&self.secret).unwrap();
+        mac.update(&challenge.signed_bytes());
+        mac
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in bytes {
+        count += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(difficulty: u8) -> Challenge {
+        Challenge::new(0x0123456789abcdef, 1_700_000_000_000, 30_000, difficulty)
+    }
+
+    #[test]
+    fn counts_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10, 0xff]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn solve_finds_lowest_solution() {
+        let challenge = challenge(12);
+        let solution = challenge.solve();
+
+        assert_eq!(solution.challenge, challenge);
+        assert!(challenge.is_solved_by(solution.solution));
+        assert!((0..solution.solution).all(|solution| !challenge.is_solved_by(solution)));
+        // Without any difficulty, everything is a solution.
+        assert_eq!(self::challenge(0).solve().solution, 0);
+    }
+
+    #[test]
+    fn solutions_are_stable() {
+        // Clients compute the same hash, so this must never change.
+        let mut expected = 0x0123456789abcdefu64.to_be_bytes().to_vec();
+        expected.extend_from_slice(&1_700_000_000_000u128.to_be_bytes());
+        assert_eq!(challenge(8).prefix().to_vec(), expected);
+        assert_eq!(challenge(8).solve().solution, 51);
+        assert_eq!(challenge(16).solve().solution, 14898);
+    }
+
+    #[test]
+    fn solutions_depend_on_challenge() {
+        let solution = challenge(16).solve().solution;
+        let other = Challenge::new(0x0123456789abcdee, 1_700_000_000_000, 30_000, 16);
+        assert!(!other.is_solved_by(solution));
+    }
+
+    #[test]
+    fn challenges_are_signed() {
+        let key = ChallengeKey::new(b"cluster secret");
+        let challenge = key.issue(1, 100, 50, 8);
+        assert!(key.verify(&challenge));
+        assert!(!ChallengeKey::new(b"other secret").verify(&challenge));
+        assert!(!key.verify(&Challenge::new(1, 100, 50, 8)));
+
+        // Making the challenge easier, or extending it, breaks the signature.
+        assert!(!key.verify(&Challenge {
+            difficulty: 0,
+            ..challenge
+        }));
+        assert!(!key.verify(&Challenge {
+            expires_at: Time::MAX,
+            ..challenge
+        }));
+
+        let token = challenge.to_token();
+        assert_eq!(Challenge::from_token(&token).unwrap(), challenge);
+        assert!(matches!(
+            Challenge::from_token(&token[1..]),
+            Err(WaitingRoomError::MalformedToken)
+        ));
+    }
+
+    #[test]
+    fn challenges_expire() {
+        let challenge = challenge(8);
+        assert_eq!(challenge.expires_at, 1_700_000_030_000);
+        assert!(!challenge.is_expired(1_700_000_030_000));
+        assert!(challenge.is_expired(1_700_000_030_001));
+    }
+}
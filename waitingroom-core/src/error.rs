@@ -1,14 +1,46 @@
+use crate::{time::Time, NodeId, RoomId};
+
 #[derive(Debug)]
 pub enum WaitingRoomError {
     TicketExpired,
     TicketNotInQueue,
     TicketAtWrongNode,
+    /// The ticket was given out by a different room.
+    TicketAtWrongRoom,
     TicketCannotLeaveYet,
+    /// The user was kicked out of the queue by an administrator.
+    TicketRevoked,
     PassExpired,
     PassNotInList,
+    /// The pass was given out by a different room.
+    PassAtWrongRoom,
+    /// The pass was revoked by an administrator.
+    PassRevoked,
+    /// The snapshot was taken of a different room. See [`crate::snapshot::RoomSnapshot`].
+    SnapshotAtWrongRoom,
+    /// The snapshot was taken at a different node.
+    SnapshotAtWrongNode,
+    /// The queue has reached its maximum length. The user can try again after about `retry_after` milliseconds,
+    /// when enough users should have been let out of the queue to make space.
+    QueueFull {
+        retry_after: Time,
+    },
+    /// The room requires a challenge to be solved before joining. See [`crate::challenge`].
+    ChallengeRequired,
+    /// The challenge was not signed by the room, is expired or was already used, or the solution does not solve it.
+    InvalidChallengeSolution,
+    /// An observer rejected the action, with this reason. See [`crate::observer::WaitingRoomObserver::allow`].
+    Vetoed(String),
+    RoomNotFound(RoomId),
+    RoomAlreadyExists(RoomId),
     QPIDNotInitialized,
     FaultFalsePositive,
+    InvalidTokenSignature,
+    MalformedToken,
+    TokenCodecNotConfigured,
+    InvalidSettings(SettingsError),
     NetworkError(NetworkError),
+    JournalError(JournalError),
 }
 
 impl std::fmt::Display for WaitingRoomError {
@@ -17,12 +49,33 @@ impl std::fmt::Display for WaitingRoomError {
             WaitingRoomError::TicketExpired => write!(f, "Ticket expired"),
             WaitingRoomError::TicketNotInQueue => write!(f, "Ticket not in queue"),
             WaitingRoomError::TicketAtWrongNode => write!(f, "Ticket at wrong node"),
+            WaitingRoomError::TicketAtWrongRoom => write!(f, "Ticket at wrong room"),
             WaitingRoomError::TicketCannotLeaveYet => write!(f, "Ticket cannot leave yet"),
+            WaitingRoomError::TicketRevoked => write!(f, "Ticket revoked"),
             WaitingRoomError::PassExpired => write!(f, "Pass expired"),
             WaitingRoomError::PassNotInList => write!(f, "Pass not in list"),
+            WaitingRoomError::PassAtWrongRoom => write!(f, "Pass at wrong room"),
+            WaitingRoomError::PassRevoked => write!(f, "Pass revoked"),
+            WaitingRoomError::SnapshotAtWrongRoom => write!(f, "Snapshot at wrong room"),
+            WaitingRoomError::SnapshotAtWrongNode => write!(f, "Snapshot at wrong node"),
+            WaitingRoomError::QueueFull { retry_after } => {
+                write!(f, "Queue full, retry after {} ms", retry_after)
+            }
+            WaitingRoomError::ChallengeRequired => write!(f, "Challenge required"),
+            WaitingRoomError::InvalidChallengeSolution => write!(f, "Invalid challenge solution"),
+            WaitingRoomError::Vetoed(reason) => write!(f, "Vetoed: {}", reason),
+            WaitingRoomError::RoomNotFound(room_id) => write!(f, "Room {} not found", room_id),
+            WaitingRoomError::RoomAlreadyExists(room_id) => {
+                write!(f, "Room {} already exists", room_id)
+            }
             WaitingRoomError::QPIDNotInitialized => write!(f, "QPID not initialized"),
             WaitingRoomError::FaultFalsePositive => write!(f, "Fault detection false positive"),
+            WaitingRoomError::InvalidTokenSignature => write!(f, "Invalid token signature"),
+            WaitingRoomError::MalformedToken => write!(f, "Malformed token"),
+            WaitingRoomError::TokenCodecNotConfigured => write!(f, "Token codec not configured"),
+            WaitingRoomError::InvalidSettings(err) => write!(f, "Invalid settings: {}", err),
             WaitingRoomError::NetworkError(err) => write!(f, "Network Error: {:?}", err),
+            WaitingRoomError::JournalError(err) => write!(f, "Journal Error: {}", err),
         }
     }
 }
@@ -31,10 +84,143 @@ impl std::fmt::Display for WaitingRoomError {
 pub enum NetworkError {
     NodeIDAlreadyUsed,
     DestNodeNotFound,
+    /// There is no address known for the node with this ID.
+    AddressUnknown(NodeId),
+    Io(std::io::Error),
+    Wire(WireError),
+}
+
+impl From<std::io::Error> for NetworkError {
+    fn from(val: std::io::Error) -> Self {
+        NetworkError::Io(val)
+    }
+}
+
+impl From<WireError> for NetworkError {
+    fn from(val: WireError) -> Self {
+        NetworkError::Wire(val)
+    }
+}
+
+/// Returned when a message can't be encoded or decoded. See [`crate::wire`].
+#[derive(Debug)]
+pub enum WireError {
+    /// The message ended before everything was read.
+    UnexpectedEnd,
+    /// There were this many bytes left after the message was read.
+    TrailingBytes(usize),
+    /// The message was sent with a protocol version we don't support.
+    UnsupportedVersion { version: u16, supported: (u16, u16) },
+    /// The message type is not known in the protocol version of the message.
+    UnknownMessageType(u8),
+    /// The message is too large to send.
+    TooLarge(usize),
+    /// Any other problem with the message.
+    Invalid(String),
 }
 
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::UnexpectedEnd => write!(f, "Unexpected end of message"),
+            WireError::TrailingBytes(count) => {
+                write!(f, "{} unexpected bytes after the message", count)
+            }
+            WireError::UnsupportedVersion { version, supported } => write!(
+                f,
+                "Protocol version {} is not supported, only versions {} to {} are",
+                version, supported.0, supported.1
+            ),
+            WireError::UnknownMessageType(message_type) => {
+                write!(f, "Unknown message type {}", message_type)
+            }
+            WireError::TooLarge(size) => write!(f, "Message of {} bytes is too large", size),
+            WireError::Invalid(reason) => write!(f, "Invalid message: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
 impl From<NetworkError> for WaitingRoomError {
     fn from(val: NetworkError) -> Self {
         WaitingRoomError::NetworkError(val)
     }
 }
+
+/// Returned when the journal can't be read or written. See [`crate::journal`].
+#[derive(Debug)]
+pub enum JournalError {
+    Io(std::io::Error),
+    /// The journal was written in a format we can't read.
+    Wire(WireError),
+}
+
+impl std::fmt::Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalError::Io(err) => write!(f, "{}", err),
+            JournalError::Wire(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+impl From<std::io::Error> for JournalError {
+    fn from(val: std::io::Error) -> Self {
+        JournalError::Io(val)
+    }
+}
+
+impl From<JournalError> for WaitingRoomError {
+    fn from(val: JournalError) -> Self {
+        WaitingRoomError::JournalError(val)
+    }
+}
+
+/// Returned when settings are inconsistent. See [`crate::settings::GeneralWaitingRoomSettings::validate`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum SettingsError {
+    /// The setting with this name must not be zero.
+    Zero(&'static str),
+    /// The `lower` setting must be less than the `higher` setting. Both are (name, value) pairs.
+    NotLessThan {
+        lower: (&'static str, u128),
+        higher: (&'static str, u128),
+    },
+    /// The `lower` setting must not be greater than the `higher` setting. Both are (name, value) pairs.
+    GreaterThan {
+        lower: (&'static str, u128),
+        higher: (&'static str, u128),
+    },
+    /// Any other problem with a setting, with the name of the setting and a description.
+    Invalid(&'static str, String),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Zero(name) => write!(f, "`{}` must not be zero", name),
+            SettingsError::NotLessThan { lower, higher } => write!(
+                f,
+                "`{}` ({}) must be less than `{}` ({})",
+                lower.0, lower.1, higher.0, higher.1
+            ),
+            SettingsError::GreaterThan { lower, higher } => write!(
+                f,
+                "`{}` ({}) must not be greater than `{}` ({})",
+                lower.0, lower.1, higher.0, higher.1
+            ),
+            SettingsError::Invalid(name, reason) => write!(f, "`{}` is invalid: {}", name, reason),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl From<SettingsError> for WaitingRoomError {
+    fn from(val: SettingsError) -> Self {
+        WaitingRoomError::InvalidSettings(val)
+    }
+}
@@ -0,0 +1,447 @@
+//! An append-only journal of the changes to the state of a waiting room, so nothing is lost when the process
+//! crashes between two snapshots.
+//!
+//! The journal starts with a version, followed by records that are each prefixed with their length as 4 big endian
+//! bytes. The first record is always a [`RoomSnapshot`], and every other record is a [`JournalEntry`] with the time
+//! it happened. Compacting the journal replaces all records with a single snapshot of the current state.
+//!
+//! A crash can leave a partly written record at the end of the file. Everything from that record on is ignored
+//! when the journal is replayed, and cut off when it is opened again.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{JournalError, WireError},
+    pass::Pass,
+    snapshot::RoomSnapshot,
+    ticket::{Ticket, TicketIdentifier},
+    time::Time,
+};
+
+/// The version of the journal format. This is the first two bytes of the journal.
+/// It must be increased whenever [`JournalEntry`] or [`RoomSnapshot`] changes.
+pub const JOURNAL_VERSION: u16 = 1;
+
+/// The number of entries after which the rooms compact the journal at the next cleanup.
+pub const DEFAULT_COMPACTION_THRESHOLD: usize = 10000;
+
+const HEADER_LENGTH: usize = 2;
+const LENGTH_PREFIX_LENGTH: usize = 4;
+
+/// A change to the state of a room. Only changes to users who are not skip or drain tickets are journaled.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum JournalEntry {
+    /// A ticket was added to the queue, or moved there from the pre-queue.
+    Enqueue(Ticket),
+    /// A ticket was added to the pre-queue. See [`crate::settings::ActivationWindow`].
+    PreQueue(Ticket),
+    /// A ticket was refreshed when the user checked in.
+    TicketRefresh(Ticket),
+    /// A ticket was let out of the queue, and put on the queue leaving list.
+    LetOut(Ticket),
+    /// A ticket was taken off the queue leaving list, and its pass was put on the on site list.
+    Leave(Pass),
+    /// A pass was refreshed. If it was not on the on site list yet, it was added.
+    PassRefresh(Pass),
+    /// A ticket was removed, because the user abandoned it or was kicked out.
+    TicketRemoved(TicketIdentifier),
+    /// A pass was removed, because the user released it or it was revoked.
+    PassRemoved(TicketIdentifier),
+    TicketRevoked {
+        identifier: TicketIdentifier,
+        until: Time,
+    },
+    PassRevoked {
+        identifier: TicketIdentifier,
+        until: Time,
+    },
+    /// Everything that expired before this time was removed at a cleanup.
+    Expire(Time),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum JournalRecord {
+    Snapshot(RoomSnapshot),
+    Entry(Time, JournalEntry),
+}
+
+/// The journal file of a room. See the module documentation for the format.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// The number of entries written since the journal was last compacted.
+    entries: usize,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if it doesn't exist. Returns the journal, and the state replayed
+    /// from it, if it had any. Anything after the last complete record is cut off, so new entries are written
+    /// right after it.
+    pub fn open(path: impl AsRef<Path>) -> Result<(Self, Option<RoomSnapshot>), JournalError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        let replayed = replay(&bytes)?;
+        file.set_len(replayed.length as u64)?;
+        let mut journal = Self {
+            path,
+            file,
+            entries: replayed.entries,
+        };
+        if replayed.length == 0 {
+            journal.file.write_all(&JOURNAL_VERSION.to_be_bytes())?;
+        }
+        Ok((journal, replayed.snapshot))
+    }
+
+    /// Writes an entry to the end of the journal. It is handed to the operating system straight away, so it
+    /// survives the process crashing, but not necessarily the machine crashing.
+    pub fn append(&mut self, time: Time, entry: JournalEntry) -> Result<(), JournalError> {
+        self.write_record(&JournalRecord::Entry(time, entry))?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Replaces everything in the journal with the snapshot. The new journal is written next to the old one
+    /// first, so a crash while compacting leaves one of the two intact.
+    pub fn compact(&mut self, snapshot: &RoomSnapshot) -> Result<(), JournalError> {
+        let mut compacted_path = self.path.clone().into_os_string();
+        compacted_path.push(".compacted");
+        let mut compacted = File::create(&compacted_path)?;
+        compacted.write_all(&JOURNAL_VERSION.to_be_bytes())?;
+        compacted.write_all(&encode_record(&JournalRecord::Snapshot(snapshot.clone())))?;
+        compacted.sync_all()?;
+        fs::rename(&compacted_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.entries = 0;
+        Ok(())
+    }
+
+    /// Returns the number of entries written since the journal was last compacted.
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    /// Returns true if no entries were written since the journal was last compacted.
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Returns true if enough entries were written that the journal should be compacted.
+    pub fn needs_compaction(&self) -> bool {
+        self.entries >= DEFAULT_COMPACTION_THRESHOLD
+    }
+
+    fn write_record(&mut self, record: &JournalRecord) -> Result<(), JournalError> {
+        // The record is written at once, so a crash can only leave a part of the last record.
+        self.file.write_all(&encode_record(record))?;
+        Ok(())
+    }
+}
+
+fn encode_record(record: &JournalRecord) -> Vec<u8> {
+    // Serialising to a Vec can't fail.
+    let payload = bincode::serialize(record).unwrap();
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(payload);
+    bytes
+}
+
+/// What was read from a journal.
+#[derive(Debug)]
+pub struct Replayed {
+    /// The state after all complete entries were applied, with the time of the last one as the time it was taken.
+    /// This is `None` if the journal didn't have a complete snapshot.
+    pub snapshot: Option<RoomSnapshot>,
+    /// The number of entries after the snapshot.
+    pub entries: usize,
+    /// The length in bytes of the complete records, including the version.
+    pub length: usize,
+}
+
+/// Reads a journal, stopping at the first record that is not complete.
+pub fn replay(bytes: &[u8]) -> Result<Replayed, JournalError> {
+    let mut replayed = Replayed {
+        snapshot: None,
+        entries: 0,
+        length: 0,
+    };
+    if bytes.len() < HEADER_LENGTH {
+        return Ok(replayed);
+    }
+    let version = u16::from_be_bytes([bytes[0], bytes[1]]);
+    if version != JOURNAL_VERSION {
+        return Err(JournalError::Wire(WireError::UnsupportedVersion {
+            version,
+            supported: (JOURNAL_VERSION, JOURNAL_VERSION),
+        }));
+    }
+    replayed.length = HEADER_LENGTH;
+
+    while let Some(record) = read_record(&bytes[replayed.length..]) {
+        match (record, replayed.snapshot.as_mut()) {
+            (JournalRecord::Snapshot(snapshot), None) => replayed.snapshot = Some(snapshot),
+            (JournalRecord::Entry(time, entry), Some(snapshot)) => {
+                apply(snapshot, entry);
+                snapshot.taken_at = time;
+                replayed.entries += 1;
+            }
+            _ => {
+                return Err(JournalError::Wire(WireError::Invalid(
+                    "the journal must start with exactly one snapshot".to_string(),
+                )))
+            }
+        }
+        let record_length = u32::from_be_bytes(
+            bytes[replayed.length..replayed.length + LENGTH_PREFIX_LENGTH]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        replayed.length += LENGTH_PREFIX_LENGTH + record_length;
+    }
+    Ok(replayed)
+}
+
+/// Reads the record at the start of `bytes`. Returns `None` if it was not written completely.
+fn read_record(bytes: &[u8]) -> Option<JournalRecord> {
+    let length =
+        u32::from_be_bytes(bytes.get(..LENGTH_PREFIX_LENGTH)?.try_into().unwrap()) as usize;
+    let payload = bytes.get(LENGTH_PREFIX_LENGTH..LENGTH_PREFIX_LENGTH + length)?;
+    bincode::deserialize(payload).ok()
+}
+
+/// Applies an entry to the state, the same way the rooms apply the change it was written for.
+fn apply(snapshot: &mut RoomSnapshot, entry: JournalEntry) {
+    let remove_ticket = |tickets: &mut Vec<Ticket>, identifier: TicketIdentifier| {
+        tickets.retain(|ticket| ticket.identifier != identifier)
+    };
+    let replace_ticket = |tickets: &mut Vec<Ticket>, new: Ticket| {
+        for ticket in tickets.iter_mut() {
+            if ticket.identifier == new.identifier {
+                *ticket = new;
+            }
+        }
+    };
+
+    match entry {
+        JournalEntry::Enqueue(ticket) => {
+            remove_ticket(&mut snapshot.pre_queue, ticket.identifier);
+            snapshot.queue.push(ticket);
+        }
+        JournalEntry::PreQueue(ticket) => snapshot.pre_queue.push(ticket),
+        JournalEntry::TicketRefresh(ticket) => {
+            replace_ticket(&mut snapshot.queue, ticket);
+            replace_ticket(&mut snapshot.pre_queue, ticket);
+            replace_ticket(&mut snapshot.queue_leaving_list, ticket);
+        }
+        JournalEntry::LetOut(ticket) => {
+            remove_ticket(&mut snapshot.queue, ticket.identifier);
+            remove_ticket(&mut snapshot.pre_queue, ticket.identifier);
+            snapshot.queue_leaving_list.push(ticket);
+        }
+        JournalEntry::Leave(pass) => {
+            remove_ticket(&mut snapshot.queue_leaving_list, pass.identifier);
+            snapshot.on_site_list.push(pass);
+        }
+        JournalEntry::PassRefresh(pass) => {
+            match snapshot
+                .on_site_list
+                .iter_mut()
+                .find(|p| p.identifier == pass.identifier)
+            {
+                Some(existing) => *existing = pass,
+                None => snapshot.on_site_list.push(pass),
+            }
+        }
+        JournalEntry::TicketRemoved(identifier) => {
+            remove_ticket(&mut snapshot.queue, identifier);
+            remove_ticket(&mut snapshot.pre_queue, identifier);
+            remove_ticket(&mut snapshot.queue_leaving_list, identifier);
+        }
+        JournalEntry::PassRemoved(identifier) => snapshot
+            .on_site_list
+            .retain(|pass| pass.identifier != identifier),
+        JournalEntry::TicketRevoked { identifier, until } => {
+            snapshot.revoked_tickets.push((identifier, until))
+        }
+        JournalEntry::PassRevoked { identifier, until } => {
+            snapshot.revoked_passes.push((identifier, until))
+        }
+        JournalEntry::Expire(time) => {
+            snapshot.queue.retain(|ticket| ticket.expiry_time >= time);
+            snapshot
+                .pre_queue
+                .retain(|ticket| ticket.expiry_time >= time);
+            snapshot
+                .queue_leaving_list
+                .retain(|ticket| ticket.expiry_time > time);
+            snapshot.on_site_list.retain(|pass| pass.expiry_time > time);
+            snapshot.revoked_tickets.retain(|(_, until)| *until >= time);
+            snapshot.revoked_passes.retain(|(_, until)| *until >= time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        random::{DeterministicRandomProvider, RandomProvider},
+        DEFAULT_ROOM_ID,
+    };
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "waitingroom-journal-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn empty_snapshot() -> RoomSnapshot {
+        RoomSnapshot {
+            room_id: DEFAULT_ROOM_ID,
+            node_id: 0,
+            taken_at: 0,
+            queue: vec![],
+            pre_queue: vec![],
+            queue_leaving_list: vec![],
+            on_site_list: vec![],
+            revoked_tickets: vec![],
+            revoked_passes: vec![],
+        }
+    }
+
+    /// A few users going through the room, with the time of every entry.
+    fn entries() -> Vec<(Time, JournalEntry)> {
+        let ticket = |identifier| Ticket::new_with_time_and_identifier(identifier, 0, 0, 10, 100);
+        let mut evicted = ticket(1);
+        evicted.set_eviction_time(20);
+        let pass = Pass::from_ticket(evicted, 1000, &crate::time::DummyTimeProvider::new());
+        vec![
+            (0, JournalEntry::Enqueue(ticket(1))),
+            (0, JournalEntry::Enqueue(ticket(2))),
+            (0, JournalEntry::Enqueue(ticket(3))),
+            (10, JournalEntry::TicketRefresh(ticket(2))),
+            (20, JournalEntry::LetOut(evicted)),
+            (30, JournalEntry::TicketRemoved(3)),
+            (
+                30,
+                JournalEntry::TicketRevoked {
+                    identifier: 3,
+                    until: 130,
+                },
+            ),
+            (40, JournalEntry::Leave(pass)),
+            (50, JournalEntry::PassRefresh(pass)),
+            (200, JournalEntry::Expire(200)),
+        ]
+    }
+
+    /// The identifiers in every list of the state, to compare states.
+    fn summary(snapshot: &RoomSnapshot) -> [Vec<TicketIdentifier>; 4] {
+        let tickets =
+            |tickets: &Vec<Ticket>| tickets.iter().map(|ticket| ticket.identifier).collect();
+        [
+            tickets(&snapshot.queue),
+            tickets(&snapshot.queue_leaving_list),
+            snapshot
+                .on_site_list
+                .iter()
+                .map(|pass| pass.identifier)
+                .collect(),
+            snapshot.revoked_tickets.iter().map(|(id, _)| *id).collect(),
+        ]
+    }
+
+    #[test]
+    fn replay_entries() {
+        let path = temp_path("replay");
+        let (mut journal, replayed) = Journal::open(&path).unwrap();
+        assert!(replayed.is_none());
+        journal.compact(&empty_snapshot()).unwrap();
+        for (time, entry) in entries().into_iter().take(9) {
+            journal.append(time, entry).unwrap();
+        }
+        assert_eq!(journal.len(), 9);
+
+        let (journal, replayed) = Journal::open(&path).unwrap();
+        let replayed = replayed.unwrap();
+        assert_eq!(journal.len(), 9);
+        assert_eq!(replayed.taken_at, 50);
+        assert_eq!(summary(&replayed), [vec![2], vec![], vec![1], vec![3]]);
+
+        // Compacting keeps the state, but not the entries.
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        journal.compact(&replayed).unwrap();
+        let (journal, compacted) = Journal::open(&path).unwrap();
+        assert!(journal.is_empty());
+        assert_eq!(summary(&compacted.unwrap()), summary(&replayed));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn crash_at_random_offsets() {
+        let path = temp_path("crash");
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        journal.compact(&empty_snapshot()).unwrap();
+        // The state after every number of entries.
+        let mut states = vec![summary(&empty_snapshot())];
+        let mut expected = empty_snapshot();
+        for (time, entry) in entries() {
+            journal.append(time, entry).unwrap();
+            apply(&mut expected, entry);
+            states.push(summary(&expected));
+        }
+        let bytes = fs::read(&path).unwrap();
+
+        let random_provider = DeterministicRandomProvider::new(1);
+        for _ in 0..100 {
+            let offset = random_provider.random_u64() as usize % bytes.len();
+            fs::write(&path, &bytes[..offset]).unwrap();
+
+            // Every complete entry is replayed, and nothing else.
+            let (mut journal, replayed) = Journal::open(&path).unwrap();
+            let Some(replayed) = replayed else {
+                // The crash happened before the snapshot was written completely.
+                assert!(journal.is_empty());
+                continue;
+            };
+            assert_eq!(summary(&replayed), states[journal.len()]);
+
+            // New entries are written after the last complete one.
+            let entries_before = journal.len();
+            journal.append(300, JournalEntry::TicketRemoved(2)).unwrap();
+            let (journal, _) = Journal::open(&path).unwrap();
+            assert_eq!(journal.len(), entries_before + 1);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = (JOURNAL_VERSION + 1).to_be_bytes().to_vec();
+        bytes.extend(encode_record(&JournalRecord::Snapshot(empty_snapshot())));
+        assert!(matches!(
+            replay(&bytes),
+            Err(JournalError::Wire(WireError::UnsupportedVersion { .. }))
+        ));
+    }
+}
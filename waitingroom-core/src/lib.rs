@@ -1,25 +1,80 @@
+use challenge::{Challenge, ChallengeSolution};
 use pass::Pass;
-use ticket::Ticket;
+use throughput::AdmissionEstimate;
+use ticket::{PriorityClass, Ticket, DEFAULT_PRIORITY_CLASS};
+use token::TokenCodec;
 
+pub mod admission;
+pub mod challenge;
 mod error;
+pub mod journal;
 pub mod network;
+pub mod observer;
 pub mod pass;
 pub mod random;
+pub mod registry;
 pub mod settings;
+pub mod snapshot;
+pub mod status;
+pub mod throughput;
 pub mod ticket;
 pub mod time;
+pub mod token;
+pub mod wire;
 
-pub use error::WaitingRoomError;
+pub use error::{JournalError, NetworkError, SettingsError, WaitingRoomError, WireError};
 
 /// The type for node identifiers. This is specified here to allow for easy changes in the future.
 pub type NodeId = usize;
 
+/// The type for room identifiers. Every waiting room in a [`registry::WaitingRoomRegistry`] has its own ID,
+/// and tickets and passes can only be used in the room they were given out by.
+pub type RoomId = u64;
+
+/// The room ID of waiting rooms that were not given one. This is fine as long as there is only one room.
+pub const DEFAULT_ROOM_ID: RoomId = 0;
+
 /// These functions are able to be triggered by actions from the user.
 /// In most implementations, they will be called by a server on behalf of the user.
 pub trait WaitingRoomUserTriggered {
+    /// Returns the ID of this room. Tickets and passes from other rooms are rejected.
+    fn room_id(&self) -> RoomId;
+
     /// This is the first function the user should call when they want to join the waiting room.
     /// It returns a ticket that the user can use to check in and eventually leave the waiting room.
-    fn join(&mut self) -> Result<Ticket, WaitingRoomError>;
+    /// If the room requires a challenge to be solved, this returns [`WaitingRoomError::ChallengeRequired`],
+    /// and [`WaitingRoomUserTriggered::join_with_solution`] should be used instead.
+    fn join(&mut self) -> Result<Ticket, WaitingRoomError> {
+        self.join_with_priority(DEFAULT_PRIORITY_CLASS)
+    }
+
+    /// The same as [`WaitingRoomUserTriggered::join`], but the ticket gets the given priority class.
+    /// See [`PriorityClass`]. This should only be called for users that are known to be allowed in that class.
+    fn join_with_priority(
+        &mut self,
+        priority_class: PriorityClass,
+    ) -> Result<Ticket, WaitingRoomError>;
+
+    /// Returns a new challenge the user has to solve before they can join, or `None` if the room doesn't
+    /// require one. See [`challenge`]. The challenge can be solved at any node with the same
+    /// [`challenge::ChallengeKey`], and only once at every node.
+    fn challenge(&mut self) -> Option<Challenge>;
+
+    /// The same as [`WaitingRoomUserTriggered::join`], but with the solution to a challenge from
+    /// [`WaitingRoomUserTriggered::challenge`]. If the room doesn't require a challenge, the solution is ignored.
+    fn join_with_solution(
+        &mut self,
+        solution: ChallengeSolution,
+    ) -> Result<Ticket, WaitingRoomError> {
+        self.join_with_priority_and_solution(DEFAULT_PRIORITY_CLASS, solution)
+    }
+
+    /// The same as [`WaitingRoomUserTriggered::join_with_solution`], but the ticket gets the given priority class.
+    fn join_with_priority_and_solution(
+        &mut self,
+        priority_class: PriorityClass,
+        solution: ChallengeSolution,
+    ) -> Result<Ticket, WaitingRoomError>;
 
     /// This is the function the user should call periodically to refresh their ticket and
     /// get an updated position estimate. If the estimated position is 0, the user should
@@ -34,6 +89,116 @@ pub trait WaitingRoomUserTriggered {
     /// This function is used to validate whether a pass is valid. If it is valid, it is refreshed and returned.
     /// If it is invalid, an error is returned instead.
     fn validate_and_refresh_pass(&mut self, pass: Pass) -> Result<Pass, WaitingRoomError>;
+
+    /// This is the function the user should call when they leave the queue without going to the site,
+    /// for example when they close the tab. Their ticket is removed right away, instead of keeping
+    /// its place until it expires. The ticket can't be used anymore afterwards.
+    fn abandon(&mut self, ticket: Ticket) -> Result<(), WaitingRoomError>;
+
+    /// This is the function the user should call when they are done on the site, for example after
+    /// they finished their checkout. The pass is removed right away, so the slot on the site is free
+    /// for the next user, instead of staying taken until the pass expires.
+    fn release(&mut self, pass: Pass) -> Result<(), WaitingRoomError>;
+}
+
+/// These functions are the same as the ones in [`WaitingRoomUserTriggered`], but they take and return
+/// signed tokens instead of plain tickets and passes. This means they can be given tokens straight
+/// from untrusted clients, since a token that has been tampered with is rejected.
+pub trait WaitingRoomTokenTriggered: WaitingRoomUserTriggered {
+    /// Returns the codec used to encode and decode tokens, if one is configured.
+    fn token_codec(&self) -> Option<&TokenCodec>;
+
+    /// See [`WaitingRoomUserTriggered::join`]. Returns a ticket token.
+    fn join_with_token(&mut self) -> Result<String, WaitingRoomError> {
+        // The codec is checked first, so no ticket is given out that can't be handed to the user.
+        self.configured_token_codec()?;
+        let ticket = self.join()?;
+        Ok(self.configured_token_codec()?.encode_ticket(&ticket))
+    }
+
+    /// See [`WaitingRoomUserTriggered::join_with_priority`]. Returns a ticket token.
+    fn join_with_priority_with_token(
+        &mut self,
+        priority_class: PriorityClass,
+    ) -> Result<String, WaitingRoomError> {
+        self.configured_token_codec()?;
+        let ticket = self.join_with_priority(priority_class)?;
+        Ok(self.configured_token_codec()?.encode_ticket(&ticket))
+    }
+
+    /// See [`WaitingRoomUserTriggered::join_with_solution`]. Returns a ticket token.
+    fn join_with_solution_with_token(
+        &mut self,
+        solution: ChallengeSolution,
+    ) -> Result<String, WaitingRoomError> {
+        self.configured_token_codec()?;
+        let ticket = self.join_with_solution(solution)?;
+        Ok(self.configured_token_codec()?.encode_ticket(&ticket))
+    }
+
+    /// See [`WaitingRoomUserTriggered::check_in`]. Takes a ticket token, and returns a response
+    /// containing the refreshed ticket token.
+    fn check_in_with_token(
+        &mut self,
+        ticket_token: &str,
+    ) -> Result<TokenCheckInResponse, WaitingRoomError> {
+        let ticket = self.configured_token_codec()?.decode_ticket(ticket_token)?;
+        let response = self.check_in(ticket)?;
+        Ok(TokenCheckInResponse {
+            new_ticket_token: self
+                .configured_token_codec()?
+                .encode_ticket(&response.new_ticket),
+            position_estimate: response.position_estimate,
+            admission_estimate: response.admission_estimate,
+        })
+    }
+
+    /// See [`WaitingRoomUserTriggered::leave`]. Takes a ticket token, and returns a pass token.
+    fn leave_with_token(&mut self, ticket_token: &str) -> Result<String, WaitingRoomError> {
+        let ticket = self.configured_token_codec()?.decode_ticket(ticket_token)?;
+        let pass = self.leave(ticket)?;
+        Ok(self.configured_token_codec()?.encode_pass(&pass))
+    }
+
+    /// See [`WaitingRoomUserTriggered::validate_and_refresh_pass`]. Takes a pass token, and returns
+    /// the refreshed pass token.
+    fn validate_and_refresh_pass_with_token(
+        &mut self,
+        pass_token: &str,
+    ) -> Result<String, WaitingRoomError> {
+        let pass = self.configured_token_codec()?.decode_pass(pass_token)?;
+        let pass = self.validate_and_refresh_pass(pass)?;
+        Ok(self.configured_token_codec()?.encode_pass(&pass))
+    }
+
+    /// See [`WaitingRoomUserTriggered::abandon`]. Takes a ticket token.
+    fn abandon_with_token(&mut self, ticket_token: &str) -> Result<(), WaitingRoomError> {
+        let ticket = self.configured_token_codec()?.decode_ticket(ticket_token)?;
+        self.abandon(ticket)
+    }
+
+    /// See [`WaitingRoomUserTriggered::release`]. Takes a pass token.
+    fn release_with_token(&mut self, pass_token: &str) -> Result<(), WaitingRoomError> {
+        let pass = self.configured_token_codec()?.decode_pass(pass_token)?;
+        self.release(pass)
+    }
+
+    /// Returns the token codec, or an error if none is configured.
+    fn configured_token_codec(&self) -> Result<&TokenCodec, WaitingRoomError> {
+        self.token_codec()
+            .ok_or(WaitingRoomError::TokenCodecNotConfigured)
+    }
+}
+
+/// Returned by the [`WaitingRoomTokenTriggered::check_in_with_token`] function.
+#[derive(Debug)]
+pub struct TokenCheckInResponse {
+    /// This is the token of the refreshed ticket. See [`CheckInResponse::new_ticket`].
+    pub new_ticket_token: String,
+    /// See [`CheckInResponse::position_estimate`].
+    pub position_estimate: usize,
+    /// See [`CheckInResponse::admission_estimate`].
+    pub admission_estimate: Option<AdmissionEstimate>,
 }
 
 /// Returned by the [`WaitingRoomUserTriggered::check_in`] function.
@@ -46,6 +211,9 @@ pub struct CheckInResponse {
     /// If the estimate is 0, the user is at the front of the queue and should
     /// call [`WaitingRoomUserTriggered::leave`].
     pub position_estimate: usize,
+    /// This is when the user is expected to be let out of the queue, based on the number of users that were
+    /// let out recently. It is `None` if too few users were let out to make an estimate.
+    pub admission_estimate: Option<AdmissionEstimate>,
 }
 
 /// These functions are able to be triggered by timers.
@@ -0,0 +1,569 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use waitingroom_metrics::{Counter, Metrics, MetricsSink, RecorderSink};
+
+use crate::{
+    error::{NetworkError, WireError},
+    wire::WireMessage,
+    NodeId,
+};
+
+use super::{Message, Network, NetworkHandle};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The length of the MAC on every message, in bytes.
+const MAC_LENGTH: usize = 32;
+/// The length of everything in front of the message: the MAC, the session, the sequence number and the send time.
+const HEADER_LENGTH: usize = MAC_LENGTH + 8 + 8 + 8;
+/// The number of sequence numbers below the highest one received that are still accepted, so messages that
+/// are reordered by the network are not rejected.
+const REPLAY_WINDOW: u64 = 128;
+/// How old a message can be when it is received, unless it is changed with
+/// [`AuthenticatedNetwork::set_max_message_age`].
+pub const DEFAULT_MAX_MESSAGE_AGE: Duration = Duration::from_secs(30);
+
+/// A message together with the MAC that proves it was sent by a node that knows the cluster secret.
+#[derive(Clone)]
+pub struct AuthenticatedMessage<M> {
+    message: M,
+    /// Identifies the handle the message was sent with. A node gets a higher session every time it joins.
+    session: u64,
+    /// Counts the messages sent from the session to the receiving node, starting at 0.
+    sequence: u64,
+    /// The wall clock time the message was sent at, in milliseconds since the Unix epoch.
+    sent_at: u64,
+    mac: [u8; MAC_LENGTH],
+}
+
+impl<M: Debug> Debug for AuthenticatedMessage<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The MAC is left out, it only makes the logs harder to read.
+        self.message.fmt(f)
+    }
+}
+
+/// On the wire, the MAC comes first, followed by the session (8 bytes), the sequence number (8 bytes), the send
+/// time (8 bytes) and the message itself.
+impl<M: WireMessage> WireMessage for AuthenticatedMessage<M> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.mac.to_vec();
+        bytes.extend_from_slice(&self.session.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.sent_at.to_be_bytes());
+        bytes.extend(self.message.to_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (header, message) = bytes.split_at(HEADER_LENGTH);
+        Ok(Self {
+            message: M::from_bytes(message)?,
+            session: u64::from_be_bytes(header[MAC_LENGTH..MAC_LENGTH + 8].try_into().unwrap()),
+            sequence: u64::from_be_bytes(
+                header[MAC_LENGTH + 8..MAC_LENGTH + 16].try_into().unwrap(),
+            ),
+            sent_at: u64::from_be_bytes(header[MAC_LENGTH + 16..].try_into().unwrap()),
+            mac: header[..MAC_LENGTH].try_into().unwrap(),
+        })
+    }
+}
+
+/// The messages received from a single node, to find replayed ones. Only the current session of the node is
+/// accepted, and only sequence numbers within [`REPLAY_WINDOW`] of the highest one that were not seen before.
+#[derive(Debug, Clone, Copy)]
+struct ReplayWindow {
+    session: u64,
+    highest: u64,
+    /// Bit `n` is set if the message with sequence number `highest - n` was received.
+    seen: u128,
+}
+
+impl ReplayWindow {
+    fn new(session: u64, sequence: u64) -> Self {
+        Self {
+            session,
+            highest: sequence,
+            seen: 1,
+        }
+    }
+
+    /// Records the message, and returns false if it was already received, or is too old to tell.
+    fn accept(&mut self, session: u64, sequence: u64) -> bool {
+        if session < self.session {
+            return false;
+        }
+        if session > self.session {
+            // The node joined again, so its sequence numbers start over.
+            *self = Self::new(session, sequence);
+            return true;
+        }
+        if sequence > self.highest {
+            let shift = sequence - self.highest;
+            self.seen = if shift < REPLAY_WINDOW {
+                self.seen << shift
+            } else {
+                0
+            } | 1;
+            self.highest = sequence;
+            return true;
+        }
+        let offset = self.highest - sequence;
+        if offset >= REPLAY_WINDOW || self.seen & (1 << offset) != 0 {
+            return false;
+        }
+        self.seen |= 1 << offset;
+        true
+    }
+}
+
+/// Wraps any other network, and authenticates every message sent over it with a secret shared by all nodes in the cluster.
+/// The MAC is an HMAC-SHA256 over the sending node, the receiving node, the session, the sequence number, the send
+/// time and the encoded message, so a message can't be changed, sent by a process that doesn't know the secret, or
+/// be passed off as coming from another node.
+///
+/// Messages can't be replayed either. Every time a node joins, it gets a session that is higher than its previous
+/// ones, and it numbers the messages it sends to each node. A node only accepts messages from the latest session of
+/// the sender, and every sequence number only once. Sequence numbers that are far behind the highest one received
+/// are rejected as well, since they can't be told apart from replayed messages.
+///
+/// A node that just joined doesn't know the sessions of the other nodes yet, so it also rejects messages that were
+/// sent longer than the maximum message age ago. Otherwise a recorded message could be replayed after every
+/// restart. The clocks of the nodes must be closer together than this age, see
+/// [`AuthenticatedNetwork::set_max_message_age`].
+///
+/// Messages that fail any of these checks are dropped, logged and counted in the [`Counter::RejectedMessages`] metric.
+pub struct AuthenticatedNetwork<N> {
+    inner: N,
+    secret: Arc<Vec<u8>>,
+    /// The last session given out, so nodes that join within the same clock tick still get higher sessions.
+    last_session: Arc<AtomicU64>,
+    max_message_age: Duration,
+    /// Where the rejected messages are counted.
+    metrics_sink: Arc<dyn MetricsSink>,
+}
+
+impl<N: Clone> Clone for AuthenticatedNetwork<N> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            secret: self.secret.clone(),
+            last_session: self.last_session.clone(),
+            max_message_age: self.max_message_age,
+            metrics_sink: self.metrics_sink.clone(),
+        }
+    }
+}
+
+impl<N: Debug> Debug for AuthenticatedNetwork<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The secret is left out on purpose, so it can't end up in logs.
+        f.debug_struct("AuthenticatedNetwork")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<N> AuthenticatedNetwork<N> {
+    pub fn new(inner: N, secret: &[u8]) -> Self {
+        Self {
+            inner,
+            secret: Arc::new(secret.to_vec()),
+            last_session: Arc::new(AtomicU64::new(0)),
+            max_message_age: DEFAULT_MAX_MESSAGE_AGE,
+            metrics_sink: Arc::new(RecorderSink),
+        }
+    }
+
+    /// Sets how long ago a message can have been sent when it is received. Older messages are rejected. This has
+    /// to be larger than the latency of the network plus the difference between the clocks of the nodes, and is
+    /// only used by nodes that join afterwards.
+    pub fn set_max_message_age(&mut self, max_message_age: Duration) {
+        self.max_message_age = max_message_age;
+    }
+
+    /// Replaces the sink the rejected messages are counted in. This is only used by nodes that join afterwards.
+    pub fn set_metrics_sink(&mut self, metrics_sink: Arc<dyn MetricsSink>) {
+        self.metrics_sink = metrics_sink;
+    }
+
+    pub fn inner(&self) -> &N {
+        &self.inner
+    }
+
+    /// Returns a session higher than any given out before, also by a previous run of the process. The wall clock
+    /// is used, so a node that restarts gets a higher session as long as the clock doesn't go back.
+    fn next_session(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let previous = self
+            .last_session
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap();
+        now.max(previous + 1)
+    }
+}
+
+impl<M, N> Network<M> for AuthenticatedNetwork<N>
+where
+    M: WireMessage + Debug,
+    N: Network<AuthenticatedMessage<M>>,
+{
+    type NetworkHandle = AuthenticatedNetworkHandle<N::NetworkHandle>;
+
+    fn join(&self, node: NodeId) -> Result<Self::NetworkHandle, NetworkError> {
+        let mut metrics = Metrics::for_node(node);
+        metrics.set_sink(self.metrics_sink.clone());
+        Ok(AuthenticatedNetworkHandle {
+            node,
+            inner: self.inner.join(node)?,
+            secret: self.secret.clone(),
+            session: self.next_session(),
+            max_message_age: self.max_message_age.as_millis() as u64,
+            next_sequences: Mutex::new(HashMap::new()),
+            replay_windows: Mutex::new(HashMap::new()),
+            metrics,
+        })
+    }
+
+    fn all_nodes(&self) -> Result<Vec<NodeId>, NetworkError> {
+        self.inner.all_nodes()
+    }
+}
+
+pub struct AuthenticatedNetworkHandle<H> {
+    node: NodeId,
+    inner: H,
+    secret: Arc<Vec<u8>>,
+    session: u64,
+    /// In milliseconds, like the send time of the messages.
+    max_message_age: u64,
+    /// The sequence number of the next message to each node.
+    next_sequences: Mutex<HashMap<NodeId, u64>>,
+    /// The messages received from each node, to reject replayed ones.
+    replay_windows: Mutex<HashMap<NodeId, ReplayWindow>>,
+    metrics: Metrics,
+}
+
+impl<H: Debug> Debug for AuthenticatedNetworkHandle<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthenticatedNetworkHandle")
+            .field("node", &self.node)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<H> AuthenticatedNetworkHandle<H> {
+    fn mac<M>(
+        &self,
+        from_node: NodeId,
+        to_node: NodeId,
+        message: &AuthenticatedMessage<M>,
+    ) -> HmacSha256
+    where
+        M: WireMessage,
+    {
+        // HMAC accepts keys of any length, so this can never fail.
+        let mut mac = HmacSha256::new_from_slice(&self.secret).unwrap();
+        mac.update(&(from_node as u64).to_be_bytes());
+        mac.update(&(to_node as u64).to_be_bytes());
+        mac.update(&message.session.to_be_bytes());
+        mac.update(&message.sequence.to_be_bytes());
+        mac.update(&message.sent_at.to_be_bytes());
+        mac.update(&message.message.to_bytes());
+        mac
+    }
+
+    /// Returns true if the message was sent longer than the maximum message age ago.
+    fn is_stale(&self, sent_at: u64) -> bool {
+        now_millis().saturating_sub(sent_at) > self.max_message_age
+    }
+
+    /// Returns true if the message from the node was not received before. The message is recorded, so the
+    /// next time it is rejected.
+    fn is_new(&self, from_node: NodeId, session: u64, sequence: u64) -> bool {
+        let mut replay_windows = self.replay_windows.lock().unwrap();
+        match replay_windows.get_mut(&from_node) {
+            Some(window) => window.accept(session, sequence),
+            None => {
+                replay_windows.insert(from_node, ReplayWindow::new(session, sequence));
+                true
+            }
+        }
+    }
+}
+
+impl<M, H> NetworkHandle<M> for AuthenticatedNetworkHandle<H>
+where
+    M: WireMessage + Debug,
+    H: NetworkHandle<AuthenticatedMessage<M>>,
+{
+    fn send_message(&self, to_node: NodeId, message: M) -> Result<(), NetworkError> {
+        let sequence = {
+            let mut next_sequences = self.next_sequences.lock().unwrap();
+            let next_sequence = next_sequences.entry(to_node).or_insert(0);
+            *next_sequence += 1;
+            *next_sequence - 1
+        };
+        let mut message = AuthenticatedMessage {
+            message,
+            session: self.session,
+            sequence,
+            sent_at: now_millis(),
+            mac: [0; MAC_LENGTH],
+        };
+        message.mac = self
+            .mac(self.node, to_node, &message)
+            .finalize()
+            .into_bytes()
+            .into();
+        self.inner.send_message(to_node, message)
+    }
+
+    fn receive_message(&self) -> Result<Option<Message<M>>, NetworkError> {
+        // Rejected messages are skipped, so a node that is flooded with them still gets to its valid messages.
+        while let Some(message) = self.inner.receive_message()? {
+            let mac = self.mac(message.from_node, message.to_node, &message.message);
+            if mac.verify_slice(&message.message.mac).is_err() {
+                log::warn!(
+                    "[NET] {} <- {}: rejected message with an invalid MAC: {:?}",
+                    self.node,
+                    message.from_node,
+                    message.message
+                );
+            } else if self.is_stale(message.message.sent_at) {
+                log::warn!(
+                    "[NET] {} <- {}: rejected message sent at {}, which is too old: {:?}",
+                    self.node,
+                    message.from_node,
+                    message.message.sent_at,
+                    message.message
+                );
+            } else if !self.is_new(
+                message.from_node,
+                message.message.session,
+                message.message.sequence,
+            ) {
+                log::warn!(
+                    "[NET] {} <- {}: rejected replayed message {} of session {}: {:?}",
+                    self.node,
+                    message.from_node,
+                    message.message.sequence,
+                    message.message.session,
+                    message.message
+                );
+            } else {
+                return Ok(Some(Message {
+                    from_node: message.from_node,
+                    to_node: message.to_node,
+                    message: message.message.message,
+                }));
+            }
+            self.metrics.increment_counter(Counter::RejectedMessages, 1);
+        }
+        Ok(None)
+    }
+}
+
+/// Returns the wall clock time in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::{DummyMessage, DummyNetwork, Latency},
+        time::DummyTimeProvider,
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TestMessage(u8);
+
+    impl WireMessage for TestMessage {
+        fn to_bytes(&self) -> Vec<u8> {
+            vec![self.0]
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+            match bytes {
+                [value] => Ok(TestMessage(*value)),
+                _ => Err(WireError::Invalid("expected a single byte".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unauthenticated_messages() {
+        let time_provider = DummyTimeProvider::new();
+        let dummy_network: DummyNetwork<AuthenticatedMessage<TestMessage>> =
+            DummyNetwork::new(time_provider.clone(), Latency::Fixed(0));
+        let network = AuthenticatedNetwork::new(dummy_network.clone(), b"cluster secret");
+        let node_0 = network.join(0).unwrap();
+        let node_1 = network.join(1).unwrap();
+
+        // A process that doesn't know the secret, but can reach the nodes.
+        let attacker = AuthenticatedNetwork::new(dummy_network.clone(), b"wrong secret")
+            .join(2)
+            .unwrap();
+
+        node_0.send_message(1, TestMessage(1)).unwrap();
+        attacker.send_message(1, TestMessage(2)).unwrap();
+        node_0.send_message(1, TestMessage(3)).unwrap();
+
+        let received = node_1.receive_message().unwrap().unwrap();
+        assert_eq!((received.from_node, received.message), (0, TestMessage(1)));
+        // The attacker's message is skipped.
+        let received = node_1.receive_message().unwrap().unwrap();
+        assert_eq!((received.from_node, received.message), (0, TestMessage(3)));
+        assert!(node_1.receive_message().unwrap().is_none());
+
+        // A message that is changed on the way is rejected too.
+        node_0.send_message(1, TestMessage(4)).unwrap();
+        dummy_network.get_messages_mut()[0].message.message.message = TestMessage(5);
+        assert!(node_1.receive_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_replayed_messages() {
+        let time_provider = DummyTimeProvider::new();
+        let dummy_network: DummyNetwork<AuthenticatedMessage<TestMessage>> =
+            DummyNetwork::new(time_provider.clone(), Latency::Fixed(0));
+        let network = AuthenticatedNetwork::new(dummy_network.clone(), b"cluster secret");
+        let node_0 = network.join(0).unwrap();
+        let node_1 = network.join(1).unwrap();
+        let sent = |index: usize| {
+            dummy_network.get_messages_mut()[index]
+                .message
+                .message
+                .clone()
+        };
+        let replay = |message: &AuthenticatedMessage<TestMessage>| {
+            dummy_network.get_messages_mut().push(DummyMessage {
+                message: Message {
+                    from_node: 0,
+                    to_node: 1,
+                    message: message.clone(),
+                },
+                arrival_time: 0,
+            });
+        };
+
+        node_0.send_message(1, TestMessage(1)).unwrap();
+        let first = sent(0);
+        assert_eq!(
+            node_1.receive_message().unwrap().unwrap().message,
+            TestMessage(1)
+        );
+        // Someone who recorded the message sends it again.
+        replay(&first);
+        assert!(node_1.receive_message().unwrap().is_none());
+
+        // Messages that are reordered by the network are still accepted, but only once.
+        node_0.send_message(1, TestMessage(2)).unwrap();
+        node_0.send_message(1, TestMessage(3)).unwrap();
+        dummy_network.get_messages_mut().swap(0, 1);
+        let second = sent(1);
+        assert_eq!(
+            node_1.receive_message().unwrap().unwrap().message,
+            TestMessage(3)
+        );
+        assert_eq!(
+            node_1.receive_message().unwrap().unwrap().message,
+            TestMessage(2)
+        );
+        replay(&second);
+        assert!(node_1.receive_message().unwrap().is_none());
+
+        // Messages that are too far behind can't be told apart from replayed ones.
+        node_0.send_message(1, TestMessage(4)).unwrap();
+        let late = dummy_network.get_messages_mut().remove(0).message.message;
+        for _ in 0..REPLAY_WINDOW {
+            node_0.send_message(1, TestMessage(5)).unwrap();
+            node_1.receive_message().unwrap().unwrap();
+        }
+        replay(&late);
+        assert!(node_1.receive_message().unwrap().is_none());
+
+        // When node 0 joins again, it starts a new session, after which the old one is not accepted anymore.
+        dummy_network.remove_node(0);
+        let node_0_again = network.join(0).unwrap();
+        node_0_again.send_message(1, TestMessage(6)).unwrap();
+        assert_eq!(
+            node_1.receive_message().unwrap().unwrap().message,
+            TestMessage(6)
+        );
+        node_0.send_message(1, TestMessage(7)).unwrap();
+        assert!(node_1.receive_message().unwrap().is_none());
+
+        // Changing the sequence number breaks the MAC.
+        node_0_again.send_message(1, TestMessage(8)).unwrap();
+        dummy_network.get_messages_mut()[0].message.message.sequence = 100;
+        assert!(node_1.receive_message().unwrap().is_none());
+        // Changing the send time breaks the MAC as well.
+        node_0_again.send_message(1, TestMessage(9)).unwrap();
+        dummy_network.get_messages_mut()[0].message.message.sent_at += 1;
+        assert!(node_1.receive_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_old_messages_after_restart() {
+        let time_provider = DummyTimeProvider::new();
+        let dummy_network: DummyNetwork<AuthenticatedMessage<TestMessage>> =
+            DummyNetwork::new(time_provider.clone(), Latency::Fixed(0));
+        let mut network = AuthenticatedNetwork::new(dummy_network.clone(), b"cluster secret");
+        network.set_max_message_age(Duration::from_millis(200));
+        let node_0 = network.join(0).unwrap();
+        let node_1 = network.join(1).unwrap();
+
+        node_0.send_message(1, TestMessage(1)).unwrap();
+        let recorded = dummy_network.get_messages_mut()[0].message.message.clone();
+        node_1.receive_message().unwrap().unwrap();
+
+        // Node 1 restarts, so it doesn't know the session of node 0 anymore. The recorded message is replayed
+        // to it, but it was sent too long ago.
+        std::thread::sleep(Duration::from_millis(300));
+        drop(node_1);
+        dummy_network.remove_node(1);
+        let node_1 = network.join(1).unwrap();
+        dummy_network.get_messages_mut().push(DummyMessage {
+            message: Message {
+                from_node: 0,
+                to_node: 1,
+                message: recorded,
+            },
+            arrival_time: 0,
+        });
+        assert!(node_1.receive_message().unwrap().is_none());
+
+        // New messages from the same session are still accepted.
+        node_0.send_message(1, TestMessage(2)).unwrap();
+        assert_eq!(
+            node_1.receive_message().unwrap().unwrap().message,
+            TestMessage(2)
+        );
+    }
+}
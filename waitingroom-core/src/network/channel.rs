@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use crate::{error::NetworkError, NodeId};
+
+use super::{Message, Network, NetworkHandle};
+
+/// An in-process network that can be shared between threads. Every node gets its own channel,
+/// so each node can run on its own thread (or task) with real timers, without opening sockets.
+/// Like [`super::DummyNetwork`], messages to nodes that are not in the network are ignored.
+#[derive(Debug)]
+pub struct ChannelNetwork<M> {
+    nodes: Arc<Mutex<HashMap<NodeId, Sender<Message<M>>>>>,
+}
+
+// Deriving Clone would require the message type to be Clone as well.
+impl<M> Clone for ChannelNetwork<M> {
+    fn clone(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+        }
+    }
+}
+
+impl<M> Default for ChannelNetwork<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> ChannelNetwork<M> {
+    pub fn new() -> Self {
+        Self {
+            nodes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Removes a node from the network, as if it crashed. Messages that were not received yet are lost.
+    pub fn remove_node(&self, node: NodeId) {
+        self.nodes.lock().unwrap().remove(&node);
+    }
+}
+
+impl<M> Network<M> for ChannelNetwork<M>
+where
+    M: Debug + Send,
+{
+    type NetworkHandle = ChannelNetworkHandle<M>;
+
+    fn join(&self, node: NodeId) -> Result<Self::NetworkHandle, NetworkError> {
+        log::debug!("[NET] Node {} joined", node);
+        let mut nodes = self.nodes.lock().unwrap();
+        if nodes.contains_key(&node) {
+            return Err(NetworkError::NodeIDAlreadyUsed);
+        }
+        let (sender, receiver) = mpsc::channel();
+        nodes.insert(node, sender);
+        Ok(ChannelNetworkHandle {
+            node,
+            network: self.clone(),
+            receiver,
+        })
+    }
+
+    fn all_nodes(&self) -> Result<Vec<NodeId>, NetworkError> {
+        Ok(self.nodes.lock().unwrap().keys().copied().collect())
+    }
+}
+
+/// The handle of a node in a [`ChannelNetwork`]. Dropping it removes the node from the network.
+pub struct ChannelNetworkHandle<M> {
+    node: NodeId,
+    network: ChannelNetwork<M>,
+    receiver: Receiver<Message<M>>,
+}
+
+impl<M> Debug for ChannelNetworkHandle<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelNetworkHandle")
+            .field("node", &self.node)
+            .field("network", &"...")
+            .finish()
+    }
+}
+
+impl<M> NetworkHandle<M> for ChannelNetworkHandle<M>
+where
+    M: Debug + Send,
+{
+    fn send_message(&self, to_node: NodeId, message: M) -> Result<(), NetworkError> {
+        log::debug!("[NET] {} -> {}: {:?}", self.node, to_node, message);
+        let nodes = self.network.nodes.lock().unwrap();
+        match nodes.get(&to_node) {
+            Some(sender) => {
+                // This only fails if the receiving handle was dropped, which is the same as the node being gone.
+                let _ = sender.send(Message {
+                    from_node: self.node,
+                    to_node,
+                    message,
+                });
+            }
+            None => {
+                log::debug!("Network message sent to {} is being ignored, because this node is not in the network", to_node);
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_message(&self) -> Result<Option<Message<M>>, NetworkError> {
+        match self.receiver.try_recv() {
+            Ok(message) => {
+                log::debug!(
+                    "[NET] {} <- {}: {:?}",
+                    self.node,
+                    message.from_node,
+                    message.message
+                );
+                Ok(Some(message))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl<M> Drop for ChannelNetworkHandle<M> {
+    fn drop(&mut self) {
+        self.network.remove_node(self.node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn messages_between_threads() {
+        let network = ChannelNetwork::new();
+        let receiver = network.join(0).unwrap();
+        assert!(matches!(
+            network.join(0),
+            Err(NetworkError::NodeIDAlreadyUsed)
+        ));
+
+        let senders: Vec<_> = (1..=4)
+            .map(|node| {
+                let handle = network.join(node).unwrap();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        handle.send_message(0, (node, i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        // The sending threads are done, so their handles have left the network.
+        assert_eq!(network.all_nodes().unwrap(), vec![0]);
+
+        let mut next = [0; 5];
+        while let Some(message) = receiver.receive_message().unwrap() {
+            let (node, i) = message.message;
+            assert_eq!(message.from_node, node);
+            assert_eq!(i, next[node], "Messages from one node arrive in order");
+            next[node] += 1;
+        }
+        assert_eq!(next, [0, 100, 100, 100, 100]);
+    }
+}
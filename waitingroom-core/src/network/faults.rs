@@ -0,0 +1,309 @@
+use crate::{
+    random::{DeterministicRandomProvider, RandomProvider},
+    time::Time,
+    NodeId,
+};
+
+/// Faults applied to messages sent over a single (directed) link.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkFaults {
+    /// The probability (0.0 to 1.0) that a message is lost.
+    pub drop_probability: f64,
+    /// The probability (0.0 to 1.0) that a message is delivered twice.
+    /// The copy gets its own latency, so it can arrive before the original.
+    pub duplicate_probability: f64,
+    /// The probability (0.0 to 1.0) that a message is delayed by up to `reorder_max_delay` on top of its latency,
+    /// so it can arrive after messages that were sent later.
+    pub reorder_probability: f64,
+    pub reorder_max_delay: Time,
+}
+
+impl LinkFaults {
+    fn is_none(&self) -> bool {
+        self.drop_probability <= 0.0
+            && self.duplicate_probability <= 0.0
+            && self.reorder_probability <= 0.0
+    }
+}
+
+/// A partition splits `nodes` from the rest of the network. While it is open, no messages are delivered
+/// between the nodes in the partition and the nodes outside of it, including messages that were already in flight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    /// The name is used to heal the partition by hand. Multiple partitions can have the same name.
+    pub name: String,
+    pub nodes: Vec<NodeId>,
+    pub opens_at: Time,
+    /// The partition is healed at this time. If this is `None`, it stays open until it is healed by hand.
+    pub heals_at: Option<Time>,
+}
+
+impl Partition {
+    fn is_open(&self, now: Time) -> bool {
+        self.opens_at <= now
+            && match self.heals_at {
+                Some(heals_at) => now < heals_at,
+                None => true,
+            }
+    }
+
+    fn separates(&self, from_node: NodeId, to_node: NodeId) -> bool {
+        self.nodes.contains(&from_node) != self.nodes.contains(&to_node)
+    }
+}
+
+/// The faults a [`super::DummyNetwork`] applies to its messages. By default, there are no faults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultSetting {
+    /// Applies to every link that is not in `links`.
+    pub default_link: LinkFaults,
+    /// Faults for specific links, given as ((from, to), faults).
+    pub links: Vec<((NodeId, NodeId), LinkFaults)>,
+    pub partitions: Vec<Partition>,
+}
+
+impl FaultSetting {
+    pub fn to_faults(&self, random_provider: DeterministicRandomProvider) -> Faults {
+        Faults {
+            setting: self.clone(),
+            random_provider: Some(random_provider),
+        }
+    }
+}
+
+/// What should happen to a message that is sent.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Delivery {
+    Drop,
+    /// Deliver the message once for every extra delay in the list.
+    Deliver(Vec<Time>),
+}
+
+/// Fault settings together with the random provider used to decide which messages are affected.
+/// Random numbers are only used for links that have faults, so adding faults to some links doesn't change
+/// the latencies of a simulation that uses the same random provider.
+#[derive(Clone, Default)]
+pub struct Faults {
+    setting: FaultSetting,
+    random_provider: Option<DeterministicRandomProvider>,
+}
+
+impl std::fmt::Debug for Faults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Faults").field(&self.setting).finish()
+    }
+}
+
+impl Faults {
+    pub(super) fn set_link(&mut self, from_node: NodeId, to_node: NodeId, faults: LinkFaults) {
+        self.setting
+            .links
+            .retain(|(link, _)| *link != (from_node, to_node));
+        self.setting.links.push(((from_node, to_node), faults));
+    }
+
+    pub(super) fn open_partition(&mut self, name: &str, nodes: Vec<NodeId>, now: Time) {
+        self.setting.partitions.push(Partition {
+            name: name.to_string(),
+            nodes,
+            opens_at: now,
+            heals_at: None,
+        });
+    }
+
+    pub(super) fn heal_partition(&mut self, name: &str, now: Time) {
+        for partition in self.setting.partitions.iter_mut() {
+            if partition.name == name && partition.is_open(now) {
+                partition.heals_at = Some(now);
+            }
+        }
+    }
+
+    pub(super) fn is_partitioned(&self, from_node: NodeId, to_node: NodeId, now: Time) -> bool {
+        self.setting
+            .partitions
+            .iter()
+            .any(|partition| partition.is_open(now) && partition.separates(from_node, to_node))
+    }
+
+    pub(super) fn on_send(&self, from_node: NodeId, to_node: NodeId, now: Time) -> Delivery {
+        if self.is_partitioned(from_node, to_node, now) {
+            return Delivery::Drop;
+        }
+
+        let link = self
+            .setting
+            .links
+            .iter()
+            .find(|(link, _)| *link == (from_node, to_node))
+            .map(|(_, faults)| faults)
+            .unwrap_or(&self.setting.default_link);
+        if link.is_none() {
+            return Delivery::Deliver(vec![0]);
+        }
+
+        if self.happens(link.drop_probability) {
+            return Delivery::Drop;
+        }
+        let mut extra_delays = vec![self.reorder_delay(link)];
+        if self.happens(link.duplicate_probability) {
+            extra_delays.push(self.reorder_delay(link));
+        }
+        Delivery::Deliver(extra_delays)
+    }
+
+    fn reorder_delay(&self, link: &LinkFaults) -> Time {
+        if link.reorder_max_delay > 0 && self.happens(link.reorder_probability) {
+            self.random_provider().random_u64() as Time % (link.reorder_max_delay + 1)
+        } else {
+            0
+        }
+    }
+
+    fn happens(&self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        self.random_provider().random_f64() < probability
+    }
+
+    fn random_provider(&self) -> &DeterministicRandomProvider {
+        self.random_provider
+            .as_ref()
+            .expect("Random provider must be provided for random faults")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::{DummyNetwork, Latency, Network, NetworkHandle},
+        time::DummyTimeProvider,
+    };
+
+    use super::*;
+
+    fn receive_all(handle: &impl NetworkHandle<u32>) -> Vec<u32> {
+        let mut messages = vec![];
+        while let Some(message) = handle.receive_message().unwrap() {
+            messages.push(message.message);
+        }
+        messages
+    }
+
+    #[test]
+    fn drops_and_duplicates_are_seeded() {
+        let run = |seed| {
+            let time_provider = DummyTimeProvider::new();
+            let network = DummyNetwork::new(time_provider.clone(), Latency::Fixed(10));
+            network.set_faults(
+                FaultSetting {
+                    default_link: LinkFaults {
+                        drop_probability: 0.3,
+                        duplicate_probability: 0.3,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }
+                .to_faults(DeterministicRandomProvider::new(seed)),
+            );
+            let sender = network.join(0).unwrap();
+            let receiver = network.join(1).unwrap();
+            for i in 0..100 {
+                sender.send_message(1, i).unwrap();
+            }
+            time_provider.increase_by(10);
+            receive_all(&receiver)
+        };
+
+        let received = run(1);
+        assert_eq!(
+            received,
+            run(1),
+            "The same seed should give the same faults"
+        );
+        assert!(
+            (0..100).any(|i| !received.contains(&i)),
+            "Some should be dropped"
+        );
+        assert!(
+            (0..100).any(|i| received.iter().filter(|m| **m == i).count() == 2),
+            "Some should be duplicated"
+        );
+    }
+
+    #[test]
+    fn per_link_faults() {
+        let time_provider = DummyTimeProvider::new();
+        let network = DummyNetwork::new(time_provider.clone(), Latency::Fixed(10));
+        network.set_faults(
+            FaultSetting {
+                links: vec![(
+                    (0, 1),
+                    LinkFaults {
+                        drop_probability: 1.0,
+                        ..Default::default()
+                    },
+                )],
+                ..Default::default()
+            }
+            .to_faults(DeterministicRandomProvider::new(1)),
+        );
+        let handles: Vec<_> = (0..3).map(|node| network.join(node).unwrap()).collect();
+
+        handles[0].send_message(1, 1).unwrap();
+        handles[0].send_message(2, 2).unwrap();
+        handles[1].send_message(0, 3).unwrap();
+        time_provider.increase_by(10);
+
+        assert_eq!(receive_all(&handles[1]), Vec::<u32>::new());
+        assert_eq!(receive_all(&handles[2]), vec![2]);
+        assert_eq!(receive_all(&handles[0]), vec![3]);
+    }
+
+    #[test]
+    fn partitions_open_and_heal() {
+        let time_provider = DummyTimeProvider::new();
+        let network = DummyNetwork::new(time_provider.clone(), Latency::Fixed(10));
+        network.set_faults(
+            FaultSetting {
+                partitions: vec![Partition {
+                    name: "split".to_string(),
+                    nodes: vec![0],
+                    opens_at: 100,
+                    heals_at: Some(200),
+                }],
+                ..Default::default()
+            }
+            .to_faults(DeterministicRandomProvider::new(1)),
+        );
+        let handles: Vec<_> = (0..3).map(|node| network.join(node).unwrap()).collect();
+
+        // Sent before the partition opens, but it arrives while it is open, so it is lost.
+        time_provider.increase_by(95);
+        handles[1].send_message(0, 1).unwrap();
+        time_provider.increase_by(10);
+        assert_eq!(receive_all(&handles[0]), Vec::<u32>::new());
+
+        // Nodes on the same side can still talk to each other.
+        handles[1].send_message(2, 2).unwrap();
+        time_provider.increase_by(10);
+        assert_eq!(receive_all(&handles[2]), vec![2]);
+
+        // After the partition heals, messages arrive again.
+        time_provider.increase_by(100);
+        handles[1].send_message(0, 3).unwrap();
+        time_provider.increase_by(10);
+        assert_eq!(receive_all(&handles[0]), vec![3]);
+
+        // Partitions can also be opened and healed by hand.
+        network.open_partition("manual", vec![0, 1]);
+        handles[2].send_message(0, 4).unwrap();
+        time_provider.increase_by(10);
+        assert_eq!(receive_all(&handles[0]), Vec::<u32>::new());
+        network.heal_partition("manual");
+        handles[2].send_message(0, 5).unwrap();
+        time_provider.increase_by(10);
+        assert_eq!(receive_all(&handles[0]), vec![5]);
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt::Debug;
+
+use crate::{
+    error::SettingsError,
+    random::{DeterministicRandomProvider, RandomProvider},
+    settings::ensure_less_than,
+    NodeId,
+};
+
+/// The highest latency a random sample can have, one hour. The tails of the normal and Pareto distributions are
+/// cut off here, so the arrival time of a message can't overflow. A message this late is as good as lost anyway.
+pub const MAX_SAMPLED_LATENCY: u128 = 60 * 60 * 1000;
+
+/// The latency of messages in a [`super::DummyNetwork`]. All random latencies use a
+/// [`DeterministicRandomProvider`], so simulations with the same seed get the same latencies.
+#[derive(Clone)]
+pub enum Latency {
+    Fixed(u128),
+    /// Uniformly distributed between the minimum (inclusive) and maximum (exclusive).
+    UniformRandom(u128, u128, DeterministicRandomProvider),
+    /// Normally distributed with the given mean and standard deviation. Negative samples become 0, and samples
+    /// are at most [`MAX_SAMPLED_LATENCY`].
+    Normal(f64, f64, DeterministicRandomProvider),
+    /// Pareto distributed with the given minimum (scale) and shape. The lower the shape, the longer the tail.
+    /// Samples are at most [`MAX_SAMPLED_LATENCY`].
+    Pareto(f64, f64, DeterministicRandomProvider),
+    /// A different latency for some pairs of nodes.
+    PerLink(LinkLatencies),
+    /// A different latency depending on the regions of the sending and receiving nodes.
+    Regions(RegionLatencies),
+}
+
+/// The setting for a [`Latency`], without the random provider.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LatencySetting {
+    Fixed(u128),
+    /// Minimum (inclusive) and maximum (exclusive). The minimum must be less than the maximum.
+    UniformRandom(u128, u128),
+    /// Mean and standard deviation.
+    Normal(u128, u128),
+    /// Minimum (scale) and shape. The shape must be greater than 0.
+    Pareto(u128, f64),
+    /// `default` is used for every pair of nodes that is not in `links`.
+    /// Links work in both directions, so (1, 2) also sets the latency from 2 to 1.
+    PerLink {
+        default: Box<LatencySetting>,
+        links: Vec<((NodeId, NodeId), LatencySetting)>,
+    },
+    /// Every node in `node_regions` is in the region with the given label.
+    /// Nodes in the same region use `within_region`. Nodes in different regions use the latency between
+    /// their regions from `between_regions` if it is there, and `default` otherwise.
+    /// Nodes that are not in any region are treated as being in a region of their own.
+    Regions {
+        node_regions: Vec<(NodeId, String)>,
+        within_region: Box<LatencySetting>,
+        between_regions: Vec<((String, String), LatencySetting)>,
+        default: Box<LatencySetting>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkLatencies {
+    default: Box<Latency>,
+    links: Vec<((NodeId, NodeId), Latency)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RegionLatencies {
+    node_regions: Vec<(NodeId, String)>,
+    within_region: Box<Latency>,
+    between_regions: Vec<((String, String), Latency)>,
+    default: Box<Latency>,
+}
+
+impl LatencySetting {
+    /// Builds the latency. Returns an error if the setting, or any setting in it, would give latencies that
+    /// can't be sampled.
+    pub fn to_latency(
+        &self,
+        random_provider: Option<DeterministicRandomProvider>,
+    ) -> Result<Latency, SettingsError> {
+        let required_random_provider = || {
+            random_provider
+                .clone()
+                .expect("Random provider must be provided for random latency")
+        };
+        Ok(match self {
+            Self::Fixed(latency) => Latency::Fixed(*latency),
+            Self::UniformRandom(min, max) => {
+                ensure_less_than(("latency.min", *min), ("latency.max", *max))?;
+                Latency::UniformRandom(*min, *max, required_random_provider())
+            }
+            // The standard deviation can't be negative, since it is unsigned.
+            Self::Normal(mean, std_dev) => {
+                Latency::Normal(*mean as f64, *std_dev as f64, required_random_provider())
+            }
+            Self::Pareto(scale, shape) => {
+                if shape.is_nan() || *shape <= 0.0 {
+                    return Err(SettingsError::Invalid(
+                        "latency.shape",
+                        format!("the shape ({}) must be greater than 0", shape),
+                    ));
+                }
+                Latency::Pareto(*scale as f64, *shape, required_random_provider())
+            }
+            Self::PerLink { default, links } => Latency::PerLink(LinkLatencies {
+                default: Box::new(default.to_latency(random_provider.clone())?),
+                links: links
+                    .iter()
+                    .map(|(link, setting)| {
+                        Ok((*link, setting.to_latency(random_provider.clone())?))
+                    })
+                    .collect::<Result<_, SettingsError>>()?,
+            }),
+            Self::Regions {
+                node_regions,
+                within_region,
+                between_regions,
+                default,
+            } => Latency::Regions(RegionLatencies {
+                node_regions: node_regions.clone(),
+                within_region: Box::new(within_region.to_latency(random_provider.clone())?),
+                between_regions: between_regions
+                    .iter()
+                    .map(|(regions, setting)| {
+                        Ok((
+                            regions.clone(),
+                            setting.to_latency(random_provider.clone())?,
+                        ))
+                    })
+                    .collect::<Result<_, SettingsError>>()?,
+                default: Box::new(default.to_latency(random_provider.clone())?),
+            }),
+        })
+    }
+}
+
+impl Latency {
+    /// Picks the latency of a single message from `from_node` to `to_node`.
+    pub fn sample(&self, from_node: NodeId, to_node: NodeId) -> u128 {
+        match self {
+            Self::Fixed(latency) => *latency,
+            Self::UniformRandom(min, max, random_provider) => {
+                let random = random_provider.random_u64() as u128;
+                min + random % (max - min)
+            }
+            Self::Normal(mean, std_dev, random_provider) => {
+                // Box-Muller transform. 1 - u is in (0, 1], so the logarithm is always finite.
+                let u1 = 1.0 - random_provider.random_f64();
+                let u2 = random_provider.random_f64();
+                let standard_normal =
+                    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                ((mean + std_dev * standard_normal).max(0.0).round() as u128)
+                    .min(MAX_SAMPLED_LATENCY)
+            }
+            Self::Pareto(scale, shape, random_provider) => {
+                // Inverse transform sampling. With a low shape, samples can be far too large for a latency.
+                let u = 1.0 - random_provider.random_f64();
+                ((scale / u.powf(1.0 / shape)).round() as u128).min(MAX_SAMPLED_LATENCY)
+            }
+            Self::PerLink(link_latencies) => link_latencies
+                .links
+                .iter()
+                .find(|(link, _)| *link == (from_node, to_node) || *link == (to_node, from_node))
+                .map(|(_, latency)| latency)
+                .unwrap_or(&link_latencies.default)
+                .sample(from_node, to_node),
+            Self::Regions(region_latencies) => {
+                let region_of = |node| {
+                    region_latencies
+                        .node_regions
+                        .iter()
+                        .find(|(n, _)| *n == node)
+                        .map(|(_, region)| region)
+                };
+                match (region_of(from_node), region_of(to_node)) {
+                    (Some(from_region), Some(to_region)) if from_region == to_region => {
+                        &region_latencies.within_region
+                    }
+                    (Some(from_region), Some(to_region)) => region_latencies
+                        .between_regions
+                        .iter()
+                        .find(|((a, b), _)| {
+                            (a == from_region && b == to_region)
+                                || (a == to_region && b == from_region)
+                        })
+                        .map(|(_, latency)| latency)
+                        .unwrap_or(&region_latencies.default),
+                    _ => &region_latencies.default,
+                }
+                .sample(from_node, to_node)
+            }
+        }
+    }
+}
+
+impl Debug for Latency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed(arg0) => f.debug_tuple("Fixed").field(arg0).finish(),
+            Self::UniformRandom(arg0, arg1, _) => {
+                f.debug_tuple("Random").field(arg0).field(arg1).finish()
+            }
+            Self::Normal(arg0, arg1, _) => f.debug_tuple("Normal").field(arg0).field(arg1).finish(),
+            Self::Pareto(arg0, arg1, _) => f.debug_tuple("Pareto").field(arg0).field(arg1).finish(),
+            Self::PerLink(arg0) => f.debug_tuple("PerLink").field(arg0).finish(),
+            Self::Regions(arg0) => f.debug_tuple("Regions").field(arg0).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(setting: LatencySetting, seed: u64) -> Vec<u128> {
+        let latency = setting
+            .to_latency(Some(DeterministicRandomProvider::new(seed)))
+            .unwrap();
+        (0..10000).map(|_| latency.sample(0, 1)).collect()
+    }
+
+    fn mean(samples: &[u128]) -> f64 {
+        samples.iter().sum::<u128>() as f64 / samples.len() as f64
+    }
+
+    #[test]
+    fn distributions() {
+        let normal = samples(LatencySetting::Normal(100, 10), 1);
+        assert_eq!(normal, samples(LatencySetting::Normal(100, 10), 1));
+        assert!((mean(&normal) - 100.0).abs() < 1.0);
+        assert!(normal.iter().all(|l| (40..160).contains(l)));
+
+        // For a Pareto distribution with shape 3, the mean is 1.5 times the scale.
+        let pareto = samples(LatencySetting::Pareto(20, 3.0), 1);
+        assert!(pareto.iter().all(|l| *l >= 20));
+        assert!((mean(&pareto) - 30.0).abs() < 2.0);
+        assert!(
+            pareto.iter().any(|l| *l > 100),
+            "There should be a long tail"
+        );
+    }
+
+    #[test]
+    fn long_tails_are_capped() {
+        // With such a low shape, most samples would not even fit in a u128.
+        let pareto = samples(LatencySetting::Pareto(20, 0.01), 1);
+        assert!(pareto
+            .iter()
+            .all(|l| (20..=MAX_SAMPLED_LATENCY).contains(l)));
+        assert!(pareto.contains(&MAX_SAMPLED_LATENCY));
+
+        let normal = samples(LatencySetting::Normal(MAX_SAMPLED_LATENCY, 1000), 1);
+        assert!(normal.iter().all(|l| *l <= MAX_SAMPLED_LATENCY));
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let random_provider = || Some(DeterministicRandomProvider::new(1));
+        for shape in [0.0, -1.0, f64::NAN] {
+            assert!(matches!(
+                LatencySetting::Pareto(20, shape).to_latency(random_provider()),
+                Err(SettingsError::Invalid("latency.shape", _))
+            ));
+        }
+        assert!(matches!(
+            LatencySetting::UniformRandom(10, 10).to_latency(random_provider()),
+            Err(SettingsError::NotLessThan { .. })
+        ));
+        // Invalid settings are found inside other settings as well.
+        assert!(LatencySetting::PerLink {
+            default: Box::new(LatencySetting::Fixed(10)),
+            links: vec![((1, 2), LatencySetting::Pareto(20, 0.0))],
+        }
+        .to_latency(random_provider())
+        .is_err());
+    }
+
+    #[test]
+    fn links_and_regions() {
+        let per_link = LatencySetting::PerLink {
+            default: Box::new(LatencySetting::Fixed(10)),
+            links: vec![((1, 2), LatencySetting::Fixed(50))],
+        }
+        .to_latency(None)
+        .unwrap();
+        assert_eq!(per_link.sample(0, 1), 10);
+        assert_eq!(per_link.sample(1, 2), 50);
+        assert_eq!(per_link.sample(2, 1), 50);
+
+        let regions = LatencySetting::Regions {
+            node_regions: vec![
+                (0, "eu".to_string()),
+                (1, "eu".to_string()),
+                (2, "us".to_string()),
+                (3, "ap".to_string()),
+            ],
+            within_region: Box::new(LatencySetting::Fixed(2)),
+            between_regions: vec![(
+                ("eu".to_string(), "us".to_string()),
+                LatencySetting::Fixed(80),
+            )],
+            default: Box::new(LatencySetting::Fixed(200)),
+        }
+        .to_latency(None)
+        .unwrap();
+        assert_eq!(regions.sample(0, 1), 2);
+        assert_eq!(regions.sample(2, 0), 80);
+        assert_eq!(regions.sample(3, 1), 200);
+        // Node 4 is not in a region.
+        assert_eq!(regions.sample(4, 0), 200);
+    }
+}
@@ -6,9 +6,26 @@ use std::{
 
 use log;
 
+mod auth;
+mod channel;
+mod faults;
+mod latency;
+mod rooms;
+mod tcp;
+mod versioned;
+pub use auth::{
+    AuthenticatedMessage, AuthenticatedNetwork, AuthenticatedNetworkHandle, DEFAULT_MAX_MESSAGE_AGE,
+};
+pub use channel::{ChannelNetwork, ChannelNetworkHandle};
+use faults::Delivery;
+pub use faults::{FaultSetting, Faults, LinkFaults, Partition};
+pub use latency::{Latency, LatencySetting, LinkLatencies, RegionLatencies, MAX_SAMPLED_LATENCY};
+pub use rooms::{RoomMessage, RoomNetwork, RoomNetworkHandle, SharedNetwork};
+pub use tcp::{TcpNetwork, TcpNetworkHandle, TcpNetworkSettings};
+pub use versioned::{VersionedMessage, VersionedNetwork, VersionedNetworkHandle};
+
 use crate::{
     error::NetworkError,
-    random::{DeterministicRandomProvider, RandomProvider},
     time::{DummyTimeProvider, TimeProvider},
     NodeId,
 };
@@ -33,41 +50,6 @@ pub trait NetworkHandle<M>: Debug {
     fn receive_message(&self) -> Result<Option<Message<M>>, NetworkError>;
 }
 
-#[derive(Clone)]
-pub enum Latency {
-    Fixed(u128),
-    UniformRandom(u128, u128, DeterministicRandomProvider),
-}
-
-pub enum LatencySetting {
-    Fixed(u128),
-    UniformRandom(u128, u128),
-}
-
-impl LatencySetting {
-    pub fn to_latency(&self, random_provider: Option<DeterministicRandomProvider>) -> Latency {
-        match self {
-            Self::Fixed(latency) => Latency::Fixed(*latency),
-            Self::UniformRandom(min, max) => Latency::UniformRandom(
-                *min,
-                *max,
-                random_provider.expect("Random provider must be provided for random latency"),
-            ),
-        }
-    }
-}
-
-impl Debug for Latency {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Fixed(arg0) => f.debug_tuple("Fixed").field(arg0).finish(),
-            Self::UniformRandom(arg0, arg1, _) => {
-                f.debug_tuple("Random").field(arg0).field(arg1).finish()
-            }
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct DummyMessage<M> {
     message: Message<M>,
@@ -85,6 +67,7 @@ where
     messages: Rc<RefCell<Vec<DummyMessage<M>>>>,
     time_provider: DummyTimeProvider,
     latency: Latency,
+    faults: Rc<RefCell<Faults>>,
 }
 
 impl<M> Network<M> for DummyNetwork<M>
@@ -120,9 +103,35 @@ where
             messages: Rc::new(RefCell::new(Vec::new())),
             time_provider,
             latency,
+            faults: Rc::new(RefCell::new(Faults::default())),
         }
     }
 
+    /// Replaces the faults applied to messages sent from now on. Partitions also apply to messages that are already in flight.
+    pub fn set_faults(&self, faults: Faults) {
+        *self.faults.borrow_mut() = faults;
+    }
+
+    pub fn set_link_faults(&self, from_node: NodeId, to_node: NodeId, faults: LinkFaults) {
+        self.faults
+            .borrow_mut()
+            .set_link(from_node, to_node, faults);
+    }
+
+    /// Opens a partition between the given nodes and the rest of the network, until it is healed with [`Self::heal_partition`].
+    pub fn open_partition(&self, name: &str, nodes: Vec<NodeId>) {
+        let now_time = self.time_provider.get_now_time();
+        self.faults
+            .borrow_mut()
+            .open_partition(name, nodes, now_time);
+    }
+
+    /// Heals all open partitions with the given name.
+    pub fn heal_partition(&self, name: &str) {
+        let now_time = self.time_provider.get_now_time();
+        self.faults.borrow_mut().heal_partition(name, now_time);
+    }
+
     fn add_node(&self, node: NodeId) -> bool {
         if self.nodes.borrow().contains(&node) {
             false
@@ -151,30 +160,41 @@ where
             return Ok(());
             // return Err(NetworkError::DestNodeNotFound);
         }
-        let latency = match &self.latency {
-            Latency::Fixed(latency) => *latency,
-            Latency::UniformRandom(min, max, random_provider) => {
-                let random = random_provider.random_u64() as u128;
-                min + random % (max - min)
+        let now_time = self.time_provider.get_now_time();
+
+        let extra_delays = match self.faults.borrow().on_send(from_node, to_node, now_time) {
+            Delivery::Drop => {
+                log::debug!("[NET] {} -> {}: message dropped", from_node, to_node);
+                return Ok(());
             }
+            Delivery::Deliver(extra_delays) => extra_delays,
         };
 
-        let now_time = self.time_provider.get_now_time();
+        for extra_delay in extra_delays {
+            let latency = self.latency.sample(from_node, to_node);
 
-        self.messages.borrow_mut().push(DummyMessage {
-            message: Message {
-                from_node,
-                to_node,
-                message,
-            },
-            arrival_time: now_time + latency,
-        });
+            self.messages.borrow_mut().push(DummyMessage {
+                message: Message {
+                    from_node,
+                    to_node,
+                    message: message.clone(),
+                },
+                arrival_time: now_time + latency + extra_delay,
+            });
+        }
         Ok(())
     }
 
     fn receive_message(&self, node: NodeId) -> Result<Option<Message<M>>, NetworkError> {
         let mut messages = self.messages.borrow_mut();
         let now_time = self.time_provider.get_now_time();
+        let faults = self.faults.borrow();
+        // Messages that arrive while their sender is on the other side of a partition are lost.
+        messages.retain(|m| {
+            !(m.message.to_node == node
+                && m.arrival_time <= now_time
+                && faults.is_partitioned(m.message.from_node, node, now_time))
+        });
         let index = messages
             .iter()
             .position(|m| m.message.to_node == node && m.arrival_time <= now_time);
@@ -0,0 +1,297 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    error::{NetworkError, WireError},
+    wire::WireMessage,
+    NodeId, RoomId,
+};
+
+use super::{Message, Network, NetworkHandle};
+
+/// A message for a single room, sent over a [`SharedNetwork`].
+#[derive(Debug, Clone)]
+pub struct RoomMessage<M> {
+    pub room_id: RoomId,
+    pub message: M,
+}
+
+/// On the wire, the room ID comes first, followed by the message itself.
+impl<M: WireMessage> WireMessage for RoomMessage<M> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.room_id.to_be_bytes().to_vec();
+        bytes.extend(self.message.to_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        if bytes.len() < 8 {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (room_id, message) = bytes.split_at(8);
+        Ok(Self {
+            room_id: RoomId::from_be_bytes(room_id.try_into().unwrap()),
+            message: M::from_bytes(message)?,
+        })
+    }
+}
+
+/// The connection of a single node to the inner network, shared by all rooms on that node.
+struct Connection<H, M> {
+    handle: H,
+    /// Messages that were received for a room, but not picked up by that room yet.
+    mailboxes: HashMap<RoomId, VecDeque<Message<M>>>,
+}
+
+type Connections<H, M> = Arc<Mutex<HashMap<NodeId, Connection<H, M>>>>;
+
+/// Lets multiple rooms share a single network. Every node joins the inner network once, no matter how many
+/// rooms it has, and messages are tagged with the room they are for. Use [`SharedNetwork::room`] to get the
+/// network to give to a single room.
+pub struct SharedNetwork<N, M>
+where
+    N: Network<RoomMessage<M>>,
+{
+    inner: N,
+    connections: Connections<N::NetworkHandle, M>,
+}
+
+impl<N, M> Clone for SharedNetwork<N, M>
+where
+    N: Network<RoomMessage<M>> + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            connections: self.connections.clone(),
+        }
+    }
+}
+
+impl<N, M> Debug for SharedNetwork<N, M>
+where
+    N: Network<RoomMessage<M>>,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedNetwork")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<N, M> SharedNetwork<N, M>
+where
+    N: Network<RoomMessage<M>> + Clone,
+{
+    pub fn new(inner: N) -> Self {
+        Self {
+            inner,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the network for a single room.
+    pub fn room(&self, room_id: RoomId) -> RoomNetwork<N, M> {
+        RoomNetwork {
+            shared: self.clone(),
+            room_id,
+        }
+    }
+}
+
+/// The part of a [`SharedNetwork`] used by a single room.
+pub struct RoomNetwork<N, M>
+where
+    N: Network<RoomMessage<M>>,
+{
+    shared: SharedNetwork<N, M>,
+    room_id: RoomId,
+}
+
+impl<N, M> Clone for RoomNetwork<N, M>
+where
+    N: Network<RoomMessage<M>> + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            room_id: self.room_id,
+        }
+    }
+}
+
+impl<N, M> Debug for RoomNetwork<N, M>
+where
+    N: Network<RoomMessage<M>>,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoomNetwork")
+            .field("room_id", &self.room_id)
+            .field("shared", &self.shared)
+            .finish()
+    }
+}
+
+impl<N, M> Network<M> for RoomNetwork<N, M>
+where
+    N: Network<RoomMessage<M>>,
+    M: Debug,
+{
+    type NetworkHandle = RoomNetworkHandle<N::NetworkHandle, M>;
+
+    fn join(&self, node: NodeId) -> Result<Self::NetworkHandle, NetworkError> {
+        let mut connections = self.shared.connections.lock().unwrap();
+        let connection = match connections.entry(node) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            // This is the first room on this node, so the node joins the inner network.
+            Entry::Vacant(entry) => entry.insert(Connection {
+                handle: self.shared.inner.join(node)?,
+                mailboxes: HashMap::new(),
+            }),
+        };
+        match connection.mailboxes.entry(self.room_id) {
+            Entry::Occupied(_) => return Err(NetworkError::NodeIDAlreadyUsed),
+            Entry::Vacant(entry) => {
+                entry.insert(VecDeque::new());
+            }
+        }
+        Ok(RoomNetworkHandle {
+            node,
+            room_id: self.room_id,
+            connections: self.shared.connections.clone(),
+        })
+    }
+
+    fn all_nodes(&self) -> Result<Vec<NodeId>, NetworkError> {
+        self.shared.inner.all_nodes()
+    }
+}
+
+/// The handle of a single room on a node in a [`SharedNetwork`]. When the handles of all rooms on a node
+/// are dropped, the node leaves the inner network.
+pub struct RoomNetworkHandle<H, M> {
+    node: NodeId,
+    room_id: RoomId,
+    connections: Connections<H, M>,
+}
+
+impl<H, M> Debug for RoomNetworkHandle<H, M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoomNetworkHandle")
+            .field("node", &self.node)
+            .field("room_id", &self.room_id)
+            .finish()
+    }
+}
+
+impl<H, M> NetworkHandle<M> for RoomNetworkHandle<H, M>
+where
+    H: NetworkHandle<RoomMessage<M>>,
+    M: Debug,
+{
+    fn send_message(&self, to_node: NodeId, message: M) -> Result<(), NetworkError> {
+        let connections = self.connections.lock().unwrap();
+        connections[&self.node].handle.send_message(
+            to_node,
+            RoomMessage {
+                room_id: self.room_id,
+                message,
+            },
+        )
+    }
+
+    fn receive_message(&self) -> Result<Option<Message<M>>, NetworkError> {
+        let mut connections = self.connections.lock().unwrap();
+        let connection = connections.get_mut(&self.node).unwrap();
+        if let Some(message) = connection
+            .mailboxes
+            .get_mut(&self.room_id)
+            .unwrap()
+            .pop_front()
+        {
+            return Ok(Some(message));
+        }
+
+        // Messages for other rooms on this node are kept until those rooms ask for them.
+        while let Some(message) = connection.handle.receive_message()? {
+            let room_message = Message {
+                from_node: message.from_node,
+                to_node: message.to_node,
+                message: message.message.message,
+            };
+            if message.message.room_id == self.room_id {
+                return Ok(Some(room_message));
+            }
+            match connection.mailboxes.get_mut(&message.message.room_id) {
+                Some(mailbox) => mailbox.push_back(room_message),
+                None => log::debug!(
+                    "Network message for room {} is being ignored, because node {} does not have this room",
+                    message.message.room_id,
+                    self.node
+                ),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<H, M> Drop for RoomNetworkHandle<H, M> {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(connection) = connections.get_mut(&self.node) {
+            connection.mailboxes.remove(&self.room_id);
+            if connection.mailboxes.is_empty() {
+                connections.remove(&self.node);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::{DummyNetwork, Latency},
+        time::DummyTimeProvider,
+    };
+
+    use super::*;
+
+    #[test]
+    fn messages_are_routed_to_their_room() {
+        let time_provider = DummyTimeProvider::new();
+        let dummy_network: DummyNetwork<RoomMessage<u32>> =
+            DummyNetwork::new(time_provider.clone(), Latency::Fixed(0));
+        let network = SharedNetwork::new(dummy_network.clone());
+
+        let checkout_0 = network.room(1).join(0).unwrap();
+        let checkout_1 = network.room(1).join(1).unwrap();
+        let drop_1 = network.room(2).join(1).unwrap();
+        assert!(matches!(
+            network.room(1).join(1),
+            Err(NetworkError::NodeIDAlreadyUsed)
+        ));
+        // Every node only joined the inner network once.
+        assert_eq!(dummy_network.all_nodes().unwrap(), vec![0, 1]);
+
+        checkout_0.send_message(1, 10).unwrap();
+        checkout_1.send_message(1, 11).unwrap();
+        drop_1.send_message(1, 20).unwrap();
+        // Node 0 doesn't have room 2, so this is ignored.
+        drop_1.send_message(0, 21).unwrap();
+
+        // The messages for room 1 that arrived first are kept for room 1.
+        let received = drop_1.receive_message().unwrap().unwrap();
+        assert_eq!((received.from_node, received.message), (1, 20));
+        assert!(drop_1.receive_message().unwrap().is_none());
+
+        let mut received = vec![];
+        while let Some(message) = checkout_1.receive_message().unwrap() {
+            received.push((message.from_node, message.message));
+        }
+        assert_eq!(received, vec![(0, 10), (1, 11)]);
+        assert!(checkout_0.receive_message().unwrap().is_none());
+    }
+}
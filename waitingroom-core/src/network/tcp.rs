@@ -0,0 +1,625 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    io::{ErrorKind, Read, Write},
+    marker::PhantomData,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    error::{NetworkError, WireError},
+    wire::WireMessage,
+    NodeId,
+};
+
+use super::{Message, Network, NetworkHandle};
+
+/// Frames larger than this are rejected, so a corrupt length prefix can't make us allocate huge buffers.
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+/// How often the listener checks whether the handle has been dropped.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The incoming connections of a node, by the number they were accepted as.
+type IncomingStreams = Arc<Mutex<HashMap<u64, TcpStream>>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TcpNetworkSettings {
+    /// The time to wait before reconnecting after the first failed connection attempt.
+    /// This doubles after every failed attempt, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub connect_timeout: Duration,
+    /// The number of messages that are kept for a node while we can't reach it.
+    /// Messages sent when the queue is full are dropped.
+    pub queue_capacity: usize,
+}
+
+impl Default for TcpNetworkSettings {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(1),
+            queue_capacity: 1024,
+        }
+    }
+}
+
+/// A network that sends messages over TCP. Every node listens on the address from the address book,
+/// and every node keeps one outgoing connection to each node it sends messages to.
+///
+/// Every connection starts with the ID of the sending node (8 bytes, big endian), after which it contains
+/// messages framed with their length (4 bytes, big endian). Outgoing connections are made on a separate thread
+/// per destination, which reconnects with exponential backoff, so sending a message never blocks.
+/// Received messages are put in a queue, which is read without blocking by [`NetworkHandle::receive_message`].
+#[derive(Debug)]
+pub struct TcpNetwork<M> {
+    addresses: Arc<Mutex<HashMap<NodeId, SocketAddr>>>,
+    joined_nodes: Arc<Mutex<HashSet<NodeId>>>,
+    settings: TcpNetworkSettings,
+    _message: PhantomData<fn() -> M>,
+}
+
+// Deriving Clone would require the message type to be Clone as well.
+impl<M> Clone for TcpNetwork<M> {
+    fn clone(&self) -> Self {
+        Self {
+            addresses: self.addresses.clone(),
+            joined_nodes: self.joined_nodes.clone(),
+            settings: self.settings,
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<M> TcpNetwork<M> {
+    pub fn new(addresses: HashMap<NodeId, SocketAddr>) -> Self {
+        Self::with_settings(addresses, TcpNetworkSettings::default())
+    }
+
+    pub fn with_settings(
+        addresses: HashMap<NodeId, SocketAddr>,
+        settings: TcpNetworkSettings,
+    ) -> Self {
+        Self {
+            addresses: Arc::new(Mutex::new(addresses)),
+            joined_nodes: Arc::new(Mutex::new(HashSet::new())),
+            settings,
+            _message: PhantomData,
+        }
+    }
+
+    /// Adds or changes the address of a node. Existing connections to the node are kept
+    /// until they fail, after which the new address is used.
+    pub fn set_address(&self, node: NodeId, address: SocketAddr) {
+        self.addresses.lock().unwrap().insert(node, address);
+    }
+
+    pub fn remove_address(&self, node: NodeId) {
+        self.addresses.lock().unwrap().remove(&node);
+    }
+
+    pub fn get_address(&self, node: NodeId) -> Option<SocketAddr> {
+        self.addresses.lock().unwrap().get(&node).copied()
+    }
+}
+
+impl<M> Network<M> for TcpNetwork<M>
+where
+    M: WireMessage + Debug + Send + 'static,
+{
+    type NetworkHandle = TcpNetworkHandle<M>;
+
+    fn join(&self, node: NodeId) -> Result<Self::NetworkHandle, NetworkError> {
+        log::debug!("[NET] Node {} joined", node);
+        let address = self
+            .get_address(node)
+            .ok_or(NetworkError::AddressUnknown(node))?;
+
+        if !self.joined_nodes.lock().unwrap().insert(node) {
+            return Err(NetworkError::NodeIDAlreadyUsed);
+        }
+
+        let listener = match TcpListener::bind(address).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        }) {
+            Ok(listener) => listener,
+            Err(err) => {
+                self.joined_nodes.lock().unwrap().remove(&node);
+                return Err(err.into());
+            }
+        };
+        // When binding to port 0 the OS picks a port, which the other nodes need to know about.
+        if let Ok(local_address) = listener.local_addr() {
+            self.set_address(node, local_address);
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let incoming_streams = Arc::new(Mutex::new(HashMap::new()));
+
+        let listener_thread = {
+            let shutdown = shutdown.clone();
+            let incoming_streams = incoming_streams.clone();
+            thread::spawn(move || {
+                accept_connections(node, listener, sender, shutdown, incoming_streams)
+            })
+        };
+
+        Ok(TcpNetworkHandle {
+            node,
+            network: self.clone(),
+            receiver,
+            outgoing: Mutex::new(HashMap::new()),
+            incoming_streams,
+            shutdown,
+            listener_thread: Some(listener_thread),
+        })
+    }
+
+    fn all_nodes(&self) -> Result<Vec<NodeId>, NetworkError> {
+        Ok(self.addresses.lock().unwrap().keys().copied().collect())
+    }
+}
+
+/// The handle of a node in a [`TcpNetwork`]. Dropping it closes the listener and all connections.
+pub struct TcpNetworkHandle<M> {
+    node: NodeId,
+    network: TcpNetwork<M>,
+    receiver: Receiver<Message<M>>,
+    /// One queue per destination, each emptied by its own writer thread.
+    outgoing: Mutex<HashMap<NodeId, SyncSender<Vec<u8>>>>,
+    /// These are kept so the reader threads can be stopped when the handle is dropped.
+    /// A reader thread removes its stream when the connection is closed.
+    incoming_streams: IncomingStreams,
+    shutdown: Arc<AtomicBool>,
+    /// Joined when the handle is dropped, so the address can be used again right away.
+    listener_thread: Option<JoinHandle<()>>,
+}
+
+impl<M> Debug for TcpNetworkHandle<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpNetworkHandle")
+            .field("node", &self.node)
+            .field("network", &"...")
+            .finish()
+    }
+}
+
+impl<M> NetworkHandle<M> for TcpNetworkHandle<M>
+where
+    M: WireMessage + Debug + Send + 'static,
+{
+    fn send_message(&self, to_node: NodeId, message: M) -> Result<(), NetworkError> {
+        log::debug!("[NET] {} -> {}: {:?}", self.node, to_node, message);
+        if self.network.get_address(to_node).is_none() {
+            // Like the other networks, a message that can never arrive is dropped, so one unknown node doesn't
+            // stop a node from sending the same message to the others.
+            log::warn!(
+                "[NET] {} -> {}: node has no address, dropping message",
+                self.node,
+                to_node
+            );
+            return Ok(());
+        }
+
+        let payload = message.to_bytes();
+        if payload.len() > MAX_FRAME_LENGTH {
+            return Err(WireError::TooLarge(payload.len()).into());
+        }
+        let mut frame = Vec::with_capacity(4 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+
+        let mut outgoing = self.outgoing.lock().unwrap();
+        let queue = outgoing.entry(to_node).or_insert_with(|| {
+            let (sender, receiver) = mpsc::sync_channel(self.network.settings.queue_capacity);
+            let network = self.network.clone();
+            let shutdown = self.shutdown.clone();
+            let from_node = self.node;
+            thread::spawn(move || write_messages(from_node, to_node, network, receiver, shutdown));
+            sender
+        });
+
+        match queue.try_send(frame) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                // Like a message to a node that is down, this message is lost.
+                log::warn!(
+                    "[NET] {} -> {}: queue is full, dropping message",
+                    self.node,
+                    to_node
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                // The writer thread only stops when we're shutting down.
+                log::warn!(
+                    "[NET] {} -> {}: writer has stopped, dropping message",
+                    self.node,
+                    to_node
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_message(&self) -> Result<Option<Message<M>>, NetworkError> {
+        match self.receiver.try_recv() {
+            Ok(message) => {
+                log::debug!(
+                    "[NET] {} <- {}: {:?}",
+                    self.node,
+                    message.from_node,
+                    message.message
+                );
+                Ok(Some(message))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl<M> Drop for TcpNetworkHandle<M> {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        for (_, stream) in self.incoming_streams.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(listener_thread) = self.listener_thread.take() {
+            let _ = listener_thread.join();
+        }
+        self.network.joined_nodes.lock().unwrap().remove(&self.node);
+    }
+}
+
+fn accept_connections<M>(
+    node: NodeId,
+    listener: TcpListener,
+    sender: Sender<Message<M>>,
+    shutdown: Arc<AtomicBool>,
+    incoming_streams: IncomingStreams,
+) where
+    M: WireMessage + Send + 'static,
+{
+    let mut next_stream_id = 0;
+    while !shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, address)) => {
+                log::debug!("[NET] {} accepted connection from {}", node, address);
+                // Accepted streams may inherit non-blocking mode from the listener.
+                if stream.set_nonblocking(false).is_err() {
+                    continue;
+                }
+                let stream_id = next_stream_id;
+                next_stream_id += 1;
+                if let Ok(stream_clone) = stream.try_clone() {
+                    incoming_streams
+                        .lock()
+                        .unwrap()
+                        .insert(stream_id, stream_clone);
+                }
+                let sender = sender.clone();
+                let incoming_streams = incoming_streams.clone();
+                thread::spawn(move || {
+                    read_messages(node, stream, sender);
+                    incoming_streams.lock().unwrap().remove(&stream_id);
+                });
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(err) => {
+                log::warn!("[NET] {} failed to accept connection: {}", node, err);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Reads messages from an incoming connection until it is closed.
+fn read_messages<M>(node: NodeId, mut stream: TcpStream, sender: Sender<Message<M>>)
+where
+    M: WireMessage,
+{
+    let mut node_id_bytes = [0; 8];
+    if stream.read_exact(&mut node_id_bytes).is_err() {
+        return;
+    }
+    let from_node = u64::from_be_bytes(node_id_bytes) as NodeId;
+
+    loop {
+        let mut length_bytes = [0; 4];
+        if stream.read_exact(&mut length_bytes).is_err() {
+            break;
+        }
+        let length = u32::from_be_bytes(length_bytes) as usize;
+        if length > MAX_FRAME_LENGTH {
+            log::warn!(
+                "[NET] {} <- {}: frame of {} bytes is too large, closing connection",
+                node,
+                from_node,
+                length
+            );
+            break;
+        }
+
+        let mut payload = vec![0; length];
+        if stream.read_exact(&mut payload).is_err() {
+            break;
+        }
+
+        // The frame is complete, so a message we can't decode doesn't affect the ones after it.
+        match M::from_bytes(&payload) {
+            Ok(message) => {
+                let message = Message {
+                    from_node,
+                    to_node: node,
+                    message,
+                };
+                if sender.send(message).is_err() {
+                    // The handle has been dropped.
+                    break;
+                }
+            }
+            Err(err) => {
+                log::warn!("[NET] {} <- {}: dropping message: {}", node, from_node, err);
+            }
+        }
+    }
+    log::debug!("[NET] {} <- {}: connection closed", node, from_node);
+}
+
+/// Writes all messages queued for one destination, (re)connecting when needed.
+fn write_messages<M>(
+    from_node: NodeId,
+    to_node: NodeId,
+    network: TcpNetwork<M>,
+    receiver: Receiver<Vec<u8>>,
+    shutdown: Arc<AtomicBool>,
+) {
+    let settings = network.settings;
+    let mut stream: Option<TcpStream> = None;
+    let mut backoff = settings.initial_backoff;
+
+    while let Ok(frame) = receiver.recv() {
+        loop {
+            if shutdown.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let connection = match &mut stream {
+                Some(connection) => connection,
+                None => match connect(from_node, to_node, &network) {
+                    Ok(connection) => {
+                        backoff = settings.initial_backoff;
+                        stream.insert(connection)
+                    }
+                    Err(err) => {
+                        log::debug!(
+                            "[NET] {} -> {}: connection failed, retrying in {:?}: {:?}",
+                            from_node,
+                            to_node,
+                            backoff,
+                            err
+                        );
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(settings.max_backoff);
+                        continue;
+                    }
+                },
+            };
+
+            match connection.write_all(&frame) {
+                Ok(()) => break,
+                Err(err) => {
+                    // The frame is sent again on a new connection.
+                    log::debug!(
+                        "[NET] {} -> {}: connection lost: {}",
+                        from_node,
+                        to_node,
+                        err
+                    );
+                    stream = None;
+                }
+            }
+        }
+    }
+}
+
+fn connect<M>(
+    from_node: NodeId,
+    to_node: NodeId,
+    network: &TcpNetwork<M>,
+) -> Result<TcpStream, NetworkError> {
+    let address = network
+        .get_address(to_node)
+        .ok_or(NetworkError::AddressUnknown(to_node))?;
+    let mut stream = TcpStream::connect_timeout(&address, network.settings.connect_timeout)?;
+    stream.set_nodelay(true)?;
+    stream.write_all(&(from_node as u64).to_be_bytes())?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TestMessage(String);
+
+    impl WireMessage for TestMessage {
+        fn to_bytes(&self) -> Vec<u8> {
+            self.0.as_bytes().to_vec()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+            String::from_utf8(bytes.to_vec())
+                .map(TestMessage)
+                .map_err(|err| WireError::Invalid(err.to_string()))
+        }
+    }
+
+    fn localhost_network(node_count: usize) -> TcpNetwork<TestMessage> {
+        let addresses = (0..node_count)
+            .map(|node| (node, "127.0.0.1:0".parse().unwrap()))
+            .collect();
+        TcpNetwork::with_settings(
+            addresses,
+            TcpNetworkSettings {
+                initial_backoff: Duration::from_millis(5),
+                max_backoff: Duration::from_millis(50),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Polls the handle until a message arrives, or panics after a few seconds.
+    fn wait_for_message(handle: &TcpNetworkHandle<TestMessage>) -> Message<TestMessage> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(message) = handle.receive_message().unwrap() {
+                return message;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("No message received");
+    }
+
+    #[test]
+    fn messages_between_nodes() {
+        let network = localhost_network(3);
+        let handles: Vec<_> = (0..3).map(|node| network.join(node).unwrap()).collect();
+
+        assert!(matches!(
+            network.join(1),
+            Err(NetworkError::NodeIDAlreadyUsed)
+        ));
+        assert!(handles[0].receive_message().unwrap().is_none());
+
+        for i in 0..10 {
+            handles[0]
+                .send_message(2, TestMessage(format!("from 0: {}", i)))
+                .unwrap();
+            handles[1]
+                .send_message(2, TestMessage(format!("from 1: {}", i)))
+                .unwrap();
+        }
+
+        // Messages from one node arrive in the order they were sent.
+        let mut next = [0, 0];
+        for _ in 0..20 {
+            let message = wait_for_message(&handles[2]);
+            assert_eq!(message.to_node, 2);
+            assert_eq!(
+                message.message,
+                TestMessage(format!(
+                    "from {}: {}",
+                    message.from_node, next[message.from_node]
+                ))
+            );
+            next[message.from_node] += 1;
+        }
+        assert_eq!(next, [10, 10]);
+
+        // A node without an address is not an error, the message is dropped.
+        handles[0]
+            .send_message(5, TestMessage("nobody".to_string()))
+            .unwrap();
+        assert!(handles[0].outgoing.lock().unwrap().get(&5).is_none());
+    }
+
+    #[test]
+    fn reconnect_after_restart() {
+        let network = localhost_network(2);
+        let sender = network.join(0).unwrap();
+        let receiver = network.join(1).unwrap();
+
+        sender
+            .send_message(1, TestMessage("first".to_string()))
+            .unwrap();
+        assert_eq!(
+            wait_for_message(&receiver).message,
+            TestMessage("first".to_string())
+        );
+
+        // Restart node 1 on the same address. The sender has to notice the old connection is gone and reconnect.
+        drop(receiver);
+        let receiver = network.join(1).unwrap();
+
+        // Messages sent just after the restart can be lost on the old connection, like with a real crash.
+        let start = Instant::now();
+        loop {
+            sender
+                .send_message(1, TestMessage("second".to_string()))
+                .unwrap();
+            thread::sleep(Duration::from_millis(10));
+            if let Some(message) = receiver.receive_message().unwrap() {
+                assert_eq!(message.from_node, 0);
+                assert_eq!(message.message, TestMessage("second".to_string()));
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Never reconnected"
+            );
+        }
+    }
+
+    #[test]
+    fn messages_wait_for_node_to_come_up() {
+        let network = localhost_network(2);
+        let sender = network.join(0).unwrap();
+
+        // Reserve a port for node 1 without listening on it yet, so the first attempts fail.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        network.set_address(1, port);
+
+        sender
+            .send_message(1, TestMessage("early".to_string()))
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let receiver = network.join(1).unwrap();
+        assert_eq!(
+            wait_for_message(&receiver).message,
+            TestMessage("early".to_string())
+        );
+    }
+
+    #[test]
+    fn closed_connections_are_forgotten() {
+        let network = localhost_network(2);
+        let sender = network.join(0).unwrap();
+        let receiver = network.join(1).unwrap();
+
+        sender
+            .send_message(1, TestMessage("hello".to_string()))
+            .unwrap();
+        wait_for_message(&receiver);
+        assert_eq!(receiver.incoming_streams.lock().unwrap().len(), 1);
+
+        // Dropping the sender closes its connection, after which the receiver stops keeping the stream.
+        drop(sender);
+        let start = Instant::now();
+        while !receiver.incoming_streams.lock().unwrap().is_empty() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Closed stream was kept"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
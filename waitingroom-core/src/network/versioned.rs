@@ -0,0 +1,288 @@
+use std::{collections::HashMap, fmt::Debug, sync::Mutex};
+
+use crate::{
+    error::{NetworkError, WireError},
+    wire::{VersionedWireMessage, WireMessage},
+    NodeId,
+};
+
+use super::{Message, Network, NetworkHandle};
+
+/// A message together with the protocol version it is encoded at, and the newest version the sending node supports.
+#[derive(Clone)]
+pub struct VersionedMessage<M> {
+    message: M,
+    version: u16,
+    sender_version: u16,
+}
+
+impl<M: Debug> Debug for VersionedMessage<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The versions are left out, they only make the logs harder to read.
+        self.message.fmt(f)
+    }
+}
+
+/// On the wire, the version the message is encoded at (2 bytes) and the newest version the sender supports (2 bytes)
+/// come first, followed by the message itself.
+impl<M: VersionedWireMessage> WireMessage for VersionedMessage<M> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.version.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.sender_version.to_be_bytes());
+        bytes.extend(self.message.to_bytes_at(self.version));
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        if bytes.len() < 4 {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (versions, message) = bytes.split_at(4);
+        Ok(Self {
+            message: M::from_bytes(message)?,
+            version: u16::from_be_bytes([versions[0], versions[1]]),
+            sender_version: u16::from_be_bytes([versions[2], versions[3]]),
+        })
+    }
+}
+
+/// Wraps any other network, and lets nodes running different builds be in the same cluster, so it can be
+/// upgraded one node at a time. Every message tells the receiver the newest protocol version its sender
+/// supports, and messages to a node are encoded at the newest version both nodes support.
+///
+/// Until a node has received a message from another node, it sends to it at its own version. When an older
+/// node can't decode those messages, they are lost like any other message, until the older node sends
+/// something itself. Nodes always talk to each other regularly, for example for fault detection.
+///
+/// When this wraps an [`super::AuthenticatedNetwork`], the MAC covers the message at the version it is sent at.
+pub struct VersionedNetwork<N> {
+    inner: N,
+    /// The newest version this node uses, if it was lowered with [`VersionedNetwork::with_version`].
+    version: Option<u16>,
+}
+
+impl<N: Clone> Clone for VersionedNetwork<N> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            version: self.version,
+        }
+    }
+}
+
+impl<N: Debug> Debug for VersionedNetwork<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VersionedNetwork")
+            .field("inner", &self.inner)
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+impl<N> VersionedNetwork<N> {
+    pub fn new(inner: N) -> Self {
+        Self {
+            inner,
+            version: None,
+        }
+    }
+
+    /// Creates a network on which nodes never send messages newer than `version`. During an upgrade, this keeps
+    /// the new nodes at the version of the old ones until every node runs the new build. The version is
+    /// clamped to the versions the message type supports.
+    pub fn with_version(inner: N, version: u16) -> Self {
+        Self {
+            inner,
+            version: Some(version),
+        }
+    }
+
+    pub fn inner(&self) -> &N {
+        &self.inner
+    }
+}
+
+impl<M, N> Network<M> for VersionedNetwork<N>
+where
+    M: VersionedWireMessage + Debug,
+    N: Network<VersionedMessage<M>>,
+{
+    type NetworkHandle = VersionedNetworkHandle<N::NetworkHandle>;
+
+    fn join(&self, node: NodeId) -> Result<Self::NetworkHandle, NetworkError> {
+        let version = self
+            .version
+            .unwrap_or(M::VERSION)
+            .clamp(M::MIN_SUPPORTED_VERSION, M::VERSION);
+        Ok(VersionedNetworkHandle {
+            node,
+            inner: self.inner.join(node)?,
+            version,
+            peer_versions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn all_nodes(&self) -> Result<Vec<NodeId>, NetworkError> {
+        self.inner.all_nodes()
+    }
+}
+
+pub struct VersionedNetworkHandle<H> {
+    node: NodeId,
+    inner: H,
+    /// The newest version this node sends messages at.
+    version: u16,
+    /// The newest version each node supports, from the last message received from it.
+    peer_versions: Mutex<HashMap<NodeId, u16>>,
+}
+
+impl<H: Debug> Debug for VersionedNetworkHandle<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VersionedNetworkHandle")
+            .field("node", &self.node)
+            .field("inner", &self.inner)
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+impl<H> VersionedNetworkHandle<H> {
+    /// Returns the version messages to the given node are encoded at.
+    pub fn version_for(&self, node: NodeId) -> u16 {
+        match self.peer_versions.lock().unwrap().get(&node) {
+            Some(peer_version) => self.version.min(*peer_version),
+            None => self.version,
+        }
+    }
+}
+
+impl<M, H> NetworkHandle<M> for VersionedNetworkHandle<H>
+where
+    M: VersionedWireMessage + Debug,
+    H: NetworkHandle<VersionedMessage<M>>,
+{
+    fn send_message(&self, to_node: NodeId, message: M) -> Result<(), NetworkError> {
+        let version = self.version_for(to_node);
+        if version < M::MIN_SUPPORTED_VERSION {
+            // The other node only supports versions this build can't encode anymore.
+            log::warn!(
+                "[NET] {} -> {}: node only supports version {}, dropping message: {:?}",
+                self.node,
+                to_node,
+                version,
+                message
+            );
+            return Ok(());
+        }
+        self.inner.send_message(
+            to_node,
+            VersionedMessage {
+                message,
+                version,
+                sender_version: self.version,
+            },
+        )
+    }
+
+    fn receive_message(&self) -> Result<Option<Message<M>>, NetworkError> {
+        let Some(message) = self.inner.receive_message()? else {
+            return Ok(None);
+        };
+        let previous = self
+            .peer_versions
+            .lock()
+            .unwrap()
+            .insert(message.from_node, message.message.sender_version);
+        if previous != Some(message.message.sender_version) {
+            log::info!(
+                "[NET] {} <- {}: node supports version {}",
+                self.node,
+                message.from_node,
+                message.message.sender_version
+            );
+        }
+        Ok(Some(Message {
+            from_node: message.from_node,
+            to_node: message.to_node,
+            message: message.message.message,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::{DummyNetwork, Latency},
+        time::DummyTimeProvider,
+    };
+
+    use super::*;
+
+    /// Version 1 only has the first byte, version 2 added the second one.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TestMessage(u8, u8);
+
+    impl WireMessage for TestMessage {
+        fn to_bytes(&self) -> Vec<u8> {
+            self.to_bytes_at(Self::VERSION)
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+            match bytes {
+                [1, first] => Ok(TestMessage(*first, 0)),
+                [2, first, second] => Ok(TestMessage(*first, *second)),
+                _ => Err(WireError::Invalid("unexpected message".to_string())),
+            }
+        }
+    }
+
+    impl VersionedWireMessage for TestMessage {
+        const VERSION: u16 = 2;
+        const MIN_SUPPORTED_VERSION: u16 = 1;
+
+        fn to_bytes_at(&self, version: u16) -> Vec<u8> {
+            match version {
+                1 => vec![1, self.0],
+                _ => vec![2, self.0, self.1],
+            }
+        }
+    }
+
+    #[test]
+    fn negotiates_version() {
+        let time_provider = DummyTimeProvider::new();
+        let dummy_network: DummyNetwork<VersionedMessage<TestMessage>> =
+            DummyNetwork::new(time_provider.clone(), Latency::Fixed(0));
+        let new_node = VersionedNetwork::new(dummy_network.clone())
+            .join(0)
+            .unwrap();
+        // A node running a build that only knows version 1.
+        let old_node = VersionedNetwork::with_version(dummy_network.clone(), 1)
+            .join(1)
+            .unwrap();
+
+        // The new node hasn't heard from the old one, so it uses its own version.
+        assert_eq!(new_node.version_for(1), 2);
+        old_node.send_message(0, TestMessage(1, 0)).unwrap();
+        let sent = dummy_network.get_messages_mut()[0].message.message.clone();
+        assert_eq!((sent.version, sent.sender_version), (1, 1));
+        assert_eq!(
+            new_node.receive_message().unwrap().unwrap().message,
+            TestMessage(1, 0)
+        );
+
+        // From now on, the new node sends the old node messages it understands.
+        assert_eq!(new_node.version_for(1), 1);
+        new_node.send_message(1, TestMessage(2, 3)).unwrap();
+        let sent = dummy_network.get_messages_mut()[0].message.message.clone();
+        assert_eq!((sent.version, sent.sender_version), (1, 2));
+        assert_eq!(sent.to_bytes(), vec![0, 1, 0, 2, 1, 2]);
+        // The old node learns the new node supports version 2, but doesn't go past its own version.
+        old_node.receive_message().unwrap().unwrap();
+        assert_eq!(old_node.version_for(0), 1);
+
+        let decoded = VersionedMessage::<TestMessage>::from_bytes(&[0, 1, 0, 2, 1, 2]).unwrap();
+        assert_eq!(decoded.message, TestMessage(2, 0));
+        assert_eq!((decoded.version, decoded.sender_version), (1, 2));
+    }
+}
@@ -0,0 +1,79 @@
+//! Observers get told about everything that happens to the users in a waiting room, for example to feed it into
+//! analytics or an audit log. They can also reject some actions before they happen.
+
+use std::fmt::Debug;
+
+use crate::{
+    ticket::{PriorityClass, TicketIdentifier},
+    time::Time,
+    NodeId, RoomId,
+};
+
+/// Something that happened in a waiting room. Passes have the identifier of the ticket they were created from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitingRoomEvent {
+    pub kind: WaitingRoomEventKind,
+    /// The room the event happened in.
+    pub room_id: RoomId,
+    /// The node the event happened on.
+    pub node_id: NodeId,
+    /// The time of the event, from the [`crate::time::TimeProvider`] of the room.
+    pub time: Time,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitingRoomEventKind {
+    /// A user joined the queue. This can be vetoed.
+    Join {
+        ticket: TicketIdentifier,
+        priority_class: PriorityClass,
+    },
+    /// A user checked in, and was told their position.
+    CheckIn {
+        ticket: TicketIdentifier,
+        position_estimate: usize,
+    },
+    /// A user was let out of the queue at an eviction. They still need to leave the queue to get a pass.
+    LetOut { ticket: TicketIdentifier },
+    /// A user left the queue and got a pass. This can be vetoed.
+    Leave { ticket: TicketIdentifier },
+    /// A user left the queue without being let in.
+    Abandon { ticket: TicketIdentifier },
+    /// A user left the site before their pass expired.
+    Release { pass: TicketIdentifier },
+    /// A pass expired, because it was not used in time.
+    PassExpired { pass: TicketIdentifier },
+    /// A node was added to the network of a distributed room.
+    NodeAdded { member: NodeId },
+    /// A node was removed from the network of a distributed room.
+    NodeRemoved { member: NodeId },
+}
+
+impl WaitingRoomEventKind {
+    /// Returns true if observers can veto this event with [`WaitingRoomObserver::allow`].
+    pub fn can_veto(&self) -> bool {
+        matches!(
+            self,
+            WaitingRoomEventKind::Join { .. } | WaitingRoomEventKind::Leave { .. }
+        )
+    }
+}
+
+/// Gets told about the events in a waiting room. Both methods do nothing by default, so an observer only needs
+/// to implement the ones it is interested in.
+pub trait WaitingRoomObserver: Debug + Send {
+    /// Called before an event that can be vetoed (see [`WaitingRoomEventKind::can_veto`]) happens.
+    /// Returning an error with a reason stops the action, and the room returns [`crate::WaitingRoomError::Vetoed`].
+    fn allow(&mut self, _event: &WaitingRoomEvent) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called after every event.
+    fn notify(&mut self, _event: &WaitingRoomEvent) {}
+}
+
+/// An observer that does nothing. This is what the rooms start with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl WaitingRoomObserver for NoopObserver {}
@@ -3,17 +3,21 @@ use serde::{Deserialize, Serialize};
 use crate::{
     ticket::{Ticket, TicketIdentifier},
     time::{Time, TimeProvider},
-    NodeId,
+    NodeId, RoomId,
 };
 
 /// The user gets a pass when they leave the queue.
 /// It is used to show that they are allowed to visit the site.
+/// Like tickets, passes are fully trusted, so they should be signed using
+/// [`crate::token::TokenCodec`] if they are editable by the user.
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct Pass {
     /// The identifier of the ticket that this pass was created from.
     pub identifier: TicketIdentifier,
     /// The node id the pass was last refreshed on.
     pub node_id: NodeId,
+    /// The ID of the room the pass was given out by. It can't be used in any other room.
+    pub room_id: RoomId,
     /// The time the original ticket was added to the queue.
     pub queue_join_time: Time,
     /// The time the pass was created.
@@ -27,7 +31,7 @@ pub struct Pass {
 impl Pass {
     /// Creates a new pass from a ticket. The pass will expire after `pass_expiry_time` milliseconds.
     /// The pass is created at the current time, and the expiry time is calculated from that.
-    /// The node id, identifier and queue join time are gotten from the ticket.
+    /// The node id, room id, identifier and queue join time are gotten from the ticket.
     pub fn from_ticket<T>(ticket: Ticket, pass_expiry_time: Time, time_provider: &T) -> Self
     where
         T: TimeProvider,
@@ -37,6 +41,7 @@ impl Pass {
         Self {
             identifier: ticket.identifier,
             node_id: ticket.node_id,
+            room_id: ticket.room_id,
             queue_join_time: ticket.join_time,
             pass_creation_time: now_time,
             expiry_time: now_time + pass_expiry_time,
@@ -56,6 +61,7 @@ impl Pass {
             node_id,
             expiry_time: now_time + pass_expiry_time,
             identifier: self.identifier,
+            room_id: self.room_id,
             queue_join_time: self.queue_join_time,
             pass_creation_time: self.pass_creation_time,
             eviction_time: self.eviction_time,
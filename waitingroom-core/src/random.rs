@@ -6,6 +6,12 @@ pub trait RandomProvider {
     /// Returns a random u64.
     fn random_u64(&self) -> u64;
 
+    /// Returns a random f64 in the range [0, 1).
+    fn random_f64(&self) -> f64 {
+        // The top 53 bits fit exactly in the mantissa of an f64.
+        (self.random_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
     fn shuffle<T>(&self, slice: &mut [T]);
 }
 
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    challenge::{Challenge, ChallengeSolution},
+    pass::Pass,
+    ticket::{PriorityClass, Ticket},
+    CheckInResponse, RoomId, TokenCheckInResponse, WaitingRoomError, WaitingRoomMessageTriggered,
+    WaitingRoomTimerTriggered, WaitingRoomTokenTriggered, WaitingRoomUserTriggered,
+};
+
+/// A set of independent waiting rooms in one process, keyed by their room ID. Every room has its own
+/// settings and capacity, which are given when the room is created.
+///
+/// Tickets and passes carry the ID of their room, so they are routed to the right room automatically,
+/// and a ticket or pass from one room can't be used in another one.
+/// The timer and message functions are called on every room, so the rooms can share the timers.
+/// For distributed rooms, [`crate::network::SharedNetwork`] lets the rooms share the transport as well.
+#[derive(Debug)]
+pub struct WaitingRoomRegistry<W> {
+    rooms: BTreeMap<RoomId, W>,
+}
+
+impl<W> Default for WaitingRoomRegistry<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W> WaitingRoomRegistry<W> {
+    pub fn new() -> Self {
+        Self {
+            rooms: BTreeMap::new(),
+        }
+    }
+
+    /// Removes a room from the registry, and returns it.
+    pub fn remove_room(&mut self, room_id: RoomId) -> Option<W> {
+        self.rooms.remove(&room_id)
+    }
+
+    pub fn get_room(&self, room_id: RoomId) -> Result<&W, WaitingRoomError> {
+        self.rooms
+            .get(&room_id)
+            .ok_or(WaitingRoomError::RoomNotFound(room_id))
+    }
+
+    pub fn get_room_mut(&mut self, room_id: RoomId) -> Result<&mut W, WaitingRoomError> {
+        self.rooms
+            .get_mut(&room_id)
+            .ok_or(WaitingRoomError::RoomNotFound(room_id))
+    }
+
+    /// Returns the IDs of all rooms, in ascending order.
+    pub fn room_ids(&self) -> Vec<RoomId> {
+        self.rooms.keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rooms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    /// Calls the function on every room, even if it fails for some of them.
+    /// Returns the first error, all others are only logged.
+    fn for_each_room<F>(&mut self, mut f: F) -> Result<(), WaitingRoomError>
+    where
+        F: FnMut(&mut W) -> Result<(), WaitingRoomError>,
+    {
+        let mut result = Ok(());
+        for (room_id, room) in self.rooms.iter_mut() {
+            if let Err(err) = f(room) {
+                log::error!("[ROOM {}] {}", room_id, err);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<W> WaitingRoomRegistry<W>
+where
+    W: WaitingRoomUserTriggered,
+{
+    /// Adds a room to the registry, under its own room ID.
+    pub fn add_room(&mut self, room: W) -> Result<(), WaitingRoomError> {
+        let room_id = room.room_id();
+        if self.rooms.contains_key(&room_id) {
+            return Err(WaitingRoomError::RoomAlreadyExists(room_id));
+        }
+        self.rooms.insert(room_id, room);
+        Ok(())
+    }
+
+    /// See [`WaitingRoomUserTriggered::join`].
+    pub fn join(&mut self, room_id: RoomId) -> Result<Ticket, WaitingRoomError> {
+        self.get_room_mut(room_id)?.join()
+    }
+
+    /// See [`WaitingRoomUserTriggered::join_with_priority`].
+    pub fn join_with_priority(
+        &mut self,
+        room_id: RoomId,
+        priority_class: PriorityClass,
+    ) -> Result<Ticket, WaitingRoomError> {
+        self.get_room_mut(room_id)?
+            .join_with_priority(priority_class)
+    }
+
+    /// See [`WaitingRoomUserTriggered::challenge`].
+    pub fn challenge(&mut self, room_id: RoomId) -> Result<Option<Challenge>, WaitingRoomError> {
+        Ok(self.get_room_mut(room_id)?.challenge())
+    }
+
+    /// See [`WaitingRoomUserTriggered::join_with_solution`]. Challenges are only accepted by rooms with the key
+    /// they were signed with.
+    pub fn join_with_solution(
+        &mut self,
+        room_id: RoomId,
+        solution: ChallengeSolution,
+    ) -> Result<Ticket, WaitingRoomError> {
+        self.get_room_mut(room_id)?.join_with_solution(solution)
+    }
+
+    /// See [`WaitingRoomUserTriggered::check_in`]. The ticket is handled by the room that gave it out.
+    pub fn check_in(&mut self, ticket: Ticket) -> Result<CheckInResponse, WaitingRoomError> {
+        self.get_room_mut(ticket.room_id)?.check_in(ticket)
+    }
+
+    /// See [`WaitingRoomUserTriggered::leave`]. The ticket is handled by the room that gave it out.
+    pub fn leave(&mut self, ticket: Ticket) -> Result<Pass, WaitingRoomError> {
+        self.get_room_mut(ticket.room_id)?.leave(ticket)
+    }
+
+    /// See [`WaitingRoomUserTriggered::validate_and_refresh_pass`]. The pass is handled by the room that gave it out.
+    pub fn validate_and_refresh_pass(&mut self, pass: Pass) -> Result<Pass, WaitingRoomError> {
+        self.get_room_mut(pass.room_id)?
+            .validate_and_refresh_pass(pass)
+    }
+
+    /// See [`WaitingRoomUserTriggered::abandon`]. The ticket is handled by the room that gave it out.
+    pub fn abandon(&mut self, ticket: Ticket) -> Result<(), WaitingRoomError> {
+        self.get_room_mut(ticket.room_id)?.abandon(ticket)
+    }
+
+    /// See [`WaitingRoomUserTriggered::release`]. The pass is handled by the room that gave it out.
+    pub fn release(&mut self, pass: Pass) -> Result<(), WaitingRoomError> {
+        self.get_room_mut(pass.room_id)?.release(pass)
+    }
+}
+
+/// The room can't be read from a token before it is decoded, so these take the room the user is trying to use.
+/// A token that was given out by a different room is rejected by that room.
+impl<W> WaitingRoomRegistry<W>
+where
+    W: WaitingRoomTokenTriggered,
+{
+    /// See [`WaitingRoomTokenTriggered::join_with_token`].
+    pub fn join_with_token(&mut self, room_id: RoomId) -> Result<String, WaitingRoomError> {
+        self.get_room_mut(room_id)?.join_with_token()
+    }
+
+    /// See [`WaitingRoomTokenTriggered::join_with_solution_with_token`].
+    pub fn join_with_solution_with_token(
+        &mut self,
+        room_id: RoomId,
+        solution: ChallengeSolution,
+    ) -> Result<String, WaitingRoomError> {
+        self.get_room_mut(room_id)?
+            .join_with_solution_with_token(solution)
+    }
+
+    /// See [`WaitingRoomTokenTriggered::check_in_with_token`].
+    pub fn check_in_with_token(
+        &mut self,
+        room_id: RoomId,
+        ticket_token: &str,
+    ) -> Result<TokenCheckInResponse, WaitingRoomError> {
+        self.get_room_mut(room_id)?
+            .check_in_with_token(ticket_token)
+    }
+
+    /// See [`WaitingRoomTokenTriggered::leave_with_token`].
+    pub fn leave_with_token(
+        &mut self,
+        room_id: RoomId,
+        ticket_token: &str,
+    ) -> Result<String, WaitingRoomError> {
+        self.get_room_mut(room_id)?.leave_with_token(ticket_token)
+    }
+
+    /// See [`WaitingRoomTokenTriggered::validate_and_refresh_pass_with_token`].
+    pub fn validate_and_refresh_pass_with_token(
+        &mut self,
+        room_id: RoomId,
+        pass_token: &str,
+    ) -> Result<String, WaitingRoomError> {
+        self.get_room_mut(room_id)?
+            .validate_and_refresh_pass_with_token(pass_token)
+    }
+
+    /// See [`WaitingRoomTokenTriggered::abandon_with_token`].
+    pub fn abandon_with_token(
+        &mut self,
+        room_id: RoomId,
+        ticket_token: &str,
+    ) -> Result<(), WaitingRoomError> {
+        self.get_room_mut(room_id)?.abandon_with_token(ticket_token)
+    }
+
+    /// See [`WaitingRoomTokenTriggered::release_with_token`].
+    pub fn release_with_token(
+        &mut self,
+        room_id: RoomId,
+        pass_token: &str,
+    ) -> Result<(), WaitingRoomError> {
+        self.get_room_mut(room_id)?.release_with_token(pass_token)
+    }
+}
+
+impl<W> WaitingRoomTimerTriggered for WaitingRoomRegistry<W>
+where
+    W: WaitingRoomTimerTriggered,
+{
+    fn cleanup(&mut self) -> Result<(), WaitingRoomError> {
+        self.for_each_room(|room| room.cleanup())
+    }
+
+    fn eviction(&mut self) -> Result<(), WaitingRoomError> {
+        self.for_each_room(|room| room.eviction())
+    }
+
+    fn fault_detection(&mut self) -> Result<(), WaitingRoomError> {
+        self.for_each_room(|room| room.fault_detection())
+    }
+}
+
+impl<W> WaitingRoomMessageTriggered for WaitingRoomRegistry<W>
+where
+    W: WaitingRoomMessageTriggered,
+{
+    /// Lets every room receive and process a single message. Returns true if any room processed a message.
+    fn receive_message(&mut self) -> Result<bool, WaitingRoomError> {
+        let mut received = false;
+        self.for_each_room(|room| {
+            received |= room.receive_message()?;
+            Ok(())
+        })?;
+        Ok(received)
+    }
+}
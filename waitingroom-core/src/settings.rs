@@ -1,37 +1,174 @@
-#[derive(Clone, Debug, Copy)]
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    admission::{AdmissionPolicy, ConcurrentTarget, TokenBucket},
+    challenge::MAX_CHALLENGE_DIFFICULTY,
+    error::SettingsError,
+    time::Time,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GeneralWaitingRoomSettings {
     /// The intended number of users that will be allowed on the site.
     /// If there are less than this number of users on the site,
-    /// more users are let in.
+    /// more users are let in. This is only used with [`AdmissionMode::Concurrent`].
     pub target_user_count: usize,
+    /// Replaces `target_user_count` during parts of the day, for example to let fewer users in at night.
+    /// The first range that contains the time of an eviction is used. See [`CapacityRange`].
+    pub capacity_schedule: Vec<CapacityRange>,
+    /// How the room decides how many users to let in at every eviction.
+    pub admission_mode: AdmissionMode,
+    /// The most users that can be in the queue at once. Users who try to join when it is full are turned away
+    /// with [`crate::WaitingRoomError::QueueFull`]. In a distributed room, this is the queue of all nodes together,
+    /// as of the latest count, so the queue can grow a bit past it between counts.
+    /// If this is `None`, the queue can grow without limit.
+    pub max_queue_length: Option<usize>,
+    /// If this is set, users have to solve a proof-of-work challenge before they can join. See [`ChallengeSettings`].
+    pub challenge: Option<ChallengeSettings>,
 
     /// The time in milliseconds between ticket refreshes carried out by the client.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub ticket_refresh_time: u128,
     /// The time in milliseconds until a ticket expires if it is not refreshed.
     /// This should be greater than the ticket refresh time.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub ticket_expiry_time: u128,
     /// The time in milliseconds until a pass expires if it is not used.
     /// Passes are refreshed automatically when they are used.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub pass_expiry_time: u128,
 
     /// The interval in milliseconds between fault detection checks.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub fault_detection_period: u128,
     /// The time in milliseconds until a node is considered faulty if it has not responded.
+    /// This should be less than the fault detection period.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub fault_detection_timeout: u128,
     /// The time in milliseconds between calls of the fault detection function.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub fault_detection_interval: u128,
 
     /// The time in milliseconds between evictions
+    #[serde(deserialize_with = "deserialize_duration")]
     pub eviction_interval: u128,
 
     /// Time in milliseconds between calls to the cleanup function
+    #[serde(deserialize_with = "deserialize_duration")]
     pub cleanup_interval: u128,
+
+    /// If this is set, the room is only active within this window, and users who arrive before
+    /// it opens are let in by lottery. See [`ActivationWindow`].
+    pub activation_window: Option<ActivationWindow>,
+}
+
+/// The ways a room can decide how many users to let in. See [`crate::admission`].
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AdmissionMode {
+    /// Let users in until there are `target_user_count` users on the site.
+    #[default]
+    Concurrent,
+    /// Let in `users_per_minute` users, no matter how many are on the site. Up to `burst` users
+    /// can be let in at once if the room has been quiet for a while.
+    Rate { users_per_minute: u64, burst: u64 },
+}
+
+/// The proof-of-work challenge users have to solve before they can join. See [`crate::challenge`].
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChallengeSettings {
+    /// The number of leading zero bits the hash of a solution needs to have. Every extra bit doubles the work
+    /// needed to join: a browser takes about a second for 20 bits. This can be at most
+    /// [`MAX_CHALLENGE_DIFFICULTY`].
+    pub difficulty: u8,
+    /// The time in milliseconds in which a challenge needs to be solved.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub expiry_time: Time,
+}
+
+/// A part of the day in which a different `target_user_count` is used. `from` and `until` are times of day
+/// in milliseconds since midnight, in the same clock as the [`crate::time::TimeProvider`] of the room, so
+/// they are in UTC for the [`crate::time::SystemTimeProvider`]. If `until` is before `from`, the range
+/// continues past midnight.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CapacityRange {
+    /// The time of day from which this range is used.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub from: Time,
+    /// The time of day until which this range is used.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub until: Time,
+    /// The number of users allowed on the site during this range.
+    pub target_user_count: usize,
+}
+
+/// The number of milliseconds in a day.
+pub const DAY: Time = 24 * 60 * 60 * 1000;
+
+impl CapacityRange {
+    pub fn contains(&self, now: Time) -> bool {
+        let time_of_day = now % DAY;
+        if self.from <= self.until {
+            self.from <= time_of_day && time_of_day < self.until
+        } else {
+            self.from <= time_of_day || time_of_day < self.until
+        }
+    }
+}
+
+/// The window in which a room is active, for scheduled sales. Between `starts_at` and `open_at`, users join
+/// a pre-queue. Nobody is let out of the pre-queue before the room opens, so it doesn't matter how fast they
+/// clicked: when the room opens, the tickets in the pre-queue are put in a random order, ahead of everyone
+/// who arrives after the opening. Those users are let in first come, first served, as usual.
+/// Outside of the window, the room lets everyone through without queueing.
+///
+/// All times are in milliseconds, in the same clock as the [`crate::time::TimeProvider`] of the room.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActivationWindow {
+    /// The time from which users join the pre-queue instead of being let through.
+    pub starts_at: Time,
+    /// The time at which the lottery is drawn, and users start being let out of the queue.
+    pub open_at: Time,
+    /// The time after which users are let through again. If this is `None`, the room stays active.
+    #[serde(default)]
+    pub ends_at: Option<Time>,
+}
+
+/// The phase a room with an [`ActivationWindow`] is in at some point in time.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum RoomPhase {
+    /// Outside of the window, users are let through without queueing.
+    Inactive,
+    /// Before the room opens, users join the pre-queue.
+    PreQueue,
+    /// The room is open, and works like a normal waiting room.
+    Open,
+}
+
+impl ActivationWindow {
+    pub fn phase(&self, now: Time) -> RoomPhase {
+        if now < self.starts_at || self.ends_at.is_some_and(|ends_at| now >= ends_at) {
+            RoomPhase::Inactive
+        } else if now < self.open_at {
+            RoomPhase::PreQueue
+        } else {
+            RoomPhase::Open
+        }
+    }
 }
 
 impl Default for GeneralWaitingRoomSettings {
     fn default() -> Self {
         Self {
             target_user_count: 20,
+            capacity_schedule: Vec::new(),
+            admission_mode: AdmissionMode::Concurrent,
+            max_queue_length: None,
+            challenge: None,
 
             ticket_refresh_time: 20 * 1000,
             ticket_expiry_time: 45 * 1000,
@@ -43,6 +180,222 @@ impl Default for GeneralWaitingRoomSettings {
 
             eviction_interval: 5000,
             cleanup_interval: 10000,
+
+            activation_window: None,
+        }
+    }
+}
+
+impl GeneralWaitingRoomSettings {
+    /// Checks whether the settings are consistent with each other. The waiting room does not
+    /// work properly with inconsistent settings, so this should be called on any settings
+    /// that did not come from the code itself.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        ensure_non_zero("ticket_refresh_time", self.ticket_refresh_time)?;
+        ensure_non_zero("fault_detection_interval", self.fault_detection_interval)?;
+        ensure_non_zero("eviction_interval", self.eviction_interval)?;
+        ensure_non_zero("cleanup_interval", self.cleanup_interval)?;
+
+        // If a ticket expires before it needs to be refreshed, every user loses their place.
+        ensure_less_than(
+            ("ticket_refresh_time", self.ticket_refresh_time),
+            ("ticket_expiry_time", self.ticket_expiry_time),
+        )?;
+        // A new check is started every period, so the previous one needs to have timed out by then.
+        ensure_less_than(
+            ("fault_detection_timeout", self.fault_detection_timeout),
+            ("fault_detection_period", self.fault_detection_period),
+        )?;
+        // The fault detection function is what starts the checks, so it needs to be called at least once per period.
+        ensure_less_or_equal(
+            ("fault_detection_interval", self.fault_detection_interval),
+            ("fault_detection_period", self.fault_detection_period),
+        )?;
+
+        if let AdmissionMode::Rate {
+            users_per_minute,
+            burst,
+        } = self.admission_mode
+        {
+            ensure_non_zero("admission_mode.users_per_minute", users_per_minute as u128)?;
+            ensure_non_zero("admission_mode.burst", burst as u128)?;
+        }
+
+        if let Some(max_queue_length) = self.max_queue_length {
+            ensure_non_zero("max_queue_length", max_queue_length as u128)?;
+        }
+
+        if let Some(challenge) = self.challenge {
+            ensure_non_zero("challenge.difficulty", challenge.difficulty as u128)?;
+            ensure_less_or_equal(
+                ("challenge.difficulty", challenge.difficulty as u128),
+                ("the maximum difficulty", MAX_CHALLENGE_DIFFICULTY as u128),
+            )?;
+            ensure_non_zero("challenge.expiry_time", challenge.expiry_time)?;
+        }
+
+        for range in &self.capacity_schedule {
+            // Times of day can't be a day or more, since they would never be reached.
+            ensure_less_than(("capacity_schedule.from", range.from), ("one day", DAY))?;
+            ensure_less_than(("capacity_schedule.until", range.until), ("one day", DAY))?;
+            if range.from == range.until {
+                return Err(SettingsError::Invalid(
+                    "capacity_schedule",
+                    format!(
+                        "the range from {} until {} is empty",
+                        range.from, range.until
+                    ),
+                ));
+            }
+        }
+
+        if let Some(window) = self.activation_window {
+            // The lottery gives every ticket in the pre-queue a join time between the start and the opening.
+            ensure_less_than(
+                ("activation_window.starts_at", window.starts_at),
+                ("activation_window.open_at", window.open_at),
+            )?;
+            if let Some(ends_at) = window.ends_at {
+                ensure_less_than(
+                    ("activation_window.open_at", window.open_at),
+                    ("activation_window.ends_at", ends_at),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the target user count at the given time, from the first range in the capacity schedule that
+    /// contains it, or `target_user_count` if there is none.
+    pub fn target_user_count_at(&self, now: Time) -> usize {
+        self.capacity_schedule
+            .iter()
+            .find(|range| range.contains(now))
+            .map_or(self.target_user_count, |range| range.target_user_count)
+    }
+
+    /// Creates the admission policy for these settings.
+    pub fn admission_policy(&self) -> Box<dyn AdmissionPolicy> {
+        match self.admission_mode {
+            AdmissionMode::Concurrent => Box::new(ConcurrentTarget::new(self.target_user_count)),
+            AdmissionMode::Rate {
+                users_per_minute,
+                burst,
+            } => Box::new(TokenBucket::new(users_per_minute, burst)),
         }
     }
+
+    /// Returns true if the admission policy needs to be created again when the settings are
+    /// replaced with `other`. A rate policy keeps its state when the settings don't change it.
+    pub fn admission_changed(&self, other: &GeneralWaitingRoomSettings) -> bool {
+        self.admission_mode != other.admission_mode
+            || (self.admission_mode == AdmissionMode::Concurrent
+                && self.target_user_count != other.target_user_count)
+    }
+}
+
+/// Returns an error if the setting with the given name is zero.
+pub fn ensure_non_zero(name: &'static str, value: u128) -> Result<(), SettingsError> {
+    if value == 0 {
+        return Err(SettingsError::Zero(name));
+    }
+    Ok(())
+}
+
+/// Returns an error if the first setting is not less than the second setting.
+/// Both are given as (name, value) pairs.
+pub fn ensure_less_than(
+    lower: (&'static str, u128),
+    higher: (&'static str, u128),
+) -> Result<(), SettingsError> {
+    if lower.1 >= higher.1 {
+        return Err(SettingsError::NotLessThan { lower, higher });
+    }
+    Ok(())
+}
+
+/// Returns an error if the first setting is greater than the second setting.
+/// Both are given as (name, value) pairs.
+pub fn ensure_less_or_equal(
+    lower: (&'static str, u128),
+    higher: (&'static str, u128),
+) -> Result<(), SettingsError> {
+    if lower.1 > higher.1 {
+        return Err(SettingsError::GreaterThan { lower, higher });
+    }
+    Ok(())
+}
+
+/// Parses a human readable duration into milliseconds. This accepts a plain number of
+/// milliseconds ("1500"), or one or more numbers with a unit ("20s", "1m30s", "250ms").
+/// The supported units are `ms`, `s`, `m`, `h` and `d`.
+pub fn parse_duration(input: &str) -> Result<Time, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(millis) = input.parse::<Time>() {
+        return Ok(millis);
+    }
+
+    let mut total: Time = 0;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let number_length = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| format!("missing unit in duration \"{}\"", input))?;
+        if number_length == 0 {
+            return Err(format!("expected a number in duration \"{}\"", input));
+        }
+        let (number, after_number) = rest.split_at(number_length);
+        let unit_length = after_number
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(after_number.len());
+        let (unit, after_unit) = after_number.split_at(unit_length);
+
+        let multiplier = match unit.trim() {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            "d" => 24 * 60 * 60 * 1000,
+            unit => {
+                return Err(format!(
+                    "unknown unit \"{}\" in duration \"{}\"",
+                    unit, input
+                ))
+            }
+        };
+        // The number only contains digits, so this can only fail if it is too large.
+        let number = number
+            .parse::<Time>()
+            .map_err(|_| format!("duration \"{}\" is too large", input))?;
+        total = number
+            .checked_mul(multiplier)
+            .and_then(|part| total.checked_add(part))
+            .ok_or_else(|| format!("duration \"{}\" is too large", input))?;
+
+        rest = after_unit.trim_start();
+    }
+
+    Ok(total)
+}
+
+/// Deserializes a duration in milliseconds from either a number or a string accepted by [`parse_duration`].
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Time, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Duration {
+        Millis(u64),
+        Human(String),
+    }
+
+    match Duration::deserialize(deserializer)? {
+        Duration::Millis(millis) => Ok(millis as Time),
+        Duration::Human(human) => parse_duration(&human).map_err(serde::de::Error::custom),
+    }
 }
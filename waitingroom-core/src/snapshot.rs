@@ -0,0 +1,147 @@
+//! Snapshots of the state of a waiting room, so users keep their place when the process restarts.
+//!
+//! A snapshot only contains the state local to one node: the queue, the queue leaving list, the on site list
+//! and the revocations. The state shared between the nodes of a distributed room is rebuilt when the node
+//! joins the network again.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::WireError,
+    pass::Pass,
+    ticket::{Ticket, TicketIdentifier},
+    time::Time,
+    NodeId, RoomId,
+};
+
+/// The version of the snapshot format. This is the first two bytes of every encoded snapshot.
+/// It must be increased whenever [`RoomSnapshot`] changes.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// The state of a waiting room at the time it was taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSnapshot {
+    pub room_id: RoomId,
+    pub node_id: NodeId,
+    /// The time the snapshot was taken, from the [`crate::time::TimeProvider`] of the room.
+    pub taken_at: Time,
+    /// The tickets in the queue, front first.
+    pub queue: Vec<Ticket>,
+    /// The tickets of users who joined before the room opened. See [`crate::settings::ActivationWindow`].
+    pub pre_queue: Vec<Ticket>,
+    pub queue_leaving_list: Vec<Ticket>,
+    pub on_site_list: Vec<Pass>,
+    /// Revoked ticket and pass identifiers, with the time until which they need to be kept.
+    pub revoked_tickets: Vec<(TicketIdentifier, Time)>,
+    pub revoked_passes: Vec<(TicketIdentifier, Time)>,
+}
+
+impl RoomSnapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SNAPSHOT_VERSION.to_be_bytes().to_vec();
+        // Serialising to a Vec can't fail.
+        bytes.extend(bincode::serialize(self).unwrap());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        if bytes.len() < 2 {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (version, snapshot) = bytes.split_at(2);
+        let version = u16::from_be_bytes([version[0], version[1]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(WireError::UnsupportedVersion {
+                version,
+                supported: (SNAPSHOT_VERSION, SNAPSHOT_VERSION),
+            });
+        }
+        bincode::deserialize(snapshot).map_err(|err| WireError::Invalid(err.to_string()))
+    }
+
+    /// Moves all deadlines by the time between when the snapshot was taken and `now`, so tickets and passes
+    /// don't expire because the room was down. Join and eviction times are kept, since they decide the order
+    /// of the queue, which has to stay the same relative to users who joined at other nodes.
+    pub fn adjust_times(&mut self, now: Time) {
+        let shift = |time: &mut Time| {
+            *time = if now >= self.taken_at {
+                time.saturating_add(now - self.taken_at)
+            } else {
+                time.saturating_sub(self.taken_at - now)
+            }
+        };
+
+        for ticket in self
+            .queue
+            .iter_mut()
+            .chain(self.pre_queue.iter_mut())
+            .chain(self.queue_leaving_list.iter_mut())
+        {
+            shift(&mut ticket.next_refresh_time);
+            shift(&mut ticket.expiry_time);
+        }
+        for pass in self.on_site_list.iter_mut() {
+            shift(&mut pass.expiry_time);
+        }
+        for (_, until) in self
+            .revoked_tickets
+            .iter_mut()
+            .chain(self.revoked_passes.iter_mut())
+        {
+            shift(until);
+        }
+        self.taken_at = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> RoomSnapshot {
+        let ticket = Ticket::new_with_time_and_identifier(1, 1000, 0, 5000, 15000);
+        RoomSnapshot {
+            room_id: 0,
+            node_id: 0,
+            taken_at: 2000,
+            queue: vec![ticket],
+            pre_queue: vec![],
+            queue_leaving_list: vec![],
+            on_site_list: vec![],
+            revoked_tickets: vec![(2, 16000)],
+            revoked_passes: vec![],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = snapshot().to_bytes();
+        let decoded = RoomSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.queue[0].identifier, 1);
+        assert_eq!(decoded.revoked_tickets, vec![(2, 16000)]);
+
+        let mut newer = bytes.clone();
+        newer[1] += 1;
+        assert!(matches!(
+            RoomSnapshot::from_bytes(&newer),
+            Err(WireError::UnsupportedVersion { version: 2, .. })
+        ));
+        assert!(RoomSnapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn adjust_times() {
+        let mut later = snapshot();
+        later.adjust_times(12000);
+        let ticket = later.queue[0];
+        assert_eq!(ticket.join_time, 1000);
+        assert_eq!(ticket.next_refresh_time, 16000);
+        assert_eq!(ticket.expiry_time, 26000);
+        assert_eq!(later.revoked_tickets, vec![(2, 26000)]);
+
+        // The clock could also have been reset, like when the time provider starts counting from 0.
+        let mut earlier = snapshot();
+        earlier.adjust_times(0);
+        assert_eq!(earlier.queue[0].expiry_time, 14000);
+    }
+}
@@ -0,0 +1,54 @@
+//! Read-only views of the state of a waiting room, for dashboards, health checks and debugging.
+//!
+//! Taking a status doesn't change the room. The fields are a copy of the state at the time it was taken,
+//! so they are not updated afterwards.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{time::Time, NodeId, RoomId};
+
+/// The state of the queue and the site, as seen by one node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomStatus {
+    pub room_id: RoomId,
+    /// The number of users waiting in the queue of the node.
+    pub queue_length: usize,
+    /// The number of users that were let out of the queue, but did not leave it yet.
+    pub leaving_list_length: usize,
+    /// The number of users on the site that left the queue at the node.
+    pub on_site_count: usize,
+    /// The number of users the room wants on the site right now. See [`crate::settings::CapacityRange`].
+    pub target_user_count: usize,
+    /// The last time the room ran an eviction, or `None` if it didn't run one yet.
+    pub last_eviction_time: Option<Time>,
+}
+
+/// The state of a node in a distributed waiting room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub node_id: NodeId,
+    pub room: RoomStatus,
+    /// The parent of the node in the QPID tree. The root is its own parent. This is `None` until the node
+    /// has joined the network.
+    pub qpid_parent: Option<NodeId>,
+    /// True if the node is the QPID root, which decides how many users are let out of the queue.
+    pub is_root: bool,
+    /// The neighbours of the node in the spanning tree, without the node itself.
+    pub neighbours: Vec<NodeId>,
+    /// All nodes in the network, including this one.
+    pub members: Vec<NodeId>,
+    /// The iteration of the spanning tree. It is increased every time the tree is rebuilt.
+    pub tree_iteration: usize,
+    pub fault_detection: FaultDetectionStatus,
+}
+
+/// The state of the fault detection of a node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaultDetectionStatus {
+    /// The time the node last checked another node, or 0 if it didn't check one yet.
+    pub last_check_time: Time,
+    /// The node that is being checked, if it did not respond yet.
+    pub checking_node: Option<NodeId>,
+    /// The nodes that will be checked next, the next one last.
+    pub queue: Vec<NodeId>,
+}
@@ -0,0 +1,149 @@
+//! Estimates when users are let out of the queue, from the number of users that were let out recently.
+
+use std::collections::VecDeque;
+
+use crate::time::Time;
+
+/// The default time in milliseconds over which the admission throughput is measured.
+pub const DEFAULT_THROUGHPUT_WINDOW: Time = 5 * 60 * 1000;
+
+/// When a user is expected to be let out of the queue. All times are in the clock of the
+/// [`crate::time::TimeProvider`] of the room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionEstimate {
+    /// The expected time, at the throughput observed recently.
+    pub expected_at: Time,
+    /// The start of the confidence range.
+    pub earliest: Time,
+    /// The end of the confidence range.
+    pub latest: Time,
+}
+
+/// Keeps track of the number of users let out of the queue over a sliding window, to estimate when
+/// the users who are still in the queue will be let out.
+#[derive(Debug, Clone)]
+pub struct ThroughputEstimator {
+    window: Time,
+    /// The times users were let out, with the number of users, oldest first.
+    admissions: VecDeque<(Time, usize)>,
+    /// The time of the first observation. Until a full window has passed, the throughput is measured since then.
+    observed_since: Option<Time>,
+}
+
+impl ThroughputEstimator {
+    pub fn new(window: Time) -> Self {
+        Self {
+            window,
+            admissions: VecDeque::new(),
+            observed_since: None,
+        }
+    }
+
+    /// Records that `count` users were let out of the queue. The rooms also call this with a count of 0
+    /// at every eviction, so time in which nobody was let out counts towards the throughput.
+    pub fn record(&mut self, now: Time, count: usize) {
+        self.observed_since.get_or_insert(now);
+        if count > 0 {
+            self.admissions.push_back((now, count));
+        }
+        while self
+            .admissions
+            .front()
+            .is_some_and(|(time, _)| time + self.window <= now)
+        {
+            self.admissions.pop_front();
+        }
+    }
+
+    /// Estimates when the user at `position` is let out, where position 1 is the front of the queue, and 0
+    /// means the user has been let out already. Returns `None` if too few users were let out recently.
+    ///
+    /// Admissions are counted as a Poisson process, so if `n` users were let out in the window, the
+    /// confidence range is one standard deviation (the square root of `n`) on either side of the throughput.
+    pub fn estimate(&self, position: usize, now: Time) -> Option<AdmissionEstimate> {
+        if position == 0 {
+            return Some(AdmissionEstimate {
+                expected_at: now,
+                earliest: now,
+                latest: now,
+            });
+        }
+
+        // The throughput is measured after the start of the window, since users let out right at the start
+        // were let out for the time before it.
+        let start = self.observed_since?.max(now.saturating_sub(self.window));
+        let elapsed = now - start;
+        let admitted: usize = self
+            .admissions
+            .iter()
+            .filter(|(time, _)| *time > start)
+            .map(|(_, count)| count)
+            .sum();
+        // With fewer admissions, the lower end of the range would be no throughput at all.
+        if elapsed == 0 || admitted < 2 {
+            return None;
+        }
+
+        let admitted = admitted as f64;
+        let deviation = admitted.sqrt();
+        let wait_at = |admitted: f64| now + (position as f64 * elapsed as f64 / admitted) as Time;
+        Some(AdmissionEstimate {
+            expected_at: wait_at(admitted),
+            earliest: wait_at(admitted + deviation),
+            latest: wait_at(admitted - deviation),
+        })
+    }
+
+    /// Returns how long a user who was turned away because the queue is full should wait before trying again:
+    /// the time until `excess` users are let out at the recent throughput, or `fallback` without an estimate.
+    pub fn retry_after(&self, excess: usize, now: Time, fallback: Time) -> Time {
+        self.estimate(excess, now)
+            .map_or(fallback, |estimate| estimate.expected_at - now)
+            .max(1)
+    }
+}
+
+impl Default for ThroughputEstimator {
+    fn default() -> Self {
+        Self::new(DEFAULT_THROUGHPUT_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_estimate_without_admissions() {
+        let mut estimator = ThroughputEstimator::new(60000);
+        assert_eq!(estimator.estimate(3, 0), None);
+        estimator.record(0, 0);
+        estimator.record(10000, 1);
+        assert_eq!(estimator.estimate(3, 10000), None);
+        // Users who were let out already don't need to wait.
+        assert_eq!(estimator.estimate(0, 10000).unwrap().latest, 10000);
+    }
+
+    #[test]
+    fn estimate_from_throughput() {
+        let mut estimator = ThroughputEstimator::new(60000);
+        estimator.record(0, 0);
+        for second in 1..=10 {
+            estimator.record(second * 1000, 1);
+        }
+        // One user per second, so the user at position 10 is let out after 10 more seconds.
+        let estimate = estimator.estimate(10, 10000).unwrap();
+        assert_eq!(estimate.expected_at, 20000);
+        assert!(estimate.earliest < estimate.expected_at);
+        assert!(estimate.latest > estimate.expected_at);
+
+        // Nobody is let out for a while, so the throughput goes down.
+        estimator.record(20000, 0);
+        let later = estimator.estimate(10, 20000).unwrap();
+        assert_eq!(later.expected_at, 40000);
+
+        // The old admissions leave the window.
+        estimator.record(80000, 0);
+        assert_eq!(estimator.estimate(10, 80000), None);
+    }
+}
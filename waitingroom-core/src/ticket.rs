@@ -3,14 +3,22 @@ use serde::{Deserialize, Serialize};
 use crate::{
     random::RandomProvider,
     time::{Time, TimeProvider},
-    NodeId,
+    NodeId, RoomId, DEFAULT_ROOM_ID,
 };
 
 pub type TicketIdentifier = u64;
 
+/// The priority class of a ticket. Tickets with a higher class are let out of the queue before
+/// tickets with a lower class, and tickets in the same class are let out in the order they joined.
+pub type PriorityClass = u8;
+
+/// The priority class of normal users. This is the lowest class, so no one can be put behind them.
+pub const DEFAULT_PRIORITY_CLASS: PriorityClass = 0;
+
 /// Tickets are what users use to show that they are in the queue, and what position they
 /// have in the queue. When used by the waiting room, they are fully trusted. Therefore,
 /// if they are editable by the user, they should be signed to prevent tampering.
+/// See [`crate::token::TokenCodec`] for this.
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct Ticket {
     /// The type of the ticket. This is either normal, skip or drain.
@@ -26,6 +34,9 @@ pub struct Ticket {
     /// tickets, which, since they are not real users, will not actually let anyone out of the
     /// queue. This will cause the number of people on the site to decrease.
     pub ticket_type: TicketType,
+    /// The priority class of the ticket. See [`PriorityClass`]. Drain tickets have the highest
+    /// possible class, so they are always let out first.
+    pub priority_class: PriorityClass,
     /// The ticket identifier is a random number used to uniquely identify a ticket.
     /// This same identifier is set on the pass the user gets when they are let out of the queue.
     pub identifier: TicketIdentifier,
@@ -40,6 +51,8 @@ pub struct Ticket {
     pub expiry_time: Time,
     /// The node ID where the ticket was last refreshed.
     pub node_id: NodeId,
+    /// The ID of the room the ticket was given out by. It can't be used in any other room.
+    pub room_id: RoomId,
     /// The previous position estimate of the user. If the current position estimate is
     /// greater than this, the user is still shown their previous position estimate to
     /// prevent them from seeing their position go up, as this would be very discouraging.
@@ -53,8 +66,10 @@ pub struct Ticket {
 impl Ticket {
     pub fn new<T, R>(
         node_id: NodeId,
+        room_id: RoomId,
         ticket_refresh_time: Time,
         ticket_expiry_time: Time,
+        priority_class: PriorityClass,
         time_provider: &T,
         random_provider: &R,
     ) -> Self
@@ -71,8 +86,10 @@ impl Ticket {
             next_refresh_time: now_time + ticket_refresh_time,
             expiry_time: now_time + ticket_expiry_time,
             node_id,
+            room_id,
             previous_position_estimate: usize::MAX,
             ticket_type: TicketType::Normal,
+            priority_class,
             eviction_time: None,
         }
     }
@@ -93,8 +110,10 @@ impl Ticket {
             next_refresh_time: join_time + ticket_refresh_time,
             expiry_time: join_time + ticket_expiry_time,
             node_id,
+            room_id: DEFAULT_ROOM_ID,
             previous_position_estimate: usize::MAX,
             ticket_type: TicketType::Normal,
+            priority_class: DEFAULT_PRIORITY_CLASS,
             eviction_time: None,
         }
     }
@@ -114,8 +133,11 @@ impl Ticket {
             next_refresh_time: Time::MIN,
             expiry_time: Time::MAX,
             node_id,
+            // Drain tickets never leave the queue, so the room doesn't matter.
+            room_id: DEFAULT_ROOM_ID,
             previous_position_estimate: usize::MAX,
             ticket_type: TicketType::Drain,
+            priority_class: PriorityClass::MAX,
             eviction_time: None,
         }
     }
@@ -139,10 +161,12 @@ impl Ticket {
             identifier: self.identifier,
             join_time: self.join_time,
             node_id,
+            room_id: self.room_id,
             next_refresh_time: now_time + ticket_refresh_time,
             expiry_time: now_time + ticket_expiry_time,
             previous_position_estimate: position_estimate,
             ticket_type: self.ticket_type,
+            priority_class: self.priority_class,
             eviction_time: self.eviction_time,
         }
     }
@@ -178,8 +202,12 @@ impl PartialOrd for Ticket {
 }
 
 impl Ord for Ticket {
+    /// Tickets that should leave the queue first are smaller, so the higher priority class comes first.
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.join_time.cmp(&other.join_time)
+        other
+            .priority_class
+            .cmp(&self.priority_class)
+            .then_with(|| self.join_time.cmp(&other.join_time))
     }
 }
 
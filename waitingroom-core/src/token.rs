@@ -0,0 +1,243 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha256;
+
+use crate::{pass::Pass, ticket::Ticket, WaitingRoomError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The type for key identifiers. Every token carries the ID of the key it was signed with,
+/// so keys can be rotated without invalidating the tokens that are currently in use.
+pub type KeyId = u32;
+
+/// The version of the token format. This is the first byte of every token.
+const TOKEN_VERSION: u8 = 1;
+/// The length of the MAC at the end of every token, in bytes.
+const MAC_LENGTH: usize = 32;
+/// The length of the header (version, kind and key ID) at the start of every token, in bytes.
+const HEADER_LENGTH: usize = 1 + 1 + std::mem::size_of::<KeyId>();
+
+/// What a token contains. This is part of the signed header, so a ticket token can never be
+/// decoded as a pass, or the other way around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Ticket = 1,
+    Pass = 2,
+}
+
+/// The token codec turns tickets and passes into compact, MAC-protected tokens, and back.
+/// Tickets and passes are fully trusted by the waiting room, so whenever they are handed to
+/// a client, they should be encoded using this codec.
+///
+/// A token is the URL-safe base64 encoding of `version | kind | key ID | payload | MAC`,
+/// where the MAC is an HMAC-SHA256 over everything before it.
+///
+/// New tokens are always signed with the signing key. Any other known key is only used to verify
+/// tokens, which allows keys to be rotated by first adding the new key and only removing the
+/// old key once all tokens signed with it have expired.
+#[derive(Clone)]
+pub struct TokenCodec {
+    keys: Vec<(KeyId, Vec<u8>)>,
+    signing_key_id: KeyId,
+}
+
+impl TokenCodec {
+    /// Creates a new codec, which signs new tokens with the given key.
+    pub fn new(key_id: KeyId, secret: &[u8]) -> Self {
+        Self {
+            keys: vec![(key_id, secret.to_vec())],
+            signing_key_id: key_id,
+        }
+    }
+
+    /// Adds a key that is only used to verify tokens. If a key with this ID is already
+    /// known, its secret is replaced.
+    pub fn add_verification_key(&mut self, key_id: KeyId, secret: &[u8]) {
+        self.keys.retain(|(id, _)| *id != key_id);
+        self.keys.push((key_id, secret.to_vec()));
+    }
+
+    /// Adds a new key and starts signing new tokens with it. The previous signing key is kept,
+    /// so tokens signed with it are still accepted.
+    pub fn rotate(&mut self, key_id: KeyId, secret: &[u8]) {
+        self.add_verification_key(key_id, secret);
+        self.signing_key_id = key_id;
+    }
+
+    /// Removes a key, after which tokens signed with it are rejected.
+    /// The signing key cannot be removed, so this returns false if that is attempted.
+    pub fn remove_key(&mut self, key_id: KeyId) -> bool {
+        if key_id == self.signing_key_id {
+            return false;
+        }
+        self.keys.retain(|(id, _)| *id != key_id);
+        true
+    }
+
+    /// Returns the ID of the key that new tokens are signed with.
+    pub fn signing_key_id(&self) -> KeyId {
+        self.signing_key_id
+    }
+
+    pub fn encode_ticket(&self, ticket: &Ticket) -> String {
+        self.encode(TokenKind::Ticket, ticket)
+    }
+
+    pub fn decode_ticket(&self, token: &str) -> Result<Ticket, WaitingRoomError> {
+        self.decode(TokenKind::Ticket, token)
+    }
+
+    pub fn encode_pass(&self, pass: &Pass) -> String {
+        self.encode(TokenKind::Pass, pass)
+    }
+
+    pub fn decode_pass(&self, token: &str) -> Result<Pass, WaitingRoomError> {
+        self.decode(TokenKind::Pass, token)
+    }
+
+    fn mac(&self, key_id: KeyId) -> Option<HmacSha256> {
+        self.keys
+            .iter()
+            .find(|(id, _)| *id == key_id)
+            // HMAC accepts keys of any length, so this can never fail.
+            .map(|(_, secret)| HmacSha256::new_from_slice(secret).unwrap())
+    }
+
+    fn encode<V: Serialize>(&self, kind: TokenKind, value: &V) -> String {
+        let mut bytes = vec![TOKEN_VERSION, kind as u8];
+        bytes.extend_from_slice(&self.signing_key_id.to_be_bytes());
+        // Tickets and passes only contain plain data, so serialising them can't fail.
+        bytes.extend(bincode::serialize(value).unwrap());
+
+        let mut mac = self.mac(self.signing_key_id).unwrap();
+        mac.update(&bytes);
+        bytes.extend(mac.finalize().into_bytes());
+
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn decode<V: DeserializeOwned>(
+        &self,
+        kind: TokenKind,
+        token: &str,
+    ) -> Result<V, WaitingRoomError> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| WaitingRoomError::MalformedToken)?;
+
+        if bytes.len() < HEADER_LENGTH + MAC_LENGTH || bytes[0] != TOKEN_VERSION {
+            return Err(WaitingRoomError::MalformedToken);
+        }
+
+        let (signed, tag) = bytes.split_at(bytes.len() - MAC_LENGTH);
+        let key_id = KeyId::from_be_bytes(signed[2..HEADER_LENGTH].try_into().unwrap());
+
+        // An unknown key is treated the same as a bad signature, since we can't tell the two apart.
+        let mut mac = self
+            .mac(key_id)
+            .ok_or(WaitingRoomError::InvalidTokenSignature)?;
+        mac.update(signed);
+        mac.verify_slice(tag)
+            .map_err(|_| WaitingRoomError::InvalidTokenSignature)?;
+
+        // Only check what the token contains after the signature is verified, so nothing about
+        // unsigned tokens is revealed.
+        if signed[1] != kind as u8 {
+            return Err(WaitingRoomError::MalformedToken);
+        }
+
+        bincode::deserialize(&signed[HEADER_LENGTH..]).map_err(|_| WaitingRoomError::MalformedToken)
+    }
+}
+
+impl std::fmt::Debug for TokenCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The secrets should never end up in the logs.
+        f.debug_struct("TokenCodec")
+            .field(
+                "key_ids",
+                &self.keys.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            )
+            .field("signing_key_id", &self.signing_key_id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticket() -> Ticket {
+        Ticket::new_with_time_and_identifier(77429, 1000, 3, 2000, 5000)
+    }
+
+    #[test]
+    fn ticket_round_trip() {
+        let codec = TokenCodec::new(1, b"secret");
+        let token = codec.encode_ticket(&ticket());
+        let decoded = codec.decode_ticket(&token).unwrap();
+
+        assert_eq!(decoded, ticket());
+        assert_eq!(decoded.join_time, 1000);
+        assert_eq!(decoded.node_id, 3);
+        assert_eq!(decoded.expiry_time, 6000);
+    }
+
+    #[test]
+    fn tampered_token() {
+        let codec = TokenCodec::new(1, b"secret");
+        let mut bytes = URL_SAFE_NO_PAD
+            .decode(codec.encode_ticket(&ticket()))
+            .unwrap();
+        // Flip a bit in the payload.
+        bytes[HEADER_LENGTH + 1] ^= 1;
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+
+        assert!(matches!(
+            codec.decode_ticket(&token),
+            Err(WaitingRoomError::InvalidTokenSignature)
+        ));
+        assert!(matches!(
+            codec.decode_ticket("not a token"),
+            Err(WaitingRoomError::MalformedToken)
+        ));
+    }
+
+    #[test]
+    fn wrong_kind() {
+        let codec = TokenCodec::new(1, b"secret");
+        let token = codec.encode_ticket(&ticket());
+
+        assert!(matches!(
+            codec.decode_pass(&token),
+            Err(WaitingRoomError::MalformedToken)
+        ));
+    }
+
+    #[test]
+    fn key_rotation() {
+        let mut codec = TokenCodec::new(1, b"old secret");
+        let old_token = codec.encode_ticket(&ticket());
+
+        codec.rotate(2, b"new secret");
+        let new_token = codec.encode_ticket(&ticket());
+        assert!(codec.decode_ticket(&old_token).is_ok());
+        assert!(codec.decode_ticket(&new_token).is_ok());
+
+        assert!(!codec.remove_key(2));
+        assert!(codec.remove_key(1));
+        assert!(matches!(
+            codec.decode_ticket(&old_token),
+            Err(WaitingRoomError::InvalidTokenSignature)
+        ));
+        assert!(codec.decode_ticket(&new_token).is_ok());
+
+        // A codec with a different secret for the same key ID must not accept the token.
+        let other = TokenCodec::new(2, b"other secret");
+        assert!(matches!(
+            other.decode_ticket(&new_token),
+            Err(WaitingRoomError::InvalidTokenSignature)
+        ));
+    }
+}
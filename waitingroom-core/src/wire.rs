@@ -0,0 +1,503 @@
+//! A stable binary encoding for everything that is sent between nodes.
+//!
+//! All integers are big endian. Node IDs and other `usize` values are always encoded as 8 bytes,
+//! times as 16 bytes, and lists are prefixed with their length as 4 bytes.
+//! The encoding of a type must never change without changing the protocol version of the messages that contain it.
+
+use crate::{
+    admission::AdmissionState,
+    error::WireError,
+    settings::{
+        ActivationWindow, AdmissionMode, CapacityRange, ChallengeSettings,
+        GeneralWaitingRoomSettings,
+    },
+    time::Time,
+    NodeId,
+};
+
+/// The protocol versions that added optional fields to the settings. When a message is encoded at an older
+/// version, these fields are left out, and decoded as `None`.
+const MAX_QUEUE_LENGTH_SETTINGS_VERSION: u16 = 8;
+const CHALLENGE_SETTINGS_VERSION: u16 = 9;
+
+/// Messages that are sent over a real network, like [`crate::network::TcpNetwork`], need to be turned into bytes and back.
+pub trait WireMessage: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Result<Self, WireError>;
+}
+
+/// Messages whose encoding has a protocol version, so nodes running different builds can understand each other.
+/// [`WireMessage::to_bytes`] encodes at [`Self::VERSION`], and [`WireMessage::from_bytes`] decodes every version
+/// from [`Self::MIN_SUPPORTED_VERSION`] up. See [`crate::network::VersionedNetwork`] for how the version is picked.
+pub trait VersionedWireMessage: WireMessage {
+    /// The version this build encodes messages at.
+    const VERSION: u16;
+    /// The oldest version this build can still decode and encode.
+    const MIN_SUPPORTED_VERSION: u16;
+
+    /// Encodes the message at an older version, leaving out everything that was added after it. Message types
+    /// that didn't exist yet are encoded anyway, and are dropped by the node that receives them.
+    fn to_bytes_at(&self, version: u16) -> Vec<u8>;
+}
+
+/// Types that can be written as part of a message.
+pub trait WireEncode {
+    fn encode(&self, writer: &mut WireWriter);
+}
+
+/// Types that can be read as part of a message.
+pub trait WireDecode: Sized {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError>;
+}
+
+#[derive(Debug, Default)]
+pub struct WireWriter {
+    bytes: Vec<u8>,
+    /// The protocol version the message is encoded at, or `None` for the newest one.
+    version: Option<u16>,
+}
+
+impl WireWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a writer for a message that is encoded at an older protocol version. See [`VersionedWireMessage`].
+    pub fn with_version(version: u16) -> Self {
+        Self {
+            bytes: Vec::new(),
+            version: Some(version),
+        }
+    }
+
+    /// Returns true if fields that were added in the given protocol version are written.
+    pub fn at_least(&self, version: u16) -> bool {
+        self.version.unwrap_or(u16::MAX) >= version
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn put_usize(&mut self, value: usize) {
+        self.put_u64(value as u64);
+    }
+
+    pub fn put_node_id(&mut self, value: NodeId) {
+        self.put_usize(value);
+    }
+
+    pub fn put_time(&mut self, value: Time) {
+        self.put_u128(value);
+    }
+
+    pub fn put_list<T>(&mut self, values: &[T], mut put_value: impl FnMut(&mut Self, &T)) {
+        self.put_u32(values.len() as u32);
+        for value in values {
+            put_value(self, value);
+        }
+    }
+
+    /// Writes a flag for whether the value is there, followed by the value itself if it is.
+    pub fn put_option<T>(&mut self, value: &Option<T>, put_value: impl FnOnce(&mut Self, &T)) {
+        match value {
+            Some(value) => {
+                self.put_u8(1);
+                put_value(self, value);
+            }
+            None => self.put_u8(0),
+        }
+    }
+
+    pub fn put<T: WireEncode>(&mut self, value: &T) {
+        value.encode(self);
+    }
+}
+
+#[derive(Debug)]
+pub struct WireReader<'a> {
+    bytes: &'a [u8],
+    /// The protocol version the message was encoded at, or `None` for the newest one.
+    version: Option<u16>,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            version: None,
+        }
+    }
+
+    /// Sets the protocol version the rest of the message was encoded at, usually after reading it from the header.
+    pub fn set_version(&mut self, version: u16) {
+        self.version = Some(version);
+    }
+
+    /// Returns true if fields that were added in the given protocol version are there to be read.
+    /// When they are not, the decoder should fill them in with their default.
+    pub fn at_least(&self, version: u16) -> bool {
+        self.version.unwrap_or(u16::MAX) >= version
+    }
+
+    /// Returns an error if there are bytes left, since that means the message was not what we expected.
+    pub fn finish(self) -> Result<(), WireError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(WireError::TrailingBytes(self.bytes.len()))
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        if self.bytes.len() < N {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(taken.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, WireError> {
+        Ok(u8::from_be_bytes(self.take()?))
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, WireError> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, WireError> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, WireError> {
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    pub fn read_u128(&mut self) -> Result<u128, WireError> {
+        Ok(u128::from_be_bytes(self.take()?))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, WireError> {
+        let value = self.read_u64()?;
+        usize::try_from(value)
+            .map_err(|_| WireError::Invalid(format!("{} does not fit in a usize", value)))
+    }
+
+    pub fn read_node_id(&mut self) -> Result<NodeId, WireError> {
+        self.read_usize()
+    }
+
+    pub fn read_time(&mut self) -> Result<Time, WireError> {
+        self.read_u128()
+    }
+
+    pub fn read_list<T>(
+        &mut self,
+        mut read_value: impl FnMut(&mut Self) -> Result<T, WireError>,
+    ) -> Result<Vec<T>, WireError> {
+        let length = self.read_u32()?;
+        // We don't preallocate, since the length could be anything if the message is corrupt.
+        let mut values = Vec::new();
+        for _ in 0..length {
+            values.push(read_value(self)?);
+        }
+        Ok(values)
+    }
+
+    pub fn read_option<T>(
+        &mut self,
+        read_value: impl FnOnce(&mut Self) -> Result<T, WireError>,
+    ) -> Result<Option<T>, WireError> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(read_value(self)?)),
+            flag => Err(WireError::Invalid(format!("invalid option flag {}", flag))),
+        }
+    }
+
+    pub fn read<T: WireDecode>(&mut self) -> Result<T, WireError> {
+        T::decode(self)
+    }
+}
+
+impl WireEncode for GeneralWaitingRoomSettings {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put_usize(self.target_user_count);
+        writer.put_list(&self.capacity_schedule, |writer, range| writer.put(range));
+        writer.put(&self.admission_mode);
+        if writer.at_least(MAX_QUEUE_LENGTH_SETTINGS_VERSION) {
+            writer.put_option(&self.max_queue_length, |writer, length| {
+                writer.put_usize(*length)
+            });
+        }
+        if writer.at_least(CHALLENGE_SETTINGS_VERSION) {
+            writer.put_option(&self.challenge, |writer, challenge| writer.put(challenge));
+        }
+        writer.put_time(self.ticket_refresh_time);
+        writer.put_time(self.ticket_expiry_time);
+        writer.put_time(self.pass_expiry_time);
+        writer.put_time(self.fault_detection_period);
+        writer.put_time(self.fault_detection_timeout);
+        writer.put_time(self.fault_detection_interval);
+        writer.put_time(self.eviction_interval);
+        writer.put_time(self.cleanup_interval);
+        writer.put_option(&self.activation_window, |writer, window| writer.put(window));
+    }
+}
+
+impl WireDecode for GeneralWaitingRoomSettings {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        Ok(Self {
+            target_user_count: reader.read_usize()?,
+            capacity_schedule: reader.read_list(|reader| reader.read())?,
+            admission_mode: reader.read()?,
+            max_queue_length: if reader.at_least(MAX_QUEUE_LENGTH_SETTINGS_VERSION) {
+                reader.read_option(|reader| reader.read_usize())?
+            } else {
+                None
+            },
+            challenge: if reader.at_least(CHALLENGE_SETTINGS_VERSION) {
+                reader.read_option(|reader| reader.read())?
+            } else {
+                None
+            },
+            ticket_refresh_time: reader.read_time()?,
+            ticket_expiry_time: reader.read_time()?,
+            pass_expiry_time: reader.read_time()?,
+            fault_detection_period: reader.read_time()?,
+            fault_detection_timeout: reader.read_time()?,
+            fault_detection_interval: reader.read_time()?,
+            eviction_interval: reader.read_time()?,
+            cleanup_interval: reader.read_time()?,
+            activation_window: reader.read_option(|reader| reader.read())?,
+        })
+    }
+}
+
+/// The mode is written as a single byte, followed by the fields of that mode.
+impl WireEncode for AdmissionMode {
+    fn encode(&self, writer: &mut WireWriter) {
+        match self {
+            AdmissionMode::Concurrent => writer.put_u8(0),
+            AdmissionMode::Rate {
+                users_per_minute,
+                burst,
+            } => {
+                writer.put_u8(1);
+                writer.put_u64(*users_per_minute);
+                writer.put_u64(*burst);
+            }
+        }
+    }
+}
+
+impl WireDecode for AdmissionMode {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        match reader.read_u8()? {
+            0 => Ok(AdmissionMode::Concurrent),
+            1 => Ok(AdmissionMode::Rate {
+                users_per_minute: reader.read_u64()?,
+                burst: reader.read_u64()?,
+            }),
+            mode => Err(WireError::Invalid(format!(
+                "unknown admission mode {}",
+                mode
+            ))),
+        }
+    }
+}
+
+impl WireEncode for ChallengeSettings {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put_u8(self.difficulty);
+        writer.put_time(self.expiry_time);
+    }
+}
+
+impl WireDecode for ChallengeSettings {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        Ok(Self {
+            difficulty: reader.read_u8()?,
+            expiry_time: reader.read_time()?,
+        })
+    }
+}
+
+impl WireEncode for AdmissionState {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put_u128(self.tokens);
+        writer.put_time(self.updated_at);
+    }
+}
+
+impl WireDecode for AdmissionState {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        Ok(Self {
+            tokens: reader.read_u128()?,
+            updated_at: reader.read_time()?,
+        })
+    }
+}
+
+impl WireEncode for ActivationWindow {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put_time(self.starts_at);
+        writer.put_time(self.open_at);
+        writer.put_option(&self.ends_at, |writer, ends_at| writer.put_time(*ends_at));
+    }
+}
+
+impl WireDecode for ActivationWindow {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        Ok(Self {
+            starts_at: reader.read_time()?,
+            open_at: reader.read_time()?,
+            ends_at: reader.read_option(|reader| reader.read_time())?,
+        })
+    }
+}
+
+impl WireEncode for CapacityRange {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put_time(self.from);
+        writer.put_time(self.until);
+        writer.put_usize(self.target_user_count);
+    }
+}
+
+impl WireDecode for CapacityRange {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        Ok(Self {
+            from: reader.read_time()?,
+            until: reader.read_time()?,
+            target_user_count: reader.read_usize()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitives_round_trip() {
+        let mut writer = WireWriter::new();
+        writer.put_u8(7);
+        writer.put_u16(0x0102);
+        writer.put_node_id(42);
+        writer.put_time(Time::MAX);
+        writer.put_list(&[1usize, 2, 3], |writer, value| writer.put_usize(*value));
+        let bytes = writer.into_bytes();
+        assert_eq!(&bytes[..3], &[7, 1, 2]);
+
+        let mut reader = WireReader::new(&bytes);
+        assert_eq!(reader.read_u8().unwrap(), 7);
+        assert_eq!(reader.read_u16().unwrap(), 0x0102);
+        assert_eq!(reader.read_node_id().unwrap(), 42);
+        assert_eq!(reader.read_time().unwrap(), Time::MAX);
+        assert_eq!(
+            reader.read_list(|reader| reader.read_usize()).unwrap(),
+            vec![1, 2, 3]
+        );
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = GeneralWaitingRoomSettings {
+            target_user_count: 3,
+            capacity_schedule: vec![CapacityRange {
+                from: 22 * 60 * 60 * 1000,
+                until: 6 * 60 * 60 * 1000,
+                target_user_count: 1,
+            }],
+            admission_mode: AdmissionMode::Rate {
+                users_per_minute: 120,
+                burst: 10,
+            },
+            max_queue_length: Some(500),
+            challenge: Some(ChallengeSettings {
+                difficulty: 18,
+                expiry_time: 60_000,
+            }),
+            eviction_interval: 1234,
+            activation_window: Some(ActivationWindow {
+                starts_at: 10,
+                open_at: 20,
+                ends_at: None,
+            }),
+            ..Default::default()
+        };
+        let mut writer = WireWriter::new();
+        writer.put(&settings);
+        let bytes = writer.into_bytes();
+
+        let mut reader = WireReader::new(&bytes);
+        assert_eq!(
+            reader.read::<GeneralWaitingRoomSettings>().unwrap(),
+            settings
+        );
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn settings_at_older_versions() {
+        let settings = GeneralWaitingRoomSettings {
+            target_user_count: 3,
+            max_queue_length: Some(500),
+            challenge: Some(ChallengeSettings {
+                difficulty: 18,
+                expiry_time: 60_000,
+            }),
+            ..Default::default()
+        };
+        let read_at = |version: u16| {
+            let mut writer = WireWriter::with_version(version);
+            writer.put(&settings);
+            let bytes = writer.into_bytes();
+            let mut reader = WireReader::new(&bytes);
+            reader.set_version(version);
+            let decoded = reader.read::<GeneralWaitingRoomSettings>().unwrap();
+            reader.finish().unwrap();
+            decoded
+        };
+
+        // Settings from before a field was added are decoded without it.
+        assert_eq!(
+            read_at(CHALLENGE_SETTINGS_VERSION - 1),
+            GeneralWaitingRoomSettings {
+                challenge: None,
+                ..settings.clone()
+            }
+        );
+        assert_eq!(
+            read_at(MAX_QUEUE_LENGTH_SETTINGS_VERSION - 1),
+            GeneralWaitingRoomSettings {
+                max_queue_length: None,
+                challenge: None,
+                ..settings.clone()
+            }
+        );
+    }
+}
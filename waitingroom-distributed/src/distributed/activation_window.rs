@@ -0,0 +1,77 @@
+use waitingroom_core::{
+    journal::JournalEntry, network::Network, observer::WaitingRoomEventKind,
+    random::RandomProvider, settings::RoomPhase, ticket::Ticket, time::TimeProvider,
+    WaitingRoomError,
+};
+use waitingroom_metrics::Gauge;
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Returns the phase of the activation window the room is in. Rooms without a window are always open.
+    /// See [`waitingroom_core::settings::ActivationWindow`].
+    pub(super) fn phase(&self) -> RoomPhase {
+        match self.settings.activation_window {
+            Some(window) => window.phase(self.time_provider.get_now_time()),
+            None => RoomPhase::Open,
+        }
+    }
+
+    /// Adds a ticket to the room, depending on the phase it is in.
+    /// Before the room opens, the ticket goes into the pre-queue, and outside of the window the user is let through.
+    pub(super) fn admit(&mut self, ticket: Ticket) -> Result<Ticket, WaitingRoomError> {
+        self.open_if_due()?;
+        match self.phase() {
+            RoomPhase::Inactive => Ok(self.let_through(ticket)),
+            RoomPhase::PreQueue => {
+                self.pre_queue.push(ticket);
+                self.journal(JournalEntry::PreQueue(ticket));
+                Ok(ticket)
+            }
+            RoomPhase::Open => {
+                self.enqueue(ticket)?;
+                Ok(ticket)
+            }
+        }
+    }
+
+    /// Draws the lottery for the users in the pre-queue once the room has opened, and puts them in the queue.
+    /// Every node draws the lottery for its own pre-queue. Since the join times are spread evenly over the
+    /// pre-queue window on every node, the order over all nodes is random as well.
+    pub(super) fn open_if_due(&mut self) -> Result<(), WaitingRoomError> {
+        if self.pre_queue.is_empty() || self.phase() == RoomPhase::PreQueue {
+            return Ok(());
+        }
+        log::info!(
+            "[NODE {}] drawing lottery for {} tickets",
+            self.node_id,
+            self.pre_queue.len()
+        );
+        let tickets = match self.settings.activation_window {
+            Some(window) => self.pre_queue.draw(&window, &self.random_provider),
+            // The window was removed from the settings before the room opened, so there is no lottery.
+            None => self.pre_queue.take_all(),
+        };
+        for ticket in tickets {
+            self.enqueue(ticket)?;
+        }
+        Ok(())
+    }
+
+    /// Lets a user through without queueing, for when the room is outside of its activation window.
+    fn let_through(&mut self, mut ticket: Ticket) -> Ticket {
+        ticket.set_eviction_time(self.time_provider.get_now_time());
+        self.local_queue_leaving_list.push(ticket);
+        self.journal(JournalEntry::LetOut(ticket));
+        self.notify(WaitingRoomEventKind::LetOut {
+            ticket: ticket.identifier,
+        });
+        self.metrics.increment_gauge(Gauge::ToLetIn, 1);
+        ticket
+    }
+}
@@ -0,0 +1,82 @@
+use waitingroom_core::{
+    admission::{AdmissionPolicy, AdmissionState},
+    network::{Network, NetworkHandle},
+    random::RandomProvider,
+    time::TimeProvider,
+    WaitingRoomError,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Replaces the admission policy created from the settings with a custom one, on this node only.
+    /// It should be set on every node, since the policy of the QPID root is used.
+    /// It is replaced again if the admission settings are updated.
+    pub fn set_admission_policy(&mut self, admission_policy: Box<dyn AdmissionPolicy>) {
+        self.admission_policy = admission_policy;
+    }
+
+    /// Returns [`WaitingRoomError::QueueFull`] if the queue of all nodes together has reached its maximum length.
+    /// The local pre-queue is counted as well, since those users are put in the queue when the room opens.
+    pub(super) fn ensure_queue_not_full(&self) -> Result<(), WaitingRoomError> {
+        let Some(max_queue_length) = self.settings.max_queue_length else {
+            return Ok(());
+        };
+        let queue_length = self.global_queue_length() + self.pre_queue.len();
+        if queue_length < max_queue_length {
+            return Ok(());
+        }
+        // The throughput is only measured at this node, so only this node's share of the excess is waited for.
+        let excess = queue_length + 1 - max_queue_length;
+        let local_excess = (excess * self.local_queue.len())
+            .div_ceil(queue_length)
+            .max(1);
+        Err(WaitingRoomError::QueueFull {
+            retry_after: self.throughput.retry_after(
+                local_excess,
+                self.time_provider.get_now_time(),
+                self.settings.eviction_interval,
+            ),
+        })
+    }
+
+    /// Sends the state of the admission policy to every other node. The QPID root moves to whichever node
+    /// has the first user in the queue, so without this, a rate policy would start over on every new root.
+    pub(super) fn broadcast_admission_state(&mut self) -> Result<(), WaitingRoomError> {
+        let Some(state) = self.admission_policy.state() else {
+            return Ok(());
+        };
+        for member in &self.network_members {
+            if *member != self.node_id {
+                self.network_handle
+                    .send_message(*member, NodeToNodeMessage::AdmissionStateUpdate(state))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn admission_state_message(
+        &mut self,
+        state: AdmissionState,
+    ) -> Result<(), WaitingRoomError> {
+        log::debug!(
+            "[NODE {}] received admission state updated at {}",
+            self.node_id,
+            state.updated_at
+        );
+        // Messages can arrive out of order, so only newer states are used.
+        if self
+            .admission_policy
+            .state()
+            .is_none_or(|current| current.updated_at < state.updated_at)
+        {
+            self.admission_policy.set_state(state);
+        }
+        Ok(())
+    }
+}
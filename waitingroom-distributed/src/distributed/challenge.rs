@@ -0,0 +1,60 @@
+use waitingroom_core::{
+    challenge::{Challenge, ChallengeSolution},
+    network::Network,
+    random::RandomProvider,
+    time::{Time, TimeProvider},
+    WaitingRoomError,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Gives out a new challenge, if the settings require one. Challenges are signed instead of kept, so they
+    /// are never sent to other nodes. Any node with the same challenge key can check the solution.
+    pub(super) fn issue_challenge(&mut self) -> Option<Challenge> {
+        let settings = self.settings.challenge?;
+        let challenge = self.challenges.issue(
+            self.random_provider.random_u64(),
+            self.time_provider.get_now_time(),
+            settings.expiry_time,
+            settings.difficulty,
+        );
+        log::debug!(
+            "[NODE {}] issued challenge {} with difficulty {}",
+            self.node_id,
+            challenge.nonce,
+            challenge.difficulty
+        );
+        Some(challenge)
+    }
+
+    /// Checks the solution to a challenge given out by any node with the same challenge key, without using it up.
+    /// If the settings don't require a challenge, any solution is accepted.
+    pub(super) fn check_challenge(
+        &self,
+        solution: ChallengeSolution,
+        now_time: Time,
+    ) -> Result<(), WaitingRoomError> {
+        if self.settings.challenge.is_none() {
+            return Ok(());
+        }
+        self.challenges.check(solution, now_time)
+    }
+
+    /// The same as [`DistributedWaitingRoom::check_challenge`], but the challenge can't be used again at this node.
+    pub(super) fn redeem_challenge(
+        &mut self,
+        solution: ChallengeSolution,
+        now_time: Time,
+    ) -> Result<(), WaitingRoomError> {
+        if self.settings.challenge.is_none() {
+            return Ok(());
+        }
+        self.challenges.redeem(solution, now_time)
+    }
+}
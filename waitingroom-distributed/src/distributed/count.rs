@@ -5,7 +5,7 @@ use waitingroom_core::{
     NodeId, WaitingRoomError,
 };
 
-use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+use crate::{messages::NodeToNodeMessage, weight_table::QueueSummary, DistributedWaitingRoom};
 
 impl<T, R, N> DistributedWaitingRoom<T, R, N>
 where
@@ -46,6 +46,7 @@ where
         self.count_iteration = count_iteration;
         self.count_parent = Some(from_node);
         self.count_responses.clear();
+        self.count_queue_summaries.clear();
 
         // If we have any neighbours, we need to ask them to participate in the count before we can respond.
         if self.qpid_weight_table.neighbour_count() > 1 || self.node_id == from_node {
@@ -58,7 +59,7 @@ where
         } else {
             // If we don't have any neighbours, we can respond immediately.
             if self.node_id == from_node {
-                self.count_response(from_node, count_iteration, 0, 0)?;
+                self.count_response(from_node, count_iteration, 0, 0, vec![])?;
             } else {
                 self.network_handle.send_message(
                     from_node,
@@ -66,6 +67,7 @@ where
                         iteration: count_iteration,
                         queue_count: self.in_queue_count(),
                         on_site_count: self.get_local_on_site_count(),
+                        queue_summaries: self.local_queue_summary().into_iter().collect(),
                     },
                 )?;
             }
@@ -81,6 +83,7 @@ where
         count_iteration: Time,
         queue_count: usize,
         on_site_count: usize,
+        queue_summaries: Vec<QueueSummary>,
     ) -> Result<(), WaitingRoomError> {
         log::info!(
             "[NODE {}] count response fr: {} it: {} q: {} s: {}",
@@ -104,6 +107,7 @@ where
 
         self.count_responses
             .push((from_node, queue_count, on_site_count));
+        self.count_queue_summaries.extend(queue_summaries);
 
         if self.count_responses.len()
             == self
@@ -129,6 +133,8 @@ where
 
             let total_queue_count = others_queue_count + self.in_queue_count();
             let total_on_site_count = others_on_site_count + self.get_local_on_site_count();
+            let mut queue_summaries = std::mem::take(&mut self.count_queue_summaries);
+            queue_summaries.extend(self.local_queue_summary());
 
             if Some(self.node_id) == self.count_parent {
                 // We are the count root, so we need to let users out of the queue.
@@ -138,7 +144,9 @@ where
                     total_queue_count,
                     total_on_site_count,
                 );
-                self.ensure_correct_site_count(total_queue_count, total_on_site_count)?;
+                let let_out =
+                    self.ensure_correct_site_count(total_queue_count, total_on_site_count)?;
+                self.broadcast_queue_summaries(count_iteration, queue_summaries, let_out)?;
             } else {
                 // We are not the count parent node, so we need to send our total count to the parent node.
                 self.network_handle.send_message(
@@ -147,6 +155,7 @@ where
                         iteration: count_iteration,
                         queue_count: total_queue_count,
                         on_site_count: total_on_site_count,
+                        queue_summaries,
                     },
                 )?;
             }
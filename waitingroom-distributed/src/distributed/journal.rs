@@ -0,0 +1,56 @@
+use std::path::Path;
+
+use waitingroom_core::{
+    journal::{Journal, JournalEntry},
+    network::Network,
+    random::RandomProvider,
+    time::TimeProvider,
+    WaitingRoomError,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Starts writing every change to the local state of this node to the journal at `path`. If the journal already
+    /// has changes from before the process restarted, they are restored first, like a snapshot, so this has to be
+    /// called after the node joined the network. See [`DistributedWaitingRoom::restore`].
+    /// The journal is then compacted, so it only has the current state.
+    pub fn open_journal(&mut self, path: impl AsRef<Path>) -> Result<(), WaitingRoomError> {
+        let (journal, replayed) = Journal::open(path)?;
+        if let Some(snapshot) = replayed {
+            self.restore(snapshot)?;
+        }
+        self.journal = Some(journal);
+        self.compact_journal()
+    }
+
+    /// Replaces everything in the journal with a snapshot of the current state of this node.
+    /// This also happens at a cleanup once the journal has grown large enough.
+    pub fn compact_journal(&mut self) -> Result<(), WaitingRoomError> {
+        let snapshot = self.snapshot();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.compact(&snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a change to the journal, if there is one. A failed write is logged, but the change is still made,
+    /// since the node works fine without its journal until it restarts.
+    pub(super) fn journal(&mut self, entry: JournalEntry) {
+        let now_time = self.time_provider.get_now_time();
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(err) = journal.append(now_time, entry) {
+                log::error!(
+                    "[NODE {}] Failed to write to the journal: {}",
+                    self.node_id,
+                    err
+                );
+            }
+        }
+    }
+}
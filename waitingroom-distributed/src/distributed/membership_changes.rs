@@ -1,5 +1,6 @@
 use waitingroom_core::{
     network::{Network, NetworkHandle},
+    observer::WaitingRoomEventKind,
     random::RandomProvider,
     time::{Time, TimeProvider},
     NodeId, WaitingRoomError,
@@ -47,7 +48,7 @@ where
 
     pub fn add_node(&mut self, node_id: NodeId) -> Result<(), WaitingRoomError> {
         log::debug!("[{}] Adding node {}", self.node_id, node_id);
-        self.network_members.push(node_id);
+        self.add_member(node_id);
         let mut updated_tree = self.spanning_tree.clone();
         updated_tree.add_node(node_id);
 
@@ -66,6 +67,13 @@ where
             }
         }
 
+        // The new node started with its own settings, so it needs to be told about any updates made since.
+        if self.settings_version > 0 {
+            self.send_settings(vec![node_id])?;
+        }
+        // It also doesn't know which tickets and passes were revoked before it joined.
+        self.send_revocations_to(node_id)?;
+
         self.apply_new_tree(updated_tree)
     }
 
@@ -82,9 +90,7 @@ where
         );
         // We add the node to the member list *before* we check if we need to apply this update.
         // If we get conflicting messages, we'll need to know that this node is a member.
-        if !self.network_members.contains(&node_id) {
-            self.network_members.push(node_id);
-        }
+        self.add_member(node_id);
 
         // The behaviour now is the same as for restructure_tree_message, so we just call that.
         self.restructure_tree_message(tree, iteration)?;
@@ -100,7 +106,7 @@ where
             return Err(WaitingRoomError::FaultFalsePositive);
         }
 
-        self.network_members.retain(|&x| x != node_id);
+        self.remove_member(node_id);
         let mut updated_tree = self.spanning_tree.clone();
         updated_tree.remove_node(node_id);
 
@@ -135,7 +141,7 @@ where
         );
         // We remove the node from the member list *before* we check if we need to apply this update.
         // If we get conflicting messages, we'll need to know that this node is not a member.
-        self.network_members.retain(|&x| x != node_id);
+        self.remove_member(node_id);
 
         // The behaviour now is the same as for restructure_tree_message, so we just call that.
         self.restructure_tree_message(tree, iteration)?;
@@ -203,9 +209,7 @@ where
 
         // We add all the nodes in the tree to our member list:
         for node in tree.get_node_list() {
-            if !self.network_members.contains(&node) {
-                self.network_members.push(node);
-            }
+            self.add_member(node);
         }
 
         for neighbour in old_neighbours.iter() {
@@ -285,6 +289,22 @@ where
         Ok(())
     }
 
+    /// Adds a node to the member list, if it isn't a member yet.
+    fn add_member(&mut self, node_id: NodeId) {
+        if !self.network_members.contains(&node_id) {
+            self.network_members.push(node_id);
+            self.notify(WaitingRoomEventKind::NodeAdded { member: node_id });
+        }
+    }
+
+    /// Removes a node from the member list, if it is a member.
+    fn remove_member(&mut self, node_id: NodeId) {
+        if self.network_members.contains(&node_id) {
+            self.network_members.retain(|&x| x != node_id);
+            self.notify(WaitingRoomEventKind::NodeRemoved { member: node_id });
+        }
+    }
+
     fn remove_neighbour(&mut self, neighbour: NodeId) {
         log::debug!("[{}] Removing neighbour {}", self.node_id, neighbour);
         // Removing a neighbour is easier than adding one. We just remove its entry from the table.
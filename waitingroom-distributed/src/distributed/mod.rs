@@ -1,27 +1,47 @@
+use std::{collections::BTreeMap, sync::Arc};
+
 use crate::{messages::NodeToNodeMessage, weight_table::Weight};
 use waitingroom_core::{
+    admission::AdmissionPolicy,
+    challenge::{Challenge, ChallengeKey, ChallengeSolution},
+    journal::{Journal, JournalEntry},
     network::{Network, NetworkHandle},
+    observer::{NoopObserver, WaitingRoomEventKind, WaitingRoomObserver},
     pass::Pass,
     random::RandomProvider,
-    settings,
-    ticket::{Ticket, TicketType},
+    settings::{self, RoomPhase},
+    throughput::ThroughputEstimator,
+    ticket::{PriorityClass, Ticket, TicketIdentifier, TicketType},
     time::{Time, TimeProvider},
-    NodeId, WaitingRoomError, WaitingRoomMessageTriggered, WaitingRoomTimerTriggered,
-    WaitingRoomUserTriggered,
+    token::TokenCodec,
+    NodeId, RoomId, WaitingRoomError, WaitingRoomMessageTriggered, WaitingRoomTimerTriggered,
+    WaitingRoomTokenTriggered, WaitingRoomUserTriggered,
 };
-use waitingroom_local_queue::LocalQueue;
+use waitingroom_local_queue::{ChallengeList, LocalQueue, PreQueue, RevocationList};
+use waitingroom_metrics::{Counter, Gauge, Histogram, Metrics, MetricsSink};
 use waitingroom_spanning_trees::SpanningTree;
 
-use crate::weight_table::WeightTable;
+use crate::weight_table::{QueueSummary, WeightTable};
+use revocation::{PendingRevocation, Revocation};
 use settings::GeneralWaitingRoomSettings;
 
 #[cfg(test)]
 mod test;
 
+mod activation_window;
+mod admission;
+mod challenge;
 mod count;
 mod fault_detection;
+mod journal;
 mod membership_changes;
+mod observer;
+mod position;
 mod qpid;
+mod revocation;
+mod settings_update;
+mod snapshot;
+mod status;
 
 // The testing module is only available when the testing feature is enabled.
 #[cfg(feature = "testing")]
@@ -38,15 +58,50 @@ where
 {
     /// The local queue is the queue on this node. It contains all the tickets that are waiting to be let in.
     local_queue: LocalQueue,
+    /// Users who joined this node before the room opened. See [`settings::ActivationWindow`].
+    pre_queue: PreQueue,
     /// The local queue leaving list is a list of tickets that are allowed to leave the queue, but have not yet done so.
     local_queue_leaving_list: Vec<Ticket>,
     /// The local on site list is a list of passes that are currently on site.
     local_on_site_list: Vec<Pass>,
-
-    /// Settings passed in when creating the waiting room.
+    /// Tickets and passes that were revoked on any node. See revocation.rs.
+    revoked_tickets: RevocationList,
+    revoked_passes: RevocationList,
+    /// Revocations that were sent to other nodes, but not acknowledged by all of them yet.
+    pending_revocations: BTreeMap<Revocation, PendingRevocation>,
+    /// Signs the challenges this node gives out, and remembers the ones solved at this node. See challenge.rs.
+    challenges: ChallengeList,
+
+    /// Settings passed in when creating the waiting room, or the latest settings update.
     settings: GeneralWaitingRoomSettings,
+    // Also see settings_update.rs
+    /// The version of the settings in force. It is 0 until the settings are updated at runtime.
+    settings_version: u64,
+    /// The node that made the settings update. When two nodes update the settings with the same version,
+    /// the update from the highest node ID wins, so all nodes end up with the same settings.
+    settings_origin: NodeId,
+    /// The nodes the current settings were sent to, which did not acknowledge them yet. See settings_update.rs.
+    settings_unacknowledged: Vec<NodeId>,
+    /// Decides how many users are let out of the queue. Only used by the QPID root, when a count is done.
+    /// A node that becomes the root starts with a new policy, so a rate policy starts with a full bucket.
+    admission_policy: Box<dyn AdmissionPolicy>,
+    /// Measures how many users are let out of the queue, to estimate when the users in the queue are let out.
+    throughput: ThroughputEstimator,
+    /// The last time [`WaitingRoomTimerTriggered::eviction`] was called on this node. See status.rs.
+    last_eviction_time: Option<Time>,
+    /// Gets told about everything that happens on this node, and can veto joins and leaves. See observer.rs.
+    observer: Box<dyn WaitingRoomObserver>,
+    /// Every change to the local state of this node is written here, so it can be replayed after a crash. See journal.rs.
+    journal: Option<Journal>,
+    /// Where the metrics of this node are recorded. See [`waitingroom_metrics`].
+    metrics: Metrics,
     /// The node ID is a unique identifier for this node.
     node_id: NodeId,
+    /// The ID of the room this node is part of. It needs to be the same on every node of the room.
+    room_id: RoomId,
+    /// The token codec is used to encode and decode tokens given to clients. All nodes need to use the same keys,
+    /// since users can check in at any node.
+    token_codec: Option<TokenCodec>,
 
     /// The network handle is used to send and receive messages to and from other nodes.
     network_handle: N::NetworkHandle,
@@ -75,6 +130,12 @@ where
     count_iteration: Time,
     /// The count responses are used to store the responses from the neighbours in the count tree. They are aggregated sent to the parent when all responses are received.
     count_responses: Vec<(NodeId, usize, usize)>,
+    /// The queue summaries in the count responses of the current count. See position.rs.
+    count_queue_summaries: Vec<QueueSummary>,
+    /// The queue summaries of every node from the latest count, used to estimate global queue positions.
+    queue_summaries: Vec<QueueSummary>,
+    /// The count iteration the queue summaries are from. Older summaries are ignored.
+    queue_summaries_iteration: Time,
     /// The number of failed counts in a row. If this number is too high, the tree is restructured.
     failed_counts: usize,
 
@@ -101,25 +162,47 @@ where
     R: RandomProvider,
     N: Network<NodeToNodeMessage>,
 {
-    fn join(&mut self) -> Result<waitingroom_core::ticket::Ticket, WaitingRoomError> {
-        log::info!("[NODE {}] join", self.node_id);
+    fn room_id(&self) -> RoomId {
+        self.room_id
+    }
 
-        if self.qpid_parent.is_none() {
-            return Err(WaitingRoomError::QPIDNotInitialized);
-        }
-        let ticket = waitingroom_core::ticket::Ticket::new(
+    fn join_with_priority(
+        &mut self,
+        priority_class: PriorityClass,
+    ) -> Result<waitingroom_core::ticket::Ticket, WaitingRoomError> {
+        log::info!(
+            "[NODE {}] join with priority class {}",
             self.node_id,
-            self.settings.ticket_refresh_time,
-            self.settings.ticket_expiry_time,
-            &self.time_provider,
-            &self.random_provider,
+            priority_class
         );
-        log::debug!(
-            "[NODE {}] created ticket {}",
+
+        if self.settings.challenge.is_some() {
+            return Err(WaitingRoomError::ChallengeRequired);
+        }
+        self.issue_ticket(priority_class)
+    }
+
+    fn challenge(&mut self) -> Option<Challenge> {
+        self.issue_challenge()
+    }
+
+    fn join_with_priority_and_solution(
+        &mut self,
+        priority_class: PriorityClass,
+        solution: ChallengeSolution,
+    ) -> Result<Ticket, WaitingRoomError> {
+        log::info!(
+            "[NODE {}] join with priority class {} and a solution to challenge {}",
             self.node_id,
-            ticket.identifier
+            priority_class,
+            solution.challenge.nonce
         );
-        self.enqueue(ticket)?;
+
+        // The solution is only used up once the user has a ticket, so it can be tried again if the queue is full.
+        let now_time = self.time_provider.get_now_time();
+        self.check_challenge(solution, now_time)?;
+        let ticket = self.issue_ticket(priority_class)?;
+        self.redeem_challenge(solution, now_time)?;
         Ok(ticket)
     }
 
@@ -128,23 +211,43 @@ where
         ticket: waitingroom_core::ticket::Ticket,
     ) -> Result<waitingroom_core::CheckInResponse, WaitingRoomError> {
         log::info!("[NODE {}] check in {}", self.node_id, ticket.identifier);
-        if ticket.is_expired(&self.time_provider) {
+        if ticket.room_id != self.room_id {
+            return Err(WaitingRoomError::TicketAtWrongRoom);
+        }
+
+        if self.revoked_tickets.contains(ticket.identifier) {
+            return Err(WaitingRoomError::TicketRevoked);
+        }
+
+        if self
+            .stored_ticket(ticket.identifier)
+            .unwrap_or(&ticket)
+            .is_expired(&self.time_provider)
+        {
             // This happens when a user has not refreshed their ticket in time.
             return Err(WaitingRoomError::TicketExpired);
         }
 
+        if self.is_abandoned(&ticket) {
+            return Err(WaitingRoomError::TicketNotInQueue);
+        }
+
         if ticket.node_id != self.node_id {
             // This happens when the user tries to check in at a different node.
             // This is expected when the previous node went down. The user will need to re-join the queue at the new node.
             // Since, when we get here, the ticket is already confirmed to be valid, we can just add the ticket to the queue.
-            self.enqueue(ticket)?;
+            self.admit(ticket)?;
         }
 
-        // TODO: Make a better estimate of the position. A super simple way would be to multiply by the number of nodes, but that kinda sucks.
-        let position_estimate = match self.local_queue.get_position(ticket.identifier) {
+        self.open_if_due()?;
+
+        let local_position = match self.local_queue.get_position(ticket.identifier) {
             Some(position) => position + 1, // 0 is reserved for users who are allowed to leave the queue right now.
             None => {
-                if self.local_queue_leaving_list.contains(&ticket) {
+                if self.pre_queue.contains(ticket.identifier) {
+                    // The lottery has not been drawn yet, so everyone in the pre-queue could end up last.
+                    self.pre_queue.len()
+                } else if self.local_queue_leaving_list.contains(&ticket) {
                     // The ticket is in the queue leaving list.
                     // This means that the user can now leave the queue.
                     // When this happens, we send the user's position estimate as 0.
@@ -158,9 +261,16 @@ where
             }
         };
 
+        // The users in the queues of the other nodes are counted too, so the estimate is a global position.
+        let position_estimate = if self.local_queue.contains(ticket.identifier) {
+            self.global_position_estimate(&ticket, local_position)
+        } else {
+            local_position
+        };
+
         let position_estimate = if position_estimate > ticket.previous_position_estimate {
             // The ticket has moved backwards in the queue.
-            // This should never happen with a single node, but may happen with multiple nodes.
+            // This happens with multiple nodes, for example when the queue summaries of the other nodes are updated.
             // If it does, we need to send the user's old position estimate to not confuse them.
             ticket.previous_position_estimate
         } else {
@@ -171,6 +281,7 @@ where
         let ticket = self
             .local_queue
             .entry(ticket.identifier)
+            .or_else(|| self.pre_queue.entry(ticket.identifier))
             .or_else(|| {
                 // If it's not in the local queue but we did get here, it's in the queue leaving list.
                 // So, we need to update the ticket in the queue leaving list.
@@ -189,13 +300,22 @@ where
                     &self.time_provider,
                     self.node_id,
                 );
-                ticket
+                *ticket
             })
             .unwrap();
+        self.journal(JournalEntry::TicketRefresh(ticket));
 
+        self.notify(WaitingRoomEventKind::CheckIn {
+            ticket: ticket.identifier,
+            position_estimate,
+        });
         Ok(waitingroom_core::CheckInResponse {
-            new_ticket: *ticket,
+            new_ticket: ticket,
             position_estimate,
+            // The throughput is measured on this node, so it only applies to the users ahead at this node.
+            admission_estimate: self
+                .throughput
+                .estimate(local_position, self.time_provider.get_now_time()),
         })
     }
 
@@ -204,7 +324,19 @@ where
         ticket: waitingroom_core::ticket::Ticket,
     ) -> Result<waitingroom_core::pass::Pass, WaitingRoomError> {
         log::info!("[NODE {}] leave {}", self.node_id, ticket.identifier);
-        if ticket.is_expired(&self.time_provider) {
+        if ticket.room_id != self.room_id {
+            return Err(WaitingRoomError::TicketAtWrongRoom);
+        }
+
+        if self.revoked_tickets.contains(ticket.identifier) {
+            return Err(WaitingRoomError::TicketRevoked);
+        }
+
+        if self
+            .stored_ticket(ticket.identifier)
+            .unwrap_or(&ticket)
+            .is_expired(&self.time_provider)
+        {
             // This happens when a user has not refreshed their ticket in time.
             return Err(WaitingRoomError::TicketExpired);
         }
@@ -220,27 +352,30 @@ where
             Some(ticket) => *ticket,
             None => return Err(WaitingRoomError::TicketCannotLeaveYet),
         };
+        let event = WaitingRoomEventKind::Leave {
+            ticket: ticket.identifier,
+        };
+        self.allow(event)?;
 
         // The user is allowed to leave the queue.
         // We remove the ticket from the queue leaving list.
         self.local_queue_leaving_list.retain(|t| t != &ticket);
         // We know the number of items removed here is always 1.
-        metrics::gauge!(
-            "waitingoroom.to_let_in_count",
-            "node_id" => self.node_id.to_string()
-        )
-        .decrement(1);
+        self.metrics.decrement_gauge(Gauge::ToLetIn, 1);
 
         // Generate a pass for the user.
         let pass = Pass::from_ticket(ticket, self.settings.pass_expiry_time, &self.time_provider);
 
         // And add the pass to the users on site list.
         self.local_on_site_list.push(pass);
-        metrics::gauge!(
-            "waitingroom.on_site_count",
-            "node_id" => self.node_id.to_string()
-        )
-        .increment(1);
+        self.journal(JournalEntry::Leave(pass));
+        self.metrics.increment_gauge(Gauge::OnSite, 1);
+        self.metrics.increment_counter(Counter::Leaves, 1);
+        self.metrics.record_duration(
+            Histogram::WaitTime,
+            self.time_provider.get_now_time() - ticket.join_time,
+        );
+        self.notify(event);
 
         Ok(pass)
     }
@@ -250,9 +385,22 @@ where
         pass: waitingroom_core::pass::Pass,
     ) -> Result<waitingroom_core::pass::Pass, WaitingRoomError> {
         log::info!("[NODE {}] pass refresh {}", self.node_id, pass.identifier);
+        if pass.room_id != self.room_id {
+            return Err(WaitingRoomError::PassAtWrongRoom);
+        }
+
+        if self.revoked_passes.contains(pass.identifier) {
+            return Err(WaitingRoomError::PassRevoked);
+        }
+
         let now_time = self.time_provider.get_now_time();
 
-        if pass.expiry_time < now_time {
+        let expiry_time = self
+            .local_on_site_list
+            .iter()
+            .find(|p| p.identifier == pass.identifier)
+            .map_or(pass.expiry_time, |p| p.expiry_time);
+        if expiry_time < now_time {
             // The user has been inactive for too long, and their pass expired.
             return Err(WaitingRoomError::PassExpired);
         }
@@ -260,11 +408,7 @@ where
         if pass.node_id != self.node_id {
             // The previous node has (probably) gone down, so just to make sure we count this user as being on the site, we add them to the on site list.
             self.local_on_site_list.push(pass);
-            metrics::gauge!(
-                "waitingroom.on_site_count",
-                "node_id" => self.node_id.to_string()
-            )
-            .increment(1);
+            self.metrics.increment_gauge(Gauge::OnSite, 1);
         }
 
         let pass = self
@@ -280,13 +424,63 @@ where
                 pass
             });
 
-        match pass {
-            Some(pass) => Ok(*pass),
+        match pass.copied() {
+            Some(pass) => {
+                self.journal(JournalEntry::PassRefresh(pass));
+                Ok(pass)
+            }
             // If the pass is not on the list, but it was given out at the current node, they shouldn't be on the site.
             // I don't think this should ever be able to happen, but it might if we implement kicking users from the site.
             None => Err(WaitingRoomError::PassNotInList),
         }
     }
+
+    fn abandon(&mut self, ticket: Ticket) -> Result<(), WaitingRoomError> {
+        log::info!("[NODE {}] abandon {}", self.node_id, ticket.identifier);
+        if ticket.room_id != self.room_id {
+            return Err(WaitingRoomError::TicketAtWrongRoom);
+        }
+
+        if ticket.node_id != self.node_id {
+            // The ticket is in the queue of another node. If that node went down, the ticket is gone already.
+            return Err(WaitingRoomError::TicketAtWrongNode);
+        }
+
+        if !self.remove_ticket(ticket.identifier) {
+            return Err(WaitingRoomError::TicketNotInQueue);
+        }
+        self.notify(WaitingRoomEventKind::Abandon {
+            ticket: ticket.identifier,
+        });
+        Ok(())
+    }
+
+    fn release(&mut self, pass: Pass) -> Result<(), WaitingRoomError> {
+        log::info!("[NODE {}] release {}", self.node_id, pass.identifier);
+        if pass.room_id != self.room_id {
+            return Err(WaitingRoomError::PassAtWrongRoom);
+        }
+
+        // If the pass was last refreshed at another node, it is on the list of that node instead.
+        if !self.remove_pass(pass.identifier) {
+            return Err(WaitingRoomError::PassNotInList);
+        }
+        self.notify(WaitingRoomEventKind::Release {
+            pass: pass.identifier,
+        });
+        Ok(())
+    }
+}
+
+impl<T, R, N> WaitingRoomTokenTriggered for DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    fn token_codec(&self) -> Option<&TokenCodec> {
+        self.token_codec.as_ref()
+    }
 }
 
 impl<T, R, N> WaitingRoomTimerTriggered for DistributedWaitingRoom<T, R, N>
@@ -301,39 +495,66 @@ where
 
         // Remove expired tickets from the local queue.
         let removed_count = self.local_queue.remove_expired(now_time);
-        metrics::gauge!(
-            "waitingroom.in_queue_count",
-            "node_id" => self.node_id.to_string()
-        )
-        .decrement(removed_count as f64);
+        let mut expired_ticket_count = removed_count + self.pre_queue.remove_expired(now_time);
+        self.metrics
+            .decrement_gauge(Gauge::InQueue, removed_count as usize);
+
+        self.revoked_tickets.remove_expired(now_time);
+        self.revoked_passes.remove_expired(now_time);
+        self.challenges.remove_expired(now_time);
+        self.resend_revocations(now_time)?;
+        self.resend_settings()?;
 
         // Remove expired passes from the on site list.
-        self.local_on_site_list
-            .retain(|pass| pass.expiry_time > now_time);
-        metrics::gauge!(
-            "waitingroom.on_site_count",
-            "node_id" => self.node_id.to_string()
-        )
-        .set(self.local_on_site_list.len() as f64);
+        let (on_site, expired): (Vec<Pass>, Vec<Pass>) = self
+            .local_on_site_list
+            .drain(..)
+            .partition(|pass| pass.expiry_time > now_time);
+        self.local_on_site_list = on_site;
+        self.metrics
+            .increment_counter(Counter::PassExpiries, expired.len() as u64);
+        for pass in expired {
+            self.notify(WaitingRoomEventKind::PassExpired {
+                pass: pass.identifier,
+            });
+        }
+        self.metrics
+            .set_gauge(Gauge::OnSite, self.local_on_site_list.len());
 
         // We *could* trigger dequeues here, since we know a number of people need to be let out of the queue,
         // but for simplicity we won't. Instead, we'll rely on the ensure_correct_user_count function to do this.
         // TODO(later): This could be added in the future to make the system a bit faster.
 
         // Remove expired tickets from the queue leaving list.
+        let leaving_count = self.local_queue_leaving_list.len();
         self.local_queue_leaving_list
             .retain(|ticket| ticket.expiry_time > now_time);
-        metrics::gauge!(
-            "waitingroom.to_let_in_count",
-            "node_id" => self.node_id.to_string()
-        )
-        .set(self.local_queue_leaving_list.len() as f64);
+        expired_ticket_count += (leaving_count - self.local_queue_leaving_list.len()) as u64;
+        self.metrics
+            .set_gauge(Gauge::ToLetIn, self.local_queue_leaving_list.len());
+        self.metrics
+            .increment_counter(Counter::TicketExpiries, expired_ticket_count);
+
+        self.journal(JournalEntry::Expire(now_time));
+        if self
+            .journal
+            .as_ref()
+            .is_some_and(|journal| journal.needs_compaction())
+        {
+            self.compact_journal()?;
+        }
 
         Ok(())
     }
 
     fn eviction(&mut self) -> Result<(), WaitingRoomError> {
         log::info!("[NODE {}] eviction", self.node_id);
+        self.last_eviction_time = Some(self.time_provider.get_now_time());
+        // Every node draws the lottery for its own pre-queue, so this is done before the root check.
+        self.open_if_due()?;
+        // Every node estimates the wait from the users let out of its own queue. The admission estimate in
+        // check_in is based on the local position for this reason, not on the global position estimate.
+        self.throughput.record(self.time_provider.get_now_time(), 0);
         // Only start a count if we are the QPID root node.
         if self.qpid_parent != Some(self.node_id) {
             log::debug!("[NODE {}] not root node, not starting count", self.node_id);
@@ -430,7 +651,14 @@ where
                     iteration,
                     queue_count,
                     on_site_count,
-                } => self.count_response(message.from_node, iteration, queue_count, on_site_count),
+                    queue_summaries,
+                } => self.count_response(
+                    message.from_node,
+                    iteration,
+                    queue_count,
+                    on_site_count,
+                    queue_summaries,
+                ),
                 NodeToNodeMessage::FaultDetectionRequest(check_id) => {
                     self.fault_detection_request(message.from_node, check_id)
                 }
@@ -447,6 +675,33 @@ where
                     self.restructure_tree_message(spanning_tree, spanning_tree_iteration)
                 }
                 NodeToNodeMessage::NodeJoin(node_id) => self.node_join_message(node_id),
+                NodeToNodeMessage::SettingsUpdate {
+                    settings,
+                    version,
+                    origin,
+                } => self.settings_update_message(message.from_node, *settings, version, origin),
+                NodeToNodeMessage::SettingsUpdateAck { version, origin } => {
+                    self.settings_update_ack_message(message.from_node, version, origin)
+                }
+                NodeToNodeMessage::AdmissionStateUpdate(state) => {
+                    self.admission_state_message(state)
+                }
+                NodeToNodeMessage::KickTicket(ticket_identifier) => {
+                    self.kick_ticket_message(message.from_node, ticket_identifier)
+                }
+                NodeToNodeMessage::RevokePass(pass_identifier) => {
+                    self.revoke_pass_message(message.from_node, pass_identifier)
+                }
+                NodeToNodeMessage::KickTicketAck(ticket_identifier) => {
+                    self.kick_ticket_ack_message(message.from_node, ticket_identifier)
+                }
+                NodeToNodeMessage::RevokePassAck(pass_identifier) => {
+                    self.revoke_pass_ack_message(message.from_node, pass_identifier)
+                }
+                NodeToNodeMessage::QueueSummaries {
+                    iteration,
+                    summaries,
+                } => self.queue_summaries_message(message.from_node, iteration, summaries),
             }?;
             Ok(true)
         } else {
@@ -479,16 +734,32 @@ where
             node_id,
             time_provider,
             random_provider,
+            admission_policy: settings.admission_policy(),
+            throughput: ThroughputEstimator::default(),
+            last_eviction_time: None,
+            observer: Box::new(NoopObserver),
+            journal: None,
             settings,
+            settings_version: 0,
+            settings_origin: node_id,
+            settings_unacknowledged: vec![],
             network_handle,
             qpid_weight_table: WeightTable::new(node_id),
             network_members: vec![node_id],
             spanning_tree: SpanningTree::from_member_list(vec![node_id]), // Since we usually just join an existing network, we start with an empty tree.
             tree_iteration: 0, // Always 0 until we receive the first tree from another node.
             local_queue: LocalQueue::new(),
+            pre_queue: PreQueue::new(),
             local_on_site_list: vec![],
+            revoked_tickets: RevocationList::new(),
+            revoked_passes: RevocationList::new(),
+            pending_revocations: BTreeMap::new(),
+            challenges: ChallengeList::new(ChallengeKey::random()),
             local_queue_leaving_list: vec![],
             count_responses: vec![],
+            count_queue_summaries: vec![],
+            queue_summaries: vec![],
+            queue_summaries_iteration: Time::MIN,
             fd_queue: vec![],
             qpid_update_iterations: vec![],
             count_iteration: Time::MIN,
@@ -499,9 +770,36 @@ where
             should_send_find_root: false,
             qpid_last_update_values: vec![],
             failed_counts: 0,
+            room_id: waitingroom_core::DEFAULT_ROOM_ID,
+            metrics: Metrics::for_room(node_id, waitingroom_core::DEFAULT_ROOM_ID),
+            token_codec: None,
         }
     }
 
+    /// Sets the ID of this room. This should be done before any tickets are given out,
+    /// and it needs to be the same on every node of the room.
+    pub fn set_room_id(&mut self, room_id: RoomId) {
+        self.room_id = room_id;
+        self.metrics.set_room_id(room_id);
+    }
+
+    /// Replaces the sink the metrics of this node are recorded to, which is a [`waitingroom_metrics::RecorderSink`] by default.
+    pub fn set_metrics_sink(&mut self, metrics_sink: Arc<dyn MetricsSink>) {
+        self.metrics.set_sink(metrics_sink);
+    }
+
+    /// Sets the codec used for the token based operations in [`WaitingRoomTokenTriggered`].
+    /// This needs to have the same keys on every node in the network.
+    pub fn set_token_codec(&mut self, token_codec: TokenCodec) {
+        self.token_codec = Some(token_codec);
+    }
+
+    /// Sets the key challenges are signed with. This needs to be the same on every node in the network, so a
+    /// challenge can be solved at any node. By default, every node uses a random key.
+    pub fn set_challenge_key(&mut self, key: ChallengeKey) {
+        self.challenges.set_key(key);
+    }
+
     /// DO NOT CALL - Temporary testing function to overwrite the QPID parent and weight table.
     /// This will be removed once recovery is implemented (since that's basically the same system).
     pub fn testing_overwrite_qpid(
@@ -514,59 +812,168 @@ where
         self.network_members = self.qpid_weight_table.get_all_neighbours();
     }
 
+    /// Gives the user a ticket, after any challenge has been checked.
+    fn issue_ticket(&mut self, priority_class: PriorityClass) -> Result<Ticket, WaitingRoomError> {
+        if self.qpid_parent.is_none() {
+            return Err(WaitingRoomError::QPIDNotInitialized);
+        }
+        let ticket = waitingroom_core::ticket::Ticket::new(
+            self.node_id,
+            self.room_id,
+            self.settings.ticket_refresh_time,
+            self.settings.ticket_expiry_time,
+            priority_class,
+            &self.time_provider,
+            &self.random_provider,
+        );
+        log::debug!(
+            "[NODE {}] created ticket {}",
+            self.node_id,
+            ticket.identifier
+        );
+        let event = WaitingRoomEventKind::Join {
+            ticket: ticket.identifier,
+            priority_class,
+        };
+        if self.phase() != RoomPhase::Inactive {
+            self.ensure_queue_not_full()?;
+        }
+        self.allow(event)?;
+        let ticket = self.admit(ticket)?;
+        self.notify(event);
+        self.metrics.increment_counter(Counter::Joins, 1);
+        Ok(ticket)
+    }
+
     /// Add a ticket to the local queue, incrementing the metric if the ticket type is normal.
     fn enqueue(&mut self, ticket: Ticket) -> Result<(), WaitingRoomError> {
         self.local_queue.enqueue(ticket);
         if ticket.ticket_type == TicketType::Normal {
-            metrics::gauge!(
-                "waitingroom.in_queue_count",
-                "node_id" => self.node_id.to_string()
-            )
-            .increment(1);
+            self.journal(JournalEntry::Enqueue(ticket));
+            self.metrics.increment_gauge(Gauge::InQueue, 1);
         }
-        // We only call QPID insert if the current join time is less than the current QPID weight.
+        // We only call QPID insert if the new weight is less than the current QPID weight.
         // This means that all inserts that are *not* at the front of the queue don't make any QPID messages, which is nice.
-        let new_weight = Weight::new(ticket.join_time, ticket.identifier, self.node_id);
+        let new_weight = Weight::from_ticket(&ticket, self.node_id);
         if new_weight < self.qpid_weight_table.get_weight(self.node_id).unwrap() {
             self.qpid_insert(new_weight)?;
         }
         Ok(())
     }
 
+    /// Returns the copy of a ticket kept at this node, if there is one. Its deadlines are used instead of the ones
+    /// on the ticket of the user, since they are moved when the node is restored from a snapshot.
+    fn stored_ticket(&self, ticket_identifier: TicketIdentifier) -> Option<&Ticket> {
+        self.local_queue
+            .iter()
+            .chain(self.pre_queue.iter())
+            .chain(self.local_queue_leaving_list.iter())
+            .find(|ticket| ticket.identifier == ticket_identifier)
+    }
+
+    /// Returns true if the ticket was abandoned, but is still in the local queue to be skipped. See [`WaitingRoomUserTriggered::abandon`].
+    fn is_abandoned(&mut self, ticket: &Ticket) -> bool {
+        self.local_queue
+            .entry(ticket.identifier)
+            .is_some_and(|t| t.ticket_type == TicketType::Skip)
+    }
+
+    /// Removes a ticket from wherever it is on this node. Returns false if the ticket was not found.
+    fn remove_ticket(&mut self, ticket_identifier: TicketIdentifier) -> bool {
+        if self.local_queue.contains(ticket_identifier) {
+            let ticket = self.local_queue.entry(ticket_identifier).unwrap();
+            if ticket.ticket_type == TicketType::Skip {
+                // The ticket was removed already.
+                return false;
+            }
+            if self.local_queue.peek().map(|t| t.identifier) == Some(ticket_identifier) {
+                // The QPID weight of this node is the weight of this ticket. Instead of removing it, which would
+                // need the weight to be updated everywhere, it is skipped: whoever is behind it is let out in its place.
+                self.local_queue
+                    .entry(ticket_identifier)
+                    .unwrap()
+                    .set_skip();
+            } else {
+                self.local_queue.remove(ticket_identifier);
+            }
+            self.metrics.decrement_gauge(Gauge::InQueue, 1);
+        } else if self.pre_queue.remove(ticket_identifier).is_some() {
+            // The lottery has not been drawn yet, so there is nothing else to do.
+        } else if let Some(index) = self
+            .local_queue_leaving_list
+            .iter()
+            .position(|t| t.identifier == ticket_identifier)
+        {
+            // The user was let out of the queue, but never left it. Their place is taken over at the next eviction.
+            self.local_queue_leaving_list.remove(index);
+            self.metrics.decrement_gauge(Gauge::ToLetIn, 1);
+        } else {
+            return false;
+        }
+        self.journal(JournalEntry::TicketRemoved(ticket_identifier));
+        true
+    }
+
+    /// Removes a pass from the local on site list. Returns false if the pass was not found.
+    fn remove_pass(&mut self, pass_identifier: TicketIdentifier) -> bool {
+        let Some(index) = self
+            .local_on_site_list
+            .iter()
+            .position(|p| p.identifier == pass_identifier)
+        else {
+            return false;
+        };
+        self.local_on_site_list.remove(index);
+        self.metrics.decrement_gauge(Gauge::OnSite, 1);
+        self.journal(JournalEntry::PassRemoved(pass_identifier));
+        true
+    }
+
     /// Remove the element at the front of the local queue, decrementing the metric if the ticket type is normal.
     fn dequeue(&mut self) -> Option<Ticket> {
         let element = self.local_queue.dequeue();
         if element.is_some() && element.as_ref().unwrap().ticket_type == TicketType::Normal {
-            metrics::gauge!(
-                "waitingroom.in_queue_count",
-                "node_id" => self.node_id.to_string()
-            )
-            .decrement(1);
+            self.metrics.decrement_gauge(Gauge::InQueue, 1);
         }
         element
     }
 
     /// This function triggers an amount of QPID dequeue operations. The amount is the waiting room's minimum user count minus the current user count, provided in the parameter.
     /// If there are too many users on the site, this function will add dummy users to the queue, which will be dequeued by the QPID algorithm and thus lower the user count on the site.
+    /// Returns the number of users let out of the queue.
     fn ensure_correct_site_count(
         &mut self,
         queue_count: usize,
         on_site_count: usize,
-    ) -> Result<(), WaitingRoomError> {
+    ) -> Result<usize, WaitingRoomError> {
         log::info!("[NODE {}] let users out of queue", self.node_id);
-        if on_site_count < self.settings.target_user_count {
-            let to_let_out = queue_count.min(self.settings.target_user_count - on_site_count);
+        if self.phase() == RoomPhase::Inactive {
+            // The activation window has ended, so everyone who is still in the queue is let through.
+            for _ in 0..queue_count {
+                self.qpid_delete_min()?;
+            }
+            return Ok(queue_count);
+        }
+
+        // Every node has the same settings, so the schedule is the same no matter which node is the root.
+        let now_time = self.time_provider.get_now_time();
+        self.admission_policy
+            .set_target_user_count(self.settings.target_user_count_at(now_time));
+        let to_let_out =
+            self.admission_policy
+                .users_to_let_in(queue_count, on_site_count, now_time);
+        if to_let_out > 0 {
             log::debug!(
-                "[NODE {}] not enough users on site, need to let {} users out of queue",
+                "[NODE {}] need to let {} users out of queue",
                 self.node_id,
                 to_let_out
             );
-            // There is no need to let out more users than there are in the queue.
             for _ in 0..to_let_out {
                 self.qpid_delete_min()?;
             }
         }
+        self.broadcast_admission_state()?;
 
-        Ok(())
+        Ok(to_let_out)
     }
 }
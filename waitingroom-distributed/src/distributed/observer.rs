@@ -0,0 +1,45 @@
+use waitingroom_core::{
+    network::Network,
+    observer::{WaitingRoomEvent, WaitingRoomEventKind, WaitingRoomObserver},
+    random::RandomProvider,
+    time::TimeProvider,
+    WaitingRoomError,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Replaces the observer of this node. Every node has its own observer, which only sees the events
+    /// that happen on that node.
+    pub fn set_observer(&mut self, observer: Box<dyn WaitingRoomObserver>) {
+        self.observer = observer;
+    }
+
+    /// Asks the observer whether an event may happen, before it does.
+    pub(super) fn allow(&mut self, kind: WaitingRoomEventKind) -> Result<(), WaitingRoomError> {
+        let event = self.event(kind);
+        self.observer
+            .allow(&event)
+            .map_err(WaitingRoomError::Vetoed)
+    }
+
+    /// Tells the observer about an event that happened.
+    pub(super) fn notify(&mut self, kind: WaitingRoomEventKind) {
+        let event = self.event(kind);
+        self.observer.notify(&event);
+    }
+
+    fn event(&self, kind: WaitingRoomEventKind) -> WaitingRoomEvent {
+        WaitingRoomEvent {
+            kind,
+            room_id: self.room_id,
+            node_id: self.node_id,
+            time: self.time_provider.get_now_time(),
+        }
+    }
+}
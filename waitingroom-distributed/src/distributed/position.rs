@@ -0,0 +1,122 @@
+use waitingroom_core::{
+    network::{Network, NetworkHandle},
+    random::RandomProvider,
+    ticket::{Ticket, TicketType},
+    time::{Time, TimeProvider},
+    NodeId, WaitingRoomError,
+};
+
+use crate::{
+    messages::NodeToNodeMessage,
+    weight_table::{QueueSummary, Weight},
+    DistributedWaitingRoom,
+};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Returns the front and length of the local queue, to send with the count. Skip and drain tickets are left
+    /// out, since they are not users. A queue without users has no summary.
+    pub(super) fn local_queue_summary(&self) -> Option<QueueSummary> {
+        let mut users = self
+            .local_queue
+            .iter()
+            .filter(|ticket| ticket.ticket_type == TicketType::Normal);
+        let front = users.next()?;
+        Some(QueueSummary {
+            front: Weight::from_ticket(front, self.node_id),
+            count: 1 + users.count(),
+        })
+    }
+
+    /// Estimates the position of a ticket in the local queue among the tickets in the queues of all nodes.
+    /// The other nodes are estimated from the queue summaries of the latest count, which the count root sends
+    /// to every node. Without them, this is the local position.
+    pub(super) fn global_position_estimate(&self, ticket: &Ticket, local_position: usize) -> usize {
+        let weight = Weight::from_ticket(ticket, self.node_id);
+        let now_time = self.time_provider.get_now_time();
+        let others_ahead: usize = self
+            .queue_summaries
+            .iter()
+            .filter(|summary| summary.front.node_id() != self.node_id)
+            .map(|summary| summary.tickets_ahead(&weight, now_time))
+            .sum();
+        local_position + others_ahead
+    }
+
+    /// Estimates the number of tickets in the queues of all nodes together. The other nodes are counted from the
+    /// queue summaries of the latest count, like in [`DistributedWaitingRoom::global_position_estimate`].
+    pub(super) fn global_queue_length(&self) -> usize {
+        let others: usize = self
+            .queue_summaries
+            .iter()
+            .filter(|summary| summary.front.node_id() != self.node_id)
+            .map(|summary| summary.count)
+            .sum();
+        self.local_queue.len() + others
+    }
+
+    /// Keeps the queue summaries collected by the count, and sends them down the count tree. The summaries were
+    /// made before the root let `let_out` users out of the queue, so those are removed from the fronts first.
+    pub(super) fn broadcast_queue_summaries(
+        &mut self,
+        iteration: Time,
+        mut summaries: Vec<QueueSummary>,
+        let_out: usize,
+    ) -> Result<(), WaitingRoomError> {
+        let now_time = self.time_provider.get_now_time();
+        for _ in 0..let_out {
+            // The users are let out in the order of their weights, so the smallest front goes first.
+            let Some((index, _)) = summaries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, summary)| summary.front)
+            else {
+                break;
+            };
+            summaries[index].remove_front(now_time);
+            if summaries[index].count == 0 {
+                summaries.swap_remove(index);
+            }
+        }
+
+        self.queue_summaries_message(self.node_id, iteration, summaries)
+    }
+
+    /// Keeps the queue summaries, and forwards them to the neighbours other than the one they came from. The count
+    /// went up the same tree, so every node receives them once.
+    pub(super) fn queue_summaries_message(
+        &mut self,
+        from_node: NodeId,
+        iteration: Time,
+        summaries: Vec<QueueSummary>,
+    ) -> Result<(), WaitingRoomError> {
+        log::debug!(
+            "[NODE {}] received {} queue summaries from count {}",
+            self.node_id,
+            summaries.len(),
+            iteration
+        );
+        // Messages can arrive out of order, so only summaries from newer counts are used.
+        if iteration < self.queue_summaries_iteration {
+            return Ok(());
+        }
+        for node_id in self.qpid_weight_table.get_true_neighbours() {
+            if node_id != from_node && node_id != self.node_id {
+                self.network_handle.send_message(
+                    node_id,
+                    NodeToNodeMessage::QueueSummaries {
+                        iteration,
+                        summaries: summaries.clone(),
+                    },
+                )?;
+            }
+        }
+        self.queue_summaries = summaries;
+        self.queue_summaries_iteration = iteration;
+        Ok(())
+    }
+}
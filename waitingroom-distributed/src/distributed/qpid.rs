@@ -1,10 +1,13 @@
 use waitingroom_core::{
+    journal::JournalEntry,
     network::{Network, NetworkHandle},
+    observer::WaitingRoomEventKind,
     random::RandomProvider,
     ticket::TicketType,
     time::{Time, TimeProvider},
     NodeId, WaitingRoomError, WaitingRoomTimerTriggered,
 };
+use waitingroom_metrics::Gauge;
 
 use crate::{messages::NodeToNodeMessage, weight_table::Weight, DistributedWaitingRoom};
 
@@ -167,7 +170,7 @@ where
             Some(next_ticket) => {
                 self.qpid_weight_table.set(
                     self.node_id,
-                    Weight::new(next_ticket.join_time, next_ticket.identifier, self.node_id),
+                    Weight::from_ticket(next_ticket, self.node_id),
                     0,
                 );
             }
@@ -203,13 +206,15 @@ where
 
         match ticket.ticket_type {
             TicketType::Normal => {
-                ticket.set_eviction_time(self.time_provider.get_now_time());
+                let now_time = self.time_provider.get_now_time();
+                ticket.set_eviction_time(now_time);
                 self.local_queue_leaving_list.push(ticket);
-                metrics::gauge!(
-                    "waitingroom.to_let_in_count",
-                    "node_id" => self.node_id.to_string()
-                )
-                .increment(1);
+                self.journal(JournalEntry::LetOut(ticket));
+                self.throughput.record(now_time, 1);
+                self.notify(WaitingRoomEventKind::LetOut {
+                    ticket: ticket.identifier,
+                });
+                self.metrics.increment_gauge(Gauge::ToLetIn, 1);
             }
             TicketType::Drain => {
                 // This ticket is a dummy ticket. We shouldn't do anything with it.
@@ -0,0 +1,239 @@
+use waitingroom_core::{
+    journal::JournalEntry,
+    network::{Network, NetworkHandle},
+    random::RandomProvider,
+    ticket::TicketIdentifier,
+    time::{Time, TimeProvider},
+    NodeId, WaitingRoomError,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+/// A kicked ticket or a revoked pass, which is sent to other nodes until they acknowledge it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Revocation {
+    Ticket(TicketIdentifier),
+    Pass(TicketIdentifier),
+}
+
+impl Revocation {
+    fn message(self) -> NodeToNodeMessage {
+        match self {
+            Revocation::Ticket(identifier) => NodeToNodeMessage::KickTicket(identifier),
+            Revocation::Pass(identifier) => NodeToNodeMessage::RevokePass(identifier),
+        }
+    }
+}
+
+/// The nodes that did not acknowledge a revocation yet. Once the revocation expires, it doesn't matter anymore.
+#[derive(Debug)]
+pub(super) struct PendingRevocation {
+    until: Time,
+    unacknowledged: Vec<NodeId>,
+}
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Removes a user from the queue, and makes sure their ticket can't be used again on any node.
+    /// Any node can be used for this, since the ticket could be at any node. The kick is applied locally,
+    /// then sent to every other member of the network until they acknowledge it, or the ticket would have expired.
+    pub fn kick_ticket(
+        &mut self,
+        ticket_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        log::info!("[NODE {}] kick ticket {}", self.node_id, ticket_identifier);
+        let until = self.apply_ticket_kick(ticket_identifier);
+        let members = self.other_members();
+        self.send_revocation(Revocation::Ticket(ticket_identifier), until, members)
+    }
+
+    /// Removes a user from the site, and makes sure their pass can't be used again on any node.
+    /// Any node can be used for this, since the pass is held by the node it was last refreshed at.
+    /// The revocation is applied locally, then sent to every other member of the network until they acknowledge
+    /// it, or the pass would have expired.
+    pub fn revoke_pass(
+        &mut self,
+        pass_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        log::info!("[NODE {}] revoke pass {}", self.node_id, pass_identifier);
+        let until = self.apply_pass_revocation(pass_identifier);
+        let members = self.other_members();
+        self.send_revocation(Revocation::Pass(pass_identifier), until, members)
+    }
+
+    pub(super) fn kick_ticket_message(
+        &mut self,
+        from_node: NodeId,
+        ticket_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        // The kick is sent again until it is acknowledged, so it may already be applied.
+        if !self.revoked_tickets.contains(ticket_identifier) {
+            self.apply_ticket_kick(ticket_identifier);
+        }
+        self.network_handle.send_message(
+            from_node,
+            NodeToNodeMessage::KickTicketAck(ticket_identifier),
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn revoke_pass_message(
+        &mut self,
+        from_node: NodeId,
+        pass_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        // The revocation is sent again until it is acknowledged, so it may already be applied.
+        if !self.revoked_passes.contains(pass_identifier) {
+            self.apply_pass_revocation(pass_identifier);
+        }
+        self.network_handle
+            .send_message(from_node, NodeToNodeMessage::RevokePassAck(pass_identifier))?;
+        Ok(())
+    }
+
+    pub(super) fn kick_ticket_ack_message(
+        &mut self,
+        from_node: NodeId,
+        ticket_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        self.revocation_acknowledged(from_node, Revocation::Ticket(ticket_identifier));
+        Ok(())
+    }
+
+    pub(super) fn revoke_pass_ack_message(
+        &mut self,
+        from_node: NodeId,
+        pass_identifier: TicketIdentifier,
+    ) -> Result<(), WaitingRoomError> {
+        self.revocation_acknowledged(from_node, Revocation::Pass(pass_identifier));
+        Ok(())
+    }
+
+    /// Sends every revocation that is still active to a node that just joined, which doesn't know about them.
+    pub(super) fn send_revocations_to(&mut self, node_id: NodeId) -> Result<(), WaitingRoomError> {
+        let revocations: Vec<(Revocation, Time)> = self
+            .revoked_tickets
+            .iter()
+            .map(|(identifier, until)| (Revocation::Ticket(identifier), until))
+            .chain(
+                self.revoked_passes
+                    .iter()
+                    .map(|(identifier, until)| (Revocation::Pass(identifier), until)),
+            )
+            .collect();
+        for (revocation, until) in revocations {
+            self.send_revocation(revocation, until, vec![node_id])?;
+        }
+        Ok(())
+    }
+
+    /// Sends the revocations that were not acknowledged yet again. Revocations that expired, and nodes that left
+    /// the network, are forgotten.
+    pub(super) fn resend_revocations(&mut self, now: Time) -> Result<(), WaitingRoomError> {
+        let members = &self.network_members;
+        self.pending_revocations.retain(|_, pending| {
+            pending.unacknowledged.retain(|node| members.contains(node));
+            pending.until > now && !pending.unacknowledged.is_empty()
+        });
+        for (revocation, pending) in &self.pending_revocations {
+            for node in &pending.unacknowledged {
+                log::debug!(
+                    "[NODE {}] resending {:?} to {}",
+                    self.node_id,
+                    revocation,
+                    node
+                );
+                self.network_handle
+                    .send_message(*node, revocation.message())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Revokes the ticket on this node, and returns the time until which it is revoked.
+    fn apply_ticket_kick(&mut self, ticket_identifier: TicketIdentifier) -> Time {
+        // A ticket can't be used after it expired, and the expiry time is at most this far away.
+        let until = self.time_provider.get_now_time() + self.settings.ticket_expiry_time;
+        self.revoked_tickets.revoke(ticket_identifier, until);
+        self.journal(JournalEntry::TicketRevoked {
+            identifier: ticket_identifier,
+            until,
+        });
+        if self.remove_ticket(ticket_identifier) {
+            log::debug!(
+                "[NODE {}] removed kicked ticket {}",
+                self.node_id,
+                ticket_identifier
+            );
+        }
+        until
+    }
+
+    /// Revokes the pass on this node, and returns the time until which it is revoked.
+    fn apply_pass_revocation(&mut self, pass_identifier: TicketIdentifier) -> Time {
+        // A pass can't be used after it expired, and the expiry time is at most this far away.
+        let until = self.time_provider.get_now_time() + self.settings.pass_expiry_time;
+        self.revoked_passes.revoke(pass_identifier, until);
+        self.journal(JournalEntry::PassRevoked {
+            identifier: pass_identifier,
+            until,
+        });
+        if self.remove_pass(pass_identifier) {
+            log::debug!(
+                "[NODE {}] removed revoked pass {}",
+                self.node_id,
+                pass_identifier
+            );
+        }
+        until
+    }
+
+    /// Sends the revocation to the nodes, and keeps track of them until they acknowledge it.
+    fn send_revocation(
+        &mut self,
+        revocation: Revocation,
+        until: Time,
+        nodes: Vec<NodeId>,
+    ) -> Result<(), WaitingRoomError> {
+        for node in &nodes {
+            self.network_handle
+                .send_message(*node, revocation.message())?;
+        }
+        let pending = self
+            .pending_revocations
+            .entry(revocation)
+            .or_insert(PendingRevocation {
+                until,
+                unacknowledged: vec![],
+            });
+        pending.until = pending.until.max(until);
+        for node in nodes {
+            if !pending.unacknowledged.contains(&node) {
+                pending.unacknowledged.push(node);
+            }
+        }
+        Ok(())
+    }
+
+    fn revocation_acknowledged(&mut self, from_node: NodeId, revocation: Revocation) {
+        if let Some(pending) = self.pending_revocations.get_mut(&revocation) {
+            pending.unacknowledged.retain(|&node| node != from_node);
+            if pending.unacknowledged.is_empty() {
+                self.pending_revocations.remove(&revocation);
+            }
+        }
+    }
+
+    /// Returns every member of the network except this node.
+    fn other_members(&self) -> Vec<NodeId> {
+        self.network_members
+            .iter()
+            .copied()
+            .filter(|&member| member != self.node_id)
+            .collect()
+    }
+}
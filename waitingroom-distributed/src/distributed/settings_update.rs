@@ -0,0 +1,173 @@
+use waitingroom_core::{
+    network::{Network, NetworkHandle},
+    random::RandomProvider,
+    settings::GeneralWaitingRoomSettings,
+    time::TimeProvider,
+    NodeId, WaitingRoomError,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Replaces the settings of the running waiting room on all nodes. The new settings are validated first,
+    /// then applied locally and sent to every other member of the network with a new version number, until
+    /// they acknowledge it. A new `target_user_count` is used from the next eviction, and new ticket and pass
+    /// times are used from the next time a ticket or pass is created or refreshed.
+    ///
+    /// If this node missed an update, the new version may not be newer than the settings of the other nodes.
+    /// They send their settings back, which replace these on this node, and a warning is logged.
+    pub fn update_settings(
+        &mut self,
+        settings: GeneralWaitingRoomSettings,
+    ) -> Result<(), WaitingRoomError> {
+        log::info!("[NODE {}] update settings", self.node_id);
+        settings.validate()?;
+
+        self.set_settings(settings);
+        self.settings_version += 1;
+        self.settings_origin = self.node_id;
+        self.settings_unacknowledged.clear();
+
+        let members = self
+            .network_members
+            .iter()
+            .copied()
+            .filter(|&member| member != self.node_id)
+            .collect();
+        self.send_settings(members)
+    }
+
+    pub(super) fn settings_update_message(
+        &mut self,
+        from_node: NodeId,
+        settings: GeneralWaitingRoomSettings,
+        version: u64,
+        origin: NodeId,
+    ) -> Result<(), WaitingRoomError> {
+        log::debug!(
+            "[NODE {}] received settings version {} from {}",
+            self.node_id,
+            version,
+            origin
+        );
+        // The sender keeps sending the settings until they are acknowledged, even if they are not used.
+        self.network_handle.send_message(
+            from_node,
+            NodeToNodeMessage::SettingsUpdateAck { version, origin },
+        )?;
+
+        // Messages can arrive out of order, and two nodes can update the settings at the same time.
+        // Comparing (version, origin) means every node ends up with the same settings, no matter the order.
+        if (version, origin) < (self.settings_version, self.settings_origin) {
+            log::debug!(
+                "[NODE {}] ignoring outdated settings version {} from {}",
+                self.node_id,
+                version,
+                origin
+            );
+            // The sender is behind, so it is sent the newer settings.
+            return self.send_settings(vec![from_node]);
+        }
+        if (version, origin) == (self.settings_version, self.settings_origin) {
+            return Ok(());
+        }
+        // The settings were validated by the node that made the update, but that node could run a build
+        // with different rules, or have a bug. Inconsistent settings are dropped, so they don't spread.
+        if let Err(err) = settings.validate() {
+            log::warn!(
+                "[NODE {}] ignoring invalid settings version {} from {}: {}",
+                self.node_id,
+                version,
+                origin,
+                err
+            );
+            return Ok(());
+        }
+
+        if self.settings_origin == self.node_id && !self.settings_unacknowledged.is_empty() {
+            log::warn!(
+                "[NODE {}] settings version {} from this node were replaced by version {} from {} before every node had them",
+                self.node_id,
+                self.settings_version,
+                version,
+                origin
+            );
+        }
+        self.set_settings(settings);
+        self.settings_version = version;
+        self.settings_origin = origin;
+        // The node that sent these settings keeps sending them to the nodes that don't have them yet.
+        self.settings_unacknowledged.clear();
+        Ok(())
+    }
+
+    pub(super) fn settings_update_ack_message(
+        &mut self,
+        from_node: NodeId,
+        version: u64,
+        origin: NodeId,
+    ) -> Result<(), WaitingRoomError> {
+        if (version, origin) == (self.settings_version, self.settings_origin) {
+            self.settings_unacknowledged
+                .retain(|&node| node != from_node);
+        }
+        Ok(())
+    }
+
+    /// Sends the current settings to the nodes, and keeps track of them until they acknowledge them.
+    pub(super) fn send_settings(&mut self, nodes: Vec<NodeId>) -> Result<(), WaitingRoomError> {
+        for node in nodes {
+            self.network_handle.send_message(
+                node,
+                NodeToNodeMessage::SettingsUpdate {
+                    settings: Box::new(self.settings.clone()),
+                    version: self.settings_version,
+                    origin: self.settings_origin,
+                },
+            )?;
+            if !self.settings_unacknowledged.contains(&node) {
+                self.settings_unacknowledged.push(node);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the settings again to the nodes that did not acknowledge them yet. Nodes that left the network
+    /// are forgotten.
+    pub(super) fn resend_settings(&mut self) -> Result<(), WaitingRoomError> {
+        let members = &self.network_members;
+        self.settings_unacknowledged
+            .retain(|node| members.contains(node));
+        let nodes = self.settings_unacknowledged.clone();
+        if !nodes.is_empty() {
+            log::debug!(
+                "[NODE {}] resending settings version {} to {:?}",
+                self.node_id,
+                self.settings_version,
+                nodes
+            );
+        }
+        self.send_settings(nodes)
+    }
+
+    /// Replaces the settings, and creates a new admission policy if the admission settings changed.
+    fn set_settings(&mut self, settings: GeneralWaitingRoomSettings) {
+        if self.settings.admission_changed(&settings) {
+            self.admission_policy = settings.admission_policy();
+        }
+        self.settings = settings;
+    }
+
+    pub fn get_settings(&self) -> &GeneralWaitingRoomSettings {
+        &self.settings
+    }
+
+    pub fn get_settings_version(&self) -> u64 {
+        self.settings_version
+    }
+}
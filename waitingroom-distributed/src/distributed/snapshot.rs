@@ -0,0 +1,69 @@
+use waitingroom_core::{
+    network::Network, random::RandomProvider, snapshot::RoomSnapshot, ticket::TicketType,
+    time::TimeProvider, WaitingRoomError,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Takes a snapshot of the state of this node, which can be restored with [`DistributedWaitingRoom::restore`]
+    /// after the process restarts. Skip and drain tickets are left out, since they only exist to keep QPID
+    /// consistent, and QPID is rebuilt when the node joins the network again.
+    pub fn snapshot(&self) -> RoomSnapshot {
+        RoomSnapshot {
+            room_id: self.room_id,
+            node_id: self.node_id,
+            taken_at: self.time_provider.get_now_time(),
+            queue: self
+                .local_queue
+                .iter()
+                .filter(|ticket| ticket.ticket_type == TicketType::Normal)
+                .copied()
+                .collect(),
+            pre_queue: self.pre_queue.iter().copied().collect(),
+            queue_leaving_list: self.local_queue_leaving_list.clone(),
+            on_site_list: self.local_on_site_list.clone(),
+            revoked_tickets: self.revoked_tickets.iter().collect(),
+            revoked_passes: self.revoked_passes.iter().collect(),
+        }
+    }
+
+    /// Adds the users in a snapshot of this node back to it. This should be called after the node joined the
+    /// network with [`DistributedWaitingRoom::join_at`], since the restored queue is inserted into QPID.
+    /// The deadlines in the snapshot are moved by the time the node was down. See [`RoomSnapshot::adjust_times`].
+    pub fn restore(&mut self, mut snapshot: RoomSnapshot) -> Result<(), WaitingRoomError> {
+        if snapshot.room_id != self.room_id {
+            return Err(WaitingRoomError::SnapshotAtWrongRoom);
+        }
+        if snapshot.node_id != self.node_id {
+            return Err(WaitingRoomError::SnapshotAtWrongNode);
+        }
+        if self.qpid_weight_table.get_weight(self.node_id).is_none() {
+            return Err(WaitingRoomError::QPIDNotInitialized);
+        }
+        snapshot.adjust_times(self.time_provider.get_now_time());
+
+        for ticket in snapshot.queue {
+            self.enqueue(ticket)?;
+        }
+        for ticket in snapshot.pre_queue {
+            self.pre_queue.push(ticket);
+        }
+        // The metrics for these lists are set at the next cleanup.
+        self.local_queue_leaving_list
+            .extend(snapshot.queue_leaving_list);
+        self.local_on_site_list.extend(snapshot.on_site_list);
+        for (identifier, until) in snapshot.revoked_tickets {
+            self.revoked_tickets.revoke(identifier, until);
+        }
+        for (identifier, until) in snapshot.revoked_passes {
+            self.revoked_passes.revoke(identifier, until);
+        }
+        Ok(())
+    }
+}
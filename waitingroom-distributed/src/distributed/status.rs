@@ -0,0 +1,51 @@
+use waitingroom_core::{
+    network::Network,
+    random::RandomProvider,
+    status::{FaultDetectionStatus, NodeStatus, RoomStatus},
+    ticket::TicketType,
+    time::TimeProvider,
+};
+
+use crate::{messages::NodeToNodeMessage, DistributedWaitingRoom};
+
+impl<T, R, N> DistributedWaitingRoom<T, R, N>
+where
+    T: TimeProvider,
+    R: RandomProvider,
+    N: Network<NodeToNodeMessage>,
+{
+    /// Returns the current state of this node, without changing it. The queue and the site are only the users
+    /// on this node. Skip and drain tickets are left out of the queue length, since they are not users.
+    pub fn status(&self) -> NodeStatus {
+        let mut neighbours = self.qpid_weight_table.get_true_neighbours();
+        neighbours.retain(|&node_id| node_id != self.node_id);
+
+        NodeStatus {
+            node_id: self.node_id,
+            room: RoomStatus {
+                room_id: self.room_id,
+                queue_length: self
+                    .local_queue
+                    .iter()
+                    .filter(|ticket| ticket.ticket_type == TicketType::Normal)
+                    .count(),
+                leaving_list_length: self.local_queue_leaving_list.len(),
+                on_site_count: self.local_on_site_list.len(),
+                target_user_count: self
+                    .settings
+                    .target_user_count_at(self.time_provider.get_now_time()),
+                last_eviction_time: self.last_eviction_time,
+            },
+            qpid_parent: self.qpid_parent,
+            is_root: self.qpid_parent == Some(self.node_id),
+            neighbours,
+            members: self.network_members.clone(),
+            tree_iteration: self.tree_iteration,
+            fault_detection: FaultDetectionStatus {
+                last_check_time: self.fd_last_check_time,
+                checking_node: self.fd_last_check_node,
+                queue: self.fd_queue.clone(),
+            },
+        }
+    }
+}
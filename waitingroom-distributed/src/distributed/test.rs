@@ -1,12 +1,37 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
 use waitingroom_core::{
-    network::{DummyNetwork, Latency},
-    random::{DeterministicRandomProvider, RandomProvider},
-    settings::GeneralWaitingRoomSettings,
-    time::{DummyTimeProvider, Time},
-    NodeId, WaitingRoomMessageTriggered, WaitingRoomTimerTriggered, WaitingRoomUserTriggered,
+    challenge::{Challenge, ChallengeKey, ChallengeSolution},
+    network::{
+        AuthenticatedMessage, AuthenticatedNetwork, ChannelNetwork, DummyNetwork, Latency, Network,
+        NetworkHandle, RoomMessage, RoomNetwork, SharedNetwork, TcpNetwork, TcpNetworkSettings,
+        VersionedMessage, VersionedNetwork,
+    },
+    observer::{WaitingRoomEvent, WaitingRoomEventKind, WaitingRoomObserver},
+    random::{DeterministicRandomProvider, RandomProvider, TrueRandomProvider},
+    registry::WaitingRoomRegistry,
+    settings::{
+        ActivationWindow, AdmissionMode, CapacityRange, ChallengeSettings,
+        GeneralWaitingRoomSettings,
+    },
+    snapshot::RoomSnapshot,
+    ticket::Ticket,
+    time::{DummyTimeProvider, SystemTimeProvider, Time, TimeProvider},
+    token::TokenCodec,
+    NodeId, RoomId, WaitingRoomError, WaitingRoomMessageTriggered, WaitingRoomTimerTriggered,
+    WaitingRoomTokenTriggered, WaitingRoomUserTriggered,
 };
 
 use test_log::test;
+use waitingroom_metrics::{Counter, Gauge, GaugeUpdate, Histogram, Labels, MetricsSink};
+use waitingroom_spanning_trees::SpanningTree;
 
 use crate::{messages::NodeToNodeMessage, weight_table::Weight, DistributedWaitingRoom};
 
@@ -59,6 +84,75 @@ fn basic_test() {
     println!("All tests pass!");
 }
 
+#[test]
+fn token_test() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 6000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let deterministic_random_provider = DeterministicRandomProvider::new(1);
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(0));
+
+    let mut node = DistributedWaitingRoom::new(
+        settings,
+        1,
+        dummy_time_provider.clone(),
+        deterministic_random_provider,
+        dummy_network,
+    );
+
+    node.testing_overwrite_qpid(Some(1), vec![(1, Weight::new(Time::MAX, 0, 0))]);
+
+    // Without a codec, the token operations are not available.
+    assert!(matches!(
+        node.join_with_token(),
+        Err(WaitingRoomError::TokenCodecNotConfigured)
+    ));
+    assert!(matches!(
+        node.join_with_priority_with_token(1),
+        Err(WaitingRoomError::TokenCodecNotConfigured)
+    ));
+    assert!(matches!(
+        node.join_with_solution_with_token(Challenge::new(0, 0, 0, 0).solve()),
+        Err(WaitingRoomError::TokenCodecNotConfigured)
+    ));
+    // No ticket was given out that nobody holds.
+    assert_eq!(node.status().room.queue_length, 0);
+
+    let codec = TokenCodec::new(0, b"cluster secret");
+    node.set_token_codec(codec.clone());
+
+    let ticket_token = node.join_with_token().unwrap();
+    node.qpid_delete_min().unwrap();
+
+    // A user that edits their ticket to get an earlier join time should be rejected.
+    let mut forged_ticket = codec.decode_ticket(&ticket_token).unwrap();
+    forged_ticket.join_time = 0;
+    let forged_token = TokenCodec::new(0, b"guessed secret").encode_ticket(&forged_ticket);
+    assert!(matches!(
+        node.check_in_with_token(&forged_token),
+        Err(WaitingRoomError::InvalidTokenSignature)
+    ));
+
+    let checkin_result = node.check_in_with_token(&ticket_token).unwrap();
+    assert_eq!(checkin_result.position_estimate, 0);
+
+    let pass_token = node
+        .leave_with_token(&checkin_result.new_ticket_token)
+        .unwrap();
+    // A pass token can't be used as a ticket token.
+    assert!(node.check_in_with_token(&pass_token).is_err());
+    let pass_token = node
+        .validate_and_refresh_pass_with_token(&pass_token)
+        .unwrap();
+    assert!(codec.decode_pass(&pass_token).is_ok());
+}
+
 #[test]
 fn simple_distributed_test() {
     let settings = GeneralWaitingRoomSettings {
@@ -83,7 +177,7 @@ fn simple_distributed_test() {
         .collect();
     for node_id in 0..node_count {
         let mut node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             dummy_random_provider.clone(),
@@ -156,7 +250,7 @@ fn check_letting_users_out_of_queue_on_timer() {
         .collect();
     for node_id in 0..node_count {
         let mut node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             random_provider.clone(),
@@ -245,6 +339,88 @@ fn process_messages(nodes: &mut [Node], max_number: usize) -> bool {
     false
 }
 
+/// Creates a node of a room of two nodes, with node 1 as the QPID root. The nodes of a room share the time
+/// provider, the random provider and the network.
+fn two_node_room_node(
+    settings: &GeneralWaitingRoomSettings,
+    node_id: NodeId,
+    time_provider: &DummyTimeProvider,
+    random_provider: &DeterministicRandomProvider,
+    network: &DummyNetwork<NodeToNodeMessage>,
+) -> Node {
+    let mut node = DistributedWaitingRoom::new(
+        settings.clone(),
+        node_id,
+        time_provider.clone(),
+        random_provider.clone(),
+        network.clone(),
+    );
+    node.testing_overwrite_qpid(Some(1), two_node_weight_table());
+    node
+}
+
+/// The QPID weight table every node of a room of two nodes starts with.
+fn two_node_weight_table() -> Vec<(NodeId, Weight)> {
+    (0..2).map(|v| (v, Weight::new(Time::MAX, 0, 0))).collect()
+}
+
+/// Creates a room of two nodes on a network without latency, with node 1 as the QPID root.
+fn two_node_room(
+    settings: &GeneralWaitingRoomSettings,
+) -> (
+    DummyTimeProvider,
+    DummyNetwork<NodeToNodeMessage>,
+    Vec<Node>,
+) {
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(0));
+    let random_provider = DeterministicRandomProvider::new(1);
+    let nodes = (0..2)
+        .map(|node_id| {
+            two_node_room_node(
+                settings,
+                node_id,
+                &dummy_time_provider,
+                &random_provider,
+                &dummy_network,
+            )
+        })
+        .collect();
+    (dummy_time_provider, dummy_network, nodes)
+}
+
+/// Moves the time past the ticket refresh time used by the tests, and runs a cleanup and an eviction on every node.
+fn evict(time_provider: &DummyTimeProvider, nodes: &mut [Node]) {
+    time_provider.increase_by(6001);
+    nodes.iter_mut().for_each(|node| node.cleanup().unwrap());
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(nodes, 10);
+}
+
+/// Evicts, then lets every user who is allowed to leave the queue onto the site. The tickets of the users who are
+/// still queued are refreshed. Returns the number of users who left the queue.
+fn evict_and_leave(
+    time_provider: &DummyTimeProvider,
+    nodes: &mut [Node],
+    tickets: &mut Vec<Ticket>,
+) -> usize {
+    evict(time_provider, nodes);
+
+    let mut left = 0;
+    let mut still_queued = vec![];
+    for ticket in tickets.drain(..) {
+        let response = nodes[ticket.node_id].check_in(ticket).unwrap();
+        if response.position_estimate == 0 {
+            nodes[ticket.node_id].leave(response.new_ticket).unwrap();
+            left += 1;
+        } else {
+            still_queued.push(response.new_ticket);
+        }
+    }
+    *tickets = still_queued;
+    left
+}
+
 #[test]
 fn simple_fault_test() {
     let settings = GeneralWaitingRoomSettings {
@@ -268,7 +444,7 @@ fn simple_fault_test() {
         .collect();
     for node_id in 0..node_count {
         let mut node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             dummy_random_provider.clone(),
@@ -363,7 +539,7 @@ fn multi_node() {
     log::info!("Creating {} waitingroom nodes", node_configs.len());
     for (node_id, (parent, neighbour_config)) in node_configs.iter().enumerate() {
         let mut node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             random_provider.clone(),
@@ -485,7 +661,7 @@ fn mid_eviction_time_root_change() {
         .collect();
     for node_id in 0..node_count {
         let mut node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             dummy_random_provider.clone(),
@@ -548,7 +724,7 @@ fn membership_basic_add_remove() {
     log::info!("Creating {} waitingroom nodes", node_count);
     for node_id in 0..node_count {
         let node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             dummy_random_provider.clone(),
@@ -630,7 +806,7 @@ fn membership_nonempty() {
     log::info!("Creating {} waitingroom nodes", node_count);
     for node_id in 0..node_count {
         let node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             dummy_random_provider.clone(),
@@ -751,7 +927,7 @@ fn membership_regression_joins() {
     log::info!("Creating {} waitingroom nodes", node_count);
     for node_id in 0..node_count {
         let node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             dummy_random_provider.clone(),
@@ -798,3 +974,1755 @@ fn membership_regression_joins() {
 
 #[test]
 fn update_invariant_fail_reg() {}
+
+#[test]
+fn settings_update() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ..Default::default()
+    };
+
+    log::info!("Instantiating dummy time and network");
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_random_provider = DeterministicRandomProvider::new(1);
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(20));
+
+    let mut nodes: Vec<Node> = (0..5)
+        .map(|node_id| {
+            DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                dummy_time_provider.clone(),
+                dummy_random_provider.clone(),
+                dummy_network.clone(),
+            )
+        })
+        .collect();
+
+    // Only the first four nodes join for now, the last one joins after the settings are updated.
+    nodes[0].initialise_alone().unwrap();
+    for i in 1..4 {
+        nodes[i].join_at(0).unwrap();
+        dummy_time_provider.increase_by(20);
+        process_messages(&mut nodes, 10);
+        dummy_time_provider.increase_by(20);
+        process_messages(&mut nodes, 10);
+    }
+
+    // Inconsistent settings are rejected, and nothing is sent to the other nodes.
+    let result = nodes[2].update_settings(GeneralWaitingRoomSettings {
+        ticket_refresh_time: 50 * 1000,
+        ..settings.clone()
+    });
+    assert!(matches!(result, Err(WaitingRoomError::InvalidSettings(_))));
+    assert_eq!(nodes[2].get_settings_version(), 0);
+
+    let updated_settings = GeneralWaitingRoomSettings {
+        target_user_count: 5,
+        ..settings.clone()
+    };
+    nodes[2].update_settings(updated_settings.clone()).unwrap();
+    dummy_time_provider.increase_by(20);
+    process_messages(&mut nodes, 10);
+
+    for node in &nodes[..4] {
+        assert_eq!(*node.get_settings(), updated_settings);
+        assert_eq!(node.get_settings_version(), 1);
+    }
+
+    // Two nodes update the settings at the same time. Both updates get version 2,
+    // and the one from the highest node ID has to win on every node.
+    let settings_from_1 = GeneralWaitingRoomSettings {
+        target_user_count: 10,
+        ..settings.clone()
+    };
+    let settings_from_3 = GeneralWaitingRoomSettings {
+        target_user_count: 30,
+        ..settings.clone()
+    };
+    nodes[3].update_settings(settings_from_3.clone()).unwrap();
+    nodes[1].update_settings(settings_from_1).unwrap();
+    dummy_time_provider.increase_by(20);
+    process_messages(&mut nodes, 10);
+
+    for node in &nodes[..4] {
+        assert_eq!(*node.get_settings(), settings_from_3);
+        assert_eq!(node.get_settings_version(), 2);
+    }
+
+    // Inconsistent settings from another node are ignored as well.
+    nodes[0]
+        .settings_update_message(
+            1,
+            GeneralWaitingRoomSettings {
+                eviction_interval: 0,
+                ..settings.clone()
+            },
+            3,
+            1,
+        )
+        .unwrap();
+    assert_eq!(*nodes[0].get_settings(), settings_from_3);
+    assert_eq!(nodes[0].get_settings_version(), 2);
+
+    // A node that joins later is told about the latest settings.
+    nodes[4].join_at(1).unwrap();
+    dummy_time_provider.increase_by(20);
+    process_messages(&mut nodes, 10);
+    dummy_time_provider.increase_by(20);
+    process_messages(&mut nodes, 10);
+
+    assert_eq!(*nodes[4].get_settings(), settings_from_3);
+    assert_eq!(nodes[4].get_settings_version(), 2);
+}
+
+#[test]
+fn settings_update_is_resent() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_random_provider = DeterministicRandomProvider::new(1);
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(0));
+
+    let mut nodes: Vec<Node> = (0..3)
+        .map(|node_id| {
+            DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                dummy_time_provider.clone(),
+                dummy_random_provider.clone(),
+                dummy_network.clone(),
+            )
+        })
+        .collect();
+    nodes[0].initialise_alone().unwrap();
+    for i in 1..3 {
+        nodes[i].join_at(0).unwrap();
+        process_messages(&mut nodes, 10);
+    }
+    // The update is lost on its way to node 2, so node 0 sends it again at the next cleanup.
+    let settings_v1 = GeneralWaitingRoomSettings {
+        target_user_count: 5,
+        ..settings.clone()
+    };
+    dummy_network.open_partition("node 2", vec![2]);
+    nodes[0].update_settings(settings_v1.clone()).unwrap();
+    process_messages(&mut nodes, 10);
+    assert_eq!(nodes[2].get_settings_version(), 0);
+    dummy_network.heal_partition("node 2");
+    nodes[0].cleanup().unwrap();
+    process_messages(&mut nodes, 10);
+    for node in &nodes {
+        assert_eq!(*node.get_settings(), settings_v1);
+        assert_eq!(node.get_settings_version(), 1);
+    }
+    // Every node acknowledged it, so it isn't sent again.
+    assert!(nodes[0].settings_unacknowledged.is_empty());
+
+    // Node 0 misses an update from node 2, so its own update has the same version, and loses to node 2.
+    // The other nodes send node 0 the newer settings, so every node still agrees.
+    let settings_from_2 = GeneralWaitingRoomSettings {
+        target_user_count: 20,
+        ..settings.clone()
+    };
+    dummy_network.open_partition("node 0", vec![0]);
+    nodes[2].update_settings(settings_from_2.clone()).unwrap();
+    process_messages(&mut nodes, 10);
+    dummy_network.heal_partition("node 0");
+    nodes[0]
+        .update_settings(GeneralWaitingRoomSettings {
+            target_user_count: 10,
+            ..settings.clone()
+        })
+        .unwrap();
+    process_messages(&mut nodes, 10);
+    for node in &nodes {
+        assert_eq!(*node.get_settings(), settings_from_2);
+        assert_eq!(node.get_settings_version(), 2);
+    }
+}
+
+#[test]
+fn partitioned_node_is_removed() {
+    let settings = GeneralWaitingRoomSettings {
+        fault_detection_period: 500,
+        fault_detection_timeout: 199,
+        fault_detection_interval: 100,
+        ..Default::default()
+    };
+
+    log::info!("Instantiating dummy time and network");
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_random_provider = DeterministicRandomProvider::new(1);
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(20));
+
+    let mut nodes: Vec<Node> = (0..3)
+        .map(|node_id| {
+            DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                dummy_time_provider.clone(),
+                dummy_random_provider.clone(),
+                dummy_network.clone(),
+            )
+        })
+        .collect();
+
+    nodes[0].initialise_alone().unwrap();
+    for i in 1..3 {
+        nodes[i].join_at(0).unwrap();
+        dummy_time_provider.increase_by(20);
+        process_messages(&mut nodes, 10);
+        dummy_time_provider.increase_by(20);
+        process_messages(&mut nodes, 10);
+    }
+
+    // Node 2 is cut off from the others, but it is still running.
+    dummy_network.open_partition("isolate node 2", vec![2]);
+
+    for _ in 0..100 {
+        dummy_time_provider.increase_by(20);
+        process_messages(&mut nodes, 10);
+        for node in nodes.iter_mut() {
+            node.fault_detection().unwrap();
+        }
+    }
+
+    // Both sides of the partition consider the other side to be down.
+    for (node, expected_members) in nodes.iter().zip([vec![0, 1], vec![0, 1], vec![2]]) {
+        let mut members = node.network_members.clone();
+        members.sort();
+        assert_eq!(members, expected_members);
+    }
+    verify_qpid_invariant(&nodes[..2]);
+    ensure_only_single_root(&nodes[..2]);
+}
+
+#[test]
+fn nodes_on_separate_threads() {
+    type ThreadedNode = DistributedWaitingRoom<
+        SystemTimeProvider,
+        TrueRandomProvider,
+        ChannelNetwork<NodeToNodeMessage>,
+    >;
+
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 1000,
+        ticket_expiry_time: 5000,
+        eviction_interval: 50,
+        // These are long, so a slow machine doesn't cause false positives.
+        fault_detection_period: 2000,
+        fault_detection_timeout: 1000,
+        fault_detection_interval: 100,
+        ..Default::default()
+    };
+
+    let network = ChannelNetwork::new();
+    let stop = Arc::new(AtomicBool::new(false));
+    let (pass_sender, pass_receiver) = mpsc::channel();
+
+    // The nodes are created here, and then each moved to its own thread.
+    let nodes: Vec<ThreadedNode> = (0..3)
+        .map(|node_id| {
+            let mut node = DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                SystemTimeProvider::new(),
+                TrueRandomProvider::new(),
+                network.clone(),
+            );
+            node.join_at(0).unwrap();
+            node
+        })
+        .collect();
+
+    let threads: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            let stop = stop.clone();
+            let pass_sender = pass_sender.clone();
+            thread::spawn(move || {
+                let node_id = node.node_id;
+                let mut last_eviction = Instant::now();
+                let mut ticket = None;
+                while !stop.load(Ordering::SeqCst) {
+                    while node.receive_message().unwrap() {}
+
+                    if last_eviction.elapsed() >= Duration::from_millis(50) {
+                        node.eviction().unwrap();
+                        last_eviction = Instant::now();
+                    }
+                    node.fault_detection().unwrap();
+
+                    // The last node has a user that waits in the queue until they can leave.
+                    if node_id == 2 {
+                        ticket = match ticket {
+                            None => node.join().ok(),
+                            Some(ticket) => {
+                                let response = node.check_in(ticket).unwrap();
+                                if response.position_estimate == 0 {
+                                    pass_sender
+                                        .send(node.leave(response.new_ticket).unwrap())
+                                        .unwrap();
+                                    None
+                                } else {
+                                    Some(response.new_ticket)
+                                }
+                            }
+                        };
+                    }
+                    thread::sleep(Duration::from_millis(5));
+                }
+            })
+        })
+        .collect();
+
+    let pass = pass_receiver.recv_timeout(Duration::from_secs(10));
+    stop.store(true, Ordering::SeqCst);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(pass.expect("The user should have been let in").node_id, 2);
+}
+
+#[test]
+fn nodes_over_tcp() {
+    type TcpNode = DistributedWaitingRoom<
+        SystemTimeProvider,
+        DeterministicRandomProvider,
+        VersionedNetwork<
+            AuthenticatedNetwork<
+                TcpNetwork<AuthenticatedMessage<VersionedMessage<NodeToNodeMessage>>>,
+            >,
+        >,
+    >;
+
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 1000,
+        ticket_expiry_time: 5000,
+        eviction_interval: 50,
+        // These are long, so a slow machine doesn't cause false positives.
+        fault_detection_period: 2000,
+        fault_detection_timeout: 1000,
+        fault_detection_interval: 100,
+        ..Default::default()
+    };
+
+    // The OS picks a free port for every node when it joins, and the shared address book is updated.
+    let addresses = (0..3)
+        .map(|node_id| (node_id, "127.0.0.1:0".parse().unwrap()))
+        .collect();
+    let tcp_network = TcpNetwork::with_settings(
+        addresses,
+        TcpNetworkSettings {
+            initial_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_millis(50),
+            ..Default::default()
+        },
+    );
+    // This is how the nodes of a real cluster are connected: every message is encoded at a version both nodes
+    // understand, authenticated, and then sent over TCP.
+    let network = VersionedNetwork::new(AuthenticatedNetwork::new(tcp_network, b"cluster secret"));
+    let mut nodes: Vec<TcpNode> = (0..3)
+        .map(|node_id| {
+            DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                SystemTimeProvider::new(),
+                DeterministicRandomProvider::new(node_id as u64),
+                network.clone(),
+            )
+        })
+        .collect();
+    for node in nodes.iter_mut() {
+        node.join_at(0).unwrap();
+    }
+
+    // All nodes run on this thread, but the messages go through real sockets, so they arrive whenever they arrive.
+    let start = Instant::now();
+    let mut last_eviction = Instant::now();
+    let mut ticket = None;
+    let pass = loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "The user should have been let in"
+        );
+        for node in nodes.iter_mut() {
+            while node.receive_message().unwrap() {}
+            node.fault_detection().unwrap();
+        }
+        if last_eviction.elapsed() >= Duration::from_millis(50) {
+            nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+            last_eviction = Instant::now();
+        }
+
+        // The user joins at the last node, and waits in the queue until they can leave.
+        ticket = match ticket {
+            None => nodes[2].join().ok(),
+            Some(ticket) => {
+                let response = nodes[2].check_in(ticket).unwrap();
+                if response.position_estimate == 0 {
+                    break nodes[2].leave(response.new_ticket).unwrap();
+                }
+                Some(response.new_ticket)
+            }
+        };
+        thread::sleep(Duration::from_millis(5));
+    };
+
+    assert_eq!(pass.node_id, 2);
+    for node in &nodes {
+        let mut members = node.status().members;
+        members.sort();
+        assert_eq!(members, vec![0, 1, 2]);
+    }
+    assert_eq!(nodes.iter().filter(|node| node.status().is_root).count(), 1);
+}
+
+#[test]
+fn forged_messages_are_ignored() {
+    type AuthenticatedNode = DistributedWaitingRoom<
+        DummyTimeProvider,
+        DeterministicRandomProvider,
+        AuthenticatedNetwork<DummyNetwork<AuthenticatedMessage<NodeToNodeMessage>>>,
+    >;
+
+    let settings = GeneralWaitingRoomSettings::default();
+
+    log::info!("Instantiating dummy time and network");
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_random_provider = DeterministicRandomProvider::new(1);
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(20));
+    let network = AuthenticatedNetwork::new(dummy_network.clone(), b"cluster secret");
+
+    let mut nodes: Vec<AuthenticatedNode> = (0..3)
+        .map(|node_id| {
+            DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                dummy_time_provider.clone(),
+                dummy_random_provider.clone(),
+                network.clone(),
+            )
+        })
+        .collect();
+    let process_messages = |nodes: &mut [AuthenticatedNode]| {
+        dummy_time_provider.increase_by(20);
+        while nodes.iter_mut().any(|n| n.receive_message().unwrap()) {}
+    };
+
+    nodes[0].initialise_alone().unwrap();
+    for i in 1..3 {
+        nodes[i].join_at(0).unwrap();
+        process_messages(&mut nodes);
+        process_messages(&mut nodes);
+    }
+
+    // Someone who can reach the nodes, but doesn't know the secret, tries to remove node 1 from the cluster.
+    let attacker = AuthenticatedNetwork::new(dummy_network.clone(), b"wrong secret")
+        .join(3)
+        .unwrap();
+    for to_node in [0, 2] {
+        attacker
+            .send_message(
+                to_node,
+                NodeToNodeMessage::NodeRemoved(1, SpanningTree::new_empty(), 1000),
+            )
+            .unwrap();
+    }
+    process_messages(&mut nodes);
+
+    for node in &nodes {
+        let mut members = node.network_members.clone();
+        members.sort();
+        assert_eq!(members, vec![0, 1, 2]);
+    }
+}
+
+#[test]
+fn priority_classes() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 6000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    // The normal user joins first, but both VIP users are let out before them, in the order they joined.
+    let normal = nodes[0].join().unwrap();
+    process_messages(&mut nodes, 10);
+    dummy_time_provider.increase_by(10);
+    let vip0 = nodes[1].join_with_priority(1).unwrap();
+    process_messages(&mut nodes, 10);
+    dummy_time_provider.increase_by(10);
+    let vip1 = nodes[0].join_with_priority(1).unwrap();
+    process_messages(&mut nodes, 10);
+
+    let mut tickets = vec![normal, vip0, vip1];
+    // Checks in all tickets that are still in the queue, and returns the one that is allowed to leave.
+    let check_in_all = |nodes: &mut Vec<Node>, tickets: &mut Vec<Ticket>| {
+        let mut leaving = vec![];
+        for ticket in tickets.iter_mut() {
+            let response = nodes[ticket.node_id].check_in(*ticket).unwrap();
+            *ticket = response.new_ticket;
+            if response.position_estimate == 0 {
+                leaving.push(*ticket);
+            }
+        }
+        assert_eq!(leaving.len(), 1);
+        tickets.retain(|ticket| *ticket != leaving[0]);
+        nodes[leaving[0].node_id].leave(leaving[0]).unwrap();
+        leaving[0]
+    };
+
+    for expected in [vip0, vip1, normal] {
+        evict(&dummy_time_provider, &mut nodes);
+        assert_eq!(check_in_all(&mut nodes, &mut tickets), expected);
+    }
+}
+
+#[test]
+fn rooms_share_network_and_timers() {
+    type RoomNode = DistributedWaitingRoom<
+        DummyTimeProvider,
+        DeterministicRandomProvider,
+        RoomNetwork<DummyNetwork<RoomMessage<NodeToNodeMessage>>, NodeToNodeMessage>,
+    >;
+
+    const CHECKOUT: RoomId = 1;
+    const TICKET_DROP: RoomId = 2;
+
+    log::info!("Instantiating dummy time and network");
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_random_provider = DeterministicRandomProvider::new(1);
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(20));
+    let network = SharedNetwork::new(dummy_network.clone());
+
+    // Every room has its own capacity.
+    let room_settings = [
+        (
+            CHECKOUT,
+            GeneralWaitingRoomSettings {
+                target_user_count: 1,
+                ..Default::default()
+            },
+        ),
+        (
+            TICKET_DROP,
+            GeneralWaitingRoomSettings {
+                target_user_count: 3,
+                ..Default::default()
+            },
+        ),
+    ];
+
+    let mut registries: Vec<WaitingRoomRegistry<RoomNode>> = (0..3)
+        .map(|node_id| {
+            let mut registry = WaitingRoomRegistry::new();
+            for (room_id, settings) in room_settings.clone() {
+                let mut room = DistributedWaitingRoom::new(
+                    settings,
+                    node_id,
+                    dummy_time_provider.clone(),
+                    dummy_random_provider.clone(),
+                    network.room(room_id),
+                );
+                room.set_room_id(room_id);
+                registry.add_room(room).unwrap();
+            }
+            registry
+        })
+        .collect();
+    // Every node joined the network once, for both rooms.
+    assert_eq!(dummy_network.all_nodes().unwrap(), vec![0, 1, 2]);
+
+    let process_messages = |registries: &mut [WaitingRoomRegistry<RoomNode>]| {
+        for _ in 0..10 {
+            dummy_time_provider.increase_by(20);
+            while registries
+                .iter_mut()
+                .any(|registry| registry.receive_message().unwrap())
+            {}
+        }
+    };
+
+    for room_id in [CHECKOUT, TICKET_DROP] {
+        registries[0]
+            .get_room_mut(room_id)
+            .unwrap()
+            .initialise_alone()
+            .unwrap();
+        for i in 1..3 {
+            registries[i]
+                .get_room_mut(room_id)
+                .unwrap()
+                .join_at(0)
+                .unwrap();
+            process_messages(&mut registries);
+        }
+    }
+
+    let mut checkout_tickets = vec![];
+    let mut drop_tickets = vec![];
+    for registry in registries.iter_mut() {
+        checkout_tickets.push(registry.join(CHECKOUT).unwrap());
+        drop_tickets.push(registry.join(TICKET_DROP).unwrap());
+    }
+    assert!(matches!(
+        registries[0].join(3),
+        Err(WaitingRoomError::RoomNotFound(3))
+    ));
+    process_messages(&mut registries);
+
+    // The timers are shared, eviction runs for both rooms.
+    registries
+        .iter_mut()
+        .for_each(|registry| registry.eviction().unwrap());
+    process_messages(&mut registries);
+
+    let mut let_out = |tickets: &[Ticket]| {
+        tickets
+            .iter()
+            .filter(|ticket| {
+                registries[ticket.node_id]
+                    .check_in(**ticket)
+                    .unwrap()
+                    .position_estimate
+                    == 0
+            })
+            .count()
+    };
+    assert_eq!(let_out(&checkout_tickets), 1);
+    assert_eq!(let_out(&drop_tickets), 3);
+
+    // A ticket for one room can't be used in the other one.
+    let mut wrong_room_ticket = drop_tickets[0];
+    assert!(matches!(
+        registries[0]
+            .get_room_mut(CHECKOUT)
+            .unwrap()
+            .check_in(wrong_room_ticket),
+        Err(WaitingRoomError::TicketAtWrongRoom)
+    ));
+    let pass = registries[0].leave(wrong_room_ticket).unwrap();
+    assert_eq!(pass.room_id, TICKET_DROP);
+    assert!(matches!(
+        registries[0]
+            .get_room_mut(CHECKOUT)
+            .unwrap()
+            .validate_and_refresh_pass(pass),
+        Err(WaitingRoomError::PassAtWrongRoom)
+    ));
+    registries[0].validate_and_refresh_pass(pass).unwrap();
+
+    // Changing the room on the ticket doesn't help, since the room doesn't know about it.
+    wrong_room_ticket.room_id = CHECKOUT;
+    assert!(registries[0].check_in(wrong_room_ticket).is_err());
+}
+
+#[test]
+fn activation_window_lottery() {
+    let window = ActivationWindow {
+        starts_at: 1000,
+        open_at: 10000,
+        ends_at: Some(60000),
+    };
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 6000,
+        activation_window: Some(window),
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    // Before the window starts, users are let through without queueing.
+    let early = nodes[0].join().unwrap();
+    assert_eq!(nodes[0].check_in(early).unwrap().position_estimate, 0);
+    nodes[0].leave(early).unwrap();
+
+    dummy_time_provider.increase_by(window.starts_at - dummy_time_provider.get_now_time());
+    let mut tickets: Vec<Ticket> = (0..6)
+        .map(|i| {
+            dummy_time_provider.increase_by(10);
+            let ticket = nodes[i % 2].join().unwrap();
+            process_messages(&mut nodes, 10);
+            ticket
+        })
+        .collect();
+    let join_order: Vec<_> = tickets.iter().map(|ticket| ticket.identifier).collect();
+
+    // Nobody is let out of the pre-queue before the room opens, no matter how early they joined.
+    evict(&dummy_time_provider, &mut nodes);
+    for ticket in tickets.iter_mut() {
+        let response = nodes[ticket.node_id].check_in(*ticket).unwrap();
+        assert_ne!(response.position_estimate, 0);
+        *ticket = response.new_ticket;
+    }
+
+    dummy_time_provider.increase_by(window.open_at - dummy_time_provider.get_now_time());
+    let late = nodes[0].join().unwrap();
+    process_messages(&mut nodes, 10);
+    tickets.push(late);
+
+    // Checks in all tickets that are still in the queue, and returns the one that is allowed to leave.
+    let check_in_all = |nodes: &mut Vec<Node>, tickets: &mut Vec<Ticket>| {
+        let mut leaving = vec![];
+        for ticket in tickets.iter_mut() {
+            let response = nodes[ticket.node_id].check_in(*ticket).unwrap();
+            *ticket = response.new_ticket;
+            if response.position_estimate == 0 {
+                leaving.push(*ticket);
+            }
+        }
+        assert_eq!(leaving.len(), 1);
+        tickets.retain(|ticket| *ticket != leaving[0]);
+        nodes[leaving[0].node_id].leave(leaving[0]).unwrap();
+        leaving[0].identifier
+    };
+
+    let order: Vec<_> = (0..7)
+        .map(|_| {
+            evict(&dummy_time_provider, &mut nodes);
+            check_in_all(&mut nodes, &mut tickets)
+        })
+        .collect();
+    // Everyone from the pre-queue is let out before the user who arrived after the opening,
+    // but not in the order they joined.
+    assert_eq!(order[6], late.identifier);
+    assert_ne!(order[..6], join_order[..]);
+
+    // After the window ends, users are let through again.
+    dummy_time_provider.increase_by(window.ends_at.unwrap() - dummy_time_provider.get_now_time());
+    let after = nodes[1].join().unwrap();
+    assert_eq!(nodes[1].check_in(after).unwrap().position_estimate, 0);
+}
+
+#[test]
+fn rate_admission() {
+    let settings = GeneralWaitingRoomSettings {
+        // This is ignored in rate mode, so users are let in even though it is reached.
+        target_user_count: 1,
+        admission_mode: AdmissionMode::Rate {
+            users_per_minute: 10,
+            burst: 2,
+        },
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let mut tickets: Vec<Ticket> = (0..6)
+        .map(|i| {
+            let ticket = nodes[i % 2].join().unwrap();
+            process_messages(&mut nodes, 10);
+            ticket
+        })
+        .collect();
+
+    // The bucket starts with the burst, and then refills at one user every 6 seconds.
+    let left: Vec<usize> = (0..3)
+        .map(|_| evict_and_leave(&dummy_time_provider, &mut nodes, &mut tickets))
+        .collect();
+    assert_eq!(left, [2, 1, 1]);
+}
+
+#[test]
+fn abandon_and_release() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let mut tickets = vec![];
+    for node_id in [0, 1, 0, 1] {
+        dummy_time_provider.increase_by(10);
+        tickets.push(nodes[node_id].join().unwrap());
+        process_messages(&mut nodes, 10);
+    }
+
+    // The first user is at the front of the queue, and the third user is behind the second one.
+    nodes[0].abandon(tickets[0]).unwrap();
+    nodes[0].abandon(tickets[2]).unwrap();
+    assert!(matches!(
+        nodes[0].abandon(tickets[0]),
+        Err(WaitingRoomError::TicketNotInQueue)
+    ));
+    assert!(matches!(
+        nodes[0].abandon(tickets[1]),
+        Err(WaitingRoomError::TicketAtWrongNode)
+    ));
+    for ticket in [tickets[0], tickets[2]] {
+        assert!(matches!(
+            nodes[0].check_in(ticket),
+            Err(WaitingRoomError::TicketNotInQueue)
+        ));
+    }
+
+    // The second user is let in in place of the first one.
+    evict(&dummy_time_provider, &mut nodes);
+    let response = nodes[1].check_in(tickets[1]).unwrap();
+    assert_eq!(response.position_estimate, 0);
+    let pass = nodes[1].leave(response.new_ticket).unwrap();
+
+    // The pass doesn't expire for a while, so nobody else is let in until it is released.
+    evict(&dummy_time_provider, &mut nodes);
+    let response = nodes[1].check_in(tickets[3]).unwrap();
+    assert_ne!(response.position_estimate, 0);
+    nodes[1].release(pass).unwrap();
+    assert!(matches!(
+        nodes[1].release(pass),
+        Err(WaitingRoomError::PassNotInList)
+    ));
+    assert!(matches!(
+        nodes[1].validate_and_refresh_pass(pass),
+        Err(WaitingRoomError::PassNotInList)
+    ));
+    evict(&dummy_time_provider, &mut nodes);
+    assert_eq!(
+        nodes[1]
+            .check_in(response.new_ticket)
+            .unwrap()
+            .position_estimate,
+        0
+    );
+}
+
+#[test]
+fn kick_and_revoke() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let mut tickets = vec![];
+    for node_id in [0, 1, 1] {
+        dummy_time_provider.increase_by(10);
+        tickets.push(nodes[node_id].join().unwrap());
+        process_messages(&mut nodes, 10);
+    }
+
+    // The first user is at the front of the queue, and is kicked from a node that doesn't hold the ticket.
+    nodes[1].kick_ticket(tickets[0].identifier).unwrap();
+    process_messages(&mut nodes, 10);
+    for node in nodes.iter_mut() {
+        assert!(matches!(
+            node.check_in(tickets[0]),
+            Err(WaitingRoomError::TicketRevoked)
+        ));
+    }
+
+    // The second user is let in in place of the first one.
+    evict(&dummy_time_provider, &mut nodes);
+    let response = nodes[1].check_in(tickets[1]).unwrap();
+    assert_eq!(response.position_estimate, 0);
+    let pass = nodes[1].leave(response.new_ticket).unwrap();
+
+    // The pass is revoked from the other node, which removes it from the node holding it.
+    nodes[0].revoke_pass(pass.identifier).unwrap();
+    process_messages(&mut nodes, 10);
+    for node in nodes.iter_mut() {
+        assert!(matches!(
+            node.validate_and_refresh_pass(pass),
+            Err(WaitingRoomError::PassRevoked)
+        ));
+    }
+
+    // The revoked pass no longer takes up a place on the site, so the third user is let in.
+    let response = nodes[1].check_in(tickets[2]).unwrap();
+    evict(&dummy_time_provider, &mut nodes);
+    assert_eq!(
+        nodes[1]
+            .check_in(response.new_ticket)
+            .unwrap()
+            .position_estimate,
+        0
+    );
+}
+
+#[test]
+fn revocations_reach_every_node() {
+    let settings = GeneralWaitingRoomSettings {
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(0));
+    let random_provider = DeterministicRandomProvider::new(1);
+
+    let mut nodes: Vec<Node> = (0..3)
+        .map(|node_id| {
+            DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                dummy_time_provider.clone(),
+                random_provider.clone(),
+                dummy_network.clone(),
+            )
+        })
+        .collect();
+    nodes[0].initialise_alone().unwrap();
+    nodes[1].join_at(0).unwrap();
+    process_messages(&mut nodes, 10);
+
+    // The kick is lost on its way to node 1, so node 0 sends it again at the next cleanup.
+    nodes[0].kick_ticket(42).unwrap();
+    dummy_network.get_messages_mut().clear();
+    assert!(!nodes[1].revoked_tickets.contains(42));
+    dummy_time_provider.increase_by(1000);
+    nodes[0].cleanup().unwrap();
+    process_messages(&mut nodes, 10);
+    assert!(nodes[1].revoked_tickets.contains(42));
+    // Node 1 acknowledged it, so it isn't sent again.
+    assert!(nodes[0].pending_revocations.is_empty());
+
+    nodes[1].revoke_pass(43).unwrap();
+    process_messages(&mut nodes, 10);
+    assert!(nodes[0].revoked_passes.contains(43));
+    assert!(nodes[1].pending_revocations.is_empty());
+
+    // A node that joins later is told about the revocations that are still active.
+    nodes[2].join_at(0).unwrap();
+    process_messages(&mut nodes, 10);
+    assert!(nodes[2].revoked_tickets.contains(42));
+    assert!(nodes[2].revoked_passes.contains(43));
+    assert!(nodes[0].pending_revocations.is_empty());
+
+    // Once the kick expires, it isn't sent to joining nodes anymore.
+    dummy_time_provider.increase_by(15000);
+    nodes.iter_mut().for_each(|node| node.cleanup().unwrap());
+    assert!(!nodes[0].revoked_tickets.contains(42));
+    assert!(nodes[0].revoked_passes.contains(43));
+}
+
+#[test]
+fn capacity_schedule() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        // More users are let in between 20 and 40 seconds after midnight.
+        capacity_schedule: vec![CapacityRange {
+            from: 20000,
+            until: 40000,
+            target_user_count: 3,
+        }],
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let mut tickets: Vec<Ticket> = (0..6)
+        .map(|i| {
+            let ticket = nodes[i % 2].join().unwrap();
+            process_messages(&mut nodes, 10);
+            ticket
+        })
+        .collect();
+
+    // Before the range, only one user is allowed on the site. During the range, two more users are let in.
+    // After the range, the three users on the site are over the target, so nobody else is let in.
+    let left: Vec<usize> = (0..7)
+        .map(|_| evict_and_leave(&dummy_time_provider, &mut nodes, &mut tickets))
+        .collect();
+    assert_eq!(left, [1, 0, 0, 2, 0, 0, 0]);
+    assert!(dummy_time_provider.get_now_time() > 40000);
+}
+
+#[test]
+fn admission_estimate() {
+    let settings = GeneralWaitingRoomSettings {
+        admission_mode: AdmissionMode::Rate {
+            users_per_minute: 20,
+            burst: 2,
+        },
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let mut tickets: Vec<Ticket> = (0..20)
+        .map(|i| {
+            let ticket = nodes[i % 2].join().unwrap();
+            process_messages(&mut nodes, 10);
+            ticket
+        })
+        .collect();
+
+    // Nobody has been let out yet, so there is nothing to base an estimate on.
+    let response = nodes[0].check_in(tickets[0]).unwrap();
+    assert_eq!(response.admission_estimate, None);
+    tickets[0] = response.new_ticket;
+
+    // Two users are let out every 6 seconds.
+    for _ in 0..6 {
+        evict_and_leave(&dummy_time_provider, &mut nodes, &mut tickets);
+    }
+
+    // Every node estimates the wait from the users let out of its own queue.
+    let now = dummy_time_provider.get_now_time();
+    for ticket in tickets {
+        let response = nodes[ticket.node_id].check_in(ticket).unwrap();
+        let estimate = response.admission_estimate.unwrap();
+        assert!(estimate.expected_at > now);
+        assert!(estimate.earliest < estimate.expected_at);
+        assert!(estimate.expected_at < estimate.latest);
+    }
+}
+
+#[test]
+fn global_position_estimate() {
+    let settings = GeneralWaitingRoomSettings {
+        // Nobody is let in, so the queue stays the same.
+        target_user_count: 0,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let join = |nodes: &mut Vec<Node>, count: usize| -> Vec<Ticket> {
+        (0..count)
+            .map(|i| {
+                dummy_time_provider.increase_by(10);
+                let ticket = nodes[i % 2].join().unwrap();
+                process_messages(nodes, 10);
+                ticket
+            })
+            .collect()
+    };
+    let evict_now = |nodes: &mut Vec<Node>| {
+        dummy_time_provider.increase_by(10);
+        nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+        process_messages(nodes, 10);
+    };
+
+    // Before the first count, only the local queue is known.
+    let mut tickets = join(&mut nodes, 8);
+    let response = nodes[1].check_in(tickets[7]).unwrap();
+    assert_eq!(response.position_estimate, 4);
+
+    // After the count, the users at the other node are counted too. Every node only knows the front of the
+    // other queues, so the estimate can be off by one.
+    evict_now(&mut nodes);
+    let mut positions = vec![];
+    for (rank, ticket) in tickets.iter_mut().enumerate() {
+        let response = nodes[ticket.node_id].check_in(*ticket).unwrap();
+        assert!(response.position_estimate.abs_diff(rank + 1) <= 1);
+        positions.push(response.position_estimate);
+        *ticket = response.new_ticket;
+    }
+    assert!(positions[7] > 4);
+
+    // More users join behind them, which changes the summaries, but the estimates never go up.
+    join(&mut nodes, 8);
+    evict_now(&mut nodes);
+    for (ticket, position) in tickets.iter().zip(positions) {
+        let response = nodes[ticket.node_id].check_in(*ticket).unwrap();
+        assert!(response.position_estimate <= position);
+    }
+}
+
+#[test]
+fn queue_summaries_down_the_tree() {
+    let settings = GeneralWaitingRoomSettings {
+        // Nobody is let in, so the queue stays the same.
+        target_user_count: 0,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        ..Default::default()
+    };
+
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(0));
+    let random_provider = DeterministicRandomProvider::new(1);
+
+    // The nodes form a line, 0 - 1 - 2, with node 0 as the root.
+    let neighbours = [vec![0, 1], vec![0, 1, 2], vec![1, 2]];
+    let mut nodes: Vec<Node> = (0..3)
+        .map(|node_id| {
+            let mut node = DistributedWaitingRoom::new(
+                settings.clone(),
+                node_id,
+                dummy_time_provider.clone(),
+                random_provider.clone(),
+                dummy_network.clone(),
+            );
+            node.testing_overwrite_qpid(
+                Some(node_id.saturating_sub(1)),
+                neighbours[node_id]
+                    .iter()
+                    .map(|&v| (v, Weight::new(Time::MAX, 0, 0)))
+                    .collect(),
+            );
+            node
+        })
+        .collect();
+
+    // The user at node 0 abandons the queue, which leaves a skip ticket at the front of it.
+    let mut tickets = vec![];
+    for node_id in [0, 2, 2] {
+        dummy_time_provider.increase_by(10);
+        tickets.push(nodes[node_id].join().unwrap());
+        process_messages(&mut nodes, 10);
+    }
+    nodes[0].abandon(tickets[0]).unwrap();
+    process_messages(&mut nodes, 10);
+
+    dummy_time_provider.increase_by(10);
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+
+    // Node 2 is not a neighbour of the root, so it got the summaries from node 1. The skip ticket is not a user.
+    for node in &nodes {
+        assert_eq!(node.queue_summaries.len(), 1);
+        assert_eq!(node.queue_summaries[0].front.node_id(), 2);
+        assert_eq!(node.queue_summaries[0].count, 2);
+    }
+    let response = nodes[2].check_in(tickets[2]).unwrap();
+    assert_eq!(response.position_estimate, 2);
+}
+
+/// Records every event, and vetoes everything it can while `veto` is set.
+#[derive(Debug)]
+struct RecordingObserver {
+    events: Arc<Mutex<Vec<WaitingRoomEvent>>>,
+    veto: Arc<AtomicBool>,
+}
+
+impl WaitingRoomObserver for RecordingObserver {
+    fn allow(&mut self, _event: &WaitingRoomEvent) -> Result<(), String> {
+        if self.veto.load(Ordering::SeqCst) {
+            return Err("closed".to_string());
+        }
+        Ok(())
+    }
+
+    fn notify(&mut self, event: &WaitingRoomEvent) {
+        self.events.lock().unwrap().push(*event);
+    }
+}
+
+#[test]
+fn observer() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let veto = Arc::new(AtomicBool::new(false));
+    nodes[1].set_observer(Box::new(RecordingObserver {
+        events: events.clone(),
+        veto: veto.clone(),
+    }));
+
+    dummy_time_provider.increase_by(10);
+    let ticket = nodes[1].join().unwrap();
+    process_messages(&mut nodes, 10);
+
+    // A vetoed join doesn't add anyone to the queue.
+    veto.store(true, Ordering::SeqCst);
+    assert!(matches!(nodes[1].join(), Err(WaitingRoomError::Vetoed(_))));
+    veto.store(false, Ordering::SeqCst);
+
+    dummy_time_provider.increase_by(6001);
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+    let ticket = nodes[1].check_in(ticket).unwrap().new_ticket;
+
+    // A vetoed leave keeps the user in the queue, so they can try again.
+    veto.store(true, Ordering::SeqCst);
+    assert!(matches!(
+        nodes[1].leave(ticket),
+        Err(WaitingRoomError::Vetoed(_))
+    ));
+    veto.store(false, Ordering::SeqCst);
+    let pass = nodes[1].leave(ticket).unwrap();
+    nodes[1].release(pass).unwrap();
+
+    let events = events.lock().unwrap();
+    assert!(events.iter().all(|event| event.node_id == 1));
+    let kinds: Vec<WaitingRoomEventKind> = events.iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            WaitingRoomEventKind::Join {
+                ticket: ticket.identifier,
+                priority_class: 0
+            },
+            WaitingRoomEventKind::LetOut {
+                ticket: ticket.identifier
+            },
+            WaitingRoomEventKind::CheckIn {
+                ticket: ticket.identifier,
+                position_estimate: 0
+            },
+            WaitingRoomEventKind::Leave {
+                ticket: ticket.identifier
+            },
+            WaitingRoomEventKind::Release {
+                pass: pass.identifier
+            },
+        ]
+    );
+}
+
+#[test]
+fn snapshot_and_restore() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    log::info!("Instantiating dummy time and network");
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(0));
+    let random_provider = DeterministicRandomProvider::new(1);
+
+    let new_node = |node_id: NodeId| -> Node {
+        two_node_room_node(
+            &settings,
+            node_id,
+            &dummy_time_provider,
+            &random_provider,
+            &dummy_network,
+        )
+    };
+    let mut nodes: Vec<Node> = (0..2).map(new_node).collect();
+
+    let mut tickets = vec![];
+    for _ in 0..4 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(nodes[1].join().unwrap());
+        process_messages(&mut nodes, 10);
+    }
+    dummy_time_provider.increase_by(6001);
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+    let response = nodes[1].check_in(tickets[0]).unwrap();
+    assert_eq!(response.position_estimate, 0);
+    let pass = nodes[1].leave(response.new_ticket).unwrap();
+    nodes[1].kick_ticket(tickets[3].identifier).unwrap();
+    process_messages(&mut nodes, 10);
+    for ticket in tickets[1..3].iter_mut() {
+        *ticket = nodes[1].check_in(*ticket).unwrap().new_ticket;
+    }
+
+    let bytes = nodes[1].snapshot().to_bytes();
+    assert!(matches!(
+        nodes[0].restore(RoomSnapshot::from_bytes(&bytes).unwrap()),
+        Err(WaitingRoomError::SnapshotAtWrongNode)
+    ));
+
+    // Node 1 restarts, and is down for longer than tickets last without being refreshed.
+    dummy_time_provider.increase_by(20000);
+    dummy_network.remove_node(1);
+    nodes[1] = new_node(1);
+    nodes[0].testing_overwrite_qpid(Some(1), two_node_weight_table());
+    nodes[1]
+        .restore(RoomSnapshot::from_bytes(&bytes).unwrap())
+        .unwrap();
+    nodes.iter_mut().for_each(|node| node.cleanup().unwrap());
+    process_messages(&mut nodes, 10);
+
+    // Everyone kept their place.
+    for (position, ticket) in tickets[1..3].iter().enumerate() {
+        let response = nodes[1].check_in(*ticket).unwrap();
+        assert_eq!(response.position_estimate, position + 1);
+    }
+    nodes[1].validate_and_refresh_pass(pass).unwrap();
+    assert!(matches!(
+        nodes[1].check_in(tickets[3]),
+        Err(WaitingRoomError::TicketRevoked)
+    ));
+
+    // The restored queue is part of QPID again, so the front user is let out at the next eviction.
+    nodes[1].release(pass).unwrap();
+    dummy_time_provider.increase_by(6001);
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+    let response = nodes[1].check_in(tickets[1]).unwrap();
+    assert_eq!(response.position_estimate, 0);
+}
+
+#[test]
+fn journal_replay() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    log::info!("Instantiating dummy time and network");
+    let dummy_time_provider = DummyTimeProvider::new();
+    let dummy_network = DummyNetwork::new(dummy_time_provider.clone(), Latency::Fixed(0));
+    let random_provider = DeterministicRandomProvider::new(1);
+    let path =
+        std::env::temp_dir().join(format!("waitingroom-journal-replay-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let new_node = |node_id: NodeId| -> Node {
+        two_node_room_node(
+            &settings,
+            node_id,
+            &dummy_time_provider,
+            &random_provider,
+            &dummy_network,
+        )
+    };
+    let mut nodes: Vec<Node> = (0..2).map(new_node).collect();
+    nodes[1].open_journal(&path).unwrap();
+
+    let mut tickets = vec![];
+    for _ in 0..4 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(nodes[1].join().unwrap());
+        process_messages(&mut nodes, 10);
+    }
+    dummy_time_provider.increase_by(6001);
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+    let response = nodes[1].check_in(tickets[0]).unwrap();
+    let pass = nodes[1].leave(response.new_ticket).unwrap();
+    nodes[0].kick_ticket(tickets[3].identifier).unwrap();
+    process_messages(&mut nodes, 10);
+    let bytes = std::fs::read(&path).unwrap();
+
+    // Node 1 crashes without taking a snapshot, and is down for longer than tickets last without being refreshed.
+    let restart = |nodes: &mut Vec<Node>| {
+        dummy_network.remove_node(1);
+        nodes[1] = new_node(1);
+        nodes[0].testing_overwrite_qpid(Some(1), two_node_weight_table());
+        nodes[1].open_journal(&path).unwrap();
+    };
+    dummy_time_provider.increase_by(20000);
+    restart(&mut nodes);
+    for (position, ticket) in tickets[1..3].iter().enumerate() {
+        let response = nodes[1].check_in(*ticket).unwrap();
+        assert_eq!(response.position_estimate, position + 1);
+    }
+    nodes[1].validate_and_refresh_pass(pass).unwrap();
+    assert!(matches!(
+        nodes[1].check_in(tickets[3]),
+        Err(WaitingRoomError::TicketRevoked)
+    ));
+
+    // A crash can also cut off the end of the journal. Whatever was written completely is restored.
+    for _ in 0..20 {
+        let offset = random_provider.random_u64() as usize % bytes.len();
+        std::fs::write(&path, &bytes[..offset]).unwrap();
+        restart(&mut nodes);
+        let mut expected_position = 1;
+        for ticket in &tickets {
+            match nodes[1].check_in(*ticket) {
+                // The first user could have been let out, but not have left yet.
+                Ok(response) if response.position_estimate == 0 => {
+                    assert_eq!(ticket.identifier, tickets[0].identifier)
+                }
+                Ok(response) => {
+                    assert_eq!(response.position_estimate, expected_position);
+                    expected_position += 1;
+                }
+                // Users whose join was cut off are not known, so their own ticket has expired by now.
+                Err(err) => assert!(matches!(
+                    err,
+                    WaitingRoomError::TicketNotInQueue
+                        | WaitingRoomError::TicketRevoked
+                        | WaitingRoomError::TicketExpired
+                )),
+            }
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn queue_full() {
+    let settings = GeneralWaitingRoomSettings {
+        // Nobody is let out, so only the queue length changes the outcome.
+        target_user_count: 0,
+        max_queue_length: Some(4),
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+    let evict_now = |nodes: &mut Vec<Node>| {
+        dummy_time_provider.increase_by(10);
+        nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+        process_messages(nodes, 10);
+    };
+
+    // Before the first count, every node only knows its own queue.
+    let mut tickets = vec![];
+    for node_id in [0, 0, 1, 1] {
+        dummy_time_provider.increase_by(10);
+        tickets.push(nodes[node_id].join().unwrap());
+        process_messages(&mut nodes, 10);
+    }
+
+    // After the count, both nodes know the queue of all nodes together is full.
+    evict_now(&mut nodes);
+    for node in nodes.iter_mut() {
+        match node.join() {
+            Err(WaitingRoomError::QueueFull { retry_after }) => {
+                // Nobody was let out yet, so there is no better estimate than the next eviction.
+                assert_eq!(retry_after, settings.eviction_interval)
+            }
+            other => panic!("Expected the queue to be full, got {:?}", other),
+        }
+    }
+
+    // Once a user leaves the queue and the next count has happened, there is space again.
+    nodes[0].abandon(tickets[1]).unwrap();
+    evict_now(&mut nodes);
+    nodes[1].join().unwrap();
+    process_messages(&mut nodes, 10);
+    // Node 0 only finds out about the new user at the next count.
+    evict_now(&mut nodes);
+    assert!(matches!(
+        nodes[0].join(),
+        Err(WaitingRoomError::QueueFull { .. })
+    ));
+}
+
+#[test]
+fn challenge() {
+    let settings = GeneralWaitingRoomSettings {
+        challenge: Some(ChallengeSettings {
+            difficulty: 8,
+            expiry_time: 1000,
+        }),
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+    for node in nodes.iter_mut() {
+        node.set_challenge_key(ChallengeKey::new(b"cluster secret"));
+    }
+
+    assert!(matches!(
+        nodes[0].join(),
+        Err(WaitingRoomError::ChallengeRequired)
+    ));
+
+    // A challenge can be solved at any node with the same key.
+    let challenge = nodes[0].challenge().unwrap();
+    assert_eq!(challenge.difficulty, 8);
+    let solution = challenge.solve();
+    let ticket = nodes[1].join_with_solution(solution).unwrap();
+    process_messages(&mut nodes, 10);
+    nodes[1].check_in(ticket).unwrap();
+    // Every solution can only be used once.
+    assert!(matches!(
+        nodes[1].join_with_solution(solution),
+        Err(WaitingRoomError::InvalidChallengeSolution)
+    ));
+
+    // A wrong solution is rejected, but the challenge can still be solved.
+    let challenge = nodes[1].challenge().unwrap();
+    let wrong = (0..)
+        .find(|solution| !challenge.is_solved_by(*solution))
+        .unwrap();
+    assert!(matches!(
+        nodes[1].join_with_solution(ChallengeSolution {
+            challenge,
+            solution: wrong,
+        }),
+        Err(WaitingRoomError::InvalidChallengeSolution)
+    ));
+    nodes[1].join_with_solution(challenge.solve()).unwrap();
+
+    // Challenges signed with another key, or changed by the user, are rejected.
+    let forged = ChallengeKey::new(b"guessed secret").issue(1, 0, 1000, 0);
+    assert!(matches!(
+        nodes[0].join_with_solution(forged.solve()),
+        Err(WaitingRoomError::InvalidChallengeSolution)
+    ));
+    let easier = Challenge {
+        difficulty: 0,
+        ..nodes[0].challenge().unwrap()
+    };
+    assert!(matches!(
+        nodes[0].join_with_solution(easier.solve()),
+        Err(WaitingRoomError::InvalidChallengeSolution)
+    ));
+
+    // Challenges need to be solved before they expire.
+    let challenge = nodes[1].challenge().unwrap();
+    dummy_time_provider.increase_by(1001);
+    assert!(matches!(
+        nodes[1].join_with_solution(challenge.solve()),
+        Err(WaitingRoomError::InvalidChallengeSolution)
+    ));
+
+    // Without a challenge in the settings, users can join right away.
+    let settings = GeneralWaitingRoomSettings {
+        challenge: None,
+        ..settings
+    };
+    nodes[1].update_settings(settings).unwrap();
+    process_messages(&mut nodes, 10);
+    assert!(nodes[0].challenge().is_none());
+    nodes[0].join().unwrap();
+}
+
+#[test]
+fn challenge_is_kept_when_joining_fails() {
+    let settings = GeneralWaitingRoomSettings {
+        challenge: Some(ChallengeSettings {
+            difficulty: 8,
+            expiry_time: 1000,
+        }),
+        target_user_count: 1,
+        max_queue_length: Some(1),
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, dummy_network, mut nodes) = two_node_room(&settings);
+    for node in nodes.iter_mut() {
+        node.set_challenge_key(ChallengeKey::new(b"cluster secret"));
+    }
+
+    // A user who is turned away because the queue is full can use the same solution once there is space.
+    let solution = nodes[1].challenge().unwrap().solve();
+    nodes[1].join_with_solution(solution).unwrap();
+    let solution = nodes[1].challenge().unwrap().solve();
+    assert!(matches!(
+        nodes[1].join_with_solution(solution),
+        Err(WaitingRoomError::QueueFull { .. })
+    ));
+    // Once the first user is let out, there is space again.
+    dummy_time_provider.increase_by(10);
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+    nodes[1].join_with_solution(solution).unwrap();
+    assert!(matches!(
+        nodes[1].join_with_solution(solution),
+        Err(WaitingRoomError::InvalidChallengeSolution)
+    ));
+
+    // The same goes for a node that hasn't joined the network yet.
+    let mut node = DistributedWaitingRoom::new(
+        settings,
+        2,
+        dummy_time_provider.clone(),
+        DeterministicRandomProvider::new(2),
+        dummy_network,
+    );
+    node.set_challenge_key(ChallengeKey::new(b"cluster secret"));
+    let solution = nodes[0].challenge().unwrap().solve();
+    assert!(matches!(
+        node.join_with_solution(solution),
+        Err(WaitingRoomError::QPIDNotInitialized)
+    ));
+    node.testing_overwrite_qpid(Some(2), vec![(2, Weight::new(Time::MAX, 0, 0))]);
+    node.join_with_solution(solution).unwrap();
+}
+
+/// Keeps the counters and histograms recorded by all nodes, so the tests can check them.
+#[derive(Debug, Default)]
+struct RecordingSink {
+    counters: Mutex<Vec<(Counter, Labels, u64)>>,
+    histograms: Mutex<Vec<(Histogram, Labels, f64)>>,
+}
+
+impl RecordingSink {
+    fn count(&self, counter: Counter, node_id: NodeId) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(c, labels, _)| *c == counter && labels.node_id == node_id)
+            .map(|(_, _, increment)| increment)
+            .sum()
+    }
+}
+
+impl MetricsSink for RecordingSink {
+    fn gauge(&self, _gauge: Gauge, _labels: &Labels, _update: GaugeUpdate) {}
+
+    fn counter(&self, counter: Counter, labels: &Labels, increment: u64) {
+        self.counters
+            .lock()
+            .unwrap()
+            .push((counter, *labels, increment));
+    }
+
+    fn histogram(&self, histogram: Histogram, labels: &Labels, value: f64) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .push((histogram, *labels, value));
+    }
+}
+
+#[test]
+fn metrics() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let sink = Arc::new(RecordingSink::default());
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+    for node in nodes.iter_mut() {
+        node.set_room_id(7);
+        node.set_metrics_sink(sink.clone());
+    }
+
+    dummy_time_provider.increase_by(10);
+    let ticket = nodes[1].join().unwrap();
+    process_messages(&mut nodes, 10);
+    dummy_time_provider.increase_by(10);
+    nodes[0].join().unwrap();
+    process_messages(&mut nodes, 10);
+
+    // Only the first user is let out, and the other one never comes back.
+    dummy_time_provider.increase_by(6001);
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+    let ticket = nodes[1].check_in(ticket).unwrap().new_ticket;
+    nodes[1].leave(ticket).unwrap();
+
+    dummy_time_provider.increase_by(15001);
+    nodes.iter_mut().for_each(|node| node.cleanup().unwrap());
+    assert_eq!(sink.count(Counter::TicketExpiries, 0), 1);
+    assert_eq!(sink.count(Counter::PassExpiries, 1), 0);
+
+    dummy_time_provider.increase_by(60001);
+    nodes.iter_mut().for_each(|node| node.cleanup().unwrap());
+
+    for node_id in 0..2 {
+        assert_eq!(sink.count(Counter::Joins, node_id), 1);
+    }
+    assert_eq!(sink.count(Counter::Leaves, 1), 1);
+    assert_eq!(sink.count(Counter::Leaves, 0), 0);
+    assert_eq!(sink.count(Counter::TicketExpiries, 1), 0);
+    assert_eq!(sink.count(Counter::PassExpiries, 1), 1);
+    // The wait time is recorded in seconds, with the labels of the node the user left from.
+    assert_eq!(
+        *sink.histograms.lock().unwrap(),
+        vec![(
+            Histogram::WaitTime,
+            Labels {
+                node_id: 1,
+                room_id: Some(7)
+            },
+            6.011
+        )]
+    );
+}
+
+#[test]
+fn status() {
+    let settings = GeneralWaitingRoomSettings {
+        target_user_count: 1,
+        capacity_schedule: vec![CapacityRange {
+            from: 0,
+            until: 60_000,
+            target_user_count: 2,
+        }],
+        ticket_refresh_time: 6000,
+        ticket_expiry_time: 15000,
+        pass_expiry_time: 60000,
+        ..Default::default()
+    };
+
+    let (dummy_time_provider, _, mut nodes) = two_node_room(&settings);
+
+    let status = nodes[0].status();
+    assert_eq!(status.room.last_eviction_time, None);
+    assert_eq!(status.room.target_user_count, 2);
+    assert_eq!(status.qpid_parent, Some(1));
+    assert!(!status.is_root);
+    assert_eq!(status.neighbours, vec![1]);
+    assert_eq!(status.members, vec![0, 1]);
+    assert_eq!(status.tree_iteration, 0);
+    assert!(nodes[1].status().is_root);
+
+    let mut tickets = vec![];
+    for _ in 0..3 {
+        dummy_time_provider.increase_by(10);
+        tickets.push(nodes[1].join().unwrap());
+        process_messages(&mut nodes, 10);
+    }
+    assert_eq!(nodes[1].status().room.queue_length, 3);
+    assert_eq!(nodes[0].status().room.queue_length, 0);
+
+    dummy_time_provider.increase_by(6001);
+    let eviction_time = dummy_time_provider.get_now_time();
+    nodes.iter_mut().for_each(|node| node.eviction().unwrap());
+    process_messages(&mut nodes, 10);
+    let response = nodes[1].check_in(tickets[0]).unwrap();
+    nodes[1].leave(response.new_ticket).unwrap();
+
+    let status = nodes[1].status().room;
+    assert_eq!(status.queue_length, 1);
+    assert_eq!(status.leaving_list_length, 1);
+    assert_eq!(status.on_site_count, 1);
+    assert_eq!(status.last_eviction_time, Some(eviction_time));
+
+    // Outside of the capacity schedule, the target user count from the settings is used.
+    dummy_time_provider.increase_by(60_000);
+    let check_time = dummy_time_provider.get_now_time();
+    nodes[0].fault_detection().unwrap();
+    let status = nodes[0].status();
+    assert_eq!(status.room.target_user_count, 1);
+    assert_eq!(status.fault_detection.last_check_time, check_time);
+    assert_eq!(status.fault_detection.checking_node, Some(1));
+    process_messages(&mut nodes, 10);
+    assert_eq!(nodes[0].status().fault_detection.checking_node, None);
+}
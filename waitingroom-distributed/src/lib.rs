@@ -5,4 +5,4 @@ pub mod messages;
 mod distributed;
 
 pub use distributed::DistributedWaitingRoom;
-pub use weight_table::Weight;
+pub use weight_table::{QueueSummary, Weight};
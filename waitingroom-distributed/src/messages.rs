@@ -1,7 +1,42 @@
-use waitingroom_core::{time::Time, NodeId};
+use waitingroom_core::{
+    admission::AdmissionState,
+    settings::GeneralWaitingRoomSettings,
+    ticket::TicketIdentifier,
+    time::Time,
+    wire::{VersionedWireMessage, WireMessage, WireReader, WireWriter},
+    NodeId, WireError,
+};
 use waitingroom_spanning_trees::SpanningTree;
 
-use crate::weight_table::Weight;
+use crate::weight_table::{QueueSummary, Weight};
+
+/// The protocol version written in the header of every message.
+/// This needs to be increased whenever the encoding of any message changes, or a message type is added.
+/// Nodes keep decoding and encoding older versions down to [`MIN_SUPPORTED_PROTOCOL_VERSION`], so a cluster can be
+/// upgraded one node at a time. [`waitingroom_core::network::VersionedNetwork`] makes sure messages to a node
+/// running an older build are encoded at a version it understands. Fields that are added are decoded from older
+/// versions with their default, so the minimum version only has to be raised when that is not possible.
+///
+/// Version 2 added the priority class to [`Weight`]. Version 1 is not supported anymore, because nodes that don't
+/// know about priority classes would order the QPID weights differently, which breaks the QPID invariant.
+/// Version 3 added the activation window to the settings. Version 2 is not supported anymore, since the settings
+/// can't be decoded without it.
+/// Version 4 added the admission mode to the settings, and the [`NodeToNodeMessage::AdmissionStateUpdate`] message.
+/// Version 3 is not supported anymore, for the same reason.
+/// Version 5 added the [`NodeToNodeMessage::KickTicket`] and [`NodeToNodeMessage::RevokePass`] messages.
+/// Version 6 added the capacity schedule to the settings. Versions 4 and 5 are not supported anymore, since the
+/// settings can't be decoded without it.
+/// Version 7 added the queue summaries to [`NodeToNodeMessage::CountResponse`], and the
+/// [`NodeToNodeMessage::QueueSummaries`] message. Version 6 is not supported anymore, since its count responses
+/// can't be decoded.
+/// Version 8 added the maximum queue length to the settings, which is decoded from version 7 as `None`.
+/// Version 9 added the challenge settings, which are decoded from versions 7 and 8 as `None`.
+/// Version 10 added the [`NodeToNodeMessage::KickTicketAck`] and [`NodeToNodeMessage::RevokePassAck`] messages.
+/// Older nodes drop them, so kicks and revocations are sent to them again until they expire.
+/// Version 11 added the [`NodeToNodeMessage::SettingsUpdateAck`] message. Older nodes drop it, so settings updates
+/// are sent to them again at every cleanup until they are upgraded.
+pub const PROTOCOL_VERSION: u16 = 11;
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u16 = 7;
 
 #[derive(Debug, Clone)]
 pub enum NodeToNodeMessage {
@@ -20,6 +55,8 @@ pub enum NodeToNodeMessage {
         iteration: Time,
         queue_count: usize,
         on_site_count: usize,
+        /// The queue summaries of every node below the sender in the count tree, including the sender.
+        queue_summaries: Vec<QueueSummary>,
     },
     FaultDetectionRequest(Time),
     FaultDetectionResponse(Time),
@@ -27,4 +64,451 @@ pub enum NodeToNodeMessage {
     NodeRemoved(NodeId, SpanningTree, usize),
     TreeRestructure(SpanningTree, usize),
     NodeJoin(NodeId),
+    SettingsUpdate {
+        /// Boxed, since the settings are much larger than any other message.
+        settings: Box<GeneralWaitingRoomSettings>,
+        version: u64,
+        origin: NodeId,
+    },
+    /// Sent by the QPID root to every node after it let users in, so the next root continues from the same state.
+    AdmissionStateUpdate(AdmissionState),
+    /// Sent to every node when an administrator kicks a user out of the queue, since any node could have the ticket.
+    KickTicket(TicketIdentifier),
+    /// Sent to every node when an administrator revokes a pass, since any node could have the pass.
+    RevokePass(TicketIdentifier),
+    /// Sent back to the sender of a [`NodeToNodeMessage::KickTicket`], which keeps sending it until it is acknowledged.
+    KickTicketAck(TicketIdentifier),
+    /// Sent back to the sender of a [`NodeToNodeMessage::RevokePass`], which keeps sending it until it is acknowledged.
+    RevokePassAck(TicketIdentifier),
+    /// Sent down the count tree by the count root when a count is done, so every node can estimate global queue
+    /// positions. Every node forwards it to its neighbours, other than the one it came from.
+    QueueSummaries {
+        iteration: Time,
+        summaries: Vec<QueueSummary>,
+    },
+    /// Sent back to the sender of a [`NodeToNodeMessage::SettingsUpdate`], which keeps sending it until it is
+    /// acknowledged.
+    SettingsUpdateAck {
+        version: u64,
+        origin: NodeId,
+    },
+}
+
+// The message types as they are sent over the network. These must never be reused for another message.
+const QPID_UPDATE_MESSAGE: u8 = 1;
+const QPID_DELETE_MIN: u8 = 2;
+const QPID_FIND_ROOT_MESSAGE: u8 = 3;
+const COUNT_REQUEST: u8 = 4;
+const COUNT_RESPONSE: u8 = 5;
+const FAULT_DETECTION_REQUEST: u8 = 6;
+const FAULT_DETECTION_RESPONSE: u8 = 7;
+const NODE_ADDED: u8 = 8;
+const NODE_REMOVED: u8 = 9;
+const TREE_RESTRUCTURE: u8 = 10;
+const NODE_JOIN: u8 = 11;
+const SETTINGS_UPDATE: u8 = 12;
+const ADMISSION_STATE_UPDATE: u8 = 13;
+const KICK_TICKET: u8 = 14;
+const REVOKE_PASS: u8 = 15;
+const QUEUE_SUMMARIES: u8 = 16;
+const KICK_TICKET_ACK: u8 = 17;
+const REVOKE_PASS_ACK: u8 = 18;
+const SETTINGS_UPDATE_ACK: u8 = 19;
+
+/// Every message starts with the protocol version (2 bytes) and the message type (1 byte), followed by its fields in order.
+impl WireMessage for NodeToNodeMessage {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_at(PROTOCOL_VERSION)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        let mut reader = WireReader::new(bytes);
+        let version = reader.read_u16()?;
+        if !(MIN_SUPPORTED_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+            return Err(WireError::UnsupportedVersion {
+                version,
+                supported: (MIN_SUPPORTED_PROTOCOL_VERSION, PROTOCOL_VERSION),
+            });
+        }
+
+        // Fields that were added after `version` are filled in with their default while decoding.
+        reader.set_version(version);
+
+        let message = match reader.read_u8()? {
+            QPID_UPDATE_MESSAGE => NodeToNodeMessage::QPIDUpdateMessage {
+                weight: reader.read()?,
+                updated_iteration: reader.read_u64()?,
+            },
+            QPID_DELETE_MIN => NodeToNodeMessage::QPIDDeleteMin,
+            QPID_FIND_ROOT_MESSAGE => NodeToNodeMessage::QPIDFindRootMessage {
+                weight: reader.read()?,
+                updated_iteration: reader.read_u64()?,
+                last_eviction: reader.read_time()?,
+            },
+            COUNT_REQUEST => NodeToNodeMessage::CountRequest(reader.read_time()?),
+            COUNT_RESPONSE => NodeToNodeMessage::CountResponse {
+                iteration: reader.read_time()?,
+                queue_count: reader.read_usize()?,
+                on_site_count: reader.read_usize()?,
+                queue_summaries: reader.read_list(|reader| reader.read())?,
+            },
+            FAULT_DETECTION_REQUEST => {
+                NodeToNodeMessage::FaultDetectionRequest(reader.read_time()?)
+            }
+            FAULT_DETECTION_RESPONSE => {
+                NodeToNodeMessage::FaultDetectionResponse(reader.read_time()?)
+            }
+            NODE_ADDED => NodeToNodeMessage::NodeAdded(
+                reader.read_node_id()?,
+                reader.read()?,
+                reader.read_usize()?,
+            ),
+            NODE_REMOVED => NodeToNodeMessage::NodeRemoved(
+                reader.read_node_id()?,
+                reader.read()?,
+                reader.read_usize()?,
+            ),
+            TREE_RESTRUCTURE => {
+                NodeToNodeMessage::TreeRestructure(reader.read()?, reader.read_usize()?)
+            }
+            NODE_JOIN => NodeToNodeMessage::NodeJoin(reader.read_node_id()?),
+            SETTINGS_UPDATE => NodeToNodeMessage::SettingsUpdate {
+                settings: Box::new(reader.read()?),
+                version: reader.read_u64()?,
+                origin: reader.read_node_id()?,
+            },
+            ADMISSION_STATE_UPDATE => NodeToNodeMessage::AdmissionStateUpdate(reader.read()?),
+            KICK_TICKET => NodeToNodeMessage::KickTicket(reader.read_u64()?),
+            REVOKE_PASS => NodeToNodeMessage::RevokePass(reader.read_u64()?),
+            QUEUE_SUMMARIES => NodeToNodeMessage::QueueSummaries {
+                iteration: reader.read_time()?,
+                summaries: reader.read_list(|reader| reader.read())?,
+            },
+            KICK_TICKET_ACK => NodeToNodeMessage::KickTicketAck(reader.read_u64()?),
+            REVOKE_PASS_ACK => NodeToNodeMessage::RevokePassAck(reader.read_u64()?),
+            SETTINGS_UPDATE_ACK => NodeToNodeMessage::SettingsUpdateAck {
+                version: reader.read_u64()?,
+                origin: reader.read_node_id()?,
+            },
+            message_type => return Err(WireError::UnknownMessageType(message_type)),
+        };
+        reader.finish()?;
+        Ok(message)
+    }
+}
+
+impl VersionedWireMessage for NodeToNodeMessage {
+    const VERSION: u16 = PROTOCOL_VERSION;
+    const MIN_SUPPORTED_VERSION: u16 = MIN_SUPPORTED_PROTOCOL_VERSION;
+
+    fn to_bytes_at(&self, version: u16) -> Vec<u8> {
+        let mut writer = WireWriter::with_version(version);
+        writer.put_u16(version);
+        match self {
+            NodeToNodeMessage::QPIDUpdateMessage {
+                weight,
+                updated_iteration,
+            } => {
+                writer.put_u8(QPID_UPDATE_MESSAGE);
+                writer.put(weight);
+                writer.put_u64(*updated_iteration);
+            }
+            NodeToNodeMessage::QPIDDeleteMin => writer.put_u8(QPID_DELETE_MIN),
+            NodeToNodeMessage::QPIDFindRootMessage {
+                weight,
+                updated_iteration,
+                last_eviction,
+            } => {
+                writer.put_u8(QPID_FIND_ROOT_MESSAGE);
+                writer.put(weight);
+                writer.put_u64(*updated_iteration);
+                writer.put_time(*last_eviction);
+            }
+            NodeToNodeMessage::CountRequest(iteration) => {
+                writer.put_u8(COUNT_REQUEST);
+                writer.put_time(*iteration);
+            }
+            NodeToNodeMessage::CountResponse {
+                iteration,
+                queue_count,
+                on_site_count,
+                queue_summaries,
+            } => {
+                writer.put_u8(COUNT_RESPONSE);
+                writer.put_time(*iteration);
+                writer.put_usize(*queue_count);
+                writer.put_usize(*on_site_count);
+                writer.put_list(queue_summaries, |writer, summary| writer.put(summary));
+            }
+            NodeToNodeMessage::FaultDetectionRequest(check_id) => {
+                writer.put_u8(FAULT_DETECTION_REQUEST);
+                writer.put_time(*check_id);
+            }
+            NodeToNodeMessage::FaultDetectionResponse(check_id) => {
+                writer.put_u8(FAULT_DETECTION_RESPONSE);
+                writer.put_time(*check_id);
+            }
+            NodeToNodeMessage::NodeAdded(node_id, tree, iteration) => {
+                writer.put_u8(NODE_ADDED);
+                writer.put_node_id(*node_id);
+                writer.put(tree);
+                writer.put_usize(*iteration);
+            }
+            NodeToNodeMessage::NodeRemoved(node_id, tree, iteration) => {
+                writer.put_u8(NODE_REMOVED);
+                writer.put_node_id(*node_id);
+                writer.put(tree);
+                writer.put_usize(*iteration);
+            }
+            NodeToNodeMessage::TreeRestructure(tree, iteration) => {
+                writer.put_u8(TREE_RESTRUCTURE);
+                writer.put(tree);
+                writer.put_usize(*iteration);
+            }
+            NodeToNodeMessage::NodeJoin(node_id) => {
+                writer.put_u8(NODE_JOIN);
+                writer.put_node_id(*node_id);
+            }
+            NodeToNodeMessage::SettingsUpdate {
+                settings,
+                version,
+                origin,
+            } => {
+                writer.put_u8(SETTINGS_UPDATE);
+                writer.put(settings.as_ref());
+                writer.put_u64(*version);
+                writer.put_node_id(*origin);
+            }
+            NodeToNodeMessage::AdmissionStateUpdate(state) => {
+                writer.put_u8(ADMISSION_STATE_UPDATE);
+                writer.put(state);
+            }
+            NodeToNodeMessage::KickTicket(ticket_identifier) => {
+                writer.put_u8(KICK_TICKET);
+                writer.put_u64(*ticket_identifier);
+            }
+            NodeToNodeMessage::RevokePass(pass_identifier) => {
+                writer.put_u8(REVOKE_PASS);
+                writer.put_u64(*pass_identifier);
+            }
+            NodeToNodeMessage::QueueSummaries {
+                iteration,
+                summaries,
+            } => {
+                writer.put_u8(QUEUE_SUMMARIES);
+                writer.put_time(*iteration);
+                writer.put_list(summaries, |writer, summary| writer.put(summary));
+            }
+            NodeToNodeMessage::KickTicketAck(ticket_identifier) => {
+                writer.put_u8(KICK_TICKET_ACK);
+                writer.put_u64(*ticket_identifier);
+            }
+            NodeToNodeMessage::RevokePassAck(pass_identifier) => {
+                writer.put_u8(REVOKE_PASS_ACK);
+                writer.put_u64(*pass_identifier);
+            }
+            NodeToNodeMessage::SettingsUpdateAck { version, origin } => {
+                writer.put_u8(SETTINGS_UPDATE_ACK);
+                writer.put_u64(*version);
+                writer.put_node_id(*origin);
+            }
+        }
+        writer.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use waitingroom_core::settings::ChallengeSettings;
+
+    use super::*;
+
+    fn round_trip(message: NodeToNodeMessage) {
+        let bytes = message.to_bytes();
+        let decoded = NodeToNodeMessage::from_bytes(&bytes).unwrap();
+        // Weight ignores the node ID when comparing, so we compare the debug output to check every field.
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+    }
+
+    #[test]
+    fn all_variants_round_trip() {
+        let weight = Weight::new(1234, 5678, 3);
+        let tree = SpanningTree::from_member_list(vec![0, 1, 2, 3, 4, 5, 6]);
+        let messages = vec![
+            NodeToNodeMessage::QPIDUpdateMessage {
+                weight,
+                updated_iteration: 7,
+            },
+            NodeToNodeMessage::QPIDUpdateMessage {
+                weight: Weight::new(Time::MAX, 0, 1),
+                updated_iteration: 0,
+            },
+            NodeToNodeMessage::QPIDDeleteMin,
+            NodeToNodeMessage::QPIDFindRootMessage {
+                weight,
+                updated_iteration: 8,
+                last_eviction: 99,
+            },
+            NodeToNodeMessage::CountRequest(100),
+            NodeToNodeMessage::CountResponse {
+                iteration: 100,
+                queue_count: 12,
+                on_site_count: 34,
+                queue_summaries: vec![QueueSummary {
+                    front: weight,
+                    count: 12,
+                }],
+            },
+            NodeToNodeMessage::FaultDetectionRequest(200),
+            NodeToNodeMessage::FaultDetectionResponse(200),
+            NodeToNodeMessage::NodeAdded(6, tree.clone(), 3),
+            NodeToNodeMessage::NodeRemoved(2, tree.clone(), 4),
+            NodeToNodeMessage::TreeRestructure(tree, 5),
+            NodeToNodeMessage::TreeRestructure(SpanningTree::new_empty(), 0),
+            NodeToNodeMessage::NodeJoin(9),
+            NodeToNodeMessage::SettingsUpdate {
+                settings: Box::new(GeneralWaitingRoomSettings {
+                    target_user_count: 3,
+                    ..Default::default()
+                }),
+                version: 2,
+                origin: 1,
+            },
+            NodeToNodeMessage::AdmissionStateUpdate(AdmissionState {
+                tokens: 120000,
+                updated_at: 300,
+            }),
+            NodeToNodeMessage::KickTicket(42),
+            NodeToNodeMessage::RevokePass(43),
+            NodeToNodeMessage::KickTicketAck(44),
+            NodeToNodeMessage::RevokePassAck(45),
+            NodeToNodeMessage::SettingsUpdateAck {
+                version: 2,
+                origin: 1,
+            },
+            NodeToNodeMessage::QueueSummaries {
+                iteration: 100,
+                summaries: vec![QueueSummary {
+                    front: weight,
+                    count: 12,
+                }],
+            },
+        ];
+        for message in messages {
+            round_trip(message);
+        }
+    }
+
+    /// The encoding must stay the same within a protocol version, otherwise nodes running
+    /// different builds of the same version can't understand each other.
+    #[test]
+    fn encoding_is_stable() {
+        let bytes = NodeToNodeMessage::CountResponse {
+            iteration: 0x0102,
+            queue_count: 3,
+            on_site_count: 4,
+            queue_summaries: vec![],
+        }
+        .to_bytes();
+        let mut expected = vec![0, 11, COUNT_RESPONSE];
+        expected.extend_from_slice(&0x0102u128.to_be_bytes());
+        expected.extend_from_slice(&3u64.to_be_bytes());
+        expected.extend_from_slice(&4u64.to_be_bytes());
+        expected.extend_from_slice(&0u32.to_be_bytes());
+        assert_eq!(bytes, expected);
+
+        let bytes = NodeToNodeMessage::QPIDUpdateMessage {
+            weight: Weight::new(5, 6, 7),
+            updated_iteration: 8,
+        }
+        .to_bytes();
+        let mut expected = vec![0, 11, QPID_UPDATE_MESSAGE, 0];
+        expected.extend_from_slice(&5u128.to_be_bytes());
+        expected.extend_from_slice(&6u64.to_be_bytes());
+        expected.extend_from_slice(&7u64.to_be_bytes());
+        expected.extend_from_slice(&8u64.to_be_bytes());
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn older_versions_are_decoded() {
+        let settings = GeneralWaitingRoomSettings {
+            target_user_count: 5,
+            max_queue_length: Some(100),
+            challenge: Some(ChallengeSettings {
+                difficulty: 16,
+                expiry_time: 60_000,
+            }),
+            ..Default::default()
+        };
+        let message = NodeToNodeMessage::SettingsUpdate {
+            settings: Box::new(settings.clone()),
+            version: 3,
+            origin: 1,
+        };
+
+        // A node at version 8 doesn't know about challenges, so they are left out.
+        let bytes = message.to_bytes_at(8);
+        assert_eq!(bytes[..2], 8u16.to_be_bytes());
+        let NodeToNodeMessage::SettingsUpdate {
+            settings: decoded, ..
+        } = NodeToNodeMessage::from_bytes(&bytes).unwrap()
+        else {
+            panic!("expected a settings update");
+        };
+        assert_eq!(
+            *decoded,
+            GeneralWaitingRoomSettings {
+                challenge: None,
+                ..settings.clone()
+            }
+        );
+
+        // A node at version 7 doesn't know about the maximum queue length either.
+        let bytes = message.to_bytes_at(7);
+        let NodeToNodeMessage::SettingsUpdate {
+            settings: decoded, ..
+        } = NodeToNodeMessage::from_bytes(&bytes).unwrap()
+        else {
+            panic!("expected a settings update");
+        };
+        assert_eq!(
+            *decoded,
+            GeneralWaitingRoomSettings {
+                max_queue_length: None,
+                challenge: None,
+                ..settings
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_and_newer_messages() {
+        let mut bytes = NodeToNodeMessage::NodeJoin(1).to_bytes();
+
+        bytes[..2].copy_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+        assert!(matches!(
+            NodeToNodeMessage::from_bytes(&bytes),
+            Err(WireError::UnsupportedVersion { version, .. }) if version == PROTOCOL_VERSION + 1
+        ));
+
+        bytes[..2].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        bytes[2] = 200;
+        assert!(matches!(
+            NodeToNodeMessage::from_bytes(&bytes),
+            Err(WireError::UnknownMessageType(200))
+        ));
+
+        let bytes = NodeToNodeMessage::CountRequest(1).to_bytes();
+        assert!(matches!(
+            NodeToNodeMessage::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(WireError::UnexpectedEnd)
+        ));
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert!(matches!(
+            NodeToNodeMessage::from_bytes(&bytes),
+            Err(WireError::TrailingBytes(1))
+        ));
+    }
 }
@@ -1,29 +1,55 @@
-use waitingroom_core::{ticket::TicketIdentifier, time::Time, NodeId};
+use waitingroom_core::{
+    ticket::{PriorityClass, Ticket, TicketIdentifier, DEFAULT_PRIORITY_CLASS},
+    time::Time,
+    wire::{WireDecode, WireEncode, WireReader, WireWriter},
+    NodeId, WireError,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Weight {
+    priority_class: PriorityClass,
     join_time: Time,
     ticket_id: TicketIdentifier,
     node_id: NodeId,
 }
 
 impl Weight {
+    /// Creates a weight in the default priority class. This is also used for the maximum weight,
+    /// since no class is lower than the default one.
     pub fn new(join_time: Time, ticket_id: TicketIdentifier, node_id: NodeId) -> Self {
         Weight {
+            priority_class: DEFAULT_PRIORITY_CLASS,
             join_time,
             ticket_id,
             node_id,
         }
     }
 
+    /// Creates the weight of a ticket in the local queue of the given node.
+    pub fn from_ticket(ticket: &Ticket, node_id: NodeId) -> Self {
+        Weight {
+            priority_class: ticket.priority_class,
+            join_time: ticket.join_time,
+            ticket_id: ticket.identifier,
+            node_id,
+        }
+    }
+
     pub fn is_max(&self) -> bool {
         self.join_time == Time::MAX
     }
+
+    /// Returns the node that has the ticket with this weight in its local queue.
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
 }
 
 impl PartialEq for Weight {
     fn eq(&self, other: &Self) -> bool {
-        self.join_time == other.join_time && self.ticket_id == other.ticket_id
+        self.priority_class == other.priority_class
+            && self.join_time == other.join_time
+            && self.ticket_id == other.ticket_id
     }
 }
 
@@ -37,14 +63,89 @@ impl PartialOrd for Weight {
 
 impl Ord for Weight {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // We first compare on join time, then on ticket id, and finally on node id
-        self.join_time
-            .cmp(&other.join_time)
+        // We first compare on priority class (higher classes are smaller, so they leave the queue first),
+        // then on join time, then on ticket id, and finally on node id
+        other
+            .priority_class
+            .cmp(&self.priority_class)
+            .then_with(|| self.join_time.cmp(&other.join_time))
             .then_with(|| self.ticket_id.cmp(&other.ticket_id))
             .then_with(|| self.node_id.cmp(&other.node_id))
     }
 }
 
+impl WireEncode for Weight {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put_u8(self.priority_class);
+        writer.put_time(self.join_time);
+        writer.put_u64(self.ticket_id);
+        writer.put_node_id(self.node_id);
+    }
+}
+
+impl WireDecode for Weight {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        Ok(Weight {
+            priority_class: reader.read_u8()?,
+            join_time: reader.read_time()?,
+            ticket_id: reader.read_u64()?,
+            node_id: reader.read_node_id()?,
+        })
+    }
+}
+
+/// The front of the local queue of a node, and the number of tickets in it. The nodes share these through
+/// the count, so every node can estimate where its users are among all queued users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueSummary {
+    pub front: Weight,
+    pub count: usize,
+}
+
+impl QueueSummary {
+    /// Estimates how many tickets in this queue are ahead of a ticket with the given weight. Only the front
+    /// of the queue is known, so the tickets behind it are assumed to have joined evenly between the front and now.
+    pub fn tickets_ahead(&self, weight: &Weight, now: Time) -> usize {
+        if *weight <= self.front {
+            return 0;
+        }
+        if weight.priority_class != self.front.priority_class {
+            // The ticket is in a lower class than the front, so the queue could be ahead of it entirely.
+            return self.count;
+        }
+        let span = now.saturating_sub(self.front.join_time);
+        if span == 0 {
+            return self.count;
+        }
+        let waited = weight.join_time.saturating_sub(self.front.join_time);
+        ((self.count as u128 * waited / span) as usize).min(self.count)
+    }
+
+    /// Removes the front ticket from the summary, for when it is let out of the queue. The next ticket is assumed
+    /// to have joined evenly spaced between the front and now, like in [`QueueSummary::tickets_ahead`].
+    pub fn remove_front(&mut self, now: Time) {
+        let span = now.saturating_sub(self.front.join_time);
+        self.front.join_time += span / self.count as u128;
+        self.count -= 1;
+    }
+}
+
+impl WireEncode for QueueSummary {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put(&self.front);
+        writer.put_usize(self.count);
+    }
+}
+
+impl WireDecode for QueueSummary {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        Ok(QueueSummary {
+            front: reader.read()?,
+            count: reader.read_usize()?,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Entry {
     update_iteration: u64,
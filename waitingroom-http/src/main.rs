@@ -6,19 +6,21 @@ use std::sync::{Arc, Mutex};
 
 use axum::http::HeaderValue;
 
-// use foundations::cli::{Arg, ArgAction, Cli};
-// use foundations::telemetry::{init_with_server, log, tracing, TelemetryContext};
 use hyper::StatusCode;
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::rt::TokioExecutor;
+use metrics_exporter_prometheus::PrometheusBuilder;
 
-// use settings::HttpServerSettings;
+use settings::HttpServerSettings;
 use waitingroom_basic::BasicWaitingRoom;
+use waitingroom_core::challenge::{Challenge, ChallengeSolution};
 use waitingroom_core::pass::Pass;
 use waitingroom_core::random::TrueRandomProvider;
+use waitingroom_core::throughput::AdmissionEstimate;
 use waitingroom_core::ticket::Ticket;
 use waitingroom_core::time::SystemTimeProvider;
-use waitingroom_core::WaitingRoomUserTriggered;
+use waitingroom_core::{WaitingRoomError, WaitingRoomUserTriggered};
+use waitingroom_metrics::RecorderSink;
 
 use axum::{
     body::Body,
@@ -31,9 +33,12 @@ use axum::{
 
 use axum_extra::extract::cookie::{Cookie, Key, SignedCookieJar};
 
-// mod demo_server;
-// mod settings;
-// mod timers;
+mod demo_server;
+mod settings;
+mod timers;
+
+/// Environment variables starting with this prefix, followed by `__`, override the settings from the config file.
+const SETTINGS_ENV_PREFIX: &str = "WAITINGROOM_HTTP";
 
 type Client = hyper_util::client::legacy::Client<HttpConnector, Body>;
 
@@ -42,7 +47,7 @@ struct AppState {
     waitingroom: Arc<Mutex<BasicWaitingRoom<SystemTimeProvider, TrueRandomProvider>>>,
     client: Client,
     key: Key,
-    // settings: HttpServerSettings,
+    settings: HttpServerSettings,
 }
 
 #[derive(Debug)]
@@ -53,6 +58,10 @@ enum WaitingRoomStatus {
     NewPass,
     PassRefreshed,
     InvalidPass,
+    /// The queue is full, and the user should try again after this many seconds.
+    QueueFull(u64),
+    /// The user has to solve a challenge before they can join.
+    ChallengeRequired,
 }
 
 impl WaitingRoomStatus {
@@ -72,6 +81,13 @@ impl WaitingRoomStatus {
             WaitingRoomStatus::InvalidPass => {
                 "Pass invalid... Rejoining waiting room...".to_string()
             }
+            WaitingRoomStatus::QueueFull(seconds) => format!(
+                "The waiting room is full... Trying again in {} seconds...",
+                seconds
+            ),
+            WaitingRoomStatus::ChallengeRequired => {
+                "Getting ready to join the waiting room...".to_string()
+            }
             WaitingRoomStatus::NewPass => "You left the waiting room! Redirecting...".to_string(),
             WaitingRoomStatus::PassRefreshed => {
                 panic!("get_text() should not be called on PassRefreshed")
@@ -108,230 +124,436 @@ fn make_response(
     (jar, response)
 }
 
-// async fn handler(
-//     State(state): State<AppState>,
-//     mut req: Request,
-// ) -> Result<(SignedCookieJar, Response), StatusCode> {
-//     log::debug!("Request to waiting room");
-//     let jar = SignedCookieJar::from_headers(req.headers(), state.key.clone());
-//     if let Some(pass) = match jar.get("pass") {
-//         Some(cookie) => {
-//             log::debug!("Pass cookie found");
-//             let pass: Pass = serde_json::from_str(cookie.value()).unwrap();
-//             Some(pass)
-//         }
-//         None => None,
-//     } {
-//         log::add_fields! {
-//             "pass_id" => pass.identifier,
-//         }
-//         let pass = match state
-//             .waitingroom
-//             .lock()
-//             .unwrap()
-//             .validate_and_refresh_pass(pass)
-//         {
-//             Ok(pass) => pass,
-//             Err(err) => {
-//                 log::debug!("Pass was invalid: {:?}", err);
-//                 return Ok(make_response(
-//                     jar.remove("pass"),
-//                     Some(3),
-//                     WaitingRoomStatus::InvalidPass,
-//                 ));
-//             }
-//         };
-//         log::debug!("Pass refreshed");
-//         let cookie = Cookie::build(("pass", serde_json::to_string(&pass).unwrap()))
-//             .secure(true)
-//             .http_only(true);
-
-//         let path = req.uri().path();
-//         let path_query = req
-//             .uri()
-//             .path_and_query()
-//             .map(|v| v.as_str())
-//             .unwrap_or(path);
-
-//         let uri = format!("http://{}{}", state.settings.proxy_address, path_query);
-
-//         *req.uri_mut() = Uri::try_from(uri).unwrap();
-
-//         let mut response = state
-//             .client
-//             .request(req)
-//             .await
-//             .map_err(|_| StatusCode::BAD_REQUEST)?
-//             .into_response();
-
-//         response.headers_mut().insert(
-//             "X-WR-Status",
-//             WaitingRoomStatus::PassRefreshed.get_header_value(),
-//         );
-
-//         return Ok((jar.add(cookie), response));
-//     };
-
-//     let ticket: Option<Ticket> = match jar.get("ticket") {
-//         Some(cookie) => {
-//             log::debug!("Ticket cookie found");
-//             let ticket = serde_json::from_str(cookie.value()).unwrap();
-//             Some(ticket)
-//         }
-//         None => None,
-//     };
-
-//     match ticket {
-//         Some(ticket) => {
-//             log::add_fields! {
-//                 "ticket_id" => ticket.identifier,
-//             }
-//             let checkin_response = match state.waitingroom.lock().unwrap().check_in(ticket) {
-//                 Ok(checkin_response) => checkin_response,
-//                 Err(err) => {
-//                     log::debug!("Ticket was invalid: {:?}", err);
-//                     return Ok(make_response(
-//                         jar.remove("ticket"),
-//                         Some(3),
-//                         WaitingRoomStatus::InvalidTicket,
-//                     ));
-//                 }
-//             };
-
-//             log::debug!("Ticket refreshed");
-
-//             if checkin_response.position_estimate == 0 {
-//                 log::debug!("User is at the front of the queue");
-//                 let pass = state
-//                     .waitingroom
-//                     .lock()
-//                     .unwrap()
-//                     .leave(checkin_response.new_ticket)
-//                     .unwrap();
-
-//                 let cookie = Cookie::build(("pass", serde_json::to_string(&pass).unwrap()))
-//                     .secure(true)
-//                     .http_only(true);
-//                 return Ok(make_response(
-//                     jar.add(cookie).remove("ticket"),
-//                     Some(1),
-//                     WaitingRoomStatus::NewPass,
-//                 ));
-//             } else {
-//                 log::debug!("User is at position {}", checkin_response.position_estimate);
-//                 let cookie = Cookie::build((
-//                     "ticket",
-//                     serde_json::to_string(&checkin_response.new_ticket).unwrap(),
-//                 ))
-//                 .secure(true)
-//                 .http_only(true);
-
-//                 let now = std::time::SystemTime::now()
-//                     .duration_since(std::time::UNIX_EPOCH)
-//                     .unwrap()
-//                     .as_millis();
-//                 return Ok(make_response(
-//                     jar.add(cookie),
-//                     Some(
-//                         ((checkin_response.new_ticket.next_refresh_time as i128 - now as i128)
-//                             / 1000) as u64,
-//                     ),
-//                     WaitingRoomStatus::TicketRefreshed(checkin_response.position_estimate),
-//                 ));
-//             }
-//         }
-//         None => {
-//             let ticket = state.waitingroom.lock().unwrap().join().unwrap();
-//             log::add_fields! {
-//                 "ticket_id" => ticket.identifier,
-//             }
-//             log::debug!("New ticket issued");
-//             return Ok(make_response(
-//                 jar.add(
-//                     Cookie::build(("ticket", serde_json::to_string(&ticket).unwrap()))
-//                         .secure(true)
-//                         .http_only(true),
-//                 ),
-//                 Some(1),
-//                 WaitingRoomStatus::NewTicket,
-//             ));
-//         }
-//     }
-// }
+/// Adds the estimated wait to a response for a user in the queue. The wait is sent in seconds from now,
+/// so it doesn't depend on the clock of the client.
+fn add_admission_estimate(
+    response: &mut Response,
+    position: usize,
+    estimate: AdmissionEstimate,
+    now: u128,
+) {
+    let seconds_until = |time: u128| time.saturating_sub(now) / 1000;
+    let expected = seconds_until(estimate.expected_at);
+    let (earliest, latest) = (
+        seconds_until(estimate.earliest),
+        seconds_until(estimate.latest),
+    );
+
+    response.headers_mut().insert(
+        "X-WR-Estimated-Wait",
+        HeaderValue::from_str(&format!("{}", expected)).unwrap(),
+    );
+    response.headers_mut().insert(
+        "X-WR-Estimated-Wait-Range",
+        HeaderValue::from_str(&format!("{}-{}", earliest, latest)).unwrap(),
+    );
+    *response.body_mut() = Body::from(format!(
+        "{}\nEstimated wait: {} seconds ({} to {} seconds)",
+        WaitingRoomStatus::TicketRefreshed(position).get_text(),
+        expected,
+        earliest,
+        latest
+    ));
+}
+
+/// The page that solves the challenge in the browser, and then joins the waiting room with the solution.
+const CHALLENGE_PAGE: &str = include_str!("challenge.html");
+
+/// Makes a response with a challenge for the user to solve. Browsers get a page that solves it, other clients
+/// can solve it from the headers. Either way, the challenge token is sent back in the `X-WR-Challenge`
+/// header, together with the solution in the `X-WR-Solution` header.
+fn make_challenge_response(
+    jar: SignedCookieJar,
+    challenge: Challenge,
+) -> (SignedCookieJar, Response) {
+    let (jar, mut response) = make_response(jar, None, WaitingRoomStatus::ChallengeRequired);
+    for (name, value) in [
+        ("X-WR-Challenge", challenge.to_token()),
+        ("X-WR-Challenge-Prefix", hex::encode(challenge.prefix())),
+        (
+            "X-WR-Challenge-Difficulty",
+            challenge.difficulty.to_string(),
+        ),
+    ] {
+        response
+            .headers_mut()
+            .insert(name, HeaderValue::from_str(&value).unwrap());
+    }
+    response.headers_mut().insert(
+        "Content-Type",
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    *response.body_mut() = Body::from(challenge_page(&challenge));
+    (jar, response)
+}
+
+/// Fills in the challenge in [`CHALLENGE_PAGE`].
+fn challenge_page(challenge: &Challenge) -> String {
+    CHALLENGE_PAGE
+        .replace("__CHALLENGE__", &challenge.to_token())
+        .replace("__PREFIX__", &hex::encode(challenge.prefix()))
+        .replace("__DIFFICULTY__", &challenge.difficulty.to_string())
+}
+
+/// Reads the solution to a challenge from the headers of a request, if there is one.
+fn challenge_solution(req: &Request) -> Option<ChallengeSolution> {
+    let header = |name| req.headers().get(name)?.to_str().ok();
+    Some(ChallengeSolution {
+        challenge: Challenge::from_token(header("X-WR-Challenge")?).ok()?,
+        solution: u64::from_str_radix(header("X-WR-Solution")?, 16).ok()?,
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    mut req: Request,
+) -> Result<(SignedCookieJar, Response), StatusCode> {
+    log::debug!("Request to waiting room");
+    let jar = SignedCookieJar::from_headers(req.headers(), state.key.clone());
+    if let Some(pass) = match jar.get("pass") {
+        Some(cookie) => {
+            log::debug!("Pass cookie found");
+            let pass: Pass = serde_json::from_str(cookie.value()).unwrap();
+            Some(pass)
+        }
+        None => None,
+    } {
+        log::debug!("Pass id: {}", pass.identifier);
+        let pass = match state
+            .waitingroom
+            .lock()
+            .unwrap()
+            .validate_and_refresh_pass(pass)
+        {
+            Ok(pass) => pass,
+            Err(err) => {
+                log::debug!("Pass was invalid: {:?}", err);
+                return Ok(make_response(
+                    jar.remove("pass"),
+                    Some(3),
+                    WaitingRoomStatus::InvalidPass,
+                ));
+            }
+        };
+        log::debug!("Pass refreshed");
+        let cookie = Cookie::build(("pass", serde_json::to_string(&pass).unwrap()))
+            .secure(true)
+            .http_only(true);
+
+        let path = req.uri().path();
+        let path_query = req
+            .uri()
+            .path_and_query()
+            .map(|v| v.as_str())
+            .unwrap_or(path);
+
+        let uri = format!("http://{}{}", state.settings.proxy_address, path_query);
+
+        *req.uri_mut() = Uri::try_from(uri).unwrap();
+
+        let mut response = state
+            .client
+            .request(req)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?
+            .into_response();
+
+        response.headers_mut().insert(
+            "X-WR-Status",
+            WaitingRoomStatus::PassRefreshed.get_header_value(),
+        );
+
+        return Ok((jar.add(cookie), response));
+    };
+
+    let ticket: Option<Ticket> = match jar.get("ticket") {
+        Some(cookie) => {
+            log::debug!("Ticket cookie found");
+            let ticket = serde_json::from_str(cookie.value()).unwrap();
+            Some(ticket)
+        }
+        None => None,
+    };
+
+    match ticket {
+        Some(ticket) => {
+            log::debug!("Ticket id: {}", ticket.identifier);
+            let checkin_response = match state.waitingroom.lock().unwrap().check_in(ticket) {
+                Ok(checkin_response) => checkin_response,
+                Err(err) => {
+                    log::debug!("Ticket was invalid: {:?}", err);
+                    return Ok(make_response(
+                        jar.remove("ticket"),
+                        Some(3),
+                        WaitingRoomStatus::InvalidTicket,
+                    ));
+                }
+            };
+
+            log::debug!("Ticket refreshed");
+
+            if checkin_response.position_estimate == 0 {
+                log::debug!("User is at the front of the queue");
+                let pass = state
+                    .waitingroom
+                    .lock()
+                    .unwrap()
+                    .leave(checkin_response.new_ticket)
+                    .unwrap();
+
+                let cookie = Cookie::build(("pass", serde_json::to_string(&pass).unwrap()))
+                    .secure(true)
+                    .http_only(true);
+                Ok(make_response(
+                    jar.add(cookie).remove("ticket"),
+                    Some(1),
+                    WaitingRoomStatus::NewPass,
+                ))
+            } else {
+                log::debug!("User is at position {}", checkin_response.position_estimate);
+                let cookie = Cookie::build((
+                    "ticket",
+                    serde_json::to_string(&checkin_response.new_ticket).unwrap(),
+                ))
+                .secure(true)
+                .http_only(true);
+
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis();
+                let (jar, mut response) = make_response(
+                    jar.add(cookie),
+                    Some(
+                        ((checkin_response.new_ticket.next_refresh_time as i128 - now as i128)
+                            / 1000) as u64,
+                    ),
+                    WaitingRoomStatus::TicketRefreshed(checkin_response.position_estimate),
+                );
+                if let Some(estimate) = checkin_response.admission_estimate {
+                    add_admission_estimate(
+                        &mut response,
+                        checkin_response.position_estimate,
+                        estimate,
+                        now,
+                    );
+                }
+                Ok((jar, response))
+            }
+        }
+        None => {
+            let joined = match challenge_solution(&req) {
+                Some(solution) => state
+                    .waitingroom
+                    .lock()
+                    .unwrap()
+                    .join_with_solution(solution),
+                None => state.waitingroom.lock().unwrap().join(),
+            };
+            let ticket = match joined {
+                Ok(ticket) => ticket,
+                Err(
+                    err @ (WaitingRoomError::ChallengeRequired
+                    | WaitingRoomError::InvalidChallengeSolution),
+                ) => {
+                    log::debug!("User needs to solve a challenge: {}", err);
+                    let Some(challenge) = state.waitingroom.lock().unwrap().challenge() else {
+                        log::error!("The waiting room requires a challenge, but gave none out");
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    };
+                    return Ok(make_challenge_response(jar, challenge));
+                }
+                Err(WaitingRoomError::QueueFull { retry_after }) => {
+                    log::debug!("Queue is full");
+                    // Rounded up, so the user doesn't come back too early.
+                    let seconds = retry_after.div_ceil(1000) as u64;
+                    let (jar, mut response) =
+                        make_response(jar, Some(seconds), WaitingRoomStatus::QueueFull(seconds));
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    response.headers_mut().insert(
+                        "Retry-After",
+                        HeaderValue::from_str(&format!("{}", seconds)).unwrap(),
+                    );
+                    return Ok((jar, response));
+                }
+                Err(err) => {
+                    log::error!("Failed to join: {}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            };
+            log::debug!("Ticket id: {}", ticket.identifier);
+            log::debug!("New ticket issued");
+            Ok(make_response(
+                jar.add(
+                    Cookie::build(("ticket", serde_json::to_string(&ticket).unwrap()))
+                        .secure(true)
+                        .http_only(true),
+                ),
+                Some(1),
+                WaitingRoomStatus::NewTicket,
+            ))
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Obtain service information from Cargo.toml
-    // let service_info = foundations::service_info!();
-
-    // let cli = Cli::<HttpServerSettings>::new(
-    //     &service_info,
-    //     vec![Arg::new("dry-run")
-    //         .long("dry-run")
-    //         .action(ArgAction::SetTrue)
-    //         .help("Validate or generate config without running the server")],
-    // )?;
-
-    // if cli.arg_matches.get_flag("dry-run") {
-    //     return Ok(());
-    // }
-
-    // // Initialize telemetry with the settings obtained from the config.
-    // let telemetry_server_fut = init_with_server(&service_info, &cli.settings.telemetry, vec![])?;
-    // if let Some(telemetry_server_addr) = telemetry_server_fut.server_addr() {
-    //     log::info!(
-    //         "Telemetry server is listening on http://{}",
-    //         telemetry_server_addr
-    //     );
-    // }
-
-    // // Only start the demo HTTP server if it is enabled in the config.
-    // if cli.settings.demo_http_server.enabled {
-    //     tokio::spawn(demo_server::demo_server(
-    //         cli.settings.demo_http_server.listening_address,
-    //     ));
-    // }
-
-    // // The waiting room is in an Arc<Mutex<_>>, because it does not support any concurrency.
-    // let waitingroom = Arc::new(Mutex::new(BasicWaitingRoom::new(
-    //     cli.settings.waitingroom,
-    //     SystemTimeProvider::new(),
-    //     TrueRandomProvider::new(),
-    // )));
-
-    // let timers = timers::timers(waitingroom.clone(), &cli.settings.timer);
-
-    // let client: Client =
-    //     hyper_util::client::legacy::Client::<(), ()>::builder(TokioExecutor::new())
-    //         .build(HttpConnector::new());
-
-    // let app = Router::new()
-    //     .fallback(get(|state, req| {
-    //         // Each request gets its own telemetry context.
-    //         TelemetryContext::current()
-    //             .with_forked_log()
-    //             .apply(handler(state, req))
-    //     }))
-    //     .with_state(AppState {
-    //         waitingroom,
-    //         client,
-    //         key: Key::from(&hex::decode(&cli.settings.cookie_secret)?),
-    //         settings: cli.settings.clone(),
-    //     });
-
-    // let listener =
-    //     tokio::net::TcpListener::bind(std::net::SocketAddr::from(cli.settings.listening_address))
-    //         .await
-    //         .unwrap();
-    // log::info!(
-    //     "Waiting room listening on http://{}",
-    //     listener.local_addr().unwrap()
-    // );
-
-    // let web_server = axum::serve(listener, app).into_future();
-
-    // tokio::join!(timers, web_server, telemetry_server_fut)
-    //     .2
-    //     .unwrap();
+    env_logger::init();
+
+    // The only arguments are an optional path to the config file, and `--dry-run`,
+    // which only validates the config without running the server.
+    let mut config_path = None;
+    let mut dry_run = false;
+    for arg in std::env::args().skip(1) {
+        if arg == "--dry-run" {
+            dry_run = true;
+        } else {
+            config_path = Some(std::path::PathBuf::from(arg));
+        }
+    }
+
+    let settings: HttpServerSettings = match waitingroom_settings::load_with_env(
+        config_path.as_deref(),
+        Some(SETTINGS_ENV_PREFIX),
+    ) {
+        Ok(settings) => settings,
+        Err(err) => {
+            log::error!("{}", err);
+            return Err(err.into());
+        }
+    };
+
+    if dry_run {
+        log::info!("Settings are valid");
+        return Ok(());
+    }
+
+    // The room records its metrics to the recorder of the `metrics` crate, which is the Prometheus exporter.
+    // It needs to know the units and descriptions of the metrics before anything is recorded.
+    if let Some(metrics_listening_address) = settings.metrics_listening_address {
+        if let Err(err) = PrometheusBuilder::new()
+            .with_http_listener(metrics_listening_address)
+            .install()
+        {
+            log::error!("Failed to start the metrics exporter: {}", err);
+            return Err(err.into());
+        }
+        RecorderSink::describe_all();
+        log::info!(
+            "Metrics served on http://{}/metrics",
+            metrics_listening_address
+        );
+    }
+
+    // Only start the demo HTTP server if it is enabled in the config.
+    if settings.demo_http_server.enabled {
+        tokio::spawn(demo_server::demo_server(
+            settings.demo_http_server.listening_address,
+        ));
+    }
+
+    // The waiting room is in an Arc<Mutex<_>>, because it does not support any concurrency.
+    let mut waitingroom = BasicWaitingRoom::new(
+        settings.waitingroom.clone(),
+        SystemTimeProvider::new(),
+        TrueRandomProvider::new(),
+    );
+    if let Some(journal_path) = &settings.journal_path {
+        // This also restores the queue from before the server restarted.
+        if let Err(err) = waitingroom.open_journal(journal_path) {
+            log::error!("Failed to open the journal: {}", err);
+            return Err(err.to_string().into());
+        }
+    }
+    let waitingroom = Arc::new(Mutex::new(waitingroom));
+
+    let timers = timers::timers(waitingroom.clone(), &settings.timer);
+
+    let client: Client =
+        hyper_util::client::legacy::Client::<(), ()>::builder(TokioExecutor::new())
+            .build(HttpConnector::new());
+
+    let listener = tokio::net::TcpListener::bind(settings.listening_address).await?;
+    log::info!(
+        "Waiting room listening on http://{}",
+        listener.local_addr()?
+    );
+
+    let app = Router::new().fallback(get(handler)).with_state(AppState {
+        waitingroom,
+        client,
+        key: Key::from(&hex::decode(&settings.cookie_secret)?),
+        settings: settings.clone(),
+    });
+
+    let web_server = axum::serve(listener, app).into_future();
+
+    tokio::join!(timers, web_server).1?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    use super::*;
+
+    /// Runs the script of the challenge page in Node.js, with just enough of a browser around it, and returns
+    /// the solution it sends back. Returns `None` if Node.js is not installed.
+    fn solve_in_page(challenge: &Challenge) -> Option<u64> {
+        let page = challenge_page(challenge);
+        let script = page
+            .split("<script>")
+            .nth(1)
+            .and_then(|rest| rest.split("</script>").next())
+            .unwrap();
+        let browser = r#"
+            const document = { getElementById: () => ({}) };
+            const location = { href: "", reload: () => {} };
+            const fetch = (_, options) => {
+                console.log(options.headers["X-WR-Solution"]);
+                return { finally: () => {} };
+            };
+        "#;
+
+        let mut node = match Command::new("node")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(node) => node,
+            Err(err) => {
+                eprintln!(
+                    "Skipping the challenge page test, Node.js is not available: {}",
+                    err
+                );
+                return None;
+            }
+        };
+        let mut stdin = node.stdin.take().unwrap();
+        stdin.write_all(browser.as_bytes()).unwrap();
+        stdin.write_all(script.as_bytes()).unwrap();
+        drop(stdin);
+        let output = node.wait_with_output().unwrap();
+        assert!(output.status.success());
+        let solution = String::from_utf8(output.stdout).unwrap();
+        Some(u64::from_str_radix(solution.trim(), 16).unwrap())
+    }
+
+    #[test]
+    fn challenge_page_finds_same_solution() {
+        // The same challenges as the tests in waitingroom_core::challenge, so the page is checked against
+        // the SHA-256 implementation the rooms use.
+        for (difficulty, expected) in [(8, 51), (16, 14898)] {
+            let challenge =
+                Challenge::new(0x0123456789abcdef, 1_700_000_000_000, 30_000, difficulty);
+            assert_eq!(challenge.solve().solution, expected);
+            if let Some(solution) = solve_in_page(&challenge) {
+                assert_eq!(solution, expected);
+            }
+        }
+    }
+}
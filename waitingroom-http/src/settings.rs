@@ -1,9 +1,13 @@
-use foundations::settings::net::SocketAddr;
-use foundations::settings::settings;
-use foundations::telemetry::settings::TelemetrySettings;
+use std::{net::SocketAddr, path::PathBuf};
+
+use serde::Deserialize;
 use waitingroom_basic::GeneralWaitingRoomSettings;
+use waitingroom_core::settings::{deserialize_duration, ensure_non_zero};
+use waitingroom_core::SettingsError;
+use waitingroom_settings::Settings;
 
-#[settings(impl_default = false)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub(crate) struct DemoHTTPServerSettings {
     /// Whether or not to enable the demo HTTP server
     pub(crate) enabled: bool,
@@ -17,41 +21,38 @@ impl Default for DemoHTTPServerSettings {
     fn default() -> Self {
         Self {
             enabled: false,
-            listening_address: SocketAddr::from(std::net::SocketAddr::new(
+            listening_address: SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
                 8052,
-            )),
+            ),
         }
     }
 }
 
-#[settings(impl_default = false)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub(crate) struct WaitingRoomTimerSettings {
-    /// The time in milliseconds between user count syncs across nodes.
-    /// For the basic waiting room, this is a no-op.
-    pub sync_user_counts_interval: u128,
     /// The time in milliseconds between cleanup operations.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub cleanup_interval: u128,
     /// The time in milliseconds between ensuring that correct number
     /// of users are on the site.
+    #[serde(deserialize_with = "deserialize_duration")]
     pub ensure_correct_user_count_interval: u128,
 }
 
 impl Default for WaitingRoomTimerSettings {
     fn default() -> Self {
         Self {
-            sync_user_counts_interval: 10 * 1000,
             cleanup_interval: 10 * 1000,
             ensure_correct_user_count_interval: 10 * 1000,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub(crate) struct HttpServerSettings {
-    /// Telemetry settings
-    pub(crate) telemetry: TelemetrySettings,
-
     /// Basic waiting room settings
     pub(crate) waitingroom: GeneralWaitingRoomSettings,
 
@@ -61,7 +62,7 @@ pub(crate) struct HttpServerSettings {
     /// Timer settings
     pub(crate) timer: WaitingRoomTimerSettings,
 
-    /// Cookie secret
+    /// Cookie secret, hex encoded. This needs to be at least 64 bytes long.
     pub(crate) cookie_secret: String,
 
     /// Webserver listening address
@@ -69,25 +70,65 @@ pub(crate) struct HttpServerSettings {
 
     /// Address of the webserver behind the proxy
     pub(crate) proxy_address: SocketAddr,
+
+    /// Where to keep the journal of the waiting room, so the queue survives a restart.
+    /// Without it, everyone loses their place when the server restarts.
+    pub(crate) journal_path: Option<PathBuf>,
+
+    /// What address the metrics of the waiting room are served on, for Prometheus to scrape.
+    /// Without it, the metrics are not recorded.
+    pub(crate) metrics_listening_address: Option<SocketAddr>,
 }
 
 impl Default for HttpServerSettings {
     fn default() -> Self {
         Self {
-            telemetry: Default::default(),
             waitingroom: Default::default(),
             demo_http_server: Default::default(),
             timer: Default::default(),
             cookie_secret:
                 "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
-            listening_address: SocketAddr::from(std::net::SocketAddr::new(
+            listening_address: SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
                 8051,
-            )),
-            proxy_address: SocketAddr::from(std::net::SocketAddr::new(
+            ),
+            proxy_address: SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
                 8052,
-            )),
+            ),
+            journal_path: None,
+            metrics_listening_address: None,
         }
     }
 }
+
+impl Settings for HttpServerSettings {
+    fn validate(&self) -> Result<(), SettingsError> {
+        self.waitingroom.validate()?;
+
+        ensure_non_zero("timer.cleanup_interval", self.timer.cleanup_interval)?;
+        ensure_non_zero(
+            "timer.ensure_correct_user_count_interval",
+            self.timer.ensure_correct_user_count_interval,
+        )?;
+
+        // The cookie key is created from this secret, which panics if it is too short.
+        match hex::decode(&self.cookie_secret) {
+            Ok(secret) if secret.len() >= 64 => {}
+            Ok(_) => {
+                return Err(SettingsError::Invalid(
+                    "cookie_secret",
+                    "must be at least 64 bytes long".to_string(),
+                ))
+            }
+            Err(err) => {
+                return Err(SettingsError::Invalid(
+                    "cookie_secret",
+                    format!("must be hex encoded ({})", err),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,4 @@
 use crate::settings::WaitingRoomTimerSettings;
-use foundations::telemetry::{log, tracing};
 use std::sync::Arc;
 use std::sync::Mutex;
 use tokio::time::{self, Duration};
@@ -21,16 +20,15 @@ pub(crate) async fn timers(
             let mut $name = time::interval(Duration::from_millis($interval as u64));
             let waitingroom_clone = waitingroom.clone();
             let $name = async move {
-                tracing::add_span_tags! { "timer" => stringify!($name)};
-                log::debug!("Starting timer");
+                log::debug!("Starting timer {}", stringify!($name));
                 loop {
                     $name.tick().await;
-                    log::debug!("Timer triggered"; "timer" => stringify!($name));
+                    log::debug!("Timer {} triggered", stringify!($name));
                     let mut waitingroom = waitingroom_clone.lock().unwrap();
                     match $callback(&mut waitingroom) {
                         Ok(_) => {}
                         Err(err) => {
-                            log::error!("Error in timer: {:?}", err; "timer" => stringify!($name));
+                            log::error!("Error in timer {}: {:?}", stringify!($name), err);
                         }
                     }
                 }
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use waitingroom_core::{
+    challenge::{Challenge, ChallengeKey, ChallengeSolution},
+    time::Time,
+    WaitingRoomError,
+};
+
+/// Gives out signed challenges, and checks their solutions. Challenges are not kept after they are given out.
+/// Only the nonces of challenges that were solved are kept, until the challenge expires, so every challenge can
+/// only be used to join once.
+///
+/// Every node has its own list of solved challenges, so in a distributed room a solution could be used once at
+/// every node. Since a challenge expires soon after it is given out, this is not worth sending messages for.
+#[derive(Debug)]
+pub struct ChallengeList {
+    key: ChallengeKey,
+    /// The nonces of the challenges that were solved, with the time they expire.
+    redeemed: HashMap<u64, Time>,
+}
+
+impl ChallengeList {
+    pub fn new(key: ChallengeKey) -> Self {
+        Self {
+            key,
+            redeemed: HashMap::new(),
+        }
+    }
+
+    /// Replaces the key challenges are signed with. Challenges signed with the previous key are not accepted anymore.
+    pub fn set_key(&mut self, key: ChallengeKey) {
+        self.key = key;
+    }
+
+    /// Creates a new challenge, signed with the key of the list.
+    pub fn issue(&self, nonce: u64, now: Time, expiry_time: Time, difficulty: u8) -> Challenge {
+        self.key.issue(nonce, now, expiry_time, difficulty)
+    }
+
+    /// Checks whether the solution solves a challenge signed with the key of the list, which is not expired and
+    /// was not solved before. If it doesn't, an error is returned. Nothing is kept, so the same solution is
+    /// accepted again until it is redeemed with [`ChallengeList::redeem`].
+    pub fn check(&self, solution: ChallengeSolution, now: Time) -> Result<(), WaitingRoomError> {
+        let challenge = solution.challenge;
+        if !self.key.verify(&challenge)
+            || challenge.is_expired(now)
+            || self.redeemed.contains_key(&challenge.nonce)
+            || !challenge.is_solved_by(solution.solution)
+        {
+            return Err(WaitingRoomError::InvalidChallengeSolution);
+        }
+        Ok(())
+    }
+
+    /// The same as [`ChallengeList::check`], but if the solution is accepted, the challenge can't be used again.
+    pub fn redeem(
+        &mut self,
+        solution: ChallengeSolution,
+        now: Time,
+    ) -> Result<(), WaitingRoomError> {
+        self.check(solution, now)?;
+        let challenge = solution.challenge;
+        self.redeemed.insert(challenge.nonce, challenge.expires_at);
+        Ok(())
+    }
+
+    /// Returns the number of solved challenges that are remembered.
+    pub fn len(&self) -> usize {
+        self.redeemed.len()
+    }
+
+    /// Returns true if no solved challenges are remembered.
+    pub fn is_empty(&self) -> bool {
+        self.redeemed.is_empty()
+    }
+
+    /// Forgets all solved challenges that are expired at the specified time, since they are rejected anyway.
+    pub fn remove_expired(&mut self, time: Time) {
+        self.redeemed.retain(|_, expires_at| *expires_at >= time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenges_are_used_once() {
+        let mut challenges = ChallengeList::new(ChallengeKey::new(b"secret"));
+        let challenge = challenges.issue(1, 100, 50, 8);
+        let solution = challenge.solve();
+
+        // Giving out challenges doesn't keep anything.
+        challenges.issue(2, 100, 50, 8);
+        assert!(challenges.is_empty());
+
+        // Challenges that are not signed with the key are rejected.
+        let unsigned = Challenge::new(1, 100, 50, 8).solve();
+        assert!(matches!(
+            challenges.redeem(unsigned, 100),
+            Err(WaitingRoomError::InvalidChallengeSolution)
+        ));
+        // Checking a solution doesn't use it up.
+        assert!(challenges.check(solution, 110).is_ok());
+        assert!(challenges.is_empty());
+        assert!(challenges.redeem(solution, 120).is_ok());
+        assert!(matches!(
+            challenges.check(solution, 120),
+            Err(WaitingRoomError::InvalidChallengeSolution)
+        ));
+        assert!(matches!(
+            challenges.redeem(solution, 120),
+            Err(WaitingRoomError::InvalidChallengeSolution)
+        ));
+        assert_eq!(challenges.len(), 1);
+
+        challenges.remove_expired(151);
+        assert!(challenges.is_empty());
+        // Once the challenge is forgotten, it has expired, so it still can't be used again.
+        assert!(matches!(
+            challenges.redeem(solution, 151),
+            Err(WaitingRoomError::InvalidChallengeSolution)
+        ));
+    }
+}
@@ -1,17 +1,27 @@
-use std::collections::BTreeMap;
+use std::{cmp::Reverse, collections::BTreeMap};
+
+mod challenges;
+mod pre_queue;
+mod revocations;
+pub use challenges::ChallengeList;
+pub use pre_queue::PreQueue;
+pub use revocations::RevocationList;
 
 use waitingroom_core::{
-    ticket::{Ticket, TicketIdentifier},
+    ticket::{PriorityClass, Ticket, TicketIdentifier},
     time::Time,
 };
 
-/// A queue of tickets. The ordering is based on the join time specified on the ticket.
+/// The key tickets are sorted on. Tickets with a higher priority class come first, then the ones that joined first.
+type QueueKey = (Reverse<PriorityClass>, Time, TicketIdentifier);
+
+/// A queue of tickets. The ordering is based on the priority class and join time specified on the ticket.
 /// The queue is implemented as a BTreeMap. It has a linear time complexity for finding
 /// a ticket in the queue. This is not very efficient, but the local queue is not the
 /// bottleneck in the system.
 #[derive(Debug)]
 pub struct LocalQueue {
-    queue: BTreeMap<(Time, TicketIdentifier), Ticket>,
+    queue: BTreeMap<QueueKey, Ticket>,
 }
 
 impl LocalQueue {
@@ -23,12 +33,18 @@ impl LocalQueue {
 
     /// Add a ticket to the queue.
     pub fn enqueue(&mut self, ticket: Ticket) {
-        self.queue
-            .insert((ticket.join_time, ticket.identifier), ticket);
+        self.queue.insert(
+            (
+                Reverse(ticket.priority_class),
+                ticket.join_time,
+                ticket.identifier,
+            ),
+            ticket,
+        );
     }
 
-    /// Remove the ticket with the lowest join time from the queue.
-    /// If the join time is equal, the ticket with the lowest identifier is removed.
+    /// Remove the ticket with the highest priority class and the lowest join time from the queue.
+    /// If both are equal, the ticket with the lowest identifier is removed.
     pub fn dequeue(&mut self) -> Option<Ticket> {
         self.queue.pop_first().map(|(_, ticket)| ticket)
     }
@@ -37,7 +53,7 @@ impl LocalQueue {
     /// Used to update the ticket when it is refreshed.
     pub fn entry(&mut self, ticket_identifier: TicketIdentifier) -> Option<&mut Ticket> {
         self.queue.iter_mut().find_map(|(identifier, ticket)| {
-            if identifier.1 == ticket_identifier {
+            if identifier.2 == ticket_identifier {
                 Some(ticket)
             } else {
                 None
@@ -96,7 +112,12 @@ impl LocalQueue {
         count
     }
 
-    /// Returns the ticket with the lowest join time in the queue without removing it.
+    /// Returns all tickets in the order they would be dequeued.
+    pub fn iter(&self) -> impl Iterator<Item = &Ticket> {
+        self.queue.values()
+    }
+
+    /// Returns the ticket that would be dequeued next without removing it.
     pub fn peek(&self) -> Option<&Ticket> {
         self.queue.iter().next().map(|(_, ticket)| ticket)
     }
@@ -150,4 +171,34 @@ mod tests {
         assert_eq!(queue.get_position(identifier2), None);
         assert_eq!(queue.dequeue(), None);
     }
+
+    #[test]
+    fn priority_classes() {
+        let mut queue = LocalQueue::new();
+
+        let mut vip = Ticket::new_with_time_and_identifier(1, 5, 0, 0, 0);
+        vip.priority_class = 2;
+        let mut fast_lane0 = Ticket::new_with_time_and_identifier(2, 3, 0, 0, 0);
+        fast_lane0.priority_class = 1;
+        let mut fast_lane1 = Ticket::new_with_time_and_identifier(3, 4, 0, 0, 0);
+        fast_lane1.priority_class = 1;
+        let normal = Ticket::new_with_time_and_identifier(4, 0, 0, 0, 0);
+
+        queue.enqueue(normal);
+        queue.enqueue(fast_lane1);
+        queue.enqueue(vip);
+        queue.enqueue(fast_lane0);
+
+        // Higher classes come first, even though they joined later.
+        assert_eq!(queue.get_position(vip.identifier), Some(0));
+        assert_eq!(queue.get_position(fast_lane0.identifier), Some(1));
+        assert_eq!(queue.get_position(fast_lane1.identifier), Some(2));
+        assert_eq!(queue.get_position(normal.identifier), Some(3));
+        assert!(queue.entry(fast_lane1.identifier).is_some());
+
+        assert_eq!(queue.dequeue(), Some(vip));
+        assert_eq!(queue.dequeue(), Some(fast_lane0));
+        assert_eq!(queue.dequeue(), Some(fast_lane1));
+        assert_eq!(queue.dequeue(), Some(normal));
+    }
 }
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+
+use waitingroom_core::{
+    random::RandomProvider,
+    settings::ActivationWindow,
+    ticket::{Ticket, TicketIdentifier},
+    time::Time,
+};
+
+/// The tickets of users who arrived before the room opened. See [`ActivationWindow`].
+/// Nobody is let out of the pre-queue, the tickets are only moved to the normal queue when the lottery is drawn.
+/// The tickets are keyed by their identifier, so they can be found quickly when they are refreshed. A `BTreeMap`
+/// is used instead of a `HashMap`, so the lottery draws the random numbers in the same order every time.
+#[derive(Debug, Default)]
+pub struct PreQueue {
+    tickets: BTreeMap<TicketIdentifier, Ticket>,
+}
+
+impl PreQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a ticket to the pre-queue.
+    pub fn push(&mut self, ticket: Ticket) {
+        self.tickets.insert(ticket.identifier, ticket);
+    }
+
+    /// Returns the number of tickets in the pre-queue.
+    pub fn len(&self) -> usize {
+        self.tickets.len()
+    }
+
+    /// Returns true if the pre-queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tickets.is_empty()
+    }
+
+    /// Returns the tickets in the pre-queue, ordered by their identifier.
+    pub fn iter(&self) -> impl Iterator<Item = &Ticket> {
+        self.tickets.values()
+    }
+
+    /// Returns true if the pre-queue contains a ticket with the specified identifier.
+    pub fn contains(&self, ticket_identifier: TicketIdentifier) -> bool {
+        self.tickets.contains_key(&ticket_identifier)
+    }
+
+    /// Get a mutable reference to the ticket with the specified identifier.
+    /// Used to update the ticket when it is refreshed.
+    pub fn entry(&mut self, ticket_identifier: TicketIdentifier) -> Option<&mut Ticket> {
+        self.tickets.get_mut(&ticket_identifier)
+    }
+
+    /// Removes a ticket from the pre-queue by its identifier. If the ticket is not in the pre-queue, None is returned.
+    pub fn remove(&mut self, ticket_identifier: TicketIdentifier) -> Option<Ticket> {
+        self.tickets.remove(&ticket_identifier)
+    }
+
+    /// Remove all tickets where the expiry time is less than the specified time.
+    /// Returns the number of removed tickets.
+    pub fn remove_expired(&mut self, time: Time) -> u64 {
+        let count_before = self.tickets.len();
+        self.tickets.retain(|_, ticket| ticket.expiry_time >= time);
+        (count_before - self.tickets.len()) as u64
+    }
+
+    /// Draws the lottery. Every ticket gets a random join time between the start of the window and the opening,
+    /// so they are in a random order among each other, but ahead of everyone who joins after the opening.
+    /// Tickets with the same join time are ordered by their identifier, which is random as well. If the window
+    /// has no pre-queue phase at all, every ticket gets the start of the window as join time.
+    /// The tickets are removed from the pre-queue, and should be added to the normal queue.
+    pub fn draw<R>(&mut self, window: &ActivationWindow, random_provider: &R) -> Vec<Ticket>
+    where
+        R: RandomProvider,
+    {
+        let window_length = window.open_at.saturating_sub(window.starts_at);
+        let mut tickets = self.take_all();
+        for ticket in tickets.iter_mut() {
+            ticket.join_time = window.starts_at;
+            if window_length > 0 {
+                ticket.join_time += random_provider.random_u64() as Time % window_length;
+            }
+        }
+        tickets
+    }
+
+    /// Removes all tickets from the pre-queue without drawing the lottery, so they keep their join times.
+    pub fn take_all(&mut self) -> Vec<Ticket> {
+        std::mem::take(&mut self.tickets).into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use waitingroom_core::random::DeterministicRandomProvider;
+
+    use crate::LocalQueue;
+
+    use super::*;
+
+    #[test]
+    fn lottery() {
+        let window = ActivationWindow {
+            starts_at: 1000,
+            open_at: 2000,
+            ends_at: None,
+        };
+        let mut pre_queue = pre_queue_with_tickets(&window);
+        assert!(pre_queue.contains(5));
+        // Tickets expire 100ms after they joined.
+        assert_eq!(pre_queue.remove_expired(1105), 5);
+        assert!(!pre_queue.contains(4));
+
+        let draw = |seed| {
+            let mut pre_queue = pre_queue_with_tickets(&window);
+            let mut queue = LocalQueue::new();
+            for ticket in pre_queue.draw(&window, &DeterministicRandomProvider::new(seed)) {
+                assert!((window.starts_at..window.open_at).contains(&ticket.join_time));
+                queue.enqueue(ticket);
+            }
+            assert!(pre_queue.is_empty());
+            // Someone who arrives at the opening is behind everyone in the lottery.
+            queue.enqueue(Ticket::new_with_time_and_identifier(
+                100,
+                window.open_at,
+                0,
+                10,
+                100,
+            ));
+            std::iter::from_fn(|| queue.dequeue())
+                .map(|ticket| ticket.identifier)
+                .collect::<Vec<_>>()
+        };
+
+        let order = draw(1);
+        assert_eq!(order, draw(1), "The same seed should give the same order");
+        assert_ne!(order, draw(2));
+        assert_ne!(order[..20], (0..20).collect::<Vec<_>>());
+        assert_eq!(order[20], 100);
+    }
+
+    #[test]
+    fn lottery_without_pre_queue_phase() {
+        let window = ActivationWindow {
+            starts_at: 1000,
+            open_at: 1000,
+            ends_at: None,
+        };
+        let mut pre_queue = pre_queue_with_tickets(&window);
+        let tickets = pre_queue.draw(&window, &DeterministicRandomProvider::new(1));
+        assert_eq!(tickets.len(), 20);
+        assert!(tickets.iter().all(|ticket| ticket.join_time == 1000));
+    }
+
+    /// The users arrive in the order of their identifiers.
+    fn pre_queue_with_tickets(window: &ActivationWindow) -> PreQueue {
+        let mut pre_queue = PreQueue::new();
+        for identifier in 0..20 {
+            pre_queue.push(Ticket::new_with_time_and_identifier(
+                identifier,
+                window.starts_at + identifier as Time,
+                0,
+                10,
+                100,
+            ));
+        }
+        pre_queue
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use waitingroom_core::{ticket::TicketIdentifier, time::Time};
+
+/// The identifiers of tickets or passes that were revoked by an administrator, so they are rejected when the
+/// user tries to use them again, even at another node. A revocation only needs to be kept until the ticket or
+/// pass would have expired without being refreshed, so every revocation has a time it can be forgotten.
+#[derive(Debug, Default)]
+pub struct RevocationList {
+    revoked: HashMap<TicketIdentifier, Time>,
+}
+
+impl RevocationList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Revokes the identifier until the specified time.
+    pub fn revoke(&mut self, identifier: TicketIdentifier, until: Time) {
+        let until = self
+            .revoked
+            .get(&identifier)
+            .map_or(until, |current| until.max(*current));
+        self.revoked.insert(identifier, until);
+    }
+
+    /// Returns true if the identifier is revoked.
+    pub fn contains(&self, identifier: TicketIdentifier) -> bool {
+        self.revoked.contains_key(&identifier)
+    }
+
+    /// Returns all revoked identifiers, with the time until which they are kept.
+    pub fn iter(&self) -> impl Iterator<Item = (TicketIdentifier, Time)> + '_ {
+        self.revoked
+            .iter()
+            .map(|(identifier, until)| (*identifier, *until))
+    }
+
+    /// Returns the number of revoked identifiers that are still kept.
+    pub fn len(&self) -> usize {
+        self.revoked.len()
+    }
+
+    /// Returns true if no identifiers are revoked.
+    pub fn is_empty(&self) -> bool {
+        self.revoked.is_empty()
+    }
+
+    /// Forgets all revocations that were only needed until before the specified time.
+    pub fn remove_expired(&mut self, time: Time) {
+        self.revoked.retain(|_, until| *until >= time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revocations_expire() {
+        let mut revocations = RevocationList::new();
+        revocations.revoke(1, 100);
+        revocations.revoke(2, 200);
+        // Revoking again never shortens the revocation.
+        revocations.revoke(2, 50);
+        assert!(revocations.contains(1));
+        assert!(!revocations.contains(3));
+
+        revocations.remove_expired(150);
+        assert!(!revocations.contains(1));
+        assert!(revocations.contains(2));
+        assert_eq!(revocations.len(), 1);
+    }
+}
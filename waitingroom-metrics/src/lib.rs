@@ -0,0 +1,346 @@
+//! Every metric recorded by the waiting rooms, with its name, unit and labels, and the sinks they are recorded to.
+//!
+//! All metrics are labelled with `node_id`, the node that recorded it. Metrics recorded by a room are also
+//! labelled with `room_id`, so rooms in the same process don't overwrite each other's gauges. The metrics
+//! are recorded to a [`MetricsSink`], which is the [`RecorderSink`] by default. That sink passes them on to
+//! the recorder installed in the `metrics` crate, such as a Prometheus exporter.
+
+use std::{fmt::Debug, sync::Arc};
+
+use metrics::{Label, Unit};
+
+/// Metrics that go up and down, and are recorded as the current value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gauge {
+    /// The number of users in the queue of the node.
+    InQueue,
+    /// The number of users that were let out of the queue, but did not leave it yet.
+    ToLetIn,
+    /// The number of users on the site, as far as the node knows.
+    OnSite,
+}
+
+/// Metrics that only go up, and are recorded as the number of times something happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    /// Users that joined the room.
+    Joins,
+    /// Users that left the queue, and got a pass to go to the site.
+    Leaves,
+    /// Tickets that expired because they weren't refreshed, before the user left the queue.
+    TicketExpiries,
+    /// Passes that expired because they weren't used.
+    PassExpiries,
+    /// Messages from other nodes that failed authentication. This is only labelled with `node_id`.
+    RejectedMessages,
+}
+
+/// Metrics that are recorded as a distribution of values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Histogram {
+    /// The time users spent in the queue, from joining until leaving it, in seconds.
+    WaitTime,
+}
+
+impl Gauge {
+    pub const ALL: [Gauge; 3] = [Gauge::InQueue, Gauge::ToLetIn, Gauge::OnSite];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gauge::InQueue => "waitingroom.in_queue_count",
+            Gauge::ToLetIn => "waitingroom.to_let_in_count",
+            Gauge::OnSite => "waitingroom.on_site_count",
+        }
+    }
+
+    pub fn unit(&self) -> Unit {
+        Unit::Count
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Gauge::InQueue => "The number of users in the queue of the node",
+            Gauge::ToLetIn => "The number of users let out of the queue who did not leave it yet",
+            Gauge::OnSite => "The number of users on the site, as far as the node knows",
+        }
+    }
+}
+
+impl Counter {
+    pub const ALL: [Counter; 5] = [
+        Counter::Joins,
+        Counter::Leaves,
+        Counter::TicketExpiries,
+        Counter::PassExpiries,
+        Counter::RejectedMessages,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::Joins => "waitingroom.join_count",
+            Counter::Leaves => "waitingroom.leave_count",
+            Counter::TicketExpiries => "waitingroom.ticket_expiry_count",
+            Counter::PassExpiries => "waitingroom.pass_expiry_count",
+            Counter::RejectedMessages => "waitingroom.rejected_message_count",
+        }
+    }
+
+    pub fn unit(&self) -> Unit {
+        Unit::Count
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Counter::Joins => "Users that joined the room",
+            Counter::Leaves => "Users that left the queue with a pass",
+            Counter::TicketExpiries => "Tickets that expired before the user left the queue",
+            Counter::PassExpiries => "Passes that expired because they were not used",
+            Counter::RejectedMessages => "Messages from other nodes that failed authentication",
+        }
+    }
+}
+
+impl Histogram {
+    pub const ALL: [Histogram; 1] = [Histogram::WaitTime];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Histogram::WaitTime => "waitingroom.wait_time",
+        }
+    }
+
+    pub fn unit(&self) -> Unit {
+        match self {
+            Histogram::WaitTime => Unit::Seconds,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Histogram::WaitTime => "The time users spent in the queue before leaving it",
+        }
+    }
+}
+
+/// How a gauge is changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GaugeUpdate {
+    Set(f64),
+    Increment(f64),
+    Decrement(f64),
+}
+
+/// The labels every metric is recorded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Labels {
+    /// See `waitingroom_core::NodeId`.
+    pub node_id: usize,
+    /// See `waitingroom_core::RoomId`. This is `None` for metrics that don't belong to a room.
+    pub room_id: Option<u64>,
+}
+
+impl Labels {
+    /// Returns the labels as they are passed on to the `metrics` crate.
+    pub fn to_vec(&self) -> Vec<Label> {
+        let mut labels = vec![Label::new("node_id", self.node_id.to_string())];
+        if let Some(room_id) = self.room_id {
+            labels.push(Label::new("room_id", room_id.to_string()));
+        }
+        labels
+    }
+}
+
+/// Where metrics are recorded. Implement this to send the metrics somewhere else than the `metrics` crate,
+/// or to inspect them in tests.
+pub trait MetricsSink: Debug + Send + Sync {
+    fn gauge(&self, gauge: Gauge, labels: &Labels, update: GaugeUpdate);
+
+    fn counter(&self, counter: Counter, labels: &Labels, increment: u64);
+
+    fn histogram(&self, histogram: Histogram, labels: &Labels, value: f64);
+}
+
+/// Passes the metrics on to the recorder installed in the `metrics` crate.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecorderSink;
+
+impl RecorderSink {
+    /// Describes every metric to the installed recorder, so exporters can show their units and descriptions.
+    /// This should be called once, after the recorder is installed.
+    pub fn describe_all() {
+        for gauge in Gauge::ALL {
+            metrics::describe_gauge!(gauge.name(), gauge.unit(), gauge.description());
+        }
+        for counter in Counter::ALL {
+            metrics::describe_counter!(counter.name(), counter.unit(), counter.description());
+        }
+        for histogram in Histogram::ALL {
+            metrics::describe_histogram!(
+                histogram.name(),
+                histogram.unit(),
+                histogram.description()
+            );
+        }
+    }
+}
+
+impl MetricsSink for RecorderSink {
+    fn gauge(&self, gauge: Gauge, labels: &Labels, update: GaugeUpdate) {
+        let gauge = metrics::gauge!(gauge.name(), labels.to_vec());
+        match update {
+            GaugeUpdate::Set(value) => gauge.set(value),
+            GaugeUpdate::Increment(value) => gauge.increment(value),
+            GaugeUpdate::Decrement(value) => gauge.decrement(value),
+        }
+    }
+
+    fn counter(&self, counter: Counter, labels: &Labels, increment: u64) {
+        metrics::counter!(counter.name(), labels.to_vec()).increment(increment);
+    }
+
+    fn histogram(&self, histogram: Histogram, labels: &Labels, value: f64) {
+        metrics::histogram!(histogram.name(), labels.to_vec()).record(value);
+    }
+}
+
+/// Drops all metrics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl MetricsSink for NoopSink {
+    fn gauge(&self, _gauge: Gauge, _labels: &Labels, _update: GaugeUpdate) {}
+
+    fn counter(&self, _counter: Counter, _labels: &Labels, _increment: u64) {}
+
+    fn histogram(&self, _histogram: Histogram, _labels: &Labels, _value: f64) {}
+}
+
+/// Records metrics to a sink, with the labels of the node or room that holds it.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    sink: Arc<dyn MetricsSink>,
+    labels: Labels,
+}
+
+impl Metrics {
+    /// Creates a handle for the metrics of a room, which records to the [`RecorderSink`].
+    pub fn for_room(node_id: usize, room_id: u64) -> Self {
+        Self {
+            sink: Arc::new(RecorderSink),
+            labels: Labels {
+                node_id,
+                room_id: Some(room_id),
+            },
+        }
+    }
+
+    /// Creates a handle for metrics that don't belong to a room, which records to the [`RecorderSink`].
+    pub fn for_node(node_id: usize) -> Self {
+        Self {
+            sink: Arc::new(RecorderSink),
+            labels: Labels {
+                node_id,
+                room_id: None,
+            },
+        }
+    }
+
+    pub fn set_sink(&mut self, sink: Arc<dyn MetricsSink>) {
+        self.sink = sink;
+    }
+
+    pub fn set_room_id(&mut self, room_id: u64) {
+        self.labels.room_id = Some(room_id);
+    }
+
+    pub fn set_gauge(&self, gauge: Gauge, value: usize) {
+        self.sink
+            .gauge(gauge, &self.labels, GaugeUpdate::Set(value as f64));
+    }
+
+    pub fn increment_gauge(&self, gauge: Gauge, value: usize) {
+        self.sink
+            .gauge(gauge, &self.labels, GaugeUpdate::Increment(value as f64));
+    }
+
+    pub fn decrement_gauge(&self, gauge: Gauge, value: usize) {
+        self.sink
+            .gauge(gauge, &self.labels, GaugeUpdate::Decrement(value as f64));
+    }
+
+    pub fn increment_counter(&self, counter: Counter, increment: u64) {
+        self.sink.counter(counter, &self.labels, increment);
+    }
+
+    pub fn record_histogram(&self, histogram: Histogram, value: f64) {
+        self.sink.histogram(histogram, &self.labels, value);
+    }
+
+    /// Records a duration in milliseconds, as the seconds durations are recorded in.
+    pub fn record_duration(&self, histogram: Histogram, milliseconds: u128) {
+        self.record_histogram(histogram, milliseconds as f64 / 1000.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Mutex};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct RecordingSink {
+        counters: Mutex<Vec<(Counter, Labels, u64)>>,
+    }
+
+    impl MetricsSink for RecordingSink {
+        fn gauge(&self, _gauge: Gauge, _labels: &Labels, _update: GaugeUpdate) {}
+
+        fn counter(&self, counter: Counter, labels: &Labels, increment: u64) {
+            self.counters
+                .lock()
+                .unwrap()
+                .push((counter, *labels, increment));
+        }
+
+        fn histogram(&self, _histogram: Histogram, _labels: &Labels, _value: f64) {}
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let names: Vec<&str> = Gauge::ALL
+            .iter()
+            .map(Gauge::name)
+            .chain(Counter::ALL.iter().map(Counter::name))
+            .chain(Histogram::ALL.iter().map(Histogram::name))
+            .collect();
+        assert!(names.iter().all(|name| name.starts_with("waitingroom.")));
+        assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+    }
+
+    #[test]
+    fn labels() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut metrics = Metrics::for_room(3, 0);
+        metrics.set_sink(sink.clone());
+        metrics.set_room_id(7);
+        metrics.increment_counter(Counter::Joins, 2);
+
+        let labels = Labels {
+            node_id: 3,
+            room_id: Some(7),
+        };
+        assert_eq!(
+            *sink.counters.lock().unwrap(),
+            vec![(Counter::Joins, labels, 2)]
+        );
+        assert_eq!(
+            labels.to_vec(),
+            vec![Label::new("node_id", "3"), Label::new("room_id", "7")]
+        );
+        assert_eq!(
+            Metrics::for_node(3).labels.to_vec(),
+            vec![Label::new("node_id", "3")]
+        );
+    }
+}
@@ -3,7 +3,7 @@
 use waitingroom_core::{
     network::{DummyNetwork, Latency},
     random::DeterministicRandomProvider,
-    settings::GeneralWaitingRoomSettings,
+    settings::{AdmissionMode, GeneralWaitingRoomSettings},
     time::{DummyTimeProvider, Time},
     WaitingRoomMessageTriggered, WaitingRoomUserTriggered,
 };
@@ -21,6 +21,10 @@ fn main() {
 
     let settings = GeneralWaitingRoomSettings {
         target_user_count: 1,
+        capacity_schedule: Vec::new(),
+        admission_mode: AdmissionMode::Concurrent,
+        max_queue_length: None,
+        challenge: None,
         ticket_refresh_time: 6000,
         ticket_expiry_time: 15000,
         pass_expiry_time: 6000,
@@ -29,6 +33,7 @@ fn main() {
         fault_detection_interval: 100,
         eviction_interval: 5000,
         cleanup_interval: 10000,
+        activation_window: None,
     };
 
     log::info!("Instantiating dummy time and network");
@@ -50,7 +55,7 @@ fn main() {
     log::info!("Creating {} waitingroom nodes", node_configs.len());
     for (node_id, (parent, neighbour_config)) in node_configs.iter().enumerate() {
         let mut node = DistributedWaitingRoom::new(
-            settings,
+            settings.clone(),
             node_id,
             dummy_time_provider.clone(),
             random_provider.clone(),
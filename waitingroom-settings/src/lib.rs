@@ -0,0 +1,360 @@
+//! Loading of waiting room settings from YAML, TOML and env files.
+//! Settings can be overridden with environment variables, and they are always validated after loading.
+
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+use waitingroom_core::{settings::GeneralWaitingRoomSettings, SettingsError};
+
+/// Separates the levels of nested settings in env files and environment variables.
+/// For example, `WAITINGROOM__TARGET_USER_COUNT=10` sets `target_user_count` in the `waitingroom` section.
+const ENV_SEPARATOR: &str = "__";
+
+/// Settings that can be loaded by this crate. Everything that is loaded gets validated,
+/// so inconsistent settings are rejected before they are used.
+pub trait Settings: DeserializeOwned {
+    fn validate(&self) -> Result<(), SettingsError>;
+}
+
+impl Settings for GeneralWaitingRoomSettings {
+    fn validate(&self) -> Result<(), SettingsError> {
+        GeneralWaitingRoomSettings::validate(self)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, std::io::Error),
+    UnknownFormat(PathBuf),
+    Parse(String),
+    Invalid(SettingsError),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "Could not read {}: {}", path.display(), err),
+            LoadError::UnknownFormat(path) => write!(
+                f,
+                "Unknown settings format for {}, expected .yaml, .yml, .toml or .env",
+                path.display()
+            ),
+            LoadError::Parse(err) => write!(f, "Could not parse settings: {}", err),
+            LoadError::Invalid(err) => write!(f, "Invalid settings: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<SettingsError> for LoadError {
+    fn from(val: SettingsError) -> Self {
+        LoadError::Invalid(val)
+    }
+}
+
+/// The file formats settings can be loaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Toml,
+    /// One `KEY=value` pair per line. See [`ENV_SEPARATOR`] for how nested settings are set.
+    Env,
+}
+
+impl Format {
+    /// Determines the format from the extension of the path.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "env" => Some(Format::Env),
+            _ => None,
+        }
+    }
+}
+
+/// Loads and validates settings from a file. The format is determined by the file extension.
+pub fn load<S: Settings>(path: impl AsRef<Path>) -> Result<S, LoadError> {
+    load_with_env(Some(path.as_ref()), None)
+}
+
+/// Loads and validates settings from an optional file, after which the settings are overridden
+/// by all environment variables that start with `env_prefix` followed by `__`.
+/// If there is no file, all settings that are not set by environment variables get their default value.
+pub fn load_with_env<S: Settings>(
+    path: Option<&Path>,
+    env_prefix: Option<&str>,
+) -> Result<S, LoadError> {
+    let mut value = match path {
+        Some(path) => {
+            let format =
+                Format::from_path(path).ok_or_else(|| LoadError::UnknownFormat(path.into()))?;
+            let contents =
+                std::fs::read_to_string(path).map_err(|err| LoadError::Io(path.into(), err))?;
+            parse(&contents, format)?
+        }
+        None => Value::Mapping(Mapping::new()),
+    };
+
+    if let Some(env_prefix) = env_prefix {
+        let prefix = format!("{}{}", env_prefix, ENV_SEPARATOR);
+        for (key, raw_value) in std::env::vars() {
+            if let Some(key) = key.strip_prefix(&prefix) {
+                set_env_value(&mut value, key, &raw_value);
+            }
+        }
+    }
+
+    finish(value)
+}
+
+/// Parses and validates settings from a string in the given format.
+pub fn from_str<S: Settings>(contents: &str, format: Format) -> Result<S, LoadError> {
+    finish(parse(contents, format)?)
+}
+
+fn finish<S: Settings>(value: Value) -> Result<S, LoadError> {
+    let settings: S =
+        serde_yaml::from_value(value).map_err(|err| LoadError::Parse(err.to_string()))?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+fn parse(contents: &str, format: Format) -> Result<Value, LoadError> {
+    let value = match format {
+        Format::Yaml => {
+            serde_yaml::from_str(contents).map_err(|err| LoadError::Parse(err.to_string()))?
+        }
+        Format::Toml => {
+            toml::from_str(contents).map_err(|err| LoadError::Parse(err.to_string()))?
+        }
+        Format::Env => {
+            let mut value = Value::Mapping(Mapping::new());
+            for (number, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let line = line.strip_prefix("export ").unwrap_or(line);
+                let (key, raw_value) = line.split_once('=').ok_or_else(|| {
+                    LoadError::Parse(format!("line {} is not a KEY=value pair", number + 1))
+                })?;
+                set_env_value(&mut value, key.trim(), raw_value.trim());
+            }
+            value
+        }
+    };
+
+    // An empty file means all settings get their default value.
+    Ok(match value {
+        Value::Null => Value::Mapping(Mapping::new()),
+        value => value,
+    })
+}
+
+/// Sets the setting described by an env style key to the given value. The value is parsed as a YAML
+/// scalar, so numbers and booleans get the right type. Values that should stay strings can be quoted.
+fn set_env_value(value: &mut Value, key: &str, raw_value: &str) {
+    let parsed_value =
+        serde_yaml::from_str(raw_value).unwrap_or_else(|_| Value::String(raw_value.to_string()));
+
+    let mut current = value;
+    for part in key.to_lowercase().split(ENV_SEPARATOR) {
+        if !current.is_mapping() {
+            *current = Value::Mapping(Mapping::new());
+        }
+        current = current
+            .as_mapping_mut()
+            .unwrap()
+            .entry(Value::String(part.to_string()))
+            .or_insert(Value::Null);
+    }
+    *current = parsed_value;
+}
+
+#[cfg(test)]
+mod tests {
+    use waitingroom_core::settings::{AdmissionMode, CapacityRange, ChallengeSettings};
+
+    use super::*;
+
+    #[test]
+    fn yaml_with_durations() {
+        let settings: GeneralWaitingRoomSettings = from_str(
+            "target_user_count: 5\nticket_refresh_time: 20s\nticket_expiry_time: 1m30s\npass_expiry_time: 1500\n",
+            Format::Yaml,
+        )
+        .unwrap();
+
+        assert_eq!(settings.target_user_count, 5);
+        assert_eq!(settings.ticket_refresh_time, 20 * 1000);
+        assert_eq!(settings.ticket_expiry_time, 90 * 1000);
+        assert_eq!(settings.pass_expiry_time, 1500);
+        // Settings that aren't given get their default value.
+        assert_eq!(
+            settings.cleanup_interval,
+            GeneralWaitingRoomSettings::default().cleanup_interval
+        );
+    }
+
+    #[test]
+    fn toml_and_env() {
+        let toml: GeneralWaitingRoomSettings = from_str(
+            "target_user_count = 7\neviction_interval = \"2s\"\n",
+            Format::Toml,
+        )
+        .unwrap();
+        assert_eq!(toml.target_user_count, 7);
+        assert_eq!(toml.eviction_interval, 2000);
+
+        let env: GeneralWaitingRoomSettings = from_str(
+            "# comment\nTARGET_USER_COUNT=7\nexport EVICTION_INTERVAL=250ms\n",
+            Format::Env,
+        )
+        .unwrap();
+        assert_eq!(env.target_user_count, 7);
+        assert_eq!(env.eviction_interval, 250);
+    }
+
+    #[test]
+    fn admission_mode() {
+        let settings: GeneralWaitingRoomSettings = from_str(
+            "admission_mode:\n  mode: rate\n  users_per_minute: 60\n  burst: 5\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.admission_mode,
+            AdmissionMode::Rate {
+                users_per_minute: 60,
+                burst: 5,
+            }
+        );
+
+        let result: Result<GeneralWaitingRoomSettings, _> = from_str(
+            "admission_mode:\n  mode: rate\n  users_per_minute: 0\n  burst: 5\n",
+            Format::Yaml,
+        );
+        assert!(matches!(
+            result,
+            Err(LoadError::Invalid(SettingsError::Zero(
+                "admission_mode.users_per_minute"
+            )))
+        ));
+    }
+
+    #[test]
+    fn challenge() {
+        let settings: GeneralWaitingRoomSettings = from_str(
+            "challenge:\n  difficulty: 20\n  expiry_time: 1m\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.challenge,
+            Some(ChallengeSettings {
+                difficulty: 20,
+                expiry_time: 60 * 1000,
+            })
+        );
+
+        let result: Result<GeneralWaitingRoomSettings, _> = from_str(
+            "challenge:\n  difficulty: 40\n  expiry_time: 1m\n",
+            Format::Yaml,
+        );
+        assert!(matches!(
+            result,
+            Err(LoadError::Invalid(SettingsError::GreaterThan {
+                lower: ("challenge.difficulty", 40),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn capacity_schedule() {
+        let settings: GeneralWaitingRoomSettings = from_str(
+            "target_user_count: 20\ncapacity_schedule:\n  - from: 22h\n    until: 6h\n    target_user_count: 5\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.capacity_schedule,
+            vec![CapacityRange {
+                from: 22 * 60 * 60 * 1000,
+                until: 6 * 60 * 60 * 1000,
+                target_user_count: 5,
+            }]
+        );
+        // The range continues past midnight, and applies every day.
+        assert_eq!(settings.target_user_count_at(23 * 60 * 60 * 1000), 5);
+        assert_eq!(settings.target_user_count_at(24 * 60 * 60 * 1000), 5);
+        assert_eq!(settings.target_user_count_at(30 * 60 * 60 * 1000), 20);
+        assert_eq!(settings.target_user_count_at(12 * 60 * 60 * 1000), 20);
+
+        let result: Result<GeneralWaitingRoomSettings, _> = from_str(
+            "capacity_schedule:\n  - from: 1d\n    until: 6h\n    target_user_count: 5\n",
+            Format::Yaml,
+        );
+        assert!(matches!(
+            result,
+            Err(LoadError::Invalid(SettingsError::NotLessThan {
+                lower: ("capacity_schedule.from", _),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn inconsistent_settings() {
+        let result: Result<GeneralWaitingRoomSettings, _> = from_str(
+            "ticket_refresh_time: 20s\nticket_expiry_time: 10s\n",
+            Format::Yaml,
+        );
+        assert!(matches!(
+            result,
+            Err(LoadError::Invalid(SettingsError::NotLessThan {
+                lower: ("ticket_refresh_time", 20000),
+                higher: ("ticket_expiry_time", 10000),
+            }))
+        ));
+
+        let result: Result<GeneralWaitingRoomSettings, _> = from_str(
+            "fault_detection_timeout: 2s\nfault_detection_period: 1s\n",
+            Format::Yaml,
+        );
+        assert!(matches!(
+            result,
+            Err(LoadError::Invalid(SettingsError::NotLessThan { .. }))
+        ));
+
+        let result: Result<GeneralWaitingRoomSettings, _> =
+            from_str("max_queue_length: 0\n", Format::Yaml);
+        assert!(matches!(
+            result,
+            Err(LoadError::Invalid(SettingsError::Zero("max_queue_length")))
+        ));
+
+        let result: Result<GeneralWaitingRoomSettings, _> =
+            from_str("ticket_refresh_time: 20 parsecs\n", Format::Yaml);
+        assert!(matches!(result, Err(LoadError::Parse(_))));
+
+        let result: Result<GeneralWaitingRoomSettings, _> =
+            from_str("min_user_count: 1\n", Format::Yaml);
+        assert!(matches!(result, Err(LoadError::Parse(_))));
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut value = parse("waitingroom:\n  target_user_count: 5\n", Format::Yaml).unwrap();
+        set_env_value(&mut value, "WAITINGROOM__TARGET_USER_COUNT", "10");
+        set_env_value(&mut value, "WAITINGROOM__PASS_EXPIRY_TIME", "5m");
+
+        let settings: GeneralWaitingRoomSettings =
+            serde_yaml::from_value(value["waitingroom"].clone()).unwrap();
+        assert_eq!(settings.target_user_count, 10);
+        assert_eq!(settings.pass_expiry_time, 5 * 60 * 1000);
+    }
+}
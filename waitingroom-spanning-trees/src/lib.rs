@@ -1,6 +1,9 @@
 use std::vec;
 
-use waitingroom_core::NodeId;
+use waitingroom_core::{
+    wire::{WireDecode, WireEncode, WireReader, WireWriter},
+    NodeId, WireError,
+};
 
 type AdjacencyList = Vec<(NodeId, Vec<usize>)>;
 type Edge = (NodeId, NodeId);
@@ -244,6 +247,29 @@ impl SpanningTree {
     }
 }
 
+// The tree is sent as the adjacency list, so the receiving nodes get exactly the same tree.
+impl WireEncode for SpanningTree {
+    fn encode(&self, writer: &mut WireWriter) {
+        writer.put_list(&self.adjacency_list, |writer, (node_id, neighbours)| {
+            writer.put_node_id(*node_id);
+            writer.put_list(neighbours, |writer, neighbour| {
+                writer.put_node_id(*neighbour)
+            });
+        });
+    }
+}
+
+impl WireDecode for SpanningTree {
+    fn decode(reader: &mut WireReader) -> Result<Self, WireError> {
+        let adjacency_list = reader.read_list(|reader| {
+            let node_id = reader.read_node_id()?;
+            let neighbours = reader.read_list(|reader| reader.read_node_id())?;
+            Ok((node_id, neighbours))
+        })?;
+        Ok(SpanningTree { adjacency_list })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2,9 +2,9 @@ use fern::colors::ColoredLevelConfig;
 use log::LevelFilter;
 use rayon::prelude::*;
 use waitingroom_core::{
-    network::{DummyNetwork, LatencySetting},
+    network::{DummyNetwork, FaultSetting, LatencySetting},
     random::DeterministicRandomProvider,
-    settings::GeneralWaitingRoomSettings,
+    settings::{AdmissionMode, GeneralWaitingRoomSettings},
     time::{DummyTimeProvider, TimeProvider},
 };
 use waitingroom_distributed::messages::NodeToNodeMessage;
@@ -79,6 +79,10 @@ fn main() {
     let config = SimulationConfig {
         settings: GeneralWaitingRoomSettings {
             target_user_count: 200,
+            capacity_schedule: Vec::new(),
+            admission_mode: AdmissionMode::Concurrent,
+            max_queue_length: None,
+            challenge: None,
             ticket_refresh_time: 600,
             ticket_expiry_time: 2000,
             pass_expiry_time: 0,
@@ -87,9 +91,11 @@ fn main() {
             fault_detection_interval: 100,
             eviction_interval: 1000,
             cleanup_interval: 1000,
+            activation_window: None,
         },
         initial_node_count: 8,
         latency: LatencySetting::UniformRandom(5, 10),
+        faults: FaultSetting::default(),
         total_user_count: 100,
         nodes_added_count: 1,
         nodes_killed_count: 1,
@@ -122,6 +128,10 @@ fn one_one_test() {
     let config = SimulationConfig {
         settings: GeneralWaitingRoomSettings {
             target_user_count: 20,
+            capacity_schedule: Vec::new(),
+            admission_mode: AdmissionMode::Concurrent,
+            max_queue_length: None,
+            challenge: None,
             ticket_refresh_time: 600,
             ticket_expiry_time: 2000,
             pass_expiry_time: 0,
@@ -130,9 +140,11 @@ fn one_one_test() {
             fault_detection_interval: 100,
             eviction_interval: 1000,
             cleanup_interval: 1000,
+            activation_window: None,
         },
         initial_node_count: 8,
         latency: LatencySetting::UniformRandom(10, 20),
+        faults: FaultSetting::default(),
         total_user_count: 500,
         nodes_added_count: 1,
         nodes_killed_count: 1,
@@ -40,7 +40,7 @@ pub struct RunningSimulation {
 }
 
 impl RunningSimulation {
-    fn new(config: &SimulationConfig, seed: u64) -> Self {
+    fn new(config: &SimulationConfig, seed: u64) -> Result<Self, WaitingRoomError> {
         let time_provider = DummyTimeProvider::new();
         let random_providers = RandomProviders::new(seed);
 
@@ -48,24 +48,29 @@ impl RunningSimulation {
             time_provider.clone(),
             config
                 .latency
-                .to_latency(Some(random_providers.network_random_provider().clone())),
+                .to_latency(Some(random_providers.network_random_provider().clone()))?,
+        );
+        network.set_faults(
+            config
+                .faults
+                .to_faults(random_providers.fault_random_provider().clone()),
         );
 
-        Self {
+        Ok(Self {
             time_provider,
             random_providers,
             network,
             nodes: Vec::new(),
             next_node_id: 0,
-            node_settings: config.settings,
+            node_settings: config.settings.clone(),
             results: SimulationResultsBuilder::new(),
             users: Vec::new(),
-        }
+        })
     }
 
     fn add_node(&mut self) -> Result<(), WaitingRoomError> {
         let mut node = waitingroom_distributed::DistributedWaitingRoom::new(
-            self.node_settings,
+            self.node_settings.clone(),
             self.next_node_id,
             self.time_provider.clone(),
             self.random_providers.node_random_provider().clone(),
@@ -117,15 +122,7 @@ impl RunningSimulation {
         log::debug!("Number of users: {}", self.users.len());
         log::debug!("Number of nodes: {}\nNodes:", self.nodes.len());
         for node in self.nodes.iter() {
-            log::debug!(
-                "Node {}\t\tQPID parent: {:?}",
-                node.get_node_id(),
-                node.get_qpid_parent()
-            );
-            log::debug!(
-                "True neighbours: {:?}",
-                node.get_qpid_weight_table().get_true_neighbours()
-            );
+            log::debug!("Node {}\t\tStatus: {:?}", node.get_node_id(), node.status());
             log::debug!("Weight table:");
             log::debug!("Neighbour\t\tWeight");
             for (neighbour, weight) in node.get_qpid_weight_table().all_weights() {
@@ -161,11 +158,14 @@ impl RunningSimulation {
             .results
             .build(normalised_kendall_tau, self.time_provider.get_now_time());
 
-        if built_results.total_users_added != built_results.total_users_left {
+        if built_results.total_users_added
+            != built_results.total_users_left + built_results.total_users_abandoned
+        {
             log::error!(
-                "Total users added ({}) does not match total users left ({})",
+                "Total users added ({}) does not match total users left ({}) and abandoned ({})",
                 built_results.total_users_added,
-                built_results.total_users_left
+                built_results.total_users_left,
+                built_results.total_users_abandoned
             );
             self.debug_print();
             return Err(SimulationError::NotAllUsersLeft);
@@ -230,7 +230,7 @@ impl RunningSimulation {
         Ok(())
     }
 
-    fn do_user_actions(&mut self, _user_behaviour: &UserBehaviour) -> Result<(), WaitingRoomError> {
+    fn do_user_actions(&mut self, user_behaviour: &UserBehaviour) -> Result<(), WaitingRoomError> {
         let now = self.time_provider.get_now_time();
 
         let available_node_idxs = self
@@ -291,8 +291,31 @@ impl RunningSimulation {
                                 checkin_response.new_ticket,
                                 checkin_response.position_estimate,
                             );
+                            if user.next_action() == user::QueueAction::Refreshing
+                                && one_in(
+                                    self.random_providers.user_random_provider(),
+                                    user_behaviour.abandon_odds,
+                                )
+                            {
+                                user.start_abandoning();
+                            }
+                        }
+                        user::QueueAction::Abandoning => {
+                            // If the node is gone, the ticket is gone with it, so there is nothing to abandon.
+                            if let Some(node) = self
+                                .nodes
+                                .iter_mut()
+                                .find(|n| n.get_node_id() == ticket.node_id)
+                            {
+                                match node.abandon(*ticket) {
+                                    // The ticket expired and was cleaned up already.
+                                    Ok(()) | Err(WaitingRoomError::TicketNotInQueue) => {}
+                                    Err(err) => return Err(err),
+                                }
+                            }
+                            user.abandon();
+                            self.results.abandoned_user();
                         }
-                        user::QueueAction::Abandoning => todo!(),
                         user::QueueAction::Leaving => {
                             let node = match self
                                 .nodes
@@ -315,12 +338,36 @@ impl RunningSimulation {
                                 }
                                 Err(err) => return Err(err),
                             };
-                            user.leave(pass);
+                            user.leave(pass, now);
                             self.results.left_user();
                         }
                     },
-                    // We don't refresh tickets when users are on the site yet.
-                    user::UserState::OnSite { .. } => todo!(),
+                    user::UserState::OnSite { pass } => {
+                        let node = self
+                            .nodes
+                            .iter_mut()
+                            .find(|n| n.get_node_id() == pass.node_id);
+                        if one_in(
+                            self.random_providers.user_random_provider(),
+                            user_behaviour.pass_refresh_odds,
+                        ) {
+                            // The user stays on the site. If the pass is no longer valid, for example because
+                            // the node that gave it out is gone, they are done anyway.
+                            match node.map(|node| node.validate_and_refresh_pass(*pass)) {
+                                Some(Ok(new_pass)) => user.refresh_pass(new_pass, now),
+                                _ => user.finish(),
+                            }
+                            continue;
+                        }
+                        if let Some(node) = node {
+                            match node.release(*pass) {
+                                // The pass expired and was cleaned up already.
+                                Ok(()) | Err(WaitingRoomError::PassNotInList) => {}
+                                Err(err) => return Err(err),
+                            }
+                        }
+                        user.finish();
+                    }
                     user::UserState::Done { .. } => {}
                     user::UserState::Abandoned { .. } => {}
                 }
@@ -392,7 +439,8 @@ impl Simulation {
     pub fn run(&self, seed: u64) -> Result<SimulationResults, SimulationError> {
         log::info!("Running simulation with seed {}", seed);
 
-        let mut sim = RunningSimulation::new(&self.config, seed);
+        let mut sim =
+            RunningSimulation::new(&self.config, seed).map_err(SimulationError::WaitingRoom)?;
 
         match sim.initialise_network(self.config.initial_node_count) {
             Ok(_) => {}
@@ -517,3 +565,8 @@ impl Simulation {
         sim.final_checks_and_results(self.config.check_consistency)
     }
 }
+
+/// Returns true with a chance of 1 in `odds`. If `odds` is 0, this is never true.
+fn one_in<R: RandomProvider>(random_provider: &R, odds: u64) -> bool {
+    odds != 0 && random_provider.random_u64().is_multiple_of(odds)
+}
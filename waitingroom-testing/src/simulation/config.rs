@@ -1,10 +1,16 @@
-use waitingroom_core::{network::LatencySetting, settings::GeneralWaitingRoomSettings, time::Time};
+use waitingroom_core::{
+    network::{FaultSetting, LatencySetting},
+    settings::GeneralWaitingRoomSettings,
+    time::Time,
+};
 
 use super::UserBehaviour;
 
 pub struct SimulationConfig {
     pub settings: GeneralWaitingRoomSettings,
     pub latency: LatencySetting,
+    /// Message loss, duplication, reordering and partitions in the simulated network.
+    pub faults: FaultSetting,
     pub initial_node_count: usize,
     pub total_user_count: usize,
     pub nodes_killed_count: usize,
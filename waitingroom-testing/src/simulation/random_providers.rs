@@ -5,6 +5,7 @@ pub struct RandomProviders {
     node_random_provider: DeterministicRandomProvider,
     disturbance_random_provider: DeterministicRandomProvider,
     user_random_provider: DeterministicRandomProvider,
+    fault_random_provider: DeterministicRandomProvider,
 }
 
 impl RandomProviders {
@@ -19,12 +20,16 @@ impl RandomProviders {
             DeterministicRandomProvider::new(base_random_provider.random_u64());
         let user_random_provider =
             DeterministicRandomProvider::new(base_random_provider.random_u64());
+        // This one is created last, so the others stay the same as before it was added.
+        let fault_random_provider =
+            DeterministicRandomProvider::new(base_random_provider.random_u64());
 
         Self {
             network_random_provider,
             node_random_provider,
             disturbance_random_provider,
             user_random_provider,
+            fault_random_provider,
         }
     }
 
@@ -43,4 +48,8 @@ impl RandomProviders {
     pub fn user_random_provider(&self) -> &DeterministicRandomProvider {
         &self.user_random_provider
     }
+
+    pub fn fault_random_provider(&self) -> &DeterministicRandomProvider {
+        &self.fault_random_provider
+    }
 }
@@ -7,6 +7,8 @@ pub(super) struct SimulationResultsBuilder {
     total_users_added: usize,
     /// Number of users that actually left the waiting room (got a pass).
     total_users_left: usize,
+    /// Number of users that abandoned the queue before they got a pass.
+    total_users_abandoned: usize,
 
     /// Number of nodes that were added to the network.
     /// This includes the initial node(s).
@@ -21,6 +23,8 @@ pub struct SimulationResults {
     pub total_users_added: usize,
     /// Number of users that actually left the waiting room (got a pass).
     pub total_users_left: usize,
+    /// Number of users that abandoned the queue before they got a pass.
+    pub total_users_abandoned: usize,
 
     /// Number of nodes that were added to the network.
     /// This includes the initial node(s).
@@ -40,6 +44,7 @@ impl SimulationResultsBuilder {
         Self {
             total_users_added: 0,
             total_users_left: 0,
+            total_users_abandoned: 0,
             total_nodes_added: 0,
             total_nodes_removed: 0,
         }
@@ -53,6 +58,10 @@ impl SimulationResultsBuilder {
         self.total_users_left += 1;
     }
 
+    pub fn abandoned_user(&mut self) {
+        self.total_users_abandoned += 1;
+    }
+
     pub fn add_node(&mut self) {
         self.total_nodes_added += 1;
     }
@@ -67,6 +76,7 @@ impl SimulationResultsBuilder {
         SimulationResults {
             total_users_added: self.total_users_added,
             total_users_left: self.total_users_left,
+            total_users_abandoned: self.total_users_abandoned,
             total_nodes_added: self.total_nodes_added,
             total_nodes_removed: self.total_nodes_removed,
             kendall_tau,
@@ -2,7 +2,11 @@ use waitingroom_core::{pass::Pass, ticket::Ticket, time::Time};
 
 #[derive(Debug)]
 pub struct UserBehaviour {
+    /// Every time a user in the queue refreshes their ticket, there is a 1 in `abandon_odds` chance
+    /// that they abandon the queue afterwards. If this is 0, users never abandon.
     pub abandon_odds: u64,
+    /// Halfway until the pass of a user on the site expires, there is a 1 in `pass_refresh_odds` chance that they
+    /// stay on the site and refresh it. Otherwise, they release the pass. If this is 0, users never stay.
     pub pass_refresh_odds: u64,
 }
 
@@ -102,10 +106,21 @@ impl User {
         }
     }
 
-    pub fn leave(&mut self, pass: Pass) {
+    pub fn leave(&mut self, pass: Pass, now: Time) {
         assert_eq!(self.next_action(), QueueAction::Leaving);
+        self.next_action_time = pass_action_time(&pass, now);
         self.state = UserState::OnSite { pass };
-        self.next_action_time = Time::MAX; // TODO: Add refreshing pass
+    }
+
+    pub fn start_abandoning(&mut self) {
+        assert_eq!(self.next_action(), QueueAction::Refreshing);
+        if let UserState::InQueue { ticket, .. } = &self.state {
+            self.next_action_time = 0; // We want to abandon the queue ASAP
+            self.state = UserState::InQueue {
+                ticket: *ticket,
+                next_action: QueueAction::Abandoning,
+            };
+        }
     }
 
     pub fn abandon(&mut self) {
@@ -118,8 +133,9 @@ impl User {
         self.next_action_time = Time::MAX;
     }
 
-    pub fn refresh_pass(&mut self, new_pass: Pass) {
+    pub fn refresh_pass(&mut self, new_pass: Pass, now: Time) {
         assert!(self.state.is_on_site());
+        self.next_action_time = pass_action_time(&new_pass, now);
         self.state = UserState::OnSite { pass: new_pass };
     }
 
@@ -153,3 +169,9 @@ impl User {
         }
     }
 }
+
+/// Users on the site refresh or release their pass halfway until it expires, like a browser refreshing it in the
+/// background, so the pass is still valid when they do.
+fn pass_action_time(pass: &Pass, now: Time) -> Time {
+    now + pass.expiry_time.saturating_sub(now) / 2
+}